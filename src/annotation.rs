@@ -0,0 +1,242 @@
+//! Lines, arrows, rectangles and ellipses drawn on top of the image. They are kept as vector
+//! shapes, so they can be moved and resized until the image is saved.
+
+use image::{Pixel, Rgba, RgbaImage};
+use nalgebra::Vector2;
+use notan::egui::{
+    self, Color32, Context, CursorIcon, DragValue, Id, Pos2, Response, Sense, Stroke, Ui, Vec2,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::ImageGeometry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AnnotationKind {
+    Line,
+    Arrow,
+    Rectangle,
+    Ellipse,
+}
+
+impl AnnotationKind {
+    pub const ALL: [Self; 4] = [Self::Line, Self::Arrow, Self::Rectangle, Self::Ellipse];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Line => "Line",
+            Self::Arrow => "Arrow",
+            Self::Rectangle => "Rectangle",
+            Self::Ellipse => "Ellipse",
+        }
+    }
+
+    /// Whether the shape encloses an area which can be filled
+    fn is_closed(&self) -> bool {
+        matches!(self, Self::Rectangle | Self::Ellipse)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    /// From 0-1. Rectangles and ellipses span the box between `start` and `end`.
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub color: [f32; 4],
+    /// Line width, relative to the smaller image side
+    pub width: f32,
+    pub filled: bool,
+}
+
+impl Annotation {
+    pub fn new(kind: AnnotationKind, at: (f32, f32), color: [f32; 4]) -> Self {
+        Self {
+            kind,
+            start: at,
+            end: at,
+            color,
+            width: 0.005,
+            filled: false,
+        }
+    }
+
+    pub fn render(&self, img: &mut RgbaImage) {
+        let (w, h) = (img.width() as f32, img.height() as f32);
+        let start = Vector2::new(self.start.0 * w, self.start.1 * h);
+        let end = Vector2::new(self.end.0 * w, self.end.1 * h);
+        let half_width = (self.width * w.min(h)).max(1.) / 2.;
+        let head = half_width * 6. + 4.;
+
+        // only visit the pixels the shape can touch
+        let margin = Vector2::repeat(head + half_width + 1.);
+        let min = start.inf(&end) - margin;
+        let max = start.sup(&end) + margin;
+        let (x0, y0) = (min.x.clamp(0., w) as u32, min.y.clamp(0., h) as u32);
+        let (x1, y1) = (max.x.clamp(0., w) as u32, max.y.clamp(0., h) as u32);
+
+        let fill = self.filled && self.kind.is_closed();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let d = self.signed_distance(p, start, end, head);
+                let d = if fill { d } else { d.abs() };
+                // half a pixel of anti-aliasing
+                let coverage = (half_width - d + 0.5).clamp(0., 1.);
+                if coverage > 0. {
+                    img.get_pixel_mut(x, y).blend(&Rgba([
+                        (self.color[0] * 255.) as u8,
+                        (self.color[1] * 255.) as u8,
+                        (self.color[2] * 255.) as u8,
+                        (self.color[3] * coverage * 255.) as u8,
+                    ]));
+                }
+            }
+        }
+    }
+
+    /// Distance of `p` to the outline, negative inside closed shapes
+    fn signed_distance(
+        &self,
+        p: Vector2<f32>,
+        start: Vector2<f32>,
+        end: Vector2<f32>,
+        head: f32,
+    ) -> f32 {
+        match self.kind {
+            AnnotationKind::Line => segment_distance(p, start, end),
+            AnnotationKind::Arrow => {
+                let shaft = segment_distance(p, start, end);
+                let dir = end - start;
+                if dir.norm() == 0. {
+                    return shaft;
+                }
+                let back = -dir.normalize() * head;
+                // the barbs point back at 30° from the shaft
+                let (sin, cos) = (30_f32).to_radians().sin_cos();
+                let left = Vector2::new(back.x * cos - back.y * sin, back.x * sin + back.y * cos);
+                let right = Vector2::new(back.x * cos + back.y * sin, -back.x * sin + back.y * cos);
+                shaft
+                    .min(segment_distance(p, end, end + left))
+                    .min(segment_distance(p, end, end + right))
+            }
+            AnnotationKind::Rectangle => {
+                let center = (start + end) / 2.;
+                let half_size = (end - start).abs() / 2.;
+                let q = (p - center).abs() - half_size;
+                let outside = q.sup(&Vector2::zeros()).norm();
+                let inside = q.x.max(q.y).min(0.);
+                outside + inside
+            }
+            AnnotationKind::Ellipse => {
+                let center = (start + end) / 2.;
+                let radius = (end - start).abs() / 2.;
+                if radius.x < 1. || radius.y < 1. {
+                    return segment_distance(p, start, end);
+                }
+                let d = p - center;
+                let r = ((d.x / radius.x).powi(2) + (d.y / radius.y).powi(2)).sqrt();
+                // approximate the distance to the outline
+                (r - 1.) * radius.x.min(radius.y)
+            }
+        }
+    }
+
+    /// Color, width and fill of a single annotation, in a grid row
+    pub fn ui(&mut self, ui: &mut Ui) -> Response {
+        let mut r = ui.label(self.kind.name());
+        if ui
+            .color_edit_button_rgba_unmultiplied(&mut self.color)
+            .changed()
+        {
+            r.mark_changed();
+        }
+        if ui
+            .add(
+                DragValue::new(&mut self.width)
+                    .clamp_range(0.001..=0.1)
+                    .speed(0.0005),
+            )
+            .changed()
+        {
+            r.mark_changed();
+        }
+        if self.kind.is_closed() {
+            if ui.checkbox(&mut self.filled, "").changed() {
+                r.mark_changed();
+            }
+        } else {
+            ui.label("");
+        }
+        r
+    }
+}
+
+fn segment_distance(p: Vector2<f32>, a: Vector2<f32>, b: Vector2<f32>) -> f32 {
+    let ab = b - a;
+    let t = if ab.norm_squared() == 0. {
+        0.
+    } else {
+        ((p - a).dot(&ab) / ab.norm_squared()).clamp(0., 1.)
+    };
+    (p - (a + ab * t)).norm()
+}
+
+/// Draggable handles to move the ends of each annotation, or the whole annotation by its center.
+/// Returns true if an annotation changed.
+pub fn handles_ui(ctx: &Context, annotations: &mut [Annotation], geo: &ImageGeometry) -> bool {
+    let size = Vec2::new(
+        geo.dimensions.0 as f32 * geo.scale,
+        geo.dimensions.1 as f32 * geo.scale,
+    );
+    if size.x <= 0. || size.y <= 0. {
+        return false;
+    }
+    let to_screen =
+        |p: (f32, f32)| Pos2::new(geo.offset.x + p.0 * size.x, geo.offset.y + p.1 * size.y);
+    let handle_size = 10.;
+    let mut changed = false;
+
+    for (i, annotation) in annotations.iter_mut().enumerate() {
+        let center = (
+            (annotation.start.0 + annotation.end.0) / 2.,
+            (annotation.start.1 + annotation.end.1) / 2.,
+        );
+        for (handle, pos) in [(0, annotation.start), (1, annotation.end), (2, center)] {
+            let delta = egui::Area::new(Id::new("annotation handle").with(i).with(handle))
+                .fixed_pos(to_screen(pos) - Vec2::splat(handle_size / 2.))
+                .show(ctx, |ui| {
+                    let (rect, response) =
+                        ui.allocate_exact_size(Vec2::splat(handle_size), Sense::drag());
+                    let stroke = Stroke::new(1.5, ui.style().visuals.selection.bg_fill);
+                    if handle == 2 {
+                        ui.painter()
+                            .circle_stroke(rect.center(), handle_size / 2., stroke);
+                    } else {
+                        ui.painter().rect(rect, 0., Color32::WHITE, stroke);
+                    }
+                    let delta = response.drag_delta();
+                    response.on_hover_cursor(CursorIcon::Grab);
+                    delta
+                })
+                .inner;
+            if delta == Vec2::ZERO {
+                continue;
+            }
+            let delta = (delta.x / size.x, delta.y / size.y);
+            let shift = |p: &mut (f32, f32)| {
+                p.0 = (p.0 + delta.0).clamp(0., 1.);
+                p.1 = (p.1 + delta.1).clamp(0., 1.);
+            };
+            match handle {
+                0 => shift(&mut annotation.start),
+                1 => shift(&mut annotation.end),
+                _ => {
+                    shift(&mut annotation.start);
+                    shift(&mut annotation.end);
+                }
+            }
+            changed = true;
+        }
+    }
+    changed
+}
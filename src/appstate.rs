@@ -1,16 +1,36 @@
 use crate::{
-    image_editing::EditState,
+    capture::Capture,
+    compare::Blend,
+    convert::ConvertJob,
+    dicom::{DicomImage, Window},
+    download::Download,
+    fits::StretchSettings,
+    folders::FolderTree,
+    gallery::Gallery,
+    gpu::{GpuEditor, ShaderState},
+    histogram::Histogram,
+    hotfolder::HotFolder,
+    image_editing::{BatchJob, EditPreset, EditState},
+    metadata::MapPreview,
+    plugins::PluginManifest,
+    psd_layers::PsdTree,
+    scripting::ScriptJob,
     scrubber::Scrubber,
     settings::PersistentSettings,
+    tiled::TileView,
+    tonemap::ToneMapper,
     utils::{ExtendedImageInfo, Frame, Player},
+    video::Video,
+    xmp::Rating,
 };
 use egui_notify::Toasts;
-use image::RgbaImage;
+use image::{Rgba32FImage, RgbaImage};
 use nalgebra::Vector2;
 use notan::{egui::epaint::ahash::HashMap, prelude::Texture, AppState};
 use std::{
     path::PathBuf,
     sync::mpsc::{self, Receiver, Sender},
+    time::Instant,
 };
 
 #[derive(Debug, Clone)]
@@ -48,6 +68,8 @@ impl Message {
 pub struct OculanteState {
     pub image_geometry: ImageGeometry,
     pub compare_list: HashMap<PathBuf, ImageGeometry>,
+    /// How the image is blended with another one of the compare list
+    pub compare_blend: Blend,
     pub drag_enabled: bool,
     pub reset_image: bool,
     /// Is the image fully loaded?
@@ -61,19 +83,98 @@ pub struct OculanteState {
     pub message_channel: (Sender<Message>, Receiver<Message>),
     /// Channel to load images from
     pub load_channel: (Sender<PathBuf>, Receiver<PathBuf>),
+    /// The image shown, when it changed on disk
+    pub reload_channel: (Sender<PathBuf>, Receiver<PathBuf>),
     pub extended_info_channel: (Sender<ExtendedImageInfo>, Receiver<ExtendedImageInfo>),
     pub extended_info_loading: bool,
     /// The Player, responsible for loading and sending Frames
     pub player: Player,
     pub current_texture: Option<Texture>,
+    /// The unedited image, uploaded when comparing with the edit result
+    pub original_texture: Option<Texture>,
+    /// The full range pixels of `current_hdr_image`, drawn with `tone_mapper`
+    pub hdr_texture: Option<Texture>,
+    pub tone_mapper: Option<ToneMapper>,
     pub current_path: Option<PathBuf>,
     pub current_image: Option<RgbaImage>,
+    /// The current image is a frame of an animation
+    pub is_animation: bool,
+    /// The size SVG images are rendered at, relative to their own size. None for raster images.
+    pub vector_scale: Option<f32>,
+    /// The size of the image a preview stands in for, while the full image is decoded
+    pub preview_of: Option<(u32, u32)>,
+    /// A vector image is being rendered at a new size
+    pub vector_rendering: bool,
+    /// The mip levels of the current texture, once they were asked for
+    pub texture_mips: Vec<RgbaImage>,
+    /// The mip level that is shown, stretched to the size of the first
+    pub mip_level: usize,
+    /// Show every mip level side by side instead of `mip_level`
+    pub show_mip_chain: bool,
+    /// The sizes stored in the current icon, once they were asked for
+    pub icon_sizes: Vec<RgbaImage>,
+    /// The index of the shown icon size
+    pub icon_size: usize,
+    /// The layers of the current PSD file, once they were asked for
+    pub psd_tree: Option<PsdTree>,
+    /// How the current FITS image is stretched
+    pub fits_stretch: StretchSettings,
+    /// The current DICOM image and its window, once the window is adjusted
+    pub dicom: Option<(DicomImage, Window)>,
+    /// The tiles of a very large image, which is shown as an overview otherwise
+    pub tiles: Option<TileView>,
+    /// The open video, whose frames are stepped through instead of the folder
+    pub video: Option<Video>,
+    /// A camera or capture card shown live instead of an image
+    pub capture: Option<Capture>,
+    /// The devices offered for capture, listed when the menu is opened
+    pub capture_devices: Vec<String>,
+    /// The capture devices, while they are being listed
+    pub capture_device_listing: Option<Receiver<Vec<String>>>,
+    /// A link being downloaded, to be opened when complete
+    pub download: Option<Download>,
+    /// The thumbnails of the folder, shown instead of the image while open
+    pub gallery: Option<Gallery>,
+    /// The folder watched for new images to show
+    pub hot_folder: Option<HotFolder>,
+    /// What is typed to find a recent file, while the list of them is open
+    pub recent_filter: Option<String>,
+    /// The folders read for the folder panel
+    pub folder_tree: FolderTree,
+    /// Full range pixels of `current_image` if it comes from a high dynamic range source
+    pub current_hdr_image: Option<Rgba32FImage>,
     pub settings_enabled: bool,
     pub image_info: Option<ExtendedImageInfo>,
     pub tiling: usize,
     pub mouse_grab: bool,
     pub key_grab: bool,
     pub edit_state: EditState,
+    /// Edits being applied to a folder in the background
+    pub batch_job: Option<BatchJob>,
+    /// A script from the edit panel running in the background
+    pub script_job: Option<ScriptJob>,
+    /// Filters from the plugin directory, read again whenever the filter menu is opened
+    pub plugins: Vec<PluginManifest>,
+    /// When shaders linked to a file were last read again
+    pub shader_check: Instant,
+    pub convert_enabled: bool,
+    /// Files being converted to another format in the background
+    pub convert_job: Option<ConvertJob>,
+    /// A templated save that would overwrite this file, waiting for confirmation
+    pub pending_save: Option<PathBuf>,
+    /// A brush to import once it is confirmed that it replaces the one with its name
+    pub pending_brush: Option<PathBuf>,
+    /// Operator stacks copied from an image, to be pasted onto others
+    pub copied_edits: Option<EditPreset>,
+    /// The names of the saved presets, read when their list is opened
+    pub preset_names: Option<Vec<String>>,
+    /// Renders edit previews, created on first use
+    pub gpu_editor: Option<GpuEditor>,
+    /// Compiled `PixelShader` operators, in stack order
+    pub shaders: Vec<ShaderState>,
+    /// Cumulative result after each operator of the edit stacks
+    pub image_op_thumbnails: Vec<Texture>,
+    pub pixel_op_thumbnails: Vec<Texture>,
     pub pointer_over_ui: bool,
     /// Things that perisist between launches
     pub persistent_settings: PersistentSettings,
@@ -84,7 +185,22 @@ pub struct OculanteState {
     pub fullscreen_offset: Option<(i32, i32)>,
     /// List of images to cycle through. Usually the current dir or dropped files
     pub scrubber: Scrubber,
+    /// The scrubber goes through the favourite images instead of a folder
+    pub favourites_only: bool,
+    /// The current image is being loaded again after it changed on disk
+    pub reloaded: bool,
+    /// The star rating and color label of the current image
+    pub rating: Rating,
     pub checker_texture: Option<Texture>,
+    /// The histogram of the image on screen, `None` until it is computed again
+    pub histogram: Option<Histogram>,
+    /// The scope of the image on screen, `None` until it is rendered again
+    pub scope_texture: Option<Texture>,
+    /// The text the metadata tags are searched for
+    pub metadata_filter: String,
+    /// The map around where the image was taken, once it was asked for
+    pub map_preview: Option<MapPreview>,
+    pub map_texture: Option<Texture>,
     pub redraw: bool,
     pub first_start: bool,
     pub toasts: Toasts,
@@ -115,6 +231,7 @@ impl Default for OculanteState {
                 dimensions: Default::default(),
             },
             compare_list: Default::default(),
+            compare_blend: Default::default(),
             drag_enabled: Default::default(),
             reset_image: Default::default(),
             is_loaded: Default::default(),
@@ -125,11 +242,36 @@ impl Default for OculanteState {
             texture_channel: tx_channel,
             message_channel: mpsc::channel(),
             load_channel: mpsc::channel(),
+            reload_channel: mpsc::channel(),
             extended_info_channel: mpsc::channel(),
             extended_info_loading: Default::default(),
             mouse_delta: Default::default(),
             current_texture: Default::default(),
+            original_texture: Default::default(),
             current_image: Default::default(),
+            is_animation: Default::default(),
+            vector_scale: None,
+            preview_of: None,
+            vector_rendering: false,
+            texture_mips: vec![],
+            mip_level: 0,
+            show_mip_chain: false,
+            icon_sizes: vec![],
+            icon_size: 0,
+            psd_tree: None,
+            fits_stretch: Default::default(),
+            dicom: None,
+            tiles: None,
+            video: None,
+            capture: None,
+            capture_devices: vec![],
+            capture_device_listing: None,
+            download: None,
+            gallery: None,
+            hot_folder: None,
+            recent_filter: None,
+            folder_tree: Default::default(),
+            current_hdr_image: Default::default(),
             current_path: Default::default(),
             settings_enabled: Default::default(),
             image_info: Default::default(),
@@ -137,6 +279,20 @@ impl Default for OculanteState {
             mouse_grab: Default::default(),
             key_grab: Default::default(),
             edit_state: Default::default(),
+            batch_job: None,
+            plugins: vec![],
+            shader_check: Instant::now(),
+            script_job: None,
+            convert_enabled: Default::default(),
+            convert_job: None,
+            pending_save: None,
+            pending_brush: None,
+            copied_edits: None,
+            preset_names: None,
+            gpu_editor: None,
+            shaders: vec![],
+            image_op_thumbnails: vec![],
+            pixel_op_thumbnails: vec![],
             pointer_over_ui: Default::default(),
             persistent_settings: Default::default(),
             always_on_top: Default::default(),
@@ -144,7 +300,17 @@ impl Default for OculanteState {
             window_size: Default::default(),
             fullscreen_offset: Default::default(),
             scrubber: Default::default(),
+            favourites_only: false,
+            reloaded: false,
+            rating: Default::default(),
             checker_texture: Default::default(),
+            histogram: Default::default(),
+            scope_texture: Default::default(),
+            metadata_filter: Default::default(),
+            map_preview: Default::default(),
+            map_texture: Default::default(),
+            hdr_texture: None,
+            tone_mapper: None,
             redraw: Default::default(),
             first_start: true,
             toasts: Toasts::default().with_anchor(egui_notify::Anchor::BottomLeft),
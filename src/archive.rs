@@ -0,0 +1,88 @@
+//! Images inside zip archives, like comic books. An image in an archive has the path of the
+//! archive joined with its name in it, so it can be browsed like a folder. Images are read into
+//! memory, nothing is extracted.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use zip::ZipArchive;
+
+use crate::utils::is_ext_compatible;
+
+/// The largest file read from an archive. The size in the archive header isn't trusted.
+const MAX_FILE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Comic book RAR archives can't be read, but some of them are zip archives named like one
+pub const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz", "cbr"];
+
+pub fn is_archive(path: &Path) -> bool {
+    ARCHIVE_EXTENSIONS.contains(
+        &path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase()
+            .as_str(),
+    )
+}
+
+/// The archive a path is in and the name of the file in it
+pub fn split(path: &Path) -> Option<(&Path, String)> {
+    let archive = path
+        .ancestors()
+        .skip(1)
+        .find(|a| is_archive(a) && a.is_file())?;
+    let name = path
+        .strip_prefix(archive)
+        .ok()?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    Some((archive, name))
+}
+
+fn open(archive: &Path) -> Result<ZipArchive<BufReader<File>>> {
+    let file = BufReader::new(File::open(archive)?);
+    match ZipArchive::new(file) {
+        Ok(zip) => Ok(zip),
+        Err(_) if archive.extension().unwrap_or_default() == "cbr" => {
+            bail!("RAR archives are not supported")
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The images in an archive, in natural order
+pub fn images(archive: &Path) -> Result<Vec<PathBuf>> {
+    let zip = open(archive)?;
+    let mut names = zip
+        .file_names()
+        .filter(|n| !n.ends_with('/') && is_ext_compatible(Path::new(n)))
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>();
+    names.sort_unstable_by(|a, b| lexical_sort::natural_lexical_cmp(a, b));
+    Ok(names.iter().map(|n| archive.join(n)).collect())
+}
+
+/// The data of a file in an archive
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    let (archive, name) = split(path).context("Not in an archive")?;
+    let mut zip = open(archive)?;
+    let file = zip.by_name(&name)?;
+    let mut data = vec![];
+    file.take(MAX_FILE_SIZE + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > MAX_FILE_SIZE {
+        bail!("{name} is too large");
+    }
+    Ok(data)
+}
+
+/// Decode an image in an archive. Its format is guessed from the data.
+pub fn decode(path: &Path) -> Result<RgbaImage> {
+    let data = read(path)?;
+    Ok(image::load_from_memory(&data)?.into_rgba8())
+}
@@ -0,0 +1,79 @@
+//! The area around the image and behind its transparent pixels
+
+use image::{Rgba, RgbaImage};
+use notan::prelude::{Graphics, Texture, TextureFormat};
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+/// Background colors to judge the edges of an image against, or the custom background color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Display, EnumIter)]
+pub enum BackgroundPreset {
+    Dark,
+    #[strum(to_string = "Neutral gray")]
+    Gray,
+    Light,
+    #[default]
+    Custom,
+}
+
+impl BackgroundPreset {
+    /// The color of the preset, or `custom`
+    pub fn color(&self, custom: [u8; 3]) -> [u8; 3] {
+        match self {
+            BackgroundPreset::Dark => [18, 18, 18],
+            BackgroundPreset::Gray => [128, 128, 128],
+            BackgroundPreset::Light => [238, 238, 238],
+            BackgroundPreset::Custom => custom,
+        }
+    }
+
+    /// The preset after this one, to switch through them with a key
+    pub fn next(&self) -> Self {
+        match self {
+            BackgroundPreset::Dark => BackgroundPreset::Gray,
+            BackgroundPreset::Gray => BackgroundPreset::Light,
+            BackgroundPreset::Light => BackgroundPreset::Custom,
+            BackgroundPreset::Custom => BackgroundPreset::Dark,
+        }
+    }
+}
+
+/// The checkerboard behind transparent pixels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Checker {
+    /// The size of a square on screen
+    pub size: u32,
+    pub colors: [[u8; 3]; 2],
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self {
+            size: 8,
+            colors: [[153, 153, 153], [204, 204, 204]],
+        }
+    }
+}
+
+impl Checker {
+    /// Two squares in each direction, which are repeated
+    pub fn image(&self) -> RgbaImage {
+        let size = self.size.max(1);
+        RgbaImage::from_fn(size * 2, size * 2, |x, y| {
+            let [r, g, b] = self.colors[((x / size + y / size) % 2) as usize];
+            Rgba([r, g, b, 255])
+        })
+    }
+
+    pub fn texture(&self, gfx: &mut Graphics) -> Option<Texture> {
+        let img = self.image();
+        // No mipmaps for the checker pattern!
+        gfx.create_texture()
+            .from_bytes(&img, img.width(), img.height())
+            .with_mipmaps(false)
+            .with_format(TextureFormat::SRgba8)
+            .build()
+            .ok()
+    }
+}
@@ -1,11 +1,19 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    sync::mpsc::{channel, Receiver, Sender},
+    sync::Arc,
     time::Instant,
 };
 
 use image::RgbaImage;
-use log::debug;
+use log::{debug, error};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::image_loader::open_image;
+use crate::utils::{Frame, FrameSource};
+use crate::video::VIDEO_EXTENSIONS;
 
 #[derive(Debug)]
 pub struct Cache {
@@ -56,3 +64,105 @@ impl Cache {
         }
     }
 }
+
+/// How many images are prefetched at once
+const PREFETCH_THREADS: usize = 2;
+
+/// The images next to the current one, decoded in the background so they show at once when
+/// they are opened
+#[derive(Debug)]
+pub struct Prefetch {
+    frames: HashMap<PathBuf, Frame>,
+    /// Paths that should be kept when they are decoded
+    wanted: Vec<PathBuf>,
+    /// Decodes the images, created with the first request
+    pool: Option<ThreadPool>,
+    /// Counts the requests, so images queued for an earlier one are skipped
+    generation: Arc<AtomicUsize>,
+    sender: Sender<(PathBuf, Option<Frame>)>,
+    receiver: Receiver<(PathBuf, Option<Frame>)>,
+}
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            frames: Default::default(),
+            wanted: Default::default(),
+            pool: None,
+            generation: Default::default(),
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl Prefetch {
+    fn receive(&mut self) {
+        for (path, frame) in self.receiver.try_iter() {
+            if let Some(frame) = frame.filter(|_| self.wanted.contains(&path)) {
+                debug!("Prefetched {}", path.display());
+                self.frames.insert(path, frame);
+            }
+        }
+    }
+
+    /// Decode `paths` in the background and forget the images prefetched before. Images larger
+    /// than `max_texture_size`, animations, videos and tiled images are left to the player.
+    pub fn request(&mut self, paths: &[PathBuf], max_texture_size: u32) {
+        self.receive();
+        self.wanted = paths.to_vec();
+        self.frames.retain(|p, _| paths.contains(p));
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.pool.is_none() {
+            self.pool = ThreadPoolBuilder::new()
+                .num_threads(PREFETCH_THREADS)
+                .build()
+                .map_err(|e| error!("Could not start prefetching: {e}"))
+                .ok();
+        }
+        let Some(pool) = &self.pool else {
+            return;
+        };
+        for path in paths {
+            // videos are decoded by starting ffmpeg, which is too slow to do in advance
+            if self.frames.contains_key(path) || is_video(path) {
+                continue;
+            }
+            let sender = self.sender.clone();
+            let current = self.generation.clone();
+            let path = path.clone();
+            pool.spawn(move || {
+                // another image was opened while this one waited
+                if current.load(Ordering::SeqCst) != generation {
+                    return;
+                }
+                let frame = open_image(&path).ok().and_then(|frames| {
+                    frames
+                        .iter()
+                        .find(|f| !matches!(f.source, FrameSource::Preview(_)))
+                        .filter(|f| {
+                            f.source == FrameSource::Still
+                                && f.tiled.is_none()
+                                && f.video.is_none()
+                                && f.buffer.width().max(f.buffer.height()) <= max_texture_size
+                        })
+                });
+                _ = sender.send((path, frame));
+            });
+        }
+    }
+
+    /// The image at `path`, if it was prefetched
+    pub fn take(&mut self, path: &Path) -> Option<Frame> {
+        self.receive();
+        self.frames.remove(path)
+    }
+}
+
+/// Whether `path` has the extension of a video
+fn is_video(path: &Path) -> bool {
+    path.extension()
+        .map(|e| VIDEO_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,205 @@
+//! Live frames from a camera or capture card, read with `ffmpeg`, which needs to be installed.
+//! The frames are shown as they come in, with the edits applied to each one.
+
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, RgbImage};
+use log::{debug, error};
+
+use crate::appstate::Message;
+use crate::utils::{Frame, FrameSource};
+
+/// The ffmpeg input format and device name of a capture device
+fn input(device: &str) -> [String; 4] {
+    let (format, name) = if cfg!(target_os = "macos") {
+        ("avfoundation", device.to_string())
+    } else if cfg!(target_os = "windows") {
+        ("dshow", format!("video={device}"))
+    } else {
+        ("v4l2", device.to_string())
+    };
+    ["-f".into(), format.into(), "-i".into(), name]
+}
+
+/// The capture devices that can be opened, by their ffmpeg name
+pub fn devices() -> Vec<String> {
+    if !cfg!(any(target_os = "macos", target_os = "windows")) {
+        let mut devices = std::fs::read_dir("/dev")
+            .map(|dir| {
+                dir.flatten()
+                    .map(|e| e.path().to_string_lossy().to_string())
+                    .filter(|p| p.starts_with("/dev/video"))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        devices.sort();
+        return devices;
+    }
+
+    // ffmpeg lists them as part of an error
+    let format = if cfg!(target_os = "macos") {
+        "avfoundation"
+    } else {
+        "dshow"
+    };
+    let Ok(output) = Command::new("ffmpeg")
+        .args(&[
+            "-hide_banner",
+            "-list_devices",
+            "true",
+            "-f",
+            format,
+            "-i",
+            "dummy",
+        ])
+        .output()
+    else {
+        error!("Could not run ffmpeg to list capture devices");
+        return vec![];
+    };
+    let listing = String::from_utf8_lossy(&output.stderr);
+    if cfg!(target_os = "macos") {
+        // "[AVFoundation indev @ 0x..] [0] FaceTime HD Camera", for video devices before audio ones
+        listing
+            .lines()
+            .skip_while(|l| !l.contains("video devices"))
+            .take_while(|l| !l.contains("audio devices"))
+            .filter_map(|l| l.rsplit_once("] [")?.1.split_once(']'))
+            .map(|(index, _)| index.to_string())
+            .collect()
+    } else {
+        // [dshow @ 0x..] "Integrated Camera" (video)
+        listing
+            .lines()
+            .filter(|l| l.ends_with("(video)"))
+            .filter_map(|l| l.split('"').nth(1))
+            .map(|name| name.to_string())
+            .collect()
+    }
+}
+
+/// The next whitespace separated value of a PPM header
+pub fn header_value(reader: &mut impl BufRead) -> Result<u32> {
+    let mut value = String::new();
+    for byte in reader.bytes() {
+        let c = byte? as char;
+        if c.is_ascii_whitespace() {
+            if value.is_empty() {
+                continue;
+            }
+            break;
+        }
+        value.push(c);
+    }
+    Ok(value.parse()?)
+}
+
+/// Read the next frame of a stream of binary PPM images
+pub fn read_ppm(reader: &mut impl BufRead) -> Result<RgbImage> {
+    let mut magic = [0; 2];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"P6" {
+        bail!("Not a PPM frame");
+    }
+    let width = header_value(reader)?;
+    let height = header_value(reader)?;
+    if header_value(reader)? != 255 {
+        bail!("Only 8 bit frames are supported");
+    }
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    reader.read_exact(&mut pixels)?;
+    RgbImage::from_raw(width, height, pixels).context("Invalid frame size")
+}
+
+/// A running capture, which stops when dropped
+#[derive(Debug)]
+pub struct Capture {
+    pub device: String,
+    stop: Arc<AtomicBool>,
+    /// Frames sent that are not shown yet. Frames are dropped while the viewer is behind.
+    pending: Arc<AtomicUsize>,
+}
+
+impl Capture {
+    pub fn start(
+        device: &str,
+        texture_sender: Sender<Frame>,
+        message_sender: Sender<Message>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let pending = Arc::new(AtomicUsize::new(0));
+        let capture = Self {
+            device: device.to_string(),
+            stop: stop.clone(),
+            pending: pending.clone(),
+        };
+        let input = input(device);
+        std::thread::spawn(move || {
+            let child = Command::new("ffmpeg")
+                .args(&["-hide_banner", "-loglevel", "error"])
+                .args(&input)
+                .args(&["-f", "image2pipe", "-c:v", "ppm", "-"])
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut child = match child {
+                Ok(child) => child,
+                Err(e) => {
+                    _ = message_sender.send(Message::err(&format!(
+                        "Could not run ffmpeg, is it installed? {e}"
+                    )));
+                    return;
+                }
+            };
+            let Some(stdout) = child.stdout.take() else {
+                return;
+            };
+            let mut reader = BufReader::new(stdout);
+            while !stop.load(Ordering::SeqCst) {
+                let frame = match read_ppm(&mut reader) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        _ = message_sender.send(Message::err(&format!(
+                            "The capture device stopped sending frames: {e}"
+                        )));
+                        break;
+                    }
+                };
+                if pending.load(Ordering::SeqCst) > 1 {
+                    continue;
+                }
+                pending.fetch_add(1, Ordering::SeqCst);
+                let buffer = DynamicImage::ImageRgb8(frame).into_rgba8();
+                if texture_sender
+                    .send(Frame::new(buffer, 0, FrameSource::Capture))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            debug!("Stopping capture");
+            _ = child.kill();
+            _ = child.wait();
+        });
+        capture
+    }
+
+    /// A frame has been shown
+    pub fn shown(&self) {
+        _ = self
+            .pending
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |p| p.checked_sub(1));
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
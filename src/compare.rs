@@ -0,0 +1,117 @@
+//! Blending the image shown with another image of the compare list, so the differences between
+//! two versions of an image stand out.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+use image::RgbaImage;
+use log::error;
+use notan::prelude::{Graphics, Texture};
+use strum_macros::{Display, EnumIter};
+
+use crate::image_loader::open_image;
+use crate::utils::ImageExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Display, EnumIter)]
+pub enum CompareMode {
+    /// Switch between the images of the compare list
+    #[default]
+    #[strum(to_string = "A/B toggle")]
+    Toggle,
+    /// The other image half transparent on top
+    #[strum(to_string = "Onion skin")]
+    OnionSkin,
+    /// The difference of every pixel
+    Difference,
+}
+
+/// The image the current one is blended with, and the result drawn on top of it
+pub struct Blend {
+    pub mode: CompareMode,
+    pub reference: Option<PathBuf>,
+    /// Differences are multiplied by this, so small ones are visible
+    pub amplify: f32,
+    loading: Option<Receiver<RgbaImage>>,
+    image: Option<RgbaImage>,
+    pub texture: Option<Texture>,
+    /// The texture is made again before it is drawn next
+    stale: bool,
+}
+
+impl Default for Blend {
+    fn default() -> Self {
+        Self {
+            mode: Default::default(),
+            reference: None,
+            amplify: 1.,
+            loading: None,
+            image: None,
+            texture: None,
+            stale: false,
+        }
+    }
+}
+
+impl Blend {
+    /// Blend with the image at `path`, which is loaded in the background
+    pub fn set_reference(&mut self, path: &Path) {
+        let (sender, receiver) = channel();
+        let path = path.to_path_buf();
+        self.reference = Some(path.clone());
+        self.image = None;
+        self.texture = None;
+        self.loading = Some(receiver);
+        std::thread::spawn(
+            move || match open_image(&path).and_then(|r| Ok(r.recv()?)) {
+                Ok(frame) => {
+                    _ = sender.send(frame.buffer);
+                }
+                Err(e) => error!("Could not load {} to compare: {e}", path.display()),
+            },
+        );
+    }
+
+    /// The current image or the settings changed
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Make the texture drawn on top of `current` if anything changed
+    pub fn update(&mut self, gfx: &mut Graphics, current: Option<&RgbaImage>, linear: bool) {
+        if let Some(image) = self.loading.as_ref().and_then(|l| l.try_recv().ok()) {
+            self.image = Some(image);
+            self.loading = None;
+            self.stale = true;
+        }
+        if self.mode == CompareMode::Toggle {
+            self.texture = None;
+            return;
+        }
+        if !self.stale {
+            return;
+        }
+        let (Some(current), Some(image)) = (current, &self.image) else {
+            return;
+        };
+        self.stale = false;
+        self.texture = match self.mode {
+            CompareMode::OnionSkin => image.to_texture(gfx, linear),
+            _ => difference(current, image, self.amplify).to_texture(gfx, linear),
+        };
+    }
+}
+
+/// The absolute difference of every channel of `a` and `b`, multiplied by `amplify`. Where `b`
+/// is smaller than `a`, it counts as black.
+pub fn difference(a: &RgbaImage, b: &RgbaImage, amplify: f32) -> RgbaImage {
+    RgbaImage::from_fn(a.width(), a.height(), |x, y| {
+        let pa = a.get_pixel(x, y);
+        let pb = b.get_pixel_checked(x, y).copied().unwrap_or([0; 4].into());
+        let mut diff = [255; 4];
+        for c in 0..3 {
+            let d = (pa[c] as f32 - pb[c] as f32).abs() * amplify;
+            diff[c] = d.min(255.) as u8;
+        }
+        diff.into()
+    })
+}
@@ -0,0 +1,205 @@
+//! Converting many images to another format at once, from the UI or the command line
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{error, info, warn};
+use rayon::prelude::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::appstate::Message;
+use crate::export::ExportSettings;
+use crate::image_editing::ImageOperation;
+use crate::image_loader::open_image;
+use crate::scrubber::get_image_filenames_for_directory;
+use crate::utils::exif_orientation;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileStatus {
+    Waiting,
+    /// Converted to this file
+    Done(PathBuf),
+    Failed(String),
+}
+
+/// Convert a single file to `dest`, in the default export format
+pub fn convert_file(file: &Path, dest: &Path, export: &ExportSettings) -> Result<()> {
+    let mut img = open_image(file)?.recv()?.buffer;
+    let orientation = exif_orientation(file).unwrap_or(1);
+    // the EXIF data is not copied, so the orientation needs to be applied to the pixels
+    ImageOperation::AutoOrient(orientation).process_image(&mut img)?;
+    export.save(&img, dest, Some(file))
+}
+
+/// The file in `out_dir` each of `files` is converted to, and how many of them were renamed.
+/// Files with the same name get a number appended, like `unique_path` does.
+pub fn output_paths(
+    files: &[PathBuf],
+    out_dir: &Path,
+    extension: &str,
+) -> (Vec<Option<PathBuf>>, usize) {
+    // file systems may ignore case, so names differing in case also collide
+    let mut taken = HashSet::new();
+    let mut renamed = 0;
+    let paths = files
+        .iter()
+        .map(|file| {
+            let stem = file.file_stem()?.to_string_lossy().to_string();
+            let mut name = format!("{stem}.{extension}");
+            let mut i = 0;
+            while !taken.insert(name.to_lowercase()) {
+                i += 1;
+                name = format!("{stem}_{i}.{extension}");
+            }
+            if i > 0 {
+                renamed += 1;
+            }
+            Some(out_dir.join(name))
+        })
+        .collect();
+    (paths, renamed)
+}
+
+/// Convert `files` on all cores. `on_file` is called with the index and result of each file.
+/// Returns how many files were renamed because their names collided.
+pub fn convert_files(
+    files: &[PathBuf],
+    out_dir: &Path,
+    export: &ExportSettings,
+    cancel: &AtomicBool,
+    on_file: impl Fn(usize, Result<PathBuf>) + Sync,
+) -> Result<usize> {
+    std::fs::create_dir_all(out_dir).context("Could not create output dir")?;
+    // names are assigned up front, so files converted at the same time can't overwrite each other
+    let (dests, renamed) = output_paths(files, out_dir, export.format.extension());
+    files
+        .par_iter()
+        .zip(&dests)
+        .enumerate()
+        .for_each(|(i, (file, dest))| {
+            if !cancel.load(Ordering::Relaxed) {
+                on_file(
+                    i,
+                    match dest {
+                        Some(dest) => convert_file(file, dest, export).map(|_| dest.clone()),
+                        None => Err(anyhow!("File has no name")),
+                    },
+                );
+            }
+        });
+    Ok(renamed)
+}
+
+/// Converts a list of files in the background
+pub struct ConvertJob {
+    pub files: Vec<PathBuf>,
+    pub out_dir: PathBuf,
+    pub status: Arc<Mutex<Vec<FileStatus>>>,
+    pub cancel: Arc<AtomicBool>,
+    pub finished: Arc<AtomicBool>,
+}
+
+impl ConvertJob {
+    pub fn start(
+        files: Vec<PathBuf>,
+        out_dir: PathBuf,
+        export: ExportSettings,
+        message_sender: Sender<Message>,
+    ) -> Self {
+        let job = Self {
+            status: Arc::new(Mutex::new(vec![FileStatus::Waiting; files.len()])),
+            files,
+            out_dir,
+            cancel: Default::default(),
+            finished: Default::default(),
+        };
+        let files = job.files.clone();
+        let out_dir = job.out_dir.clone();
+        let status = job.status.clone();
+        let cancel = job.cancel.clone();
+        let finished = job.finished.clone();
+
+        std::thread::spawn(move || {
+            let errors = AtomicUsize::new(0);
+            let result = convert_files(&files, &out_dir, &export, &cancel, |i, result| {
+                let new_status = match result {
+                    Ok(dest) => FileStatus::Done(dest),
+                    Err(e) => {
+                        error!("Convert: {}: {e}", files[i].display());
+                        errors.fetch_add(1, Ordering::Relaxed);
+                        FileStatus::Failed(e.to_string())
+                    }
+                };
+                if let Ok(mut status) = status.lock() {
+                    status[i] = new_status;
+                }
+            });
+            let errors = errors.load(Ordering::Relaxed);
+            _ = message_sender.send(match result {
+                Err(e) => Message::err(&e.to_string()),
+                Ok(_) if errors > 0 => Message::warn(&format!(
+                    "Converted images to {}, {errors} failed",
+                    out_dir.display()
+                )),
+                Ok(renamed) if renamed > 0 => Message::warn(&format!(
+                    "Converted images to {}, {renamed} were renamed as their names collided",
+                    out_dir.display()
+                )),
+                Ok(_) => Message::info(&format!("Converted images to {}", out_dir.display())),
+            });
+            finished.store(true, Ordering::Relaxed);
+        });
+        job
+    }
+
+    pub fn progress(&self) -> f32 {
+        let done = self
+            .status
+            .lock()
+            .map(|s| s.iter().filter(|s| **s != FileStatus::Waiting).count())
+            .unwrap_or_default();
+        done as f32 / self.files.len().max(1) as f32
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+/// Convert `input`, a folder or a single image, into a 'converted' folder next to it
+pub fn run_cli(input: &Path, export: &ExportSettings) -> Result<()> {
+    let (files, parent) = if input.is_dir() {
+        (get_image_filenames_for_directory(input)?, input)
+    } else {
+        (
+            vec![input.to_path_buf()],
+            input.parent().context("Can't get parent")?,
+        )
+    };
+    let out_dir = parent.join("converted");
+    let errors = AtomicUsize::new(0);
+    let renamed = convert_files(
+        &files,
+        &out_dir,
+        export,
+        &AtomicBool::new(false),
+        |i, result| match result {
+            Ok(dest) => info!("{} -> {}", files[i].display(), dest.display()),
+            Err(e) => {
+                error!("{}: {e}", files[i].display());
+                errors.fetch_add(1, Ordering::Relaxed);
+            }
+        },
+    )?;
+    if renamed > 0 {
+        warn!("{renamed} images were renamed as their names collided");
+    }
+    let errors = errors.load(Ordering::Relaxed);
+    if errors > 0 {
+        bail!("{errors} of {} images could not be converted", files.len());
+    }
+    Ok(())
+}
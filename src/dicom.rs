@@ -0,0 +1,296 @@
+//! DICOM images, as used in medical imaging. Only uncompressed pixel data is read. Monochrome
+//! images are shown through a window, which maps a range of values to black through white.
+
+use anyhow::{bail, Context, Result};
+use image::{Rgba, RgbaImage};
+
+pub const DICOM_EXTENSIONS: &[&str] = &["dcm", "dicom"];
+
+/// Marks elements that end with a delimiter instead of having a length
+const UNDEFINED: u32 = 0xFFFF_FFFF;
+const SEQUENCE_END: (u16, u16) = (0xFFFE, 0xE0DD);
+const PIXEL_DATA: (u16, u16) = (0x7FE0, 0x0010);
+
+/// How windowed values are colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lut {
+    Gray,
+    Inverted,
+    Hot,
+    Bone,
+}
+
+impl Lut {
+    pub const ALL: [Self; 4] = [Self::Gray, Self::Inverted, Self::Hot, Self::Bone];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gray => "Grayscale",
+            Self::Inverted => "Inverted",
+            Self::Hot => "Hot iron",
+            Self::Bone => "Bone",
+        }
+    }
+
+    /// The color of `v`, from 0-1
+    fn color(&self, v: f32) -> [f32; 3] {
+        let hot = |v: f32| {
+            [
+                (v * 3.).clamp(0., 1.),
+                (v * 3. - 1.).clamp(0., 1.),
+                (v * 3. - 2.).clamp(0., 1.),
+            ]
+        };
+        match self {
+            Self::Gray => [v; 3],
+            Self::Inverted => [1. - v; 3],
+            Self::Hot => hot(v),
+            // gray with a blue tint in the shadows
+            Self::Bone => {
+                let h = hot(v);
+                [
+                    (7. * v + h[2]) / 8.,
+                    (7. * v + h[1]) / 8.,
+                    (7. * v + h[0]) / 8.,
+                ]
+            }
+        }
+    }
+}
+
+/// Which values are shown: `center` - `width` / 2 is black, `center` + `width` / 2 is white
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub center: f32,
+    pub width: f32,
+    pub lut: Lut,
+}
+
+/// The first frame of a DICOM file
+#[derive(Debug, Clone)]
+pub struct DicomImage {
+    pub width: u32,
+    pub height: u32,
+    /// 1 for monochrome, 3 for RGB
+    pub samples: usize,
+    /// Rescaled values, like Hounsfield units for CT, interleaved for RGB
+    pub values: Vec<f32>,
+    /// Low values are white
+    pub monochrome1: bool,
+    /// The window stored in the file, as center and width
+    pub window: Option<(f32, f32)>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    explicit_vr: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// The next element as its tag and value. The content of elements without a length is
+    /// skipped and their value is empty.
+    fn element(&mut self) -> Option<((u16, u16), &'a [u8])> {
+        let tag = (self.u16()?, self.u16()?);
+        // items and delimiters have no VR
+        let len = if tag.0 == 0xFFFE {
+            self.u32()?
+        } else if self.explicit_vr || tag.0 == 0x0002 {
+            match self.take(2)? {
+                b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN"
+                | b"UR" | b"UT" | b"UV" => {
+                    self.take(2)?;
+                    self.u32()?
+                }
+                _ => self.u16()? as u32,
+            }
+        } else {
+            self.u32()?
+        };
+        if len != UNDEFINED {
+            return Some((tag, self.take(len as usize)?));
+        }
+        // an item of a sequence, its elements follow
+        if tag.0 == 0xFFFE {
+            return Some((tag, &[]));
+        }
+        loop {
+            if self.element()?.0 == SEQUENCE_END {
+                return Some((tag, &[]));
+            }
+        }
+    }
+}
+
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string()
+}
+
+/// The first of several values of a decimal string
+fn decimal(value: &[u8]) -> Option<f32> {
+    text(value).split('\\').next()?.trim().parse().ok()
+}
+
+impl DicomImage {
+    pub fn read(data: &[u8]) -> Result<Self> {
+        // the header starts after a preamble, which some old files don't have
+        let start = if data.get(128..132) == Some(b"DICM".as_slice()) {
+            132
+        } else {
+            0
+        };
+        let mut reader = Reader {
+            data,
+            pos: start,
+            explicit_vr: start != 0,
+        };
+
+        let mut elements = std::collections::HashMap::new();
+        let mut pixels = None;
+        while let Some((tag, value)) = reader.element() {
+            if tag == (0x0002, 0x0010) {
+                let syntax = text(value);
+                match syntax.as_str() {
+                    "1.2.840.10008.1.2" => reader.explicit_vr = false,
+                    "1.2.840.10008.1.2.1" => reader.explicit_vr = true,
+                    _ => bail!("The transfer syntax {syntax} is not supported, only uncompressed little endian images can be opened"),
+                }
+            }
+            if tag == PIXEL_DATA {
+                pixels = Some(value);
+                break;
+            }
+            // only the image description is needed
+            if tag.0 == 0x0028 {
+                elements.insert(tag, value);
+            }
+        }
+        let pixels = pixels.context("The file has no pixel data")?;
+        let us = |element: u16| {
+            elements
+                .get(&(0x0028, element))
+                .filter(|v| v.len() >= 2)
+                .map(|v| u16::from_le_bytes([v[0], v[1]]))
+        };
+        let ds = |element: u16| elements.get(&(0x0028, element)).and_then(|v| decimal(v));
+
+        let height = us(0x0010).context("The image has no rows")? as u32;
+        let width = us(0x0011).context("The image has no columns")? as u32;
+        let samples = us(0x0002).unwrap_or(1) as usize;
+        let planar = us(0x0006).unwrap_or(0) == 1;
+        let bits = us(0x0100).unwrap_or(16);
+        let signed = us(0x0103).unwrap_or(0) == 1;
+        let slope = ds(0x1053).unwrap_or(1.);
+        let intercept = ds(0x1052).unwrap_or(0.);
+        let photometric = elements
+            .get(&(0x0028, 0x0004))
+            .map(|v| text(v))
+            .unwrap_or_default();
+        if samples != 1 && samples != 3 {
+            bail!("Images with {samples} samples per pixel are not supported");
+        }
+
+        let len = width as usize * height as usize * samples;
+        let raw: Vec<f32> = match (bits, signed) {
+            (8, false) => pixels.iter().map(|v| *v as f32).collect(),
+            (8, true) => pixels.iter().map(|v| *v as i8 as f32).collect(),
+            (16, false) => pixels
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]) as f32)
+                .collect(),
+            (16, true) => pixels
+                .chunks_exact(2)
+                .map(|c| i16::from_le_bytes([c[0], c[1]]) as f32)
+                .collect(),
+            (32, false) => pixels
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32)
+                .collect(),
+            (32, true) => pixels
+                .chunks_exact(4)
+                .map(|c| i32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f32)
+                .collect(),
+            _ => bail!("{bits} bits per sample are not supported"),
+        };
+        let Some(raw) = raw.get(..len) else {
+            bail!("The pixel data is cut off");
+        };
+        let mut values = raw
+            .iter()
+            .map(|v| v * slope + intercept)
+            .collect::<Vec<_>>();
+        // store RGB interleaved
+        if samples == 3 && planar {
+            let plane = len / 3;
+            values = (0..len).map(|i| values[(i % 3) * plane + i / 3]).collect();
+        }
+
+        Ok(Self {
+            width,
+            height,
+            samples,
+            values,
+            monochrome1: photometric == "MONOCHROME1",
+            window: ds(0x1050).zip(ds(0x1051)),
+        })
+    }
+
+    /// The smallest and largest value
+    pub fn range(&self) -> (f32, f32) {
+        self.values
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), v| {
+                (min.min(*v), max.max(*v))
+            })
+    }
+
+    /// The window of the file, or one over the whole range
+    pub fn default_window(&self) -> Window {
+        let (center, width) = self.window.unwrap_or_else(|| {
+            let (min, max) = self.range();
+            ((min + max) / 2., max - min)
+        });
+        Window {
+            center,
+            width,
+            lut: if self.monochrome1 {
+                Lut::Inverted
+            } else {
+                Lut::Gray
+            },
+        }
+    }
+
+    pub fn render(&self, window: &Window) -> RgbaImage {
+        let low = window.center - window.width / 2.;
+        let width = window.width.max(1.);
+        let unorm = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let i = (y * self.width + x) as usize * self.samples;
+            if self.samples == 3 {
+                // color images are stored for display already
+                let v = &self.values[i..i + 3];
+                return Rgba([v[0] as u8, v[1] as u8, v[2] as u8, 255]);
+            }
+            let c = window.lut.color((self.values[i] - low) / width);
+            Rgba([unorm(c[0]), unorm(c[1]), unorm(c[2]), 255])
+        })
+    }
+}
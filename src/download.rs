@@ -0,0 +1,173 @@
+//! Images opened from http(s) links. They are downloaded in the background into a folder in the
+//! cache dir named after the link, so opening the same link again uses the file already there.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::appstate::Message;
+use crate::image_loader::guess_extension;
+use crate::utils::supported_extensions;
+
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// A download in progress
+#[derive(Debug)]
+pub struct Download {
+    pub url: String,
+    received: Arc<AtomicU64>,
+    /// The size of the file, 0 if the server didn't tell
+    total: Arc<AtomicU64>,
+    finished: Arc<AtomicBool>,
+}
+
+/// The largest file that is downloaded
+const MAX_DOWNLOAD_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Numbers the folders downloads are written to before they are complete
+static STAGING: AtomicU64 = AtomicU64::new(0);
+
+/// The folder a link is downloaded to
+pub fn folder(url: &str) -> Result<PathBuf> {
+    let hash = Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    Ok(dirs::cache_dir()
+        .context("Can't get cache dir")?
+        .join("oculante")
+        .join("downloads")
+        .join(hash))
+}
+
+/// The file name of a link, if it has a supported extension
+pub fn file_name(url: &str) -> Option<&str> {
+    let path = url.split(&['?', '#'][..]).next()?;
+    let name = path.rsplit('/').next()?;
+    let extension = name.rsplit_once('.')?.1.to_lowercase();
+    supported_extensions()
+        .contains(&extension.as_str())
+        .then_some(name)
+}
+
+impl Download {
+    /// Download `url` and send the file to `load_sender` once it is complete
+    pub fn start(url: &str, load_sender: Sender<PathBuf>, message_sender: Sender<Message>) -> Self {
+        let download = Self {
+            url: url.to_string(),
+            received: Default::default(),
+            total: Default::default(),
+            finished: Default::default(),
+        };
+        let url = url.to_string();
+        let received = download.received.clone();
+        let total = download.total.clone();
+        let finished = download.finished.clone();
+        std::thread::spawn(move || {
+            match fetch(&url, &received, &total) {
+                Ok(path) => {
+                    _ = load_sender.send(path);
+                }
+                Err(e) => {
+                    _ = message_sender
+                        .send(Message::err(&format!("Could not download {url}: {e}")));
+                }
+            }
+            finished.store(true, Ordering::Relaxed);
+        });
+        download
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// From 0-1, if the size of the file is known
+    pub fn progress(&self) -> Option<f32> {
+        let total = self.total.load(Ordering::Relaxed);
+        (total > 0).then(|| self.received() as f32 / total as f32)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+fn fetch(url: &str, received: &AtomicU64, total: &AtomicU64) -> Result<PathBuf> {
+    let folder = folder(url)?;
+    if let Some(cached) = cached(&folder) {
+        debug!("{url} was downloaded before");
+        return Ok(cached);
+    }
+
+    let response = ureq::get(url).call()?;
+    if let Some(length) = response
+        .header("Content-Length")
+        .and_then(|l| l.parse().ok())
+    {
+        if length > MAX_DOWNLOAD_SIZE {
+            bail!("The file is too large");
+        }
+        total.store(length, Ordering::Relaxed);
+    }
+    let data = read_limited(response.into_reader(), MAX_DOWNLOAD_SIZE, received)?;
+
+    let name = match file_name(url) {
+        Some(name) => name.to_string(),
+        None => format!(
+            "download.{}",
+            guess_extension(&data).context("The download is not an image")?
+        ),
+    };
+    // written elsewhere first, so an interrupted download is not taken for a finished one. Every
+    // download has its own staging folder, as the same link may be downloading twice.
+    let staging = folder.with_extension(format!(
+        "{}-{}.part",
+        std::process::id(),
+        STAGING.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&staging)?;
+    std::fs::write(staging.join(&name), &data)?;
+    if std::fs::rename(&staging, &folder).is_err() {
+        _ = std::fs::remove_dir_all(&staging);
+        // another download of the link finished first
+        return cached(&folder).context("Could not store the download");
+    }
+    Ok(folder.join(name))
+}
+
+/// The file in the folder of a finished download
+fn cached(folder: &Path) -> Option<PathBuf> {
+    std::fs::read_dir(folder)
+        .ok()
+        .and_then(|mut entries| entries.next()?.ok())
+        .map(|entry| entry.path())
+}
+
+/// Read all of `reader`, counting the bytes in `received`. Fails once more than `limit` bytes
+/// arrive, whatever size the server announced.
+pub fn read_limited(reader: impl Read, limit: u64, received: &AtomicU64) -> Result<Vec<u8>> {
+    let mut reader = reader.take(limit + 1);
+    let mut data = vec![];
+    let mut buffer = [0; 65536];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..read]);
+        received.fetch_add(read as u64, Ordering::Relaxed);
+    }
+    if data.len() as u64 > limit {
+        bail!("The file is too large");
+    }
+    Ok(data)
+}
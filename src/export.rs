@@ -0,0 +1,948 @@
+//! Writing images with the encoder settings chosen for each format
+
+use std::borrow::Cow;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use image::codecs::avif::AvifEncoder;
+#[cfg(not(feature = "turbo"))]
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use img_parts::{DynImage, ImageICC};
+use notan::egui::{self, DragValue, Response, Sense, Ui, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::icc::ColorProfile;
+use crate::utils::set_exif_orientation;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Tiff,
+    Bmp,
+    Tga,
+    Avif,
+    Jxl,
+    Gif,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 9] = [
+        Self::Png,
+        Self::Jpeg,
+        Self::Webp,
+        Self::Avif,
+        Self::Jxl,
+        Self::Tiff,
+        Self::Gif,
+        Self::Bmp,
+        Self::Tga,
+    ];
+
+    /// Formats which can store several frames
+    pub const ANIMATED: [Self; 3] = [Self::Gif, Self::Webp, Self::Png];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Tiff => "tif",
+            Self::Bmp => "bmp",
+            Self::Tga => "tga",
+            Self::Avif => "avif",
+            Self::Jxl => "jxl",
+            Self::Gif => "gif",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        Self::from_extension(&path.extension()?.to_string_lossy())
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        Some(match ext.to_lowercase().as_str() {
+            "png" => Self::Png,
+            "jpg" | "jpeg" => Self::Jpeg,
+            "webp" => Self::Webp,
+            "tif" | "tiff" => Self::Tiff,
+            "bmp" => Self::Bmp,
+            "tga" => Self::Tga,
+            "avif" => Self::Avif,
+            "jxl" => Self::Jxl,
+            "gif" => Self::Gif,
+            _ => return None,
+        })
+    }
+
+    /// Extensions of all formats, for file dialogs
+    pub fn extensions() -> Vec<&'static str> {
+        Self::ALL.iter().map(|f| f.extension()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChromaSubsampling {
+    /// Full color resolution
+    S444,
+    /// Half the horizontal color resolution
+    S422,
+    /// Half the horizontal and vertical color resolution
+    S420,
+}
+
+impl ChromaSubsampling {
+    pub const ALL: [Self; 3] = [Self::S444, Self::S422, Self::S420];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::S444 => "4:4:4",
+            Self::S422 => "4:2:2",
+            Self::S420 => "4:2:0",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    Best,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+    PackBits,
+}
+
+/// A size the image is scaled down to when saving
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SizePreset {
+    pub name: String,
+    /// The image is scaled to fit into this box. 0 means no limit.
+    pub max_width: u32,
+    pub max_height: u32,
+}
+
+impl SizePreset {
+    pub fn new(name: &str, max_width: u32, max_height: u32) -> Self {
+        Self {
+            name: name.into(),
+            max_width,
+            max_height,
+        }
+    }
+
+    /// The size an image of the given size is scaled to. Images are never scaled up.
+    pub fn fit(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let limit = |max: u32, size: u32| {
+            if max == 0 {
+                1.
+            } else {
+                max as f64 / size.max(1) as f64
+            }
+        };
+        let scale = limit(self.max_width, width)
+            .min(limit(self.max_height, height))
+            .min(1.);
+        (
+            ((width as f64 * scale).round() as u32).max(1),
+            ((height as f64 * scale).round() as u32).max(1),
+        )
+    }
+
+    /// Scale down `img`, if it is larger than the preset
+    pub fn resize<'a>(&self, img: &'a RgbaImage) -> Cow<'a, RgbaImage> {
+        let (width, height) = self.fit(img.dimensions());
+        if (width, height) == img.dimensions() {
+            return Cow::Borrowed(img);
+        }
+        Cow::Owned(image::imageops::resize(
+            img,
+            width,
+            height,
+            image::imageops::FilterType::Lanczos3,
+        ))
+    }
+}
+
+/// Encoder settings for every format. The format itself is taken from the file extension.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    /// Used if there is no extension to go by, for example when applying edits to a folder
+    pub format: ExportFormat,
+    /// 1-100
+    pub jpeg_quality: u8,
+    pub jpeg_subsampling: ChromaSubsampling,
+    pub png_compression: PngCompression,
+    pub webp_lossless: bool,
+    /// 0-100, for lossy WebP
+    pub webp_quality: f32,
+    pub tiff_compression: TiffCompression,
+    /// 1-100
+    pub avif_quality: u8,
+    /// 1-10, slower speeds make smaller files
+    pub avif_speed: u8,
+    pub jxl_lossless: bool,
+    /// Butteraugli distance for lossy JPEG XL, 0-15. 1 is visually lossless.
+    pub jxl_distance: f32,
+    /// Store unedited JPEGs losslessly as JPEG XL, which can be turned back into the original file
+    pub jxl_recompress_jpeg: bool,
+    /// Don't copy EXIF, XMP and GPS data of the original to the saved file
+    pub strip_metadata: bool,
+    /// Save JPEGs that are only rotated or flipped by changing their EXIF orientation
+    pub rotate_with_exif: bool,
+    /// File name for "Save as template", relative to the folder of the image.
+    /// See `ExportSettings::templated_path` for the placeholders.
+    pub name_template: String,
+    /// Add a number to templated file names instead of overwriting existing files
+    pub auto_number: bool,
+    /// The pixels are converted to a chosen profile, `Original` keeps them and their profile as is
+    pub color_profile: ColorProfile,
+    pub size_presets: Vec<SizePreset>,
+    /// Index of the size preset the image is saved with
+    pub size_preset: usize,
+    /// Used when saving an edited animation
+    pub animation_format: ExportFormat,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            format: ExportFormat::Png,
+            jpeg_quality: 90,
+            jpeg_subsampling: ChromaSubsampling::S420,
+            png_compression: PngCompression::Default,
+            webp_lossless: true,
+            webp_quality: 80.,
+            tiff_compression: TiffCompression::Lzw,
+            avif_quality: 80,
+            avif_speed: 6,
+            jxl_lossless: true,
+            jxl_distance: 1.,
+            jxl_recompress_jpeg: true,
+            strip_metadata: false,
+            rotate_with_exif: true,
+            name_template: "{name}_edit.{ext}".into(),
+            auto_number: true,
+            color_profile: ColorProfile::Original,
+            size_presets: vec![
+                SizePreset::new("Full", 0, 0),
+                SizePreset::new("2048px long edge", 2048, 2048),
+                SizePreset::new("1080p", 1920, 1080),
+                SizePreset::new("Thumbnail 256px", 256, 256),
+            ],
+            size_preset: 0,
+            animation_format: ExportFormat::Webp,
+        }
+    }
+}
+
+impl ExportSettings {
+    /// Encode `img` in `format`. The color `profile` is embedded in PNG, JPEG, WebP and TIFF files.
+    pub fn encode(
+        &self,
+        img: &RgbaImage,
+        format: ExportFormat,
+        profile: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        let data = self.encode_pixels(img, format, profile)?;
+        match (format, profile) {
+            (ExportFormat::Png | ExportFormat::Jpeg | ExportFormat::Webp, Some(profile)) => {
+                let mut image =
+                    DynImage::from_bytes(data.into())?.context("Can't read the encoded image")?;
+                image.set_icc_profile(Some(profile.to_vec().into()));
+                Ok(image.encoder().bytes().to_vec())
+            }
+            _ => Ok(data),
+        }
+    }
+
+    /// The color profile to embed, `source` is the file the image was loaded from
+    pub fn profile(&self, source: Option<&Path>) -> Option<Vec<u8>> {
+        match self.color_profile {
+            ColorProfile::Original => read_profile(source?),
+            profile => profile.data(),
+        }
+    }
+
+    fn encode_pixels(
+        &self,
+        img: &RgbaImage,
+        format: ExportFormat,
+        profile: Option<&[u8]>,
+    ) -> Result<Vec<u8>> {
+        let mut out = Cursor::new(vec![]);
+        match format {
+            ExportFormat::Png => {
+                let compression = match self.png_compression {
+                    PngCompression::Fast => CompressionType::Fast,
+                    PngCompression::Default => CompressionType::Default,
+                    PngCompression::Best => CompressionType::Best,
+                };
+                img.write_with_encoder(PngEncoder::new_with_quality(
+                    &mut out,
+                    compression,
+                    FilterType::Adaptive,
+                ))?;
+            }
+            ExportFormat::Jpeg => {
+                // JPEG has no alpha channel
+                let rgb = DynamicImage::ImageRgba8(img.clone()).into_rgb8();
+                #[cfg(feature = "turbo")]
+                {
+                    let subsamp = match self.jpeg_subsampling {
+                        ChromaSubsampling::S444 => turbojpeg::Subsamp::None,
+                        ChromaSubsampling::S422 => turbojpeg::Subsamp::Sub2x1,
+                        ChromaSubsampling::S420 => turbojpeg::Subsamp::Sub2x2,
+                    };
+                    let data = turbojpeg::compress_image(&rgb, self.jpeg_quality as i32, subsamp)?;
+                    return Ok(data.to_vec());
+                }
+                // the built-in encoder has a fixed subsampling
+                #[cfg(not(feature = "turbo"))]
+                {
+                    rgb.write_with_encoder(JpegEncoder::new_with_quality(
+                        &mut out,
+                        self.jpeg_quality.clamp(1, 100),
+                    ))?;
+                }
+            }
+            ExportFormat::Webp => {
+                let mut encoder = webp_animation::Encoder::new_with_options(
+                    img.dimensions(),
+                    self.webp_options(),
+                )
+                .map_err(|e| anyhow!("{e:?}"))?;
+                encoder
+                    .add_frame(img.as_raw(), 0)
+                    .map_err(|e| anyhow!("{e:?}"))?;
+                // a single frame is written as a still image
+                let data = encoder.finalize(100).map_err(|e| anyhow!("{e:?}"))?;
+                return Ok(data.to_vec());
+            }
+            ExportFormat::Tiff => {
+                use tiff::encoder::compression;
+                match self.tiff_compression {
+                    TiffCompression::None => {
+                        write_tiff(&mut out, img, compression::Uncompressed, profile)?
+                    }
+                    TiffCompression::Lzw => write_tiff(&mut out, img, compression::Lzw, profile)?,
+                    TiffCompression::Deflate => {
+                        write_tiff(&mut out, img, compression::Deflate::default(), profile)?
+                    }
+                    TiffCompression::PackBits => {
+                        write_tiff(&mut out, img, compression::Packbits, profile)?
+                    }
+                }
+            }
+            ExportFormat::Avif => img.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut out,
+                self.avif_speed.clamp(1, 10),
+                self.avif_quality.clamp(1, 100),
+            ))?,
+            ExportFormat::Jxl => {
+                #[cfg(feature = "jxl_encode")]
+                {
+                    use jpegxl_rs::encode::{encoder_builder, EncoderResult};
+                    let mut encoder = encoder_builder()
+                        .has_alpha(true)
+                        .lossless(self.jxl_lossless)
+                        .quality(self.jxl_distance.clamp(0., 15.))
+                        .build()?;
+                    let result: EncoderResult<u8> =
+                        encoder.encode::<u8, u8>(img.as_raw(), img.width(), img.height())?;
+                    return Ok(result.data);
+                }
+                #[cfg(not(feature = "jxl_encode"))]
+                bail!("JPEG XL export is not available in this build");
+            }
+            ExportFormat::Gif => img.write_to(&mut out, ImageFormat::Gif)?,
+            ExportFormat::Bmp => img.write_to(&mut out, ImageFormat::Bmp)?,
+            ExportFormat::Tga => img.write_to(&mut out, ImageFormat::Tga)?,
+        }
+        Ok(out.into_inner())
+    }
+
+    /// Write `img` to `path`, in the format of its extension. `source` is the file `img` was
+    /// loaded from. If `img` still has its pixels, JPEGs can be converted to JPEG XL losslessly.
+    /// A size preset is not applied when `path` is `source`, so overwriting can't shrink it.
+    pub fn save(&self, img: &RgbaImage, path: &Path, source: Option<&Path>) -> Result<()> {
+        let img = match self.size_presets.get(self.size_preset) {
+            Some(preset) if !source.is_some_and(|s| same_file(s, path)) => preset.resize(img),
+            _ => Cow::Borrowed(img),
+        };
+        let format = match ExportFormat::from_path(path) {
+            Some(format) => format,
+            // formats without encoder settings
+            None if ImageFormat::from_path(path).is_ok() => return save_other(&img, path),
+            None => self.format,
+        };
+        #[cfg(feature = "jxl_encode")]
+        if format == ExportFormat::Jxl
+            && self.jxl_recompress_jpeg
+            && self.color_profile == ColorProfile::Original
+        {
+            if let Some(original) = source
+                .filter(|p| ExportFormat::from_path(p) == Some(ExportFormat::Jpeg))
+                .filter(|p| is_unchanged_jpeg(p, &img))
+            {
+                let mut jpeg = std::fs::read(original)?;
+                if self.strip_metadata {
+                    jpeg = strip_jpeg_metadata(jpeg)?;
+                }
+                let mut encoder = jpegxl_rs::encode::encoder_builder().build()?;
+                let data = encoder.encode_jpeg(&jpeg)?.data;
+                std::fs::write(path, data)?;
+                return Ok(());
+            }
+        }
+        let mut img = img;
+        if self.color_profile.data().is_some() {
+            let source_profile = source.and_then(read_profile);
+            crate::icc::convert(img.to_mut(), source_profile.as_deref(), self.color_profile)?;
+        }
+        let data = self.encode(&img, format, self.profile(source).as_deref())?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    /// Save a JPEG by copying `source` to `path` with a new EXIF `orientation` (1-8), instead of
+    /// encoding it again. Returns false if this is turned off or the settings change the pixels.
+    pub fn save_orientation(&self, source: &Path, path: &Path, orientation: u8) -> Result<bool> {
+        let full_size = self
+            .size_presets
+            .get(self.size_preset)
+            .map(|p| p.max_width == 0 && p.max_height == 0)
+            .unwrap_or(true);
+        let is_jpeg = |p: &Path| ExportFormat::from_path(p) == Some(ExportFormat::Jpeg);
+        if !self.rotate_with_exif
+            || self.strip_metadata
+            || self.color_profile != ColorProfile::Original
+            || !full_size
+            || !is_jpeg(source)
+            || !is_jpeg(path)
+        {
+            return Ok(false);
+        }
+        if source != path {
+            std::fs::copy(source, path)?;
+        }
+        set_exif_orientation(path, orientation)?;
+        Ok(true)
+    }
+
+    /// The file to save to according to `name_template`. The placeholders are `{name}`, the name
+    /// of `source` without extension, `{ext}`, the extension of the default format, `{date}`,
+    /// `{time}`, `{width}` and `{height}`. Images without a source are saved to `fallback_dir`.
+    pub fn templated_path(
+        &self,
+        source: Option<&Path>,
+        fallback_dir: &Path,
+        (width, height): (u32, u32),
+    ) -> PathBuf {
+        let dir = source
+            .and_then(|p| p.parent())
+            .unwrap_or(fallback_dir)
+            .to_path_buf();
+        let name = source
+            .and_then(|p| p.file_stem())
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or("untitled".into());
+        let now = chrono::Local::now();
+        let file_name = self
+            .name_template
+            .replace("{name}", &name)
+            .replace("{ext}", self.format.extension())
+            .replace("{date}", &now.format("%Y-%m-%d").to_string())
+            .replace("{time}", &now.format("%H-%M-%S").to_string())
+            .replace("{width}", &width.to_string())
+            .replace("{height}", &height.to_string());
+        let path = dir.join(file_name);
+        if self.auto_number {
+            unique_path(&path)
+        } else {
+            path
+        }
+    }
+
+    /// Encode `img` as a PNG `data:` URI, which can be embedded in HTML or CSS
+    pub fn data_uri(&self, img: &RgbaImage) -> Result<String> {
+        use base64::Engine;
+        let png = self.encode(img, ExportFormat::Png, None)?;
+        Ok(format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(png)
+        ))
+    }
+
+    /// WebP encoder options for the lossless and quality settings
+    fn webp_options(&self) -> webp_animation::EncoderOptions {
+        webp_animation::EncoderOptions {
+            encoding_config: Some(webp_animation::EncodingConfig {
+                encoding_type: if self.webp_lossless {
+                    webp_animation::EncodingType::Lossless
+                } else {
+                    webp_animation::EncodingType::Lossy(Default::default())
+                },
+                quality: self.webp_quality.clamp(0., 100.),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// Encode an animation of frames with their delay in milliseconds. All frames need to have
+    /// the same size.
+    pub fn encode_animation(
+        &self,
+        frames: &[(RgbaImage, u16)],
+        format: ExportFormat,
+    ) -> Result<Vec<u8>> {
+        let Some((first, _)) = frames.first() else {
+            bail!("The animation has no frames");
+        };
+        let (width, height) = first.dimensions();
+        let mut out = Cursor::new(vec![]);
+        match format {
+            ExportFormat::Gif => {
+                use image::codecs::gif::{GifEncoder, Repeat};
+                // the slowest speed takes very long for larger animations
+                let mut encoder = GifEncoder::new_with_speed(&mut out, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.encode_frames(frames.iter().map(|(img, delay)| {
+                    image::Frame::from_parts(
+                        img.clone(),
+                        0,
+                        0,
+                        image::Delay::from_numer_denom_ms(*delay as u32, 1),
+                    )
+                }))?;
+            }
+            ExportFormat::Webp => {
+                let mut encoder =
+                    webp_animation::Encoder::new_with_options((width, height), self.webp_options())
+                        .map_err(|e| anyhow!("{e:?}"))?;
+                let mut timestamp = 0;
+                for (img, delay) in frames {
+                    encoder
+                        .add_frame(img.as_raw(), timestamp)
+                        .map_err(|e| anyhow!("{e:?}"))?;
+                    timestamp += *delay as i32;
+                }
+                let data = encoder.finalize(timestamp).map_err(|e| anyhow!("{e:?}"))?;
+                return Ok(data.to_vec());
+            }
+            ExportFormat::Png => {
+                let mut encoder = png::Encoder::new(&mut out, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                // loop forever
+                encoder.set_animated(frames.len() as u32, 0)?;
+                let mut writer = encoder.write_header()?;
+                for (img, delay) in frames {
+                    writer.set_frame_delay(*delay, 1000)?;
+                    writer.write_image_data(img.as_raw())?;
+                }
+                writer.finish()?;
+            }
+            _ => bail!("{} can't store animations", format.extension()),
+        }
+        Ok(out.into_inner())
+    }
+
+    /// Write an animation to `path`, in the format of its extension
+    pub fn save_animation(&self, frames: &[(RgbaImage, u16)], path: &Path) -> Result<()> {
+        let format = ExportFormat::from_path(path).unwrap_or(self.animation_format);
+        let frames = match self.size_presets.get(self.size_preset) {
+            Some(preset) => frames
+                .iter()
+                .map(|(img, delay)| (preset.resize(img).into_owned(), *delay))
+                .collect(),
+            None => frames.to_vec(),
+        };
+        let data = self.encode_animation(&frames, format)?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) -> Response {
+        let mut r = ui.allocate_response(Vec2::ZERO, Sense::hover());
+        egui::Grid::new("export settings").show(ui, |ui| {
+            ui.label("File name").on_hover_text(
+                "Used by 'Save as template'. {name}, {ext}, {date}, {time}, {width} and {height} are replaced. Folders are relative to the image.",
+            );
+            if ui.text_edit_singleline(&mut self.name_template).changed() {
+                r.mark_changed();
+            }
+            ui.end_row();
+
+            ui.label("Number duplicates")
+                .on_hover_text("Add a number to the file name instead of overwriting a file");
+            if ui.checkbox(&mut self.auto_number, "").changed() {
+                r.mark_changed();
+            }
+            ui.end_row();
+
+            ui.label("Remove metadata").on_hover_text(
+                "Don't copy EXIF, XMP and GPS data of the original image to the saved file",
+            );
+            if ui.checkbox(&mut self.strip_metadata, "").changed() {
+                r.mark_changed();
+            }
+            ui.end_row();
+
+            ui.label("Rotate JPEGs with EXIF").on_hover_text(
+                "Save JPEGs that are only rotated or flipped by changing their orientation tag. This keeps their quality and metadata, but some programs ignore the tag.",
+            );
+            if ui.checkbox(&mut self.rotate_with_exif, "").changed() {
+                r.mark_changed();
+            }
+            ui.end_row();
+
+            ui.label("Size")
+                .on_hover_text("Not applied when overwriting the original image");
+            egui::ComboBox::from_id_source("export size")
+                .selected_text(
+                    self.size_presets
+                        .get(self.size_preset)
+                        .map(|p| p.name.as_str())
+                        .unwrap_or("Full"),
+                )
+                .show_ui(ui, |ui| {
+                    for (i, preset) in self.size_presets.iter().enumerate() {
+                        if ui
+                            .selectable_value(&mut self.size_preset, i, &preset.name)
+                            .clicked()
+                        {
+                            r.mark_changed();
+                        }
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Animation format");
+            ui.horizontal(|ui| {
+                for format in ExportFormat::ANIMATED {
+                    if ui
+                        .selectable_value(&mut self.animation_format, format, format.extension())
+                        .clicked()
+                    {
+                        r.mark_changed();
+                    }
+                }
+            });
+            ui.end_row();
+
+            ui.label("Color profile").on_hover_text(
+                "The profile embedded in PNG, JPEG, WebP and TIFF files. The colors are converted to a chosen profile.",
+            );
+            egui::ComboBox::from_id_source("export color profile")
+                .selected_text(self.color_profile.name())
+                .show_ui(ui, |ui| {
+                    for profile in ColorProfile::ALL {
+                        if ui
+                            .selectable_value(&mut self.color_profile, profile, profile.name())
+                            .clicked()
+                        {
+                            r.mark_changed();
+                        }
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Default format").on_hover_text(
+                "Used for files without an extension and when applying edits to a folder",
+            );
+            egui::ComboBox::from_id_source("export format")
+                .selected_text(self.format.extension())
+                .show_ui(ui, |ui| {
+                    for format in ExportFormat::ALL {
+                        if ui
+                            .selectable_value(&mut self.format, format, format.extension())
+                            .clicked()
+                        {
+                            r.mark_changed();
+                        }
+                    }
+                });
+            ui.end_row();
+
+            ui.label("JPEG quality");
+            if ui
+                .add(egui::Slider::new(&mut self.jpeg_quality, 1..=100))
+                .changed()
+            {
+                r.mark_changed();
+            }
+            ui.end_row();
+
+            ui.label("JPEG subsampling")
+                .on_hover_text("Lower color resolution makes smaller files");
+            // the built-in encoder has a fixed subsampling
+            ui.add_enabled_ui(cfg!(feature = "turbo"), |ui| {
+                ui.horizontal(|ui| {
+                    for s in ChromaSubsampling::ALL {
+                        if ui
+                            .selectable_value(&mut self.jpeg_subsampling, s, s.name())
+                            .clicked()
+                        {
+                            r.mark_changed();
+                        }
+                    }
+                });
+            })
+            .response
+            .on_disabled_hover_text("Only available in builds with the turbo feature");
+            ui.end_row();
+
+            ui.label("PNG compression");
+            ui.horizontal(|ui| {
+                for c in [
+                    PngCompression::Fast,
+                    PngCompression::Default,
+                    PngCompression::Best,
+                ] {
+                    if ui
+                        .selectable_value(&mut self.png_compression, c, format!("{c:?}"))
+                        .clicked()
+                    {
+                        r.mark_changed();
+                    }
+                }
+            });
+            ui.end_row();
+
+            ui.label("WebP");
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut self.webp_lossless, "Lossless").changed() {
+                    r.mark_changed();
+                }
+                if ui
+                    .add_enabled(
+                        !self.webp_lossless,
+                        DragValue::new(&mut self.webp_quality)
+                            .clamp_range(0.0..=100.0)
+                            .prefix("quality "),
+                    )
+                    .changed()
+                {
+                    r.mark_changed();
+                }
+            });
+            ui.end_row();
+
+            ui.label("AVIF");
+            ui.horizontal(|ui| {
+                if ui
+                    .add(egui::Slider::new(&mut self.avif_quality, 1..=100).text("quality"))
+                    .changed()
+                {
+                    r.mark_changed();
+                }
+                if ui
+                    .add(
+                        DragValue::new(&mut self.avif_speed)
+                            .clamp_range(1..=10)
+                            .prefix("speed "),
+                    )
+                    .on_hover_text("Slower speeds make smaller files, but take much longer")
+                    .changed()
+                {
+                    r.mark_changed();
+                }
+            });
+            ui.end_row();
+
+            ui.label("JPEG XL");
+            ui.horizontal(|ui| {
+                if ui.checkbox(&mut self.jxl_lossless, "Lossless").changed() {
+                    r.mark_changed();
+                }
+                if ui
+                    .add_enabled(
+                        !self.jxl_lossless,
+                        DragValue::new(&mut self.jxl_distance)
+                            .clamp_range(0.0..=15.0)
+                            .speed(0.05)
+                            .prefix("distance "),
+                    )
+                    .on_hover_text("Lower is better quality, 1 is visually lossless")
+                    .changed()
+                {
+                    r.mark_changed();
+                }
+                if ui
+                    .checkbox(&mut self.jxl_recompress_jpeg, "Recompress JPEGs")
+                    .on_hover_text(
+                        "Convert unedited JPEGs losslessly, so the original file can be restored",
+                    )
+                    .changed()
+                {
+                    r.mark_changed();
+                }
+            });
+            ui.end_row();
+
+            ui.label("TIFF compression");
+            ui.horizontal(|ui| {
+                for c in [
+                    TiffCompression::None,
+                    TiffCompression::Lzw,
+                    TiffCompression::Deflate,
+                    TiffCompression::PackBits,
+                ] {
+                    if ui
+                        .selectable_value(&mut self.tiff_compression, c, format!("{c:?}"))
+                        .clicked()
+                    {
+                        r.mark_changed();
+                    }
+                }
+            });
+            ui.end_row();
+        });
+        r
+    }
+
+    /// Edit the list of size presets
+    pub fn presets_ui(&mut self, ui: &mut Ui) {
+        let mut delete = None;
+        egui::Grid::new("size presets").show(ui, |ui| {
+            ui.label("Name");
+            ui.label("Max width");
+            ui.label("Max height");
+            ui.end_row();
+            for (i, preset) in self.size_presets.iter_mut().enumerate() {
+                ui.text_edit_singleline(&mut preset.name);
+                ui.add(DragValue::new(&mut preset.max_width))
+                    .on_hover_text("0 means no limit");
+                ui.add(DragValue::new(&mut preset.max_height))
+                    .on_hover_text("0 means no limit");
+                if ui.button("🗑").clicked() {
+                    delete = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = delete {
+            self.size_presets.remove(i);
+            if self.size_preset > i {
+                self.size_preset -= 1;
+            } else if self.size_preset == i {
+                self.size_preset = 0;
+            }
+        }
+        if ui.button("➕ Add size").clicked() {
+            self.size_presets
+                .push(SizePreset::new("New size", 1024, 1024));
+        }
+    }
+}
+
+/// `path`, or the first of `name_1.ext`, `name_2.ext`... that does not exist yet
+pub fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| path.with_file_name(format!("{stem}_{i}{ext}")))
+        .find(|p| !p.exists())
+        .unwrap_or(path.to_path_buf())
+}
+
+/// Whether `a` and `b` are the same file, also through different paths
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (a.canonicalize(), b.canonicalize()),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
+
+/// The embedded color profile of an image file
+pub fn read_profile(path: &Path) -> Option<Vec<u8>> {
+    let data = std::fs::read(path).ok()?;
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    if matches!(ext.as_str(), "avif" | "avifs" | "heic" | "heics" | "heif") {
+        // the pixels were converted to sRGB when loading
+        return crate::heif::icc_profile(&data).and(ColorProfile::Srgb.data());
+    }
+    let image = DynImage::from_bytes(data.into()).ok()??;
+    image.icc_profile().map(|p| p.to_vec())
+}
+
+fn write_tiff<C: tiff::encoder::compression::Compression>(
+    out: &mut Cursor<Vec<u8>>,
+    img: &RgbaImage,
+    compression: C,
+    profile: Option<&[u8]>,
+) -> Result<()> {
+    use tiff::encoder::{colortype::RGBA8, TiffEncoder};
+    use tiff::tags::Tag;
+    let mut encoder = TiffEncoder::new(out)?;
+    let mut image =
+        encoder.new_image_with_compression::<RGBA8, C>(img.width(), img.height(), compression)?;
+    if let Some(profile) = profile {
+        // the ICC profile tag
+        image.encoder().write_tag(Tag::Unknown(34675), profile)?;
+    }
+    image.write_data(img.as_raw())?;
+    Ok(())
+}
+
+/// Write `img` with the encoder `image` has for the extension of `path`, like ICO, QOI, EXR or HDR
+fn save_other(img: &RgbaImage, path: &Path) -> Result<()> {
+    let format = ImageFormat::from_path(path)?;
+    let img = DynamicImage::ImageRgba8(img.clone());
+    // convert to what the encoders support
+    let img = match format {
+        ImageFormat::Hdr => DynamicImage::ImageRgb32F(img.into_rgb32f()),
+        ImageFormat::OpenExr => DynamicImage::ImageRgba32F(img.into_rgba32f()),
+        ImageFormat::Farbfeld => DynamicImage::ImageRgba16(img.into_rgba16()),
+        ImageFormat::Pnm => DynamicImage::ImageRgb8(img.into_rgb8()),
+        _ => img,
+    };
+    img.save_with_format(path, format)?;
+    Ok(())
+}
+
+/// Whether `img` has exactly the pixels of the JPEG at `path`, decoded the same way it is loaded.
+/// Edits, scripts and scaling all change them, so only untouched images are recompressed.
+#[cfg(feature = "jxl_encode")]
+fn is_unchanged_jpeg(path: &Path, img: &RgbaImage) -> bool {
+    let Ok(data) = std::fs::read(path) else {
+        return false;
+    };
+    #[cfg(feature = "turbo")]
+    let decoded = turbojpeg::decompress_image::<image::Rgb<u8>>(&data)
+        .map(|buf| DynamicImage::ImageRgb8(buf).to_rgba8())
+        .ok();
+    #[cfg(not(feature = "turbo"))]
+    let decoded = image::load_from_memory(&data).map(|d| d.to_rgba8()).ok();
+    decoded.as_ref() == Some(img)
+}
+
+/// Remove the EXIF, XMP and IPTC segments of a JPEG file. The color profile is kept.
+#[cfg(feature = "jxl_encode")]
+fn strip_jpeg_metadata(jpeg: Vec<u8>) -> Result<Vec<u8>> {
+    use img_parts::jpeg::{markers, Jpeg};
+    let mut jpeg = Jpeg::from_bytes(jpeg.into())?;
+    // EXIF and XMP are both stored in APP1
+    jpeg.segments_mut()
+        .retain(|s| s.marker() != markers::APP1 && s.marker() != markers::APP13);
+    Ok(jpeg.encoder().bytes().to_vec())
+}
@@ -0,0 +1,219 @@
+//! FITS images, as used in astronomy. The data is linear and often has a large range, so it is
+//! stretched for display, and the result goes to the high dynamic range pipeline.
+
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, Rgba, Rgba32FImage};
+
+use crate::utils::Frame;
+
+pub const FITS_EXTENSIONS: &[&str] = &["fits", "fit", "fts"];
+
+/// The size of header and data blocks
+const BLOCK: usize = 2880;
+const CARD: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stretch {
+    Linear,
+    Log,
+    /// Like log for bright values, but linear near black, which keeps faint detail
+    Asinh,
+}
+
+impl Stretch {
+    pub const ALL: [Self; 3] = [Self::Linear, Self::Log, Self::Asinh];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Linear => "Linear",
+            Self::Log => "Log",
+            Self::Asinh => "Asinh",
+        }
+    }
+
+    fn apply(&self, v: f32) -> f32 {
+        match self {
+            Self::Linear => v,
+            Self::Log => (1. + 1000. * v).ln() / 1001_f32.ln(),
+            Self::Asinh => (10. * v).asinh() / 10_f32.asinh(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StretchSettings {
+    pub stretch: Stretch,
+    /// The value that becomes black, from 0-1 of the range of the image
+    pub black: f32,
+    /// The value that becomes white, from 0-1 of the range of the image
+    pub white: f32,
+}
+
+impl Default for StretchSettings {
+    fn default() -> Self {
+        Self {
+            stretch: Stretch::Asinh,
+            black: 0.,
+            white: 1.,
+        }
+    }
+}
+
+/// The primary image of a FITS file
+#[derive(Debug, Clone)]
+pub struct FitsImage {
+    pub width: u32,
+    pub height: u32,
+    /// One plane for grayscale, three for color
+    pub planes: Vec<Vec<f32>>,
+}
+
+/// The value of a header card, without the comment
+fn card_value(card: &str) -> Option<&str> {
+    let value = card.get(8..)?.strip_prefix("= ")?;
+    Some(value.split('/').next()?.trim())
+}
+
+impl FitsImage {
+    pub fn read(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(b"SIMPLE") {
+            bail!("Not a FITS file");
+        }
+        let mut keys = std::collections::HashMap::new();
+        let mut header_len = None;
+        for (i, card) in data.chunks_exact(CARD).enumerate() {
+            let card = String::from_utf8_lossy(card);
+            let keyword = card.get(..8).unwrap_or_default().trim().to_string();
+            if keyword == "END" {
+                header_len = Some(((i + 1) * CARD + BLOCK - 1) / BLOCK * BLOCK);
+                break;
+            }
+            if let Some(value) = card_value(&card) {
+                keys.insert(keyword, value.to_string());
+            }
+        }
+        let header_len = header_len.context("The FITS header has no end")?;
+        let number = |key: &str| keys.get(key).and_then(|v| v.parse::<f64>().ok());
+
+        let bitpix = number("BITPIX").context("No BITPIX in header")? as i32;
+        let naxis = number("NAXIS").unwrap_or_default() as u32;
+        if naxis < 2 {
+            bail!("The FITS file has no image");
+        }
+        let width = number("NAXIS1").unwrap_or_default() as u32;
+        let height = number("NAXIS2").unwrap_or_default() as u32;
+        let depth = if naxis > 2 {
+            number("NAXIS3").unwrap_or(1.) as usize
+        } else {
+            1
+        };
+        if width == 0 || height == 0 {
+            bail!("The FITS image is empty");
+        }
+        if ![8, 16, 32, 64, -32, -64].contains(&bitpix) {
+            bail!("Unsupported BITPIX {bitpix}");
+        }
+        let zero = number("BZERO").unwrap_or(0.);
+        let scale = number("BSCALE").unwrap_or(1.);
+
+        let bytes = (bitpix.unsigned_abs() / 8) as usize;
+        let plane_len = (width as usize)
+            .checked_mul(height as usize)
+            .context("The FITS image is too large")?;
+        let planes_used = if depth >= 3 { 3 } else { 1 };
+        // the sizes come from the header, so they may be anything
+        let end = plane_len
+            .checked_mul(planes_used * bytes)
+            .and_then(|len| len.checked_add(header_len))
+            .context("The FITS image is too large")?;
+        let data = data
+            .get(header_len..end)
+            .context("The FITS image data is cut off")?;
+
+        let values =
+            data.chunks_exact(bytes)
+                .map(|b| {
+                    let raw = match bitpix {
+                        8 => b[0] as f64,
+                        16 => i16::from_be_bytes([b[0], b[1]]) as f64,
+                        32 => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        64 => i64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+                            as f64,
+                        -32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                        _ => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+                    };
+                    (zero + scale * raw) as f32
+                })
+                .collect::<Vec<_>>();
+
+        // the first row is the bottom one
+        let planes = values
+            .chunks_exact(plane_len)
+            .map(|plane| {
+                plane
+                    .chunks_exact(width as usize)
+                    .rev()
+                    .flatten()
+                    .copied()
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            planes,
+        })
+    }
+
+    /// The range between a low and a high percentile, so a few hot pixels don't make the rest
+    /// of the image black
+    fn range(&self) -> (f32, f32) {
+        let step = (self.planes[0].len() / 100_000).max(1);
+        let mut sample = self
+            .planes
+            .iter()
+            .flat_map(|p| p.iter().step_by(step))
+            .copied()
+            .filter(|v| v.is_finite())
+            .collect::<Vec<_>>();
+        if sample.is_empty() {
+            return (0., 1.);
+        }
+        sample.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let low = sample[sample.len() / 2000];
+        let high = sample[sample.len() - 1 - sample.len() / 2000];
+        (low, high.max(low + f32::EPSILON))
+    }
+
+    /// The stretched image. Values above the white point are kept.
+    pub fn stretch(&self, settings: &StretchSettings) -> Rgba32FImage {
+        let (low, high) = self.range();
+        let black = low + (high - low) * settings.black;
+        let white = (low + (high - low) * settings.white).max(black + f32::EPSILON);
+        let value = |plane: &Vec<f32>, i: usize| {
+            let v = plane.get(i).copied().unwrap_or_default();
+            let v = if v.is_finite() { v } else { black };
+            settings
+                .stretch
+                .apply(((v - black) / (white - black)).max(0.))
+        };
+        Rgba32FImage::from_fn(self.width, self.height, |x, y| {
+            let i = (y * self.width + x) as usize;
+            match self.planes.as_slice() {
+                [r, g, b, ..] => Rgba([value(r, i), value(g, i), value(b, i), 1.]),
+                [gray, ..] => {
+                    let v = value(gray, i);
+                    Rgba([v, v, v, 1.])
+                }
+                [] => Rgba([0., 0., 0., 1.]),
+            }
+        })
+    }
+
+    /// A still frame of the stretched image
+    pub fn frame(&self, settings: &StretchSettings) -> Frame {
+        let hdr = self.stretch(settings);
+        let buffer = DynamicImage::ImageRgba32F(hdr.clone()).into_rgba8();
+        Frame::new_still_hdr(buffer, hdr)
+    }
+}
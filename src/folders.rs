@@ -0,0 +1,75 @@
+//! The folder panel: a tree of folders to move to other ones than the folder of the current
+//! image, and the images of the folder selected in it. Folders are read when they are expanded.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::scrubber::get_image_filenames_for_directory;
+
+#[derive(Debug, Default)]
+pub struct FolderTree {
+    /// The folder whose images are listed, the folder of the current image if none
+    pub selected: Option<PathBuf>,
+    subfolders: HashMap<PathBuf, Vec<PathBuf>>,
+    images: HashMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl FolderTree {
+    /// The folders in `folder`, read the first time they are asked for
+    pub fn subfolders(&mut self, folder: &Path) -> &[PathBuf] {
+        self.subfolders
+            .entry(folder.to_path_buf())
+            .or_insert_with(|| subfolders(folder))
+    }
+
+    /// The images in `folder`, read the first time they are asked for
+    pub fn images(&mut self, folder: &Path) -> &[PathBuf] {
+        self.images
+            .entry(folder.to_path_buf())
+            .or_insert_with(|| get_image_filenames_for_directory(folder).unwrap_or_default())
+    }
+
+    /// Read the folders again, after they changed on disk
+    pub fn refresh(&mut self) {
+        self.subfolders.clear();
+        self.images.clear();
+    }
+}
+
+/// The folders in `folder` in natural order, without hidden ones
+pub fn subfolders(folder: &Path) -> Vec<PathBuf> {
+    let mut folders = std::fs::read_dir(folder)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    folders.sort_unstable_by(|a, b| lexical_sort::natural_lexical_cmp(&name(a), &name(b)));
+    folders
+}
+
+/// The name of a folder in the tree, or the whole path for roots
+pub fn name(folder: &Path) -> String {
+    folder
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| folder.to_string_lossy().to_string())
+}
+
+/// The folders the tree starts at: the home folder and the root of `current`
+pub fn roots(current: &Path) -> Vec<PathBuf> {
+    let mut roots = vec![];
+    if let Some(home) = dirs::home_dir() {
+        roots.push(home);
+    }
+    if let Some(root) = current.ancestors().last() {
+        if !root.as_os_str().is_empty() && !roots.iter().any(|r| r == root) {
+            roots.push(root.to_path_buf());
+        }
+    }
+    roots
+}
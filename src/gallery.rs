@@ -0,0 +1,119 @@
+//! A grid of thumbnails of the images in the current folder. Thumbnails are decoded in the
+//! background as they come into view, kept while the gallery is open and stored on disk.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use anyhow::Result;
+use image::RgbaImage;
+use log::debug;
+use notan::prelude::{Graphics, Texture};
+
+use crate::image_loader::open_image;
+use crate::thumbnails;
+use crate::utils::ImageExt;
+
+/// The longest side of a thumbnail
+pub const THUMBNAIL_SIZE: u32 = 160;
+
+enum Thumbnail {
+    Loading,
+    Failed,
+    Ready(Texture),
+}
+
+pub struct Gallery {
+    /// The index of the entry with keyboard focus
+    pub selected: usize,
+    /// Scroll the selected entry into view, after it was moved with the keyboard
+    pub scroll_to_selected: bool,
+    thumbnails: HashMap<PathBuf, Thumbnail>,
+    sender: Sender<(PathBuf, Option<RgbaImage>)>,
+    receiver: Receiver<(PathBuf, Option<RgbaImage>)>,
+}
+
+impl Gallery {
+    pub fn new(selected: usize) -> Self {
+        let (sender, receiver) = channel();
+        Self {
+            selected,
+            scroll_to_selected: true,
+            thumbnails: Default::default(),
+            sender,
+            receiver,
+        }
+    }
+
+    /// Upload the thumbnails that finished decoding
+    pub fn receive(&mut self, gfx: &mut Graphics) {
+        for (path, thumbnail) in self.receiver.try_iter() {
+            let texture = thumbnail.and_then(|t| t.to_texture(gfx, true));
+            self.thumbnails.insert(
+                path,
+                texture.map(Thumbnail::Ready).unwrap_or(Thumbnail::Failed),
+            );
+        }
+    }
+
+    /// The thumbnail of `path`, which is decoded in the background the first time it is asked
+    /// for. `None` while it is loading, or if the file can't be decoded.
+    pub fn thumbnail(&mut self, path: &Path) -> Option<&Texture> {
+        if !self.thumbnails.contains_key(path) {
+            self.thumbnails
+                .insert(path.to_path_buf(), Thumbnail::Loading);
+            let sender = self.sender.clone();
+            let path = path.to_path_buf();
+            rayon::spawn(move || {
+                let thumbnail = make_thumbnail(&path)
+                    .map_err(|e| debug!("No thumbnail for {}: {e}", path.display()))
+                    .ok();
+                _ = sender.send((path, thumbnail));
+            });
+        }
+        match self.thumbnails.get(path) {
+            Some(Thumbnail::Ready(texture)) => Some(texture),
+            _ => None,
+        }
+    }
+
+    /// The thumbnail of `path` could not be decoded
+    pub fn failed(&self, path: &Path) -> bool {
+        matches!(self.thumbnails.get(path), Some(Thumbnail::Failed))
+    }
+
+    /// Move the keyboard focus by `offset` entries, staying within `len`
+    pub fn select(&mut self, offset: isize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        self.selected = (self.selected as isize + offset).clamp(0, len as isize - 1) as usize;
+        self.scroll_to_selected = true;
+    }
+}
+
+/// The first frame of an image, scaled down to fit into `THUMBNAIL_SIZE`. It is made from the
+/// thumbnail stored on disk if there is one, and stored otherwise.
+pub fn make_thumbnail(path: &Path) -> Result<RgbaImage> {
+    if let Some(stored) = thumbnails::load(path) {
+        return Ok(fit(stored, THUMBNAIL_SIZE));
+    }
+    let large = fit(open_image(path)?.recv()?.buffer, thumbnails::LARGE_SIZE);
+    if let Err(e) = thumbnails::store(path, &large) {
+        debug!("Thumbnail of {} not stored: {e}", path.display());
+    }
+    Ok(fit(large, THUMBNAIL_SIZE))
+}
+
+/// Scale `img` down so its longest side is at most `size`
+fn fit(img: RgbaImage, size: u32) -> RgbaImage {
+    let scale = size as f32 / img.width().max(img.height()).max(1) as f32;
+    if scale >= 1. {
+        return img;
+    }
+    image::imageops::thumbnail(
+        &img,
+        ((img.width() as f32 * scale).round() as u32).max(1),
+        ((img.height() as f32 * scale).round() as u32).max(1),
+    )
+}
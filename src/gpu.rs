@@ -0,0 +1,661 @@
+//! Preview of the pixel operator stack on the GPU.
+//!
+//! Supported operators are encoded into a small program of op codes and parameters
+//! which a fixed fragment shader interprets. The CPU path in `image_editing` stays the
+//! reference and is always used for the final result.
+//!
+//! Custom shaders of the `PixelShader` operator are compiled at runtime, see `ShaderState`.
+
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use notan::prelude::*;
+
+use crate::image_editing::{EditState, ImageOperation};
+use crate::layers::BlendMode;
+use crate::plugins::PluginParam;
+
+/// The most operators the shader can run in one pass
+pub const MAX_OPS: usize = 16;
+
+/// Floats in the uniform buffer: code, first and second parameters per op and the op count
+pub const PROGRAM_LEN: usize = MAX_OPS * 4 * 3 + 4;
+
+const VERT: ShaderSource = notan::vertex_shader! {
+    r#"
+    #version 450
+    layout(location = 0) in vec2 a_pos;
+    layout(location = 1) in vec2 a_uv;
+
+    layout(location = 0) out vec2 v_uv;
+
+    void main() {
+        v_uv = a_uv;
+        gl_Position = vec4(a_pos, 0.0, 1.0);
+    }
+    "#
+};
+
+// Keep the op codes in sync with `encode_op`
+const FRAG: ShaderSource = notan::fragment_shader! {
+    r#"
+    #version 450
+    precision highp float;
+
+    layout(location = 0) in vec2 v_uv;
+    layout(binding = 0) uniform sampler2D u_texture;
+    layout(set = 0, binding = 1) uniform Program {
+        vec4 u_code[16];
+        vec4 u_a[16];
+        vec4 u_b[16];
+        vec4 u_info;
+    };
+
+    layout(location = 0) out vec4 color;
+
+    void main() {
+        vec4 p = texture(u_texture, v_uv);
+        int count = int(u_info.x);
+        for (int i = 0; i < 16; i++) {
+            if (i >= count) {
+                break;
+            }
+            int code = int(u_code[i].x);
+            vec4 a = u_a[i];
+            vec4 b = u_b[i];
+            if (code == 1) {
+                p.rgb += a.x;
+            } else if (code == 2) {
+                p.rgb *= a.x;
+            } else if (code == 3) {
+                p.rgb = mix(vec3(a.x), vec3(a.y), p.rgb);
+            } else if (code == 4) {
+                p.rgb = round(p.rgb * a.x) / a.x;
+            } else if (code == 5) {
+                p = mix(p, a, a.w);
+            } else if (code == 6) {
+                float v = dot(p.rgb, vec3(0.59, 0.3, 0.11));
+                p.rgb = mix(p.rgb, vec3(v), a.x);
+            } else if (code == 7) {
+                p *= a;
+            } else if (code == 8) {
+                p.rgb += a.rgb;
+            } else if (code == 9) {
+                p.rgb = 1.0 - p.rgb;
+            } else if (code == 10) {
+                p.rgb *= p.a;
+            } else if (code == 11) {
+                p.rgb /= p.a;
+            } else if (code == 12) {
+                p.rgb = clamp((p.rgb - a.rgb) / b.rgb, 0.0, 1.0);
+            } else if (code == 13) {
+                p.rgb = clamp(p.rgb * a.rgb, 0.0, 1.0);
+            } else if (code == 14) {
+                p[int(a.x)] = p[int(a.y)];
+            }
+        }
+        color = p;
+    }
+    "#
+};
+
+/// Op code and parameters for a single operator, or None if it has no GPU version
+fn encode_op(op: &ImageOperation) -> Option<(f32, [f32; 4], [f32; 4])> {
+    let zero = [0.; 4];
+    Some(match op {
+        ImageOperation::Brightness(amt) => (1., [*amt as f32 / 255., 0., 0., 0.], zero),
+        ImageOperation::Exposure(amt) => {
+            (2., [2_f32.powf(*amt as f32 / 100. * 4.), 0., 0., 0.], zero)
+        }
+        ImageOperation::Contrast(val) => {
+            let factor = (1.015_686_3 * (*val as f32 / 255. + 1.0))
+                / (1.0 * (1.015_686_3 - *val as f32 / 255.));
+            (2., [factor, 0., 0., 0.], zero)
+        }
+        ImageOperation::Equalize(bounds) => (
+            3.,
+            [bounds.0 as f32 / 255., bounds.1 as f32 / 255., 0., 0.],
+            zero,
+        ),
+        ImageOperation::Posterize(levels) => (4., [*levels as f32, 0., 0., 0.], zero),
+        ImageOperation::Fill(col) => (5., col.map(|c| c as f32 / 255.), zero),
+        ImageOperation::Desaturate(amt) => (6., [*amt as f32 / 100., 0., 0., 0.], zero),
+        ImageOperation::Mult(amt) => (
+            7.,
+            [
+                amt[0] as f32 / 255.,
+                amt[1] as f32 / 255.,
+                amt[2] as f32 / 255.,
+                1.,
+            ],
+            zero,
+        ),
+        ImageOperation::Add(amt) => (
+            8.,
+            [
+                amt[0] as f32 / 255.,
+                amt[1] as f32 / 255.,
+                amt[2] as f32 / 255.,
+                0.,
+            ],
+            zero,
+        ),
+        ImageOperation::Invert => (9., zero, zero),
+        ImageOperation::MMult => (10., zero, zero),
+        ImageOperation::MDiv => (11., zero, zero),
+        ImageOperation::AutoLevels(Some(levels)) => {
+            let mut black = zero;
+            let mut range = [1.; 4];
+            for c in 0..3 {
+                black[c] = levels[c].0 as f32 / 255.;
+                range[c] = (levels[c].1 as f32 - levels[c].0 as f32).max(1.) / 255.;
+            }
+            (12., black, range)
+        }
+        ImageOperation::AutoContrast(Some((black, white))) => {
+            let range = (*white as f32 - *black as f32).max(1.) / 255.;
+            let black = *black as f32 / 255.;
+            (12., [black, black, black, 0.], [range, range, range, 1.])
+        }
+        ImageOperation::AutoWhiteBalance(Some(gains)) => (
+            13.,
+            [
+                gains[0] as f32 / 1000.,
+                gains[1] as f32 / 1000.,
+                gains[2] as f32 / 1000.,
+                1.,
+            ],
+            zero,
+        ),
+        ImageOperation::ChannelSwap((dst, src)) => (
+            14.,
+            [*dst as usize as f32, *src as usize as f32, 0., 0.],
+            zero,
+        ),
+        _ => return None,
+    })
+}
+
+/// Encode the operators into the uniform program, or None if any of them is not supported
+pub fn encode_ops(ops: &[ImageOperation]) -> Option<Vec<f32>> {
+    if ops.len() > MAX_OPS {
+        return None;
+    }
+    let mut program = vec![0.; PROGRAM_LEN];
+    for (i, op) in ops.iter().enumerate() {
+        let (code, a, b) = encode_op(op)?;
+        program[i * 4] = code;
+        program[(MAX_OPS + i) * 4..][..4].copy_from_slice(&a);
+        program[(MAX_OPS * 2 + i) * 4..][..4].copy_from_slice(&b);
+    }
+    program[MAX_OPS * 3 * 4] = ops.len() as f32;
+    Some(program)
+}
+
+/// Encode the pixel stack of an edit, if everything it does can be previewed on the GPU
+pub fn encode_edit(edit_state: &EditState) -> Option<Vec<f32>> {
+    // these are only composited on the CPU, custom shaders run on its result
+    if edit_state.paint_strokes.iter().any(|s| !s.is_empty())
+        || !edit_state.layers.is_empty()
+        || !edit_state.annotations.is_empty()
+        || !edit_state.redactions.is_empty()
+        || !edit_state.shaders().is_empty()
+    {
+        return None;
+    }
+    let mut ops = vec![];
+    for item in edit_state.pixel_op_stack.iter().filter(|i| i.active) {
+        if item.mask.is_some() || item.blend_mode != BlendMode::Normal {
+            return None;
+        }
+        ops.push(item.operation.clone());
+    }
+    encode_ops(&ops)
+}
+
+fn vertex_info() -> VertexInfo {
+    VertexInfo::new()
+        .attr(0, VertexFormat::Float32x2)
+        .attr(1, VertexFormat::Float32x2)
+}
+
+/// Vertex and index buffer of a quad covering the whole target
+fn quad_buffers(gfx: &mut Graphics) -> Result<(Buffer, Buffer)> {
+    #[rustfmt::skip]
+    let vertices: [f32; 16] = [
+        -1.0, -1.0, 0.0, 0.0,
+        1.0, -1.0, 1.0, 0.0,
+        1.0, 1.0, 1.0, 1.0,
+        -1.0, 1.0, 0.0, 1.0,
+    ];
+    let vertex_buffer = gfx
+        .create_vertex_buffer()
+        .with_info(&vertex_info())
+        .with_data(&vertices)
+        .build()
+        .map_err(|e| anyhow!(e))?;
+
+    let index_buffer = gfx
+        .create_index_buffer()
+        .with_data(&[0_u32, 1, 2, 0, 2, 3])
+        .build()
+        .map_err(|e| anyhow!(e))?;
+    Ok((vertex_buffer, index_buffer))
+}
+
+/// GPU resources to render the pixel stack
+pub struct GpuEditor {
+    pipeline: Pipeline,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    uniform_buffer: Buffer,
+    /// The input of the pixel stack
+    source: Option<Texture>,
+    target: Option<RenderTexture>,
+}
+
+impl GpuEditor {
+    pub fn new(gfx: &mut Graphics) -> Result<Self> {
+        let pipeline = gfx
+            .create_pipeline()
+            .from(&VERT, &FRAG)
+            .with_vertex_info(&vertex_info())
+            .with_texture_location(0, "u_texture")
+            .build()
+            .map_err(|e| anyhow!(e))?;
+
+        let (vertex_buffer, index_buffer) = quad_buffers(gfx)?;
+
+        let uniform_buffer = gfx
+            .create_uniform_buffer(1, "Program")
+            .with_data(&[0_f32; PROGRAM_LEN])
+            .build()
+            .map_err(|e| anyhow!(e))?;
+
+        Ok(Self {
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            uniform_buffer,
+            source: None,
+            target: None,
+        })
+    }
+
+    /// The input image changed and has to be uploaded again
+    pub fn invalidate_source(&mut self) {
+        self.source = None;
+    }
+
+    /// Run `program` on `img`, which is only uploaded if the source was invalidated
+    pub fn render(
+        &mut self,
+        gfx: &mut Graphics,
+        img: &RgbaImage,
+        program: &[f32],
+        linear_mag_filter: bool,
+    ) -> Result<Texture> {
+        let (width, height) = img.dimensions();
+
+        if self.source.is_none() {
+            self.source = Some(
+                gfx.create_texture()
+                    .from_bytes(img, width, height)
+                    .with_filter(TextureFilter::Nearest, TextureFilter::Nearest)
+                    .build()
+                    .map_err(|e| anyhow!(e))?,
+            );
+        }
+        let source = self
+            .source
+            .as_ref()
+            .ok_or_else(|| anyhow!("No source texture"))?;
+
+        let target_fits = self
+            .target
+            .as_ref()
+            .map(|t| t.width() as u32 == width && t.height() as u32 == height)
+            .unwrap_or_default();
+        if !target_fits {
+            self.target = Some(
+                gfx.create_render_texture(width, height)
+                    .with_filter(
+                        TextureFilter::Linear,
+                        if linear_mag_filter {
+                            TextureFilter::Linear
+                        } else {
+                            TextureFilter::Nearest
+                        },
+                    )
+                    .build()
+                    .map_err(|e| anyhow!(e))?,
+            );
+        }
+        let target = self
+            .target
+            .as_ref()
+            .ok_or_else(|| anyhow!("No render target"))?;
+
+        gfx.set_buffer_data(&self.uniform_buffer, program);
+
+        let mut renderer = gfx.create_renderer();
+        renderer.begin(Some(ClearOptions::color(Color::TRANSPARENT)));
+        renderer.set_size(width as _, height as _);
+        renderer.set_pipeline(&self.pipeline);
+        renderer.bind_texture(0, source);
+        renderer.bind_buffers(&[
+            &self.vertex_buffer,
+            &self.index_buffer,
+            &self.uniform_buffer,
+        ]);
+        renderer.draw(0, 6);
+        renderer.end();
+        gfx.render_to(target, &renderer);
+
+        Ok(target.texture().clone())
+    }
+}
+
+/// Custom shaders are compiled at runtime, so they are written for the OpenGL backend directly
+const RAW_VERT: &str = r#"#version 330
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_uv;
+
+out vec2 v_uv;
+
+void main() {
+    v_uv = a_uv;
+    gl_Position = vec4(a_pos, 0.0, 1.0);
+}
+"#;
+
+/// Declarations available to custom shaders, which only have to define `main()`.
+/// The inputs are named like on Shadertoy, so snippets from there work as well.
+pub const SHADER_HEADER: &str = r#"#version 330
+precision highp float;
+
+in vec2 v_uv;
+uniform sampler2D u_texture;
+layout(std140) uniform Inputs {
+    vec3 iResolution;
+    float iTime;
+    vec4 iMouse;
+    float iZoom;
+};
+layout(std140) uniform Params {
+    vec4 u_params[4];
+};
+out vec4 color;
+
+#define iChannel0 u_texture
+"#;
+
+/// Shadertoy shaders define `mainImage()` instead of `main()`
+const MAIN_IMAGE: &str = r#"
+void main() {
+    mainImage(color, v_uv * iResolution.xy);
+}
+"#;
+
+/// Floats in the `Inputs` uniform block, including std140 padding
+const INPUTS_LEN: usize = 12;
+
+/// The most parameters a shader can have. They are packed into `vec4 u_params[4]`.
+pub const MAX_SHADER_PARAMS: usize = 16;
+
+/// A built-in shader with its parameters, as name, min, max and default
+pub struct ShaderPreset {
+    pub name: &'static str,
+    pub fragment: &'static str,
+    pub params: &'static [(&'static str, i32, i32, i32)],
+}
+
+impl ShaderPreset {
+    pub fn params(&self) -> Vec<PluginParam> {
+        self.params
+            .iter()
+            .map(|(name, min, max, value)| PluginParam {
+                name: name.to_string(),
+                min: *min,
+                max: *max,
+                value: *value,
+            })
+            .collect()
+    }
+}
+
+pub const SHADER_PRESETS: &[ShaderPreset] = &[
+    ShaderPreset {
+        name: "Pixelate",
+        fragment: r#"void main() {
+    vec2 cell = max(size, 1.0) / iResolution.xy;
+    color = texture(u_texture, (floor(v_uv / cell) + 0.5) * cell);
+}"#,
+        params: &[("size", 1, 128, 8)],
+    },
+    ShaderPreset {
+        name: "CRT",
+        fragment: r#"void main() {
+    vec2 uv = v_uv * 2.0 - 1.0;
+    uv *= 1.0 + curvature / 400.0 * dot(uv.yx, uv.yx);
+    uv = uv * 0.5 + 0.5;
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+    color = texture(u_texture, uv);
+    float line = 0.5 + 0.5 * sin(uv.y * iResolution.y * 3.14159);
+    color.rgb *= mix(1.0, line, scanlines / 100.0);
+}"#,
+        params: &[("curvature", 0, 100, 20), ("scanlines", 0, 100, 50)],
+    },
+    ShaderPreset {
+        name: "Sharpen",
+        fragment: r#"void main() {
+    vec2 px = 1.0 / iResolution.xy;
+    vec4 c = texture(u_texture, v_uv);
+    vec3 blur = (texture(u_texture, v_uv + vec2(px.x, 0.0)).rgb
+        + texture(u_texture, v_uv - vec2(px.x, 0.0)).rgb
+        + texture(u_texture, v_uv + vec2(0.0, px.y)).rgb
+        + texture(u_texture, v_uv - vec2(0.0, px.y)).rgb) / 4.0;
+    color = vec4(clamp(c.rgb + (c.rgb - blur) * amount / 100.0, 0.0, 1.0), c.a);
+}"#,
+        params: &[("amount", 0, 500, 100)],
+    },
+    ShaderPreset {
+        name: "False color",
+        fragment: r#"void main() {
+    vec4 c = texture(u_texture, v_uv);
+    float t = dot(c.rgb, vec3(0.2126, 0.7152, 0.0722));
+    vec3 ramp = vec3(1.5 - abs(4.0 * t - 3.0), 1.5 - abs(4.0 * t - 2.0), 1.5 - abs(4.0 * t - 1.0));
+    color = vec4(mix(c.rgb, clamp(ramp, 0.0, 1.0), opacity / 100.0), c.a);
+}"#,
+        params: &[("opacity", 0, 100, 100)],
+    },
+    ShaderPreset {
+        name: "Zebra",
+        fragment: r#"void main() {
+    vec4 c = texture(u_texture, v_uv);
+    vec2 p = v_uv * iResolution.xy;
+    if (max(c.r, max(c.g, c.b)) * 255.0 >= threshold && mod(p.x + p.y, stripe * 2.0) < stripe) {
+        c.rgb = vec3(0.0);
+    }
+    color = c;
+}"#,
+        params: &[("threshold", 0, 255, 245), ("stripe", 1, 64, 8)],
+    },
+];
+
+/// The values of the uniforms custom shaders can read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShaderInputs {
+    /// Seconds since the app started
+    pub time: f32,
+    /// Cursor position on the image, in pixels
+    pub mouse: [f32; 2],
+    pub zoom: f32,
+}
+
+/// The shader a new `PixelShader` operator starts with
+pub const DEFAULT_SHADER: &str = r#"void main() {
+    color = texture(u_texture, v_uv);
+}"#;
+
+/// A user supplied fragment shader, which is run over the edited image
+pub struct ShaderState {
+    /// The source the pipeline was built from, without `SHADER_HEADER`
+    pub fragment: String,
+    /// Why `fragment` did not compile
+    pub error: Option<String>,
+    /// The complete source, including the header and parameter names
+    source: String,
+    pipeline: Option<Pipeline>,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    uniform_buffer: Buffer,
+    params_buffer: Buffer,
+    target: Option<RenderTexture>,
+}
+
+impl ShaderState {
+    pub fn new(gfx: &mut Graphics) -> Result<Self> {
+        let (vertex_buffer, index_buffer) = quad_buffers(gfx)?;
+        let uniform_buffer = gfx
+            .create_uniform_buffer(1, "Inputs")
+            .with_data(&[0_f32; INPUTS_LEN])
+            .build()
+            .map_err(|e| anyhow!(e))?;
+        let params_buffer = gfx
+            .create_uniform_buffer(2, "Params")
+            .with_data(&[0_f32; MAX_SHADER_PARAMS])
+            .build()
+            .map_err(|e| anyhow!(e))?;
+        Ok(Self {
+            fragment: Default::default(),
+            error: None,
+            source: Default::default(),
+            pipeline: None,
+            vertex_buffer,
+            index_buffer,
+            uniform_buffer,
+            params_buffer,
+            target: None,
+        })
+    }
+
+    /// Whether the result changes over time or with the cursor, so it has to be rendered every frame
+    pub fn is_animated(&self) -> bool {
+        self.fragment.contains("iTime") || self.fragment.contains("iMouse")
+    }
+
+    /// Build a new pipeline if `fragment` or the parameter names changed. Compile errors are kept in `error`.
+    pub fn compile(&mut self, gfx: &mut Graphics, fragment: &str, params: &[PluginParam]) {
+        let mut source = SHADER_HEADER.to_string();
+        // parameters are accessed by name
+        for (i, param) in params.iter().take(MAX_SHADER_PARAMS).enumerate() {
+            source.push_str(&format!(
+                "#define {} u_params[{}][{}]\n",
+                param.name,
+                i / 4,
+                i % 4
+            ));
+        }
+        source.push_str(fragment);
+        if fragment.contains("mainImage") {
+            source.push_str(MAIN_IMAGE);
+        }
+        if source == self.source && (self.pipeline.is_some() || self.error.is_some()) {
+            return;
+        }
+        self.fragment = fragment.to_string();
+        self.source = source;
+        match gfx
+            .create_pipeline()
+            .from_raw(RAW_VERT.as_bytes(), self.source.as_bytes())
+            .with_vertex_info(&vertex_info())
+            .with_texture_location(0, "u_texture")
+            .build()
+        {
+            Ok(pipeline) => {
+                self.pipeline = Some(pipeline);
+                self.error = None;
+            }
+            Err(e) => {
+                self.pipeline = None;
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Run the shader over `img` and read the result back
+    pub fn render(
+        &mut self,
+        gfx: &mut Graphics,
+        img: &RgbaImage,
+        inputs: ShaderInputs,
+        params: &[PluginParam],
+    ) -> Result<RgbaImage> {
+        let pipeline = self
+            .pipeline
+            .as_ref()
+            .ok_or_else(|| anyhow!("The shader is not compiled"))?;
+        let (width, height) = img.dimensions();
+
+        let source = gfx
+            .create_texture()
+            .from_bytes(img, width, height)
+            .with_filter(TextureFilter::Linear, TextureFilter::Linear)
+            .build()
+            .map_err(|e| anyhow!(e))?;
+
+        let target_fits = self
+            .target
+            .as_ref()
+            .map(|t| t.width() as u32 == width && t.height() as u32 == height)
+            .unwrap_or_default();
+        if !target_fits {
+            self.target = Some(
+                gfx.create_render_texture(width, height)
+                    .build()
+                    .map_err(|e| anyhow!(e))?,
+            );
+        }
+        let target = self
+            .target
+            .as_ref()
+            .ok_or_else(|| anyhow!("No render target"))?;
+
+        #[rustfmt::skip]
+        let uniforms: [f32; INPUTS_LEN] = [
+            width as f32, height as f32, 1., inputs.time,
+            inputs.mouse[0], inputs.mouse[1], 0., 0.,
+            inputs.zoom, 0., 0., 0.,
+        ];
+        gfx.set_buffer_data(&self.uniform_buffer, &uniforms);
+        let mut values = [0_f32; MAX_SHADER_PARAMS];
+        for (v, param) in values.iter_mut().zip(params) {
+            *v = param.value as f32;
+        }
+        gfx.set_buffer_data(&self.params_buffer, &values);
+
+        let mut renderer = gfx.create_renderer();
+        renderer.begin(Some(ClearOptions::color(Color::TRANSPARENT)));
+        renderer.set_size(width as _, height as _);
+        renderer.set_pipeline(pipeline);
+        renderer.bind_texture(0, &source);
+        renderer.bind_buffers(&[
+            &self.vertex_buffer,
+            &self.index_buffer,
+            &self.uniform_buffer,
+            &self.params_buffer,
+        ]);
+        renderer.draw(0, 6);
+        renderer.end();
+        gfx.render_to(target, &renderer);
+
+        let mut result = RgbaImage::new(width, height);
+        gfx.read_pixels(target.texture())
+            .read_to(&mut result)
+            .map_err(|e| anyhow!(e))?;
+        Ok(result)
+    }
+}
@@ -0,0 +1,71 @@
+//! A grid drawn between the pixels of the image at high zoom, so pixels can be counted
+
+use std::ops::Range;
+
+use notan::draw::{Draw, DrawShapes};
+use notan::prelude::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::appstate::ImageGeometry;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PixelGrid {
+    pub enabled: bool,
+    /// The grid is drawn from this zoom on, where pixels are big enough to tell apart
+    pub min_zoom: f32,
+    /// Draw a stronger line every this many pixels, 0 for none
+    pub major_every: u32,
+    pub color: [u8; 4],
+}
+
+impl Default for PixelGrid {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_zoom: 8.,
+            major_every: 8,
+            color: [128, 128, 128, 96],
+        }
+    }
+}
+
+/// The lines between pixels in one direction that are in view: for an image `size` pixels
+/// long, drawn from `offset` at `scale` in a window `window` long
+pub fn visible_lines(offset: f32, scale: f32, size: u32, window: f32) -> Range<u32> {
+    let first = (-offset / scale).ceil().clamp(0., size as f32) as u32;
+    let last = ((window - offset) / scale).floor().clamp(0., size as f32) as u32;
+    first..last.max(first) + 1
+}
+
+impl PixelGrid {
+    /// Draw the grid over the image, if it is zoomed in far enough
+    pub fn draw(&self, draw: &mut Draw, geometry: &ImageGeometry, window: (f32, f32)) {
+        if !self.enabled || geometry.scale < self.min_zoom {
+            return;
+        }
+        let [r, g, b, a] = self.color.map(|c| c as f32 / 255.);
+        let minor = Color::new(r, g, b, a);
+        let major = Color::new(r, g, b, (a * 2.).min(1.));
+        let offset = geometry.offset;
+        let (width, height) = geometry.dimensions;
+        let top = offset.y.max(0.);
+        let bottom = (offset.y + height as f32 * geometry.scale).min(window.1);
+        let left = offset.x.max(0.);
+        let right = (offset.x + width as f32 * geometry.scale).min(window.0);
+        let color = |i: u32| match self.major_every {
+            0 => minor,
+            n if i % n == 0 => major,
+            _ => minor,
+        };
+
+        for i in visible_lines(offset.x, geometry.scale, width, window.0) {
+            let x = offset.x + i as f32 * geometry.scale;
+            draw.line((x, top), (x, bottom)).width(1.).color(color(i));
+        }
+        for i in visible_lines(offset.y, geometry.scale, height, window.1) {
+            let y = offset.y + i as f32 * geometry.scale;
+            draw.line((left, y), (right, y)).width(1.).color(color(i));
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Properties of HEIF and AVIF images that are stored in the container instead of the coded
+//! image: the rotation, mirroring and color profile of the primary item, and the timing of image
+//! sequences. Sequences are decoded by `ffmpeg`, which needs to be installed.
+
+use std::convert::TryInto;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+use log::debug;
+
+use crate::icc::ColorProfile;
+use crate::utils::{Frame, FrameSource};
+
+/// The boxes in `data`, as their type and content
+fn children(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = vec![];
+    while data.len() >= 8 {
+        let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let kind = [data[4], data[5], data[6], data[7]];
+        let (header, size) = match size {
+            // the box extends to the end
+            0 => (8, data.len()),
+            1 => match data.get(8..16) {
+                Some(large) => (16, u64::from_be_bytes(large.try_into().unwrap()) as usize),
+                None => break,
+            },
+            size => (8, size),
+        };
+        if size < header || size > data.len() {
+            break;
+        }
+        boxes.push((kind, &data[header..size]));
+        data = &data[size..];
+    }
+    boxes
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    children(data)
+        .into_iter()
+        .find(|(k, _)| k == kind)
+        .map(|(_, content)| content)
+}
+
+/// Read a big endian number of `len` bytes and advance `data`
+fn take(data: &mut &[u8], len: usize) -> Option<u32> {
+    let bytes = data.get(..len)?;
+    *data = &data[len..];
+    Some(bytes.iter().fold(0, |v, b| v << 8 | *b as u32))
+}
+
+/// The properties of the primary image, in the order they apply
+fn properties(data: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    // meta is a full box, the version and flags come before the children
+    let meta = child(data, b"meta")?.get(4..)?;
+    let iprp = child(meta, b"iprp")?;
+    let ipco = children(child(iprp, b"ipco")?);
+
+    let mut pitm = child(meta, b"pitm")?;
+    let version = take(&mut pitm, 4)? >> 24;
+    let primary = take(&mut pitm, if version == 0 { 2 } else { 4 })?;
+
+    let mut ipma = child(iprp, b"ipma")?;
+    let version_flags = take(&mut ipma, 4)?;
+    let id_len = if version_flags >> 24 == 0 { 2 } else { 4 };
+    let index_len = if version_flags & 1 == 1 { 2 } else { 1 };
+    for _ in 0..take(&mut ipma, 4)? {
+        let id = take(&mut ipma, id_len)?;
+        let count = take(&mut ipma, 1)?;
+        let mut indices = vec![];
+        for _ in 0..count {
+            // the highest bit marks essential properties
+            let index = take(&mut ipma, index_len)? & !(1 << (index_len * 8 - 1));
+            indices.push(index as usize);
+        }
+        if id == primary {
+            // indices start at 1, 0 means no property
+            return Some(
+                indices
+                    .into_iter()
+                    .filter_map(|i| ipco.get(i.checked_sub(1)?).copied())
+                    .collect(),
+            );
+        }
+    }
+    None
+}
+
+/// Apply the rotation (`irot`) and mirroring (`imir`) of the primary image to the decoded pixels,
+/// for decoders that don't do it themselves
+pub fn orient(data: &[u8], img: &mut RgbaImage) {
+    for (kind, content) in properties(data).unwrap_or_default() {
+        let Some(value) = content.first() else {
+            continue;
+        };
+        match &kind {
+            // counter-clockwise, in quarter turns
+            b"irot" => match value & 3 {
+                1 => *img = image::imageops::rotate270(img),
+                2 => *img = image::imageops::rotate180(img),
+                3 => *img = image::imageops::rotate90(img),
+                _ => (),
+            },
+            // mirrored along the vertical or horizontal axis
+            b"imir" => match value & 1 {
+                0 => *img = image::imageops::flip_horizontal(img),
+                _ => *img = image::imageops::flip_vertical(img),
+            },
+            _ => (),
+        }
+    }
+}
+
+/// The embedded ICC profile of the primary image
+pub fn icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    properties(data)?
+        .into_iter()
+        .filter(|(kind, _)| kind == b"colr")
+        .find_map(|(_, content)| match content.get(..4)? {
+            b"prof" | b"rICC" => Some(content[4..].to_vec()),
+            _ => None,
+        })
+}
+
+/// Convert the decoded pixels from the embedded ICC profile of the primary image to sRGB, which
+/// is what they are displayed as
+pub fn to_srgb(data: &[u8], img: &mut RgbaImage) {
+    if let Some(profile) = icc_profile(data) {
+        if let Err(e) = crate::icc::convert(img, Some(&profile), ColorProfile::Srgb) {
+            debug!("{e}");
+        }
+    }
+}
+
+/// The largest frame of a sequence, in bytes
+const MAX_FRAME_SIZE: u64 = 512 * 1024 * 1024;
+
+/// The track of an image sequence (`avis` or `heics`), which is played like an animation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sequence {
+    pub width: u32,
+    pub height: u32,
+    /// The delay of every frame, in milliseconds
+    pub delays: Vec<u16>,
+}
+
+impl Sequence {
+    /// The sequence in a file, if it has one with more than a single frame
+    pub fn read(data: &[u8]) -> Option<Self> {
+        // the major brand, the minor version, then the compatible brands
+        let ftyp = child(data, b"ftyp")?;
+        let sequence_brands: [&[u8]; 4] = [b"avis", b"msf1", b"hevc", b"hevx"];
+        if !ftyp
+            .chunks_exact(4)
+            .enumerate()
+            .any(|(i, brand)| i != 1 && sequence_brands.contains(&brand))
+        {
+            return None;
+        }
+
+        let moov = child(data, b"moov")?;
+        children(moov)
+            .into_iter()
+            .filter(|(kind, _)| kind == b"trak")
+            .find_map(|(_, trak)| Self::from_track(trak))
+            .filter(|sequence| sequence.delays.len() > 1)
+    }
+
+    fn from_track(trak: &[u8]) -> Option<Self> {
+        let mdia = child(trak, b"mdia")?;
+        // pictures, or video for files made by video tools
+        let handler = child(mdia, b"hdlr")?.get(8..12)?;
+        if handler != b"pict" && handler != b"vide" {
+            return None;
+        }
+
+        let mut mdhd = child(mdia, b"mdhd")?;
+        let version = take(&mut mdhd, 4)? >> 24;
+        take(&mut mdhd, if version == 1 { 16 } else { 8 })?;
+        let timescale = take(&mut mdhd, 4)?.max(1);
+
+        let stbl = child(child(mdia, b"minf")?, b"stbl")?;
+        // the first sample entry, after the version, flags and entry count
+        let (_, entry) = *children(child(stbl, b"stsd")?.get(8..)?).first()?;
+        let mut size = entry.get(24..28)?;
+        let width = take(&mut size, 2)?;
+        let height = take(&mut size, 2)?;
+
+        // the number of frames, after the version, flags and sample size
+        let mut stsz = child(stbl, b"stsz")?;
+        take(&mut stsz, 8)?;
+        let sample_count = take(&mut stsz, 4)? as usize;
+
+        let mut stts = child(stbl, b"stts")?;
+        take(&mut stts, 4)?;
+        let mut delays = vec![];
+        for _ in 0..take(&mut stts, 4)? {
+            let count = take(&mut stts, 4)? as usize;
+            let delta = take(&mut stts, 4)?;
+            let delay = (delta as u64 * 1000 / timescale as u64).min(u16::MAX as u64) as u16;
+            // the counts can't add up to more frames than there are
+            let count = count.min(sample_count - delays.len());
+            delays.extend(std::iter::repeat(delay).take(count));
+        }
+        Some(Self {
+            width,
+            height,
+            delays,
+        })
+    }
+
+    /// Decode the frames in the background and send them as an animation. Fails if `ffmpeg`
+    /// can't be started, so the primary image can be shown instead.
+    pub fn play(&self, path: &Path, sender: Sender<Frame>) -> Result<()> {
+        let frame_size = self.width as u64 * self.height as u64 * 4;
+        if frame_size == 0 {
+            bail!("The frames of the sequence have no size");
+        }
+        if frame_size > MAX_FRAME_SIZE {
+            bail!("The frames of the sequence are too large");
+        }
+        // rotation is ignored, so the frames have the size of the track. The alpha of AVIF
+        // sequences is a separate track, which is not decoded.
+        let mut child = Command::new("ffmpeg")
+            .args(&["-v", "error", "-noautorotate", "-i"])
+            .arg(path)
+            .arg("-s")
+            .arg(format!("{}x{}", self.width, self.height))
+            .args(&["-f", "rawvideo", "-pix_fmt", "rgba", "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Could not run ffmpeg, is it installed?")?;
+        let mut stdout = child.stdout.take().context("No output from ffmpeg")?;
+        let sequence = self.clone();
+        std::thread::spawn(move || {
+            for delay in &sequence.delays {
+                let mut pixels = vec![0; frame_size as usize];
+                if stdout.read_exact(&mut pixels).is_err() {
+                    break;
+                }
+                let Some(buffer) = RgbaImage::from_raw(sequence.width, sequence.height, pixels)
+                else {
+                    break;
+                };
+                if sender
+                    .send(Frame::new(buffer, *delay, FrameSource::Animation))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            debug!("Done decoding image sequence");
+            _ = child.kill();
+            _ = child.wait();
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! The histogram of the image on screen, including the edits, to judge exposure while editing
+
+use image::RgbaImage;
+use rayon::prelude::*;
+
+/// The pixel counts of each 8 bit value, per channel. Fully transparent pixels are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub red: [u64; 256],
+    pub green: [u64; 256],
+    pub blue: [u64; 256],
+    /// Rec. 709 luminance of the sRGB values
+    pub luma: [u64; 256],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            red: [0; 256],
+            green: [0; 256],
+            blue: [0; 256],
+            luma: [0; 256],
+        }
+    }
+}
+
+impl Histogram {
+    pub fn from_image(img: &RgbaImage) -> Self {
+        img.as_raw()
+            .par_chunks(4 * 4096)
+            .fold(Histogram::default, |mut hist, pixels| {
+                for p in pixels.chunks_exact(4) {
+                    if p[3] == 0 {
+                        continue;
+                    }
+                    hist.red[p[0] as usize] += 1;
+                    hist.green[p[1] as usize] += 1;
+                    hist.blue[p[2] as usize] += 1;
+                    hist.luma[luma(p[0], p[1], p[2]) as usize] += 1;
+                }
+                hist
+            })
+            .reduce(Histogram::default, |mut a, b| {
+                for (a, b) in [
+                    (&mut a.red, &b.red),
+                    (&mut a.green, &b.green),
+                    (&mut a.blue, &b.blue),
+                    (&mut a.luma, &b.luma),
+                ] {
+                    for (a, b) in a.iter_mut().zip(b) {
+                        *a += b;
+                    }
+                }
+                a
+            })
+    }
+
+    /// The channels with their names, in the order they are drawn
+    pub fn channels(&self) -> [(&'static str, &[u64; 256]); 4] {
+        [
+            ("Luminance", &self.luma),
+            ("Red", &self.red),
+            ("Green", &self.green),
+            ("Blue", &self.blue),
+        ]
+    }
+}
+
+pub fn luma(r: u8, g: u8, b: u8) -> u8 {
+    (0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32).round() as u8
+}
+
+/// The height of a bar, on a log scale to make small counts next to a peak visible
+pub fn height(count: u64, log: bool) -> f64 {
+    if log {
+        (count as f64).ln_1p()
+    } else {
+        count as f64
+    }
+}
@@ -0,0 +1,108 @@
+//! Watching a folder and showing the newest image in it as files appear, for tethered shooting
+//! or renders dropped into a folder. Files are only shown once their size stopped changing, so
+//! images still being written are not opened half done.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::debug;
+
+use crate::scrubber::get_image_filenames_for_directory;
+
+/// How often the folder is listed
+const INTERVAL: Duration = Duration::from_millis(500);
+
+/// A folder listed in a thread, until this is dropped
+#[derive(Debug)]
+pub struct HotFolder {
+    pub folder: PathBuf,
+    stop: Arc<AtomicBool>,
+}
+
+impl HotFolder {
+    /// Watch `folder`, starting with the newest image already in it. New images are sent to
+    /// `load_sender`.
+    pub fn start(folder: &Path, load_sender: Sender<PathBuf>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut scanner = FolderScanner::new(folder);
+        let thread_stop = stop.clone();
+        std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                if let Some(newest) = scanner.check() {
+                    debug!("New image in the watched folder: {}", newest.display());
+                    if load_sender.send(newest).is_err() {
+                        return;
+                    }
+                }
+                std::thread::sleep(INTERVAL);
+            }
+        });
+        Self {
+            folder: folder.to_path_buf(),
+            stop,
+        }
+    }
+}
+
+impl Drop for HotFolder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Finds the images that appear in a folder
+#[derive(Debug)]
+pub struct FolderScanner {
+    folder: PathBuf,
+    /// The time the newest image shown was modified
+    shown: SystemTime,
+    /// The newest image and its size, waiting for its size to stay the same
+    pending: Option<(PathBuf, u64)>,
+}
+
+impl FolderScanner {
+    pub fn new(folder: &Path) -> Self {
+        Self {
+            folder: folder.to_path_buf(),
+            shown: SystemTime::UNIX_EPOCH,
+            pending: None,
+        }
+    }
+
+    /// List the folder. Returns the image to show, when a new one finished writing.
+    pub fn check(&mut self) -> Option<PathBuf> {
+        let (path, modified, size) = newest(&self.folder)?;
+        if modified <= self.shown {
+            self.pending = None;
+            return None;
+        }
+        match &self.pending {
+            Some((pending, pending_size))
+                if *pending == path && *pending_size == size && size > 0 =>
+            {
+                self.shown = modified;
+                self.pending = None;
+                Some(path)
+            }
+            _ => {
+                self.pending = Some((path, size));
+                None
+            }
+        }
+    }
+}
+
+/// The image in `folder` modified last, with its modification time and size
+fn newest(folder: &Path) -> Option<(PathBuf, SystemTime, u64)> {
+    get_image_filenames_for_directory(folder)
+        .ok()?
+        .into_iter()
+        .filter_map(|p| {
+            let meta = p.metadata().ok()?;
+            Some((p, meta.modified().ok()?, meta.len()))
+        })
+        .max_by_key(|(_, modified, _)| *modified)
+}
@@ -0,0 +1,218 @@
+//! Minimal ICC profiles for common RGB color spaces, so they can be embedded in saved images
+//! without shipping profile files. They are ICC v2 matrix/TRC display profiles. Converting pixels
+//! between profiles is done by `moxcms`.
+
+use anyhow::{anyhow, Result};
+use image::RgbaImage;
+use moxcms::{Layout, TransformOptions};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorProfile {
+    /// Keep the profile of the source image, if it has one
+    Original,
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    /// Don't embed a profile
+    None,
+}
+
+impl ColorProfile {
+    pub const ALL: [Self; 5] = [
+        Self::Original,
+        Self::Srgb,
+        Self::DisplayP3,
+        Self::AdobeRgb,
+        Self::None,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Original => "Original",
+            Self::Srgb => "sRGB",
+            Self::DisplayP3 => "Display P3",
+            Self::AdobeRgb => "Adobe RGB",
+            Self::None => "None",
+        }
+    }
+
+    /// The profile data of the built-in color spaces
+    pub fn data(&self) -> Option<Vec<u8>> {
+        // primaries adapted to the D50 white of the profile connection space
+        match self {
+            Self::Srgb => Some(matrix_profile(
+                "sRGB",
+                [
+                    [0.4360747, 0.2225045, 0.0139322],
+                    [0.3850649, 0.7168786, 0.0971045],
+                    [0.1430804, 0.0606169, 0.7141733],
+                ],
+                Curve::Srgb,
+            )),
+            Self::DisplayP3 => Some(matrix_profile(
+                "Display P3",
+                [
+                    [0.5151, 0.2412, -0.0011],
+                    [0.2920, 0.6922, 0.0419],
+                    [0.1571, 0.0666, 0.7841],
+                ],
+                Curve::Srgb,
+            )),
+            Self::AdobeRgb => Some(matrix_profile(
+                "Adobe RGB (1998) compatible",
+                [
+                    [0.6097559, 0.3111242, 0.0194811],
+                    [0.2052401, 0.625656, 0.0608902],
+                    [0.149224, 0.0632197, 0.7448387],
+                ],
+                Curve::Gamma(563. / 256.),
+            )),
+            Self::Original | Self::None => None,
+        }
+    }
+
+    /// The built-in color space for converting pixels, `None` if this doesn't name one
+    fn cms_profile(&self) -> Option<moxcms::ColorProfile> {
+        match self {
+            Self::Srgb => Some(moxcms::ColorProfile::new_srgb()),
+            Self::DisplayP3 => Some(moxcms::ColorProfile::new_display_p3()),
+            Self::AdobeRgb => Some(moxcms::ColorProfile::new_adobe_rgb()),
+            Self::Original | Self::None => None,
+        }
+    }
+}
+
+/// Convert the pixels of `img` from the color space of the ICC `source` profile, or sRGB if there
+/// is none, to `target`. Does nothing if `target` isn't a built-in color space or is the source.
+pub fn convert(img: &mut RgbaImage, source: Option<&[u8]>, target: ColorProfile) -> Result<()> {
+    let Some(target_profile) = target.cms_profile() else {
+        return Ok(());
+    };
+    let source_profile = match source {
+        Some(data) if Some(data) == target.data().as_deref() => return Ok(()),
+        Some(data) => moxcms::ColorProfile::new_from_slice(data)
+            .map_err(|e| anyhow!("Can't read the color profile: {e}"))?,
+        None if target == ColorProfile::Srgb => return Ok(()),
+        None => moxcms::ColorProfile::new_srgb(),
+    };
+    let transform = source_profile
+        .create_transform_8bit(
+            Layout::Rgba,
+            &target_profile,
+            Layout::Rgba,
+            TransformOptions::default(),
+        )
+        .map_err(|e| anyhow!("Can't convert the color profile: {e}"))?;
+    let src = img.as_raw().clone();
+    transform
+        .transform(&src, img)
+        .map_err(|e| anyhow!("Can't convert the color profile: {e}"))
+}
+
+enum Curve {
+    /// The piecewise sRGB transfer function, also used by Display P3
+    Srgb,
+    Gamma(f32),
+}
+
+const D50: [f32; 3] = [0.9642, 1.0, 0.8249];
+
+fn s15_fixed16(v: f32) -> [u8; 4] {
+    ((v * 65536.).round() as i32).to_be_bytes()
+}
+
+fn xyz_tag(xyz: [f32; 3]) -> Vec<u8> {
+    let mut tag = b"XYZ \0\0\0\0".to_vec();
+    for v in xyz {
+        tag.extend(s15_fixed16(v));
+    }
+    tag
+}
+
+fn curve_tag(curve: &Curve) -> Vec<u8> {
+    let mut tag = b"curv\0\0\0\0".to_vec();
+    match curve {
+        Curve::Gamma(gamma) => {
+            tag.extend(1_u32.to_be_bytes());
+            // u8Fixed8Number
+            tag.extend(((gamma * 256.).round() as u16).to_be_bytes());
+        }
+        Curve::Srgb => {
+            let count = 1024;
+            tag.extend((count as u32).to_be_bytes());
+            for i in 0..count {
+                let v = i as f32 / (count - 1) as f32;
+                let linear = if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                };
+                tag.extend(((linear * 65535.).round() as u16).to_be_bytes());
+            }
+        }
+    }
+    tag
+}
+
+fn text_description_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"desc\0\0\0\0".to_vec();
+    tag.extend((text.len() as u32 + 1).to_be_bytes());
+    tag.extend(text.as_bytes());
+    tag.push(0);
+    // no unicode and script code descriptions
+    tag.extend([0; 4 + 4 + 2 + 1 + 67]);
+    tag
+}
+
+fn text_tag(text: &str) -> Vec<u8> {
+    let mut tag = b"text\0\0\0\0".to_vec();
+    tag.extend(text.as_bytes());
+    tag.push(0);
+    tag
+}
+
+/// A display profile with the XYZ of the red, green and blue primaries and their tone curve
+fn matrix_profile(description: &str, primaries: [[f32; 3]; 3], curve: Curve) -> Vec<u8> {
+    let trc = curve_tag(&curve);
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", text_description_tag(description)),
+        (b"cprt", text_tag("No copyright, use freely")),
+        (b"wtpt", xyz_tag(D50)),
+        (b"rXYZ", xyz_tag(primaries[0])),
+        (b"gXYZ", xyz_tag(primaries[1])),
+        (b"bXYZ", xyz_tag(primaries[2])),
+        (b"rTRC", trc.clone()),
+        (b"gTRC", trc.clone()),
+        (b"bTRC", trc),
+    ];
+
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data = vec![];
+    let data_start = 128 + 4 + tags.len() * 12;
+    for (signature, tag) in &tags {
+        // tag data is aligned to 4 bytes
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        table.extend(*signature);
+        table.extend(((data_start + data.len()) as u32).to_be_bytes());
+        table.extend((tag.len() as u32).to_be_bytes());
+        data.extend(tag);
+    }
+
+    let mut header = vec![0; 128];
+    let size = (128 + table.len() + data.len()) as u32;
+    header[0..4].copy_from_slice(&size.to_be_bytes());
+    // version 2.1
+    header[8..12].copy_from_slice(&[2, 0x10, 0, 0]);
+    header[12..16].copy_from_slice(b"mntr");
+    header[16..20].copy_from_slice(b"RGB ");
+    header[20..24].copy_from_slice(b"XYZ ");
+    header[36..40].copy_from_slice(b"acsp");
+    for (i, v) in D50.iter().enumerate() {
+        header[68 + i * 4..72 + i * 4].copy_from_slice(&s15_fixed16(*v));
+    }
+
+    [header, table, data].concat()
+}
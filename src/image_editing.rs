@@ -1,18 +1,33 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::num::NonZeroU32;
-use std::path::Path;
-
-use crate::paint::PaintStroke;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, OnceLock};
+
+use crate::annotation::Annotation;
+use crate::appstate::Message;
+use crate::convert::output_paths;
+use crate::export::ExportSettings;
+use crate::gpu;
+use crate::image_loader::open_image;
+use crate::layers::{BlendMode, Layer};
+use crate::mask::OpMask;
+use crate::paint::{Brush, FloodFill, PaintStroke, PaintTool};
+use crate::plugins::{self, PluginParam};
+use crate::redaction::Redaction;
+use crate::selection::SelectionTool;
 use crate::ui::EguiExt;
+use crate::utils::exif_orientation;
 #[cfg(not(feature = "file_open"))]
 use crate::{filebrowser, SUPPORTED_EXTENSIONS};
 use crate::{pos_from_coord, ImageGeometry};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use evalexpr::*;
 use fast_image_resize as fr;
-use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use image::{imageops, DynamicImage, GrayImage, Rgba, Rgba32FImage, RgbaImage};
 use imageproc::geometric_transformations::Interpolation;
 use log::{debug, error};
 use nalgebra::{Vector2, Vector4};
@@ -21,8 +36,12 @@ use notan::egui::{self, lerp, vec2, Color32, DragValue, Id, Pos2, Rect, Sense, S
 use notan::egui::{Response, Ui};
 use palette::{rgb::Rgb, Hsl, IntoColor};
 use rand::{thread_rng, Rng};
-use rayon::{iter::ParallelIterator, slice::ParallelSliceMut};
-use serde::{Deserialize, Serialize};
+use rayon::{
+    iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
+    slice::{ParallelSlice, ParallelSliceMut},
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use wide::f32x8;
 
 use egui_phosphor::variants::regular::*;
 
@@ -34,6 +53,9 @@ pub struct EditState {
     #[serde(skip)]
     /// The image after all non-per-pixel operations completed (expensive, so only updated if changed)
     pub result_image_op: RgbaImage,
+    #[serde(skip)]
+    /// Unclamped version of `result_image_op` for high dynamic range sources
+    pub result_image_op_f32: Option<Rgba32FImage>,
     pub painting: bool,
     #[serde(skip)]
     pub block_panning: bool,
@@ -41,10 +63,56 @@ pub struct EditState {
     pub paint_strokes: Vec<PaintStroke>,
     pub paint_fade: bool,
     #[serde(skip, default = "default_brushes")]
-    pub brushes: Vec<RgbaImage>,
-    pub pixel_op_stack: Vec<ImageOperation>,
-    pub image_op_stack: Vec<ImageOperation>,
-    pub export_extension: String,
+    pub brushes: Vec<Brush>,
+    pub pixel_op_stack: Vec<ImgOpItem>,
+    pub image_op_stack: Vec<ImgOpItem>,
+    /// Images composited on top of the edited photo, bottom first
+    #[serde(default)]
+    pub layers: Vec<Layer>,
+    #[serde(skip)]
+    pub history: EditHistory,
+    /// The displayed texture is a GPU preview and the CPU result is outdated
+    #[serde(skip)]
+    pub gpu_preview: bool,
+    /// Position of the before/after split line from 0-1, if shown
+    #[serde(skip)]
+    pub split_compare: Option<f32>,
+    /// Intermediate results, so only operators that changed need to run again
+    #[serde(skip)]
+    pub caches: EditCaches,
+    /// The results are a downscaled preview by this factor, while an operator is dragged
+    #[serde(skip)]
+    pub proxy_scale: Option<f32>,
+    /// The result before custom shaders ran, so animated ones can be rendered again
+    #[serde(skip)]
+    pub shader_input: Option<RgbaImage>,
+    /// Shapes drawn on top of the image
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+    /// Areas which are pixelated or filled on top of everything else
+    #[serde(default)]
+    pub redactions: Vec<Redaction>,
+    /// Operators added while something is selected are limited to it
+    #[serde(default)]
+    pub selection: Option<OpMask>,
+    /// Dragging on the image makes a new selection with this tool
+    #[serde(skip)]
+    pub selection_tool: Option<SelectionTool>,
+    /// Where the selection being dragged started
+    #[serde(skip)]
+    pub selection_anchor: Option<(f32, f32)>,
+    /// Settings of the magic wand selection
+    #[serde(skip)]
+    pub wand: FloodFill,
+    /// What clicking in paint mode does
+    #[serde(skip)]
+    pub paint_tool: PaintTool,
+    /// Settings of the bucket fill tool
+    #[serde(skip)]
+    pub fill: FloodFill,
+    /// The last annotation or redaction is being drawn and follows the cursor
+    #[serde(skip)]
+    pub drawing_annotation: bool,
 }
 
 impl Default for EditState {
@@ -52,6 +120,7 @@ impl Default for EditState {
         Self {
             result_pixel_op: RgbaImage::default(),
             result_image_op: RgbaImage::default(),
+            result_image_op_f32: None,
             painting: Default::default(),
             block_panning: false,
             non_destructive_painting: Default::default(),
@@ -60,29 +129,847 @@ impl Default for EditState {
             brushes: default_brushes(),
             pixel_op_stack: vec![],
             image_op_stack: vec![],
-            export_extension: "png".into(),
+            layers: vec![],
+            history: Default::default(),
+            gpu_preview: false,
+            split_compare: None,
+            caches: Default::default(),
+            proxy_scale: None,
+            shader_input: None,
+            annotations: vec![],
+            redactions: vec![],
+            selection: None,
+            selection_tool: None,
+            selection_anchor: None,
+            wand: FloodFill::default(),
+            paint_tool: PaintTool::Brush,
+            fill: FloodFill::default(),
+            drawing_annotation: false,
+        }
+    }
+}
+
+/// An entry of the edit stack
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ImgOpItemRepr")]
+pub struct ImgOpItem {
+    /// Inactive operators are skipped, but keep their values
+    pub active: bool,
+    pub operation: ImageOperation,
+    /// Limits the operator to a part of the image
+    pub mask: Option<OpMask>,
+    /// How the result is combined with the input of the operator
+    pub blend_mode: BlendMode,
+}
+
+impl ImgOpItem {
+    pub fn new(operation: ImageOperation) -> Self {
+        Self {
+            active: true,
+            operation,
+            mask: None,
+            blend_mode: BlendMode::Normal,
+        }
+    }
+
+    /// Run an image operator, blending with the original where the mask has no coverage
+    pub fn process_image(&self, img: &mut RgbaImage) -> Result<()> {
+        let Some(mask) = &self.mask else {
+            return self.operation.process_image(img);
+        };
+        let original = img.clone();
+        self.operation.process_image(img)?;
+        // Operators that change the geometry can't be masked meaningfully
+        if img.dimensions() != original.dimensions() {
+            return Ok(());
+        }
+        let coverage = mask.render(img.dimensions());
+        img.par_chunks_mut(4)
+            .zip(original.par_chunks(4))
+            .zip(coverage.as_raw().par_iter())
+            .for_each(|((px, orig), c)| {
+                let c = *c as f32 / 255.;
+                for (p, o) in px.iter_mut().zip(orig) {
+                    *p = lerp(*o as f32..=*p as f32, c) as u8;
+                }
+            });
+        Ok(())
+    }
+
+    /// Like `process_image`, on an unclamped float buffer
+    pub fn process_image_f32(&self, img: &mut Rgba32FImage) -> Result<()> {
+        let Some(mask) = &self.mask else {
+            return self.operation.process_image_f32(img);
+        };
+        let original = img.clone();
+        self.operation.process_image_f32(img)?;
+        if img.dimensions() != original.dimensions() {
+            return Ok(());
+        }
+        let coverage = mask.render(img.dimensions());
+        img.par_chunks_mut(4)
+            .zip(original.par_chunks(4))
+            .zip(coverage.as_raw().par_iter())
+            .for_each(|((px, orig), c)| {
+                let c = *c as f32 / 255.;
+                for (p, o) in px.iter_mut().zip(orig) {
+                    *p = lerp(*o..=*p, c);
+                }
+            });
+        Ok(())
+    }
+}
+
+/// Stacks used to be saved as plain operators, so accept both
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImgOpItemRepr {
+    Item {
+        active: bool,
+        operation: ImageOperation,
+        #[serde(default)]
+        mask: Option<OpMask>,
+        #[serde(default)]
+        blend_mode: BlendMode,
+    },
+    Legacy(ImageOperation),
+}
+
+impl From<ImgOpItemRepr> for ImgOpItem {
+    fn from(repr: ImgOpItemRepr) -> Self {
+        match repr {
+            ImgOpItemRepr::Item {
+                active,
+                operation,
+                mask,
+                blend_mode,
+            } => Self {
+                active,
+                operation,
+                mask,
+                blend_mode,
+            },
+            ImgOpItemRepr::Legacy(operation) => Self::new(operation),
+        }
+    }
+}
+
+/// Where the editor keeps the compile error of a shader, to show it next to the source
+pub fn shader_error_id(fragment: &str) -> Id {
+    Id::new("shader_error").with(fragment)
+}
+
+/// A named set of edit stacks which can be applied to any image
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EditPreset {
+    pub pixel_op_stack: Vec<ImgOpItem>,
+    pub image_op_stack: Vec<ImgOpItem>,
+}
+
+impl EditPreset {
+    fn dir() -> Result<PathBuf> {
+        Ok(dirs::config_local_dir()
+            .ok_or(anyhow::anyhow!("Can't get config_local dir"))?
+            .join("oculante")
+            .join("presets"))
+    }
+
+    /// The names of all saved presets, sorted
+    pub fn list() -> Vec<String> {
+        let mut names = Self::dir()
+            .and_then(|d| Ok(std::fs::read_dir(d)?))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.extension() == Some(std::ffi::OsStr::new("json")))
+                    .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// The file of the preset `name`. Names can't contain paths, so presets stay in their folder.
+    pub fn path(name: &str) -> Result<PathBuf> {
+        let name = name.trim();
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', ':']) {
+            bail!("\"{name}\" can't be used as a preset name");
+        }
+        Ok(Self::dir()?.join(format!("{name}.json")))
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        let f = std::fs::File::open(Self::path(name)?)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    pub fn save(&self, name: &str) -> Result<()> {
+        let path = Self::path(name)?;
+        std::fs::create_dir_all(Self::dir()?)?;
+        let f = std::fs::File::create(path)?;
+        Ok(serde_json::to_writer_pretty(f, self)?)
+    }
+}
+
+/// The file next to an image that stores its edits, e.g. `photo.jpg.oculante`
+pub fn sidecar_path(p: &Path) -> PathBuf {
+    let mut name = p.as_os_str().to_owned();
+    name.push(".oculante");
+    PathBuf::from(name)
+}
+
+/// The sidecar named like older versions did, e.g. `photo.oculante`, which is still read
+pub fn legacy_sidecar_path(p: &Path) -> PathBuf {
+    p.with_extension("oculante")
+}
+
+/// Write the edits of an image to its sidecar file, or remove the sidecar if there are no edits left.
+/// A sidecar with the old naming is removed too, so it is not loaded instead of these edits.
+pub fn save_sidecar(p: &Path, edit_state: &EditState) -> Result<()> {
+    let sidecar = sidecar_path(p);
+    if edit_state.has_edits() {
+        let f = std::fs::File::create(sidecar)?;
+        serde_json::to_writer_pretty(&f, edit_state)?;
+    } else if sidecar.is_file() {
+        std::fs::remove_file(sidecar)?;
+    }
+    let legacy = legacy_sidecar_path(p);
+    if legacy.is_file() {
+        std::fs::remove_file(legacy)?;
+    }
+    Ok(())
+}
+
+/// Run all active operators of `preset` on `img`. Custom shaders need the graphics context of the
+/// editor, so presets with them are refused rather than applied without them.
+pub fn apply_preset(img: &mut RgbaImage, preset: &EditPreset) -> Result<()> {
+    if preset
+        .image_op_stack
+        .iter()
+        .any(|i| i.active && matches!(i.operation, ImageOperation::PixelShader { .. }))
+    {
+        bail!("Shader operators can only be applied in the editor");
+    }
+    for item in preset.image_op_stack.iter().filter(|i| i.active) {
+        item.process_image(img)?;
+    }
+    let mut stack = preset.pixel_op_stack.clone();
+    for item in &mut stack {
+        if item.operation.needs_analysis() {
+            item.operation.analyze(img);
+        }
+    }
+    process_pixel_stack(img, &stack);
+    Ok(())
+}
+
+/// Applies edits to a list of files in the background
+pub struct BatchJob {
+    pub total: usize,
+    pub done: Arc<AtomicUsize>,
+    pub cancel: Arc<AtomicBool>,
+    pub finished: Arc<AtomicBool>,
+}
+
+impl BatchJob {
+    /// Apply `preset` to all `files` and save the results to `out_dir` in the default export format.
+    /// Results that would get the same name are numbered.
+    pub fn start(
+        files: Vec<PathBuf>,
+        out_dir: PathBuf,
+        preset: EditPreset,
+        export: ExportSettings,
+        message_sender: Sender<Message>,
+    ) -> Self {
+        let job = Self {
+            total: files.len(),
+            done: Default::default(),
+            cancel: Default::default(),
+            finished: Default::default(),
+        };
+        let done = job.done.clone();
+        let cancel = job.cancel.clone();
+        let finished = job.finished.clone();
+
+        std::thread::spawn(move || {
+            if let Err(e) = std::fs::create_dir_all(&out_dir) {
+                _ = message_sender.send(Message::err(&format!("Could not create output dir: {e}")));
+                finished.store(true, Ordering::Relaxed);
+                return;
+            }
+            // images with the same name, from subfolders or in other formats, are numbered
+            let (dests, renamed) = output_paths(&files, &out_dir, export.format.extension());
+            let mut errors = 0;
+            for (file, dest) in files.iter().zip(dests) {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                let result = dest
+                    .ok_or(anyhow::anyhow!("File has no name"))
+                    .and_then(|dest| batch_process_file(file, &dest, &preset, &export));
+                if let Err(e) = result {
+                    error!("Batch: {}: {e}", file.display());
+                    errors += 1;
+                }
+                done.fetch_add(1, Ordering::Relaxed);
+            }
+            let mut msg = format!(
+                "Wrote {} images to {}",
+                done.load(Ordering::Relaxed) - errors,
+                out_dir.display()
+            );
+            if renamed > 0 {
+                msg += &format!(", {renamed} were renamed as their names collided");
+            }
+            if errors > 0 {
+                msg += &format!(", {errors} failed");
+            }
+            _ = message_sender.send(if errors == 0 && renamed == 0 {
+                Message::info(&msg)
+            } else {
+                Message::warn(&msg)
+            });
+            finished.store(true, Ordering::Relaxed);
+        });
+        job
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.done.load(Ordering::Relaxed) as f32 / self.total.max(1) as f32
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+fn batch_process_file(
+    file: &Path,
+    dest: &Path,
+    preset: &EditPreset,
+    export: &ExportSettings,
+) -> Result<()> {
+    let mut img = open_image(file)?.recv()?.buffer;
+
+    // the orientation is specific to each file
+    let mut preset = preset.clone();
+    for item in &mut preset.image_op_stack {
+        if let ImageOperation::AutoOrient(orientation) = &mut item.operation {
+            *orientation = exif_orientation(file).unwrap_or(1);
+        }
+    }
+
+    apply_preset(&mut img, &preset)?;
+    export.save(&img, dest, Some(file))?;
+    Ok(())
+}
+
+/// Apply `edit_state` to every frame of the animation at `source` and save it to `dest`
+pub fn save_animation(
+    source: &Path,
+    dest: &Path,
+    edit_state: &EditState,
+    export: &ExportSettings,
+) -> Result<()> {
+    let mut frames = vec![];
+    for frame in open_image(source)?.iter() {
+        let mut img = frame.buffer;
+        edit_state.apply(&mut img)?;
+        frames.push((img, frame.delay));
+    }
+    export.save_animation(&frames, dest)
+}
+
+/// The parts of the edit state that can be undone
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EditSnapshot {
+    pub pixel_op_stack: Vec<ImgOpItem>,
+    pub image_op_stack: Vec<ImgOpItem>,
+    pub paint_strokes: Vec<PaintStroke>,
+    pub layers: Vec<Layer>,
+    pub annotations: Vec<Annotation>,
+    pub redactions: Vec<Redaction>,
+    pub selection: Option<OpMask>,
+}
+
+/// Undo/redo journal of the edit stack
+#[derive(Debug, Clone, Default)]
+pub struct EditHistory {
+    undo: Vec<EditSnapshot>,
+    redo: Vec<EditSnapshot>,
+    /// The last recorded state
+    current: EditSnapshot,
+    /// The edits changed since the last recorded state
+    changed: bool,
+}
+
+impl EditHistory {
+    /// How many steps can be undone at most
+    const MAX_LEN: usize = 100;
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Note that the edits changed, so the next `EditState::record_history` compares them
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+}
+
+impl EditState {
+    fn snapshot(&self) -> EditSnapshot {
+        EditSnapshot {
+            pixel_op_stack: self.pixel_op_stack.clone(),
+            image_op_stack: self.image_op_stack.clone(),
+            // drop the empty stroke waiting for input and all render state
+            paint_strokes: self
+                .paint_strokes
+                .iter()
+                .filter(|s| !s.is_empty())
+                .map(|s| PaintStroke {
+                    highlight: false,
+                    committed: false,
+                    ..s.clone()
+                })
+                .collect(),
+            layers: self.layers.clone(),
+            annotations: self.annotations.clone(),
+            redactions: self.redactions.clone(),
+            selection: self.selection.clone(),
+        }
+    }
+
+    /// Record the current edits in the history if they were marked as changed since the last
+    /// call. Call this only when no interaction is ongoing, so a slider drag becomes one step.
+    /// Returns true if a new step was recorded.
+    pub fn record_history(&mut self) -> bool {
+        if !std::mem::take(&mut self.history.changed) {
+            return false;
+        }
+        let snapshot = self.snapshot();
+        if snapshot != self.history.current {
+            let previous = std::mem::replace(&mut self.history.current, snapshot);
+            self.history.undo.push(previous);
+            if self.history.undo.len() > EditHistory::MAX_LEN {
+                self.history.undo.remove(0);
+            }
+            self.history.redo.clear();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Start a fresh history from the current edits, for example after loading them from disk
+    pub fn reset_history(&mut self) {
+        self.history = EditHistory {
+            current: self.snapshot(),
+            ..Default::default()
+        };
+    }
+
+    /// A new entry for the edit stacks, limited to the selection if there is one
+    pub fn new_item(&self, operation: ImageOperation) -> ImgOpItem {
+        ImgOpItem {
+            mask: self.selection.clone(),
+            ..ImgOpItem::new(operation)
+        }
+    }
+
+    /// The operator stacks, to be pasted onto other images
+    pub fn copy_stacks(&self) -> EditPreset {
+        EditPreset {
+            pixel_op_stack: self.pixel_op_stack.clone(),
+            image_op_stack: self.image_op_stack.clone(),
+        }
+    }
+
+    /// Replace the operator stacks, keeping paint strokes and layers
+    pub fn paste_stacks(&mut self, stacks: &EditPreset) {
+        self.pixel_op_stack = stacks.pixel_op_stack.clone();
+        self.image_op_stack = stacks.image_op_stack.clone();
+        // clearing the results makes the edit ui process everything again
+        self.result_image_op = Default::default();
+        self.result_pixel_op = Default::default();
+    }
+
+    /// Whether the edits can be previewed at a lower resolution
+    pub fn supports_proxy(&self) -> bool {
+        // layers are placed in pixels and only composited at full resolution
+        self.layers.is_empty()
+            && self.shaders().is_empty()
+            && !self
+                .image_op_stack
+                .iter()
+                .chain(&self.pixel_op_stack)
+                .any(|i| i.active && i.operation.uses_pixel_positions())
+    }
+
+    /// Whether the preview at a lower resolution differs from the result by more than its size,
+    /// as some operators work on a fixed number of pixels
+    pub fn proxy_is_approximate(&self) -> bool {
+        self.image_op_stack
+            .iter()
+            .chain(&self.pixel_op_stack)
+            .any(|i| i.active && i.operation.has_fixed_radius())
+    }
+
+    /// The sources and parameters of all active `PixelShader` operators, in order
+    pub fn shaders(&self) -> Vec<(&str, &[PluginParam])> {
+        self.image_op_stack
+            .iter()
+            .filter(|i| i.active)
+            .filter_map(|i| match &i.operation {
+                ImageOperation::PixelShader {
+                    fragment, params, ..
+                } => Some((fragment.as_str(), params.as_slice())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether a shader is read from a file, which has to be checked for changes
+    pub fn has_shader_files(&self) -> bool {
+        self.image_op_stack.iter().any(|i| {
+            matches!(
+                i.operation,
+                ImageOperation::PixelShader { path: Some(_), .. }
+            )
+        })
+    }
+
+    /// Read shaders linked to a file again. Returns true if any of them changed.
+    pub fn reload_shader_files(&mut self) -> bool {
+        let mut changed = false;
+        for item in &mut self.image_op_stack {
+            if let ImageOperation::PixelShader {
+                fragment,
+                path: Some(path),
+                ..
+            } = &mut item.operation
+            {
+                match std::fs::read_to_string(&path) {
+                    Ok(source) if source != *fragment => {
+                        debug!("Reloading shader {}", path.display());
+                        *fragment = source;
+                        changed = true;
+                    }
+                    Ok(_) => (),
+                    Err(e) => error!("Could not read shader {}: {e}", path.display()),
+                }
+            }
+        }
+        changed
+    }
+
+    /// Process both stacks and the paint strokes on a downscaled copy of `img` for quick feedback.
+    /// Sets `proxy_scale`, the full resolution result has to be computed once the interaction ends.
+    pub fn process_proxy(&mut self, img: &RgbaImage) {
+        let scale = (PROXY_SIZE as f32 / img.width().max(img.height()) as f32).min(1.);
+        self.result_image_op = proxy(img, PROXY_SIZE);
+        for item in self.image_op_stack.iter().filter(|i| i.active) {
+            let mut item = item.clone();
+            item.operation.scale_radius(scale);
+            if let Err(e) = item.process_image(&mut self.result_image_op) {
+                error!("{e}")
+            }
+        }
+        self.result_pixel_op = self.result_image_op.clone();
+        process_pixel_stack(&mut self.result_pixel_op, &self.pixel_op_stack);
+        for stroke in self.paint_strokes.iter().filter(|s| !s.is_empty()) {
+            stroke.render(&mut self.result_pixel_op, &self.brushes);
+        }
+        for annotation in &self.annotations {
+            annotation.render(&mut self.result_pixel_op);
+        }
+        for redaction in &self.redactions {
+            redaction.render(&mut self.result_pixel_op);
+        }
+        self.proxy_scale = Some(scale);
+    }
+
+    /// Whether there is anything worth saving
+    pub fn has_edits(&self) -> bool {
+        !self.pixel_op_stack.is_empty()
+            || !self.image_op_stack.is_empty()
+            || self.paint_strokes.iter().any(|s| !s.is_empty())
+            || !self.layers.is_empty()
+            || !self.annotations.is_empty()
+            || !self.redactions.is_empty()
+    }
+
+    /// The EXIF orientation (1-8) that shows the image the way the edits do, if the only edits
+    /// are rotations and flips
+    pub fn as_orientation(&self) -> Option<u8> {
+        if self.pixel_op_stack.iter().any(|i| i.active)
+            || self.paint_strokes.iter().any(|s| !s.is_empty())
+            || !self.layers.is_empty()
+            || !self.annotations.is_empty()
+            || !self.redactions.is_empty()
+        {
+            return None;
+        }
+        let mut ops = self.image_op_stack.iter().filter(|i| i.active).peekable();
+        ops.peek()?;
+        // clockwise quarter turns after an optional horizontal flip
+        let (mut turns, mut flip) = (0_u8, false);
+        for item in ops {
+            if item.mask.is_some() {
+                return None;
+            }
+            let (t, f) = match item.operation {
+                ImageOperation::Rotate(90) => (1, false),
+                ImageOperation::Rotate(180) => (2, false),
+                ImageOperation::Rotate(-90 | 270) => (3, false),
+                ImageOperation::Rotate(_) => (0, false),
+                ImageOperation::Flip(false) => (0, true),
+                // flips both ways
+                ImageOperation::Flip(true) => (2, false),
+                ImageOperation::AutoOrient(orientation) => match orientation {
+                    2 => (0, true),
+                    3 => (2, false),
+                    4 => (2, true),
+                    5 => (3, true),
+                    6 => (1, false),
+                    7 => (1, true),
+                    8 => (3, false),
+                    _ => (0, false),
+                },
+                _ => return None,
+            };
+            // a flip reverses the direction of the turns before it
+            turns = (t + if f { 4 - turns } else { turns }) % 4;
+            flip ^= f;
+        }
+        Some(match (turns, flip) {
+            (0, false) => 1,
+            (1, false) => 6,
+            (2, false) => 3,
+            (3, false) => 8,
+            (0, true) => 2,
+            (1, true) => 7,
+            (2, true) => 4,
+            _ => 5,
+        })
+    }
+
+    /// The first edit which can't be written to a JPEG without recompressing it, if any
+    #[cfg(feature = "turbo")]
+    pub fn lossless_blocker(&self) -> Option<String> {
+        if let Some(item) = self
+            .image_op_stack
+            .iter()
+            .chain(&self.pixel_op_stack)
+            .filter(|i| i.active)
+            .find(|i| i.mask.is_some() || lossless_ops(&i.operation).is_none())
+        {
+            return Some(item.operation.to_string());
+        }
+        if self.paint_strokes.iter().any(|s| !s.is_empty()) {
+            Some("Painting".into())
+        } else if !self.layers.is_empty() {
+            Some("A layer".into())
+        } else if !self.annotations.is_empty() {
+            Some("An annotation".into())
+        } else if !self.redactions.is_empty() {
+            Some("A redaction".into())
+        } else {
+            None
+        }
+    }
+
+    /// Apply all edits to `img` on the CPU, for example to a frame of an animation.
+    /// Layers need to be loaded already. Fails if there are custom shaders, see `apply_preset`.
+    pub fn apply(&self, img: &mut RgbaImage) -> Result<()> {
+        apply_preset(
+            img,
+            &EditPreset {
+                pixel_op_stack: self.pixel_op_stack.clone(),
+                image_op_stack: self.image_op_stack.clone(),
+            },
+        )?;
+        for stroke in self.paint_strokes.iter().filter(|s| !s.is_empty()) {
+            stroke.render(img, &self.brushes);
+        }
+        for layer in &self.layers {
+            layer.composite(img);
+        }
+        for annotation in &self.annotations {
+            annotation.render(img);
+        }
+        for redaction in &self.redactions {
+            redaction.render(img);
+        }
+        Ok(())
+    }
+
+    /// Revert to the previous state. Returns false if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        if let Some(snapshot) = self.history.undo.pop() {
+            let current = std::mem::replace(&mut self.history.current, snapshot.clone());
+            self.history.redo.push(current);
+            self.restore(snapshot);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Re-apply the last undone state. Returns false if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        if let Some(snapshot) = self.history.redo.pop() {
+            let current = std::mem::replace(&mut self.history.current, snapshot.clone());
+            self.history.undo.push(current);
+            self.restore(snapshot);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Remove the most recent stroke, keeping the settings of the one waiting for input.
+    /// Returns false if nothing was painted.
+    pub fn pop_stroke(&mut self) -> bool {
+        let Some(i) = self.paint_strokes.iter().rposition(|s| !s.is_empty()) else {
+            return false;
+        };
+        self.paint_strokes.remove(i);
+        // earlier strokes may be baked into the result already
+        self.result_image_op = Default::default();
+        true
+    }
+
+    fn restore(&mut self, snapshot: EditSnapshot) {
+        self.pixel_op_stack = snapshot.pixel_op_stack;
+        self.image_op_stack = snapshot.image_op_stack;
+        // keep the stroke waiting for input, so the next one doesn't continue an undone stroke
+        let pending = self
+            .paint_strokes
+            .last()
+            .filter(|s| s.is_empty())
+            .or(snapshot.paint_strokes.last())
+            .map(|s| s.without_points());
+        self.paint_strokes = snapshot.paint_strokes;
+        self.paint_strokes.extend(pending);
+        self.layers = snapshot.layers;
+        self.annotations = snapshot.annotations;
+        self.redactions = snapshot.redactions;
+        self.selection = snapshot.selection;
+        // clearing the results makes the edit ui process everything again
+        self.result_image_op = Default::default();
+        self.result_pixel_op = Default::default();
+    }
+}
+
+/// The brushes that come with oculante, decoded once
+pub fn bundled_brushes() -> &'static [Brush] {
+    static BRUSHES: OnceLock<Vec<Brush>> = OnceLock::new();
+    BRUSHES.get_or_init(|| {
+        [
+            ("brush1", &include_bytes!("../res/brushes/brush1.png")[..]),
+            ("brush2", &include_bytes!("../res/brushes/brush2.png")[..]),
+            ("brush3", &include_bytes!("../res/brushes/brush3.png")[..]),
+            ("brush4", &include_bytes!("../res/brushes/brush4.png")[..]),
+            ("brush5", &include_bytes!("../res/brushes/brush5.png")[..]),
+        ]
+        .into_iter()
+        .map(|(name, data)| Brush {
+            name: name.into(),
+            image: image::load_from_memory(data)
+                .expect("Brushes must always load")
+                .into_rgba8(),
+        })
+        .collect()
+    })
+}
+
+/// The bundled brushes, followed by the ones the user imported
+pub fn default_brushes() -> Vec<Brush> {
+    let mut brushes = bundled_brushes().to_vec();
+    brushes.extend(user_brushes());
+    brushes
+}
+
+/// Where imported brushes are kept
+pub fn brush_dir() -> Result<PathBuf> {
+    Ok(dirs::config_local_dir()
+        .ok_or(anyhow::anyhow!("Can't get config_local dir"))?
+        .join("oculante")
+        .join("brushes"))
+}
+
+/// The brushes in the brush directory, sorted by file name
+fn user_brushes() -> Vec<Brush> {
+    let mut paths = brush_dir()
+        .and_then(|d| Ok(std::fs::read_dir(d)?))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension() == Some(std::ffi::OsStr::new("png")))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|p| match image::open(p) {
+            Ok(img) => Some(Brush {
+                name: p.file_stem()?.to_string_lossy().to_string(),
+                image: brush_from_image(img),
+            }),
+            Err(e) => {
+                error!("Could not load brush {}: {e}", p.display());
+                None
+            }
+        })
+        .collect()
+}
+
+/// A white brush from a grayscale or alpha image. Without alpha, dark areas paint.
+pub fn brush_from_image(img: DynamicImage) -> RgbaImage {
+    let has_alpha = img.color().has_alpha();
+    let mut brush = img.into_rgba8();
+    for p in brush.pixels_mut() {
+        if !has_alpha {
+            let luma = (p[0] as u32 + p[1] as u32 + p[2] as u32) / 3;
+            p[3] = 255 - luma as u8;
         }
+        p[0] = 255;
+        p[1] = 255;
+        p[2] = 255;
     }
+    brush
+}
+
+/// The name of the brush imported from `path`
+pub fn brush_name(path: &Path) -> Result<String> {
+    Ok(path
+        .file_stem()
+        .ok_or(anyhow::anyhow!("The brush has no file name"))?
+        .to_string_lossy()
+        .to_string())
 }
 
-fn default_brushes() -> Vec<RgbaImage> {
-    vec![
-        image::load_from_memory(include_bytes!("../res/brushes/brush1.png"))
-            .expect("Brushes must always load")
-            .into_rgba8(),
-        image::load_from_memory(include_bytes!("../res/brushes/brush2.png"))
-            .expect("Brushes must always load")
-            .into_rgba8(),
-        image::load_from_memory(include_bytes!("../res/brushes/brush3.png"))
-            .expect("Brushes must always load")
-            .into_rgba8(),
-        image::load_from_memory(include_bytes!("../res/brushes/brush4.png"))
-            .expect("Brushes must always load")
-            .into_rgba8(),
-        image::load_from_memory(include_bytes!("../res/brushes/brush5.png"))
-            .expect("Brushes must always load")
-            .into_rgba8(),
-    ]
+/// Copy a brush image into the brush directory, so it is available from now on. An imported
+/// brush of the same name is replaced.
+pub fn import_brush(path: &Path) -> Result<Brush> {
+    let name = brush_name(path)?;
+    if bundled_brushes().iter().any(|b| b.name == name) {
+        bail!("{name} is the name of a bundled brush, rename the file first");
+    }
+    let image = brush_from_image(image::open(path)?);
+    let dir = brush_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    image.save(dir.join(&name).with_extension("png"))?;
+    Ok(Brush { name, image })
 }
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
@@ -103,6 +990,93 @@ pub enum ScaleFilter {
     Lanczos3,
 }
 
+/// Aspect ratio constraint for the crop operator ui
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+enum CropAspect {
+    #[default]
+    Free,
+    Ratio(u16, u16),
+    Custom(u16, u16),
+}
+
+impl CropAspect {
+    fn label(&self) -> String {
+        match self {
+            Self::Free => "Free".into(),
+            Self::Ratio(w, h) => format!("{w}:{h}"),
+            Self::Custom(..) => "Custom".into(),
+        }
+    }
+
+    fn ratio(&self) -> Option<f32> {
+        match self {
+            Self::Free => None,
+            Self::Ratio(w, h) | Self::Custom(w, h) => Some(*w as f32 / (*h).max(1) as f32),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum NoiseDistribution {
+    #[default]
+    Uniform,
+    Gaussian,
+    SaltPepper,
+}
+
+/// Random values for one pixel of the noise operator, hashed from the seed and the pixel index
+/// with splitmix64. This is much cheaper than seeding a generator for every pixel.
+struct PixelNoise(u64);
+
+impl PixelNoise {
+    fn new(seed: u64, index: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15).wrapping_add(index))
+    }
+
+    /// The next value from 0 to 1
+    fn sample(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1 << 24) as f32
+    }
+}
+
+/// How the target size of a resize is determined
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+pub enum ResizeMode {
+    /// Resize to exactly `dimensions`
+    #[default]
+    Absolute,
+    /// Scale by a percentage of the input size
+    Percentage(u32),
+    /// Scale to fit within `dimensions`, keeping the aspect ratio
+    Fit,
+}
+
+impl ResizeMode {
+    /// The target size for an input image of `img_dim`
+    pub fn target_dimensions(&self, dimensions: (u32, u32), img_dim: (u32, u32)) -> (u32, u32) {
+        match self {
+            Self::Absolute => dimensions,
+            Self::Percentage(percent) => (
+                (img_dim.0 as u64 * *percent as u64 / 100).max(1) as u32,
+                (img_dim.1 as u64 * *percent as u64 / 100).max(1) as u32,
+            ),
+            Self::Fit => {
+                let scale = (dimensions.0 as f32 / img_dim.0.max(1) as f32)
+                    .min(dimensions.1 as f32 / img_dim.1.max(1) as f32);
+                (
+                    ((img_dim.0 as f32 * scale).round() as u32).max(1),
+                    ((img_dim.1 as f32 * scale).round() as u32).max(1),
+                )
+            }
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
 pub enum ImageOperation {
     Brightness(i32),
@@ -122,6 +1096,11 @@ pub enum ImageOperation {
     Noise {
         amt: u8,
         mono: bool,
+        /// Seed for the noise, so that re-processing yields the same image
+        #[serde(default)]
+        seed: u64,
+        #[serde(default)]
+        distribution: NoiseDistribution,
     },
     Rotate(i16),
     HSV((u16, i32, i32)),
@@ -131,10 +1110,14 @@ pub enum ImageOperation {
     Blur(u8),
     MMult,
     MDiv,
+    /// Make pixels fully transparent
+    Erase,
     Resize {
         dimensions: (u32, u32),
         aspect: bool,
         filter: ScaleFilter,
+        #[serde(default)]
+        mode: ResizeMode,
     },
     /// Left, right, top, bottom
     // x,y (top left corner of crop), width, height
@@ -145,6 +1128,61 @@ pub enum ImageOperation {
         original_size: (u32, u32),
     },
     LUT(String),
+    /// Hue, saturation and lightness shift per hue band, see `HSL_BANDS`
+    HslBands([[i32; 3]; 8]),
+    /// Eye positions (normalized, 1.0 equals 10000) and radius (1.0 equals 1000)
+    RedEye {
+        points: Vec<(u32, u32)>,
+        radius: u16,
+    },
+    /// Per channel black and white points, computed from the histogram
+    AutoLevels(Option<[(u8, u8); 3]>),
+    /// Black and white point of the luminance, computed from the histogram
+    AutoContrast(Option<(u8, u8)>),
+    /// Per channel gain (1.0 equals 1000), computed from the image average
+    AutoWhiteBalance(Option<[u16; 3]>),
+    /// Rotate/flip according to the EXIF orientation (1-8) of the source file
+    AutoOrient(u8),
+    /// A WebAssembly filter from the plugin directory, see `plugins`
+    Plugin {
+        id: String,
+        name: String,
+        params: Vec<PluginParam>,
+    },
+    /// A GLSL fragment shader, rendered on the GPU after the other operators. See `gpu::ShaderState`.
+    #[serde(deserialize_with = "deserialize_shader")]
+    PixelShader {
+        fragment: String,
+        params: Vec<PluginParam>,
+        /// A file the fragment is read from whenever it changes
+        path: Option<PathBuf>,
+    },
+}
+
+/// Read a `PixelShader`, also from sidecars and presets written when it only had the fragment
+fn deserialize_shader<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(String, Vec<PluginParam>, Option<PathBuf>), D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Shader {
+        Fragment(String),
+        Full {
+            fragment: String,
+            #[serde(default)]
+            params: Vec<PluginParam>,
+            #[serde(default)]
+            path: Option<PathBuf>,
+        },
+    }
+    Ok(match Shader::deserialize(deserializer)? {
+        Shader::Fragment(fragment) => (fragment, vec![], None),
+        Shader::Full {
+            fragment,
+            params,
+            path,
+        } => (fragment, params, path),
+    })
 }
 
 impl fmt::Display for ImageOperation {
@@ -163,11 +1201,19 @@ impl fmt::Display for ImageOperation {
             Self::Blur(_) => write!(f, "{DROP} Blur"),
             Self::Crop(_) => write!(f, "{CROP} Crop"),
             Self::CropPerspective { .. } => write!(f, "{CROP} Perspective crop"),
+            Self::Plugin { ref name, .. } => write!(f, "{PLUG} {name}"),
+            Self::PixelShader { .. } => write!(f, "{CODE} Shader"),
             Self::Flip(_) => write!(f, "{SWAP} Flip"),
             Self::Rotate(_) => write!(f, "{ARROW_CLOCKWISE} Rotate"),
+            Self::AutoOrient(_) => write!(f, "{CAMERA_ROTATE} Auto orient"),
             Self::Invert => write!(f, "{SELECTION_INVERSE} Invert"),
             Self::ChannelSwap(_) => write!(f, "{FLOW_ARROW} Channel Copy"),
             Self::HSV(_) => write!(f, "◔ HSV"),
+            Self::HslBands(_) => write!(f, "{PALETTE} HSL bands"),
+            Self::RedEye { .. } => write!(f, "{EYE} Red eye removal"),
+            Self::AutoLevels(_) => write!(f, "{MAGIC_WAND} Auto levels"),
+            Self::AutoContrast(_) => write!(f, "{MAGIC_WAND} Auto contrast"),
+            Self::AutoWhiteBalance(_) => write!(f, "{MAGIC_WAND} Auto white balance"),
             Self::ChromaticAberration(_) => write!(f, "{CAMERA} Color Fringe"),
             Self::Resize { .. } => write!(f, "{ARROWS_IN} Resize"),
             Self::GradientMap { .. } => write!(f, "🗠 Gradient Map"),
@@ -175,6 +1221,7 @@ impl fmt::Display for ImageOperation {
             Self::MMult => write!(f, "✖ Multiply with alpha"),
             Self::ScaleImageMinMax => write!(f, "\u{2195} Scale image min max"),
             Self::MDiv => write!(f, "➗ Divide by alpha"),
+            Self::Erase => write!(f, "{ERASER} Erase"),
             Self::LUT(_) => write!(f, "{FILM_STRIP} Apply Color LUT"),
             Self::Filter3x3(_) => write!(f, "{DOTS_NINE} 3x3 Filter"),
             // _ => write!(f, "Not implemented Display"),
@@ -183,6 +1230,132 @@ impl fmt::Display for ImageOperation {
 }
 
 impl ImageOperation {
+    /// Whether this operator needs a read-only copy of the unprocessed buffer, for example to sample neighbors
+    pub fn needs_source(&self) -> bool {
+        match self {
+            Self::Expression(expr) => expr.contains("sample"),
+            _ => false,
+        }
+    }
+
+    /// Whether this operator still needs to compute its parameters from the image, see `analyze()`
+    pub fn needs_analysis(&self) -> bool {
+        matches!(
+            self,
+            Self::AutoLevels(None) | Self::AutoContrast(None) | Self::AutoWhiteBalance(None)
+        )
+    }
+
+    /// Compute the parameters of automatic adjustments from `img`
+    pub fn analyze(&mut self, img: &RgbaImage) {
+        // ignore the darkest and brightest 0.5% so outliers don't dominate
+        const CLIP: f32 = 0.005;
+        match self {
+            Self::AutoLevels(levels) => {
+                let mut hist = [[0u64; 256]; 3];
+                for p in img.pixels() {
+                    for c in 0..3 {
+                        hist[c][p[c] as usize] += 1;
+                    }
+                }
+                *levels = Some(hist.map(|h| histogram_bounds(&h, CLIP)));
+            }
+            Self::AutoContrast(bounds) => {
+                let mut hist = [0u64; 256];
+                for p in img.pixels() {
+                    let luma = 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32;
+                    hist[luma as usize] += 1;
+                }
+                *bounds = Some(histogram_bounds(&hist, CLIP));
+            }
+            Self::AutoWhiteBalance(gains) => {
+                // gray world assumption: the average color should be neutral
+                let mut sum = [0u64; 3];
+                for p in img.pixels() {
+                    for c in 0..3 {
+                        sum[c] += p[c] as u64;
+                    }
+                }
+                let gray = (sum[0] + sum[1] + sum[2]) as f32 / 3.;
+                *gains = Some(sum.map(|s| {
+                    if s == 0 {
+                        1000
+                    } else {
+                        (gray / s as f32 * 1000.).min(u16::MAX as f32) as u16
+                    }
+                }));
+            }
+            _ => (),
+        }
+    }
+
+    /// The operator as `p * mul + add` per channel (0-1), if it can be expressed like that.
+    /// Stacks of these operators are combined into one transform and run with SIMD.
+    pub fn affine(&self) -> Option<([f32; 4], [f32; 4])> {
+        let rgb = |v: f32, alpha: f32| [v, v, v, alpha];
+        Some(match self {
+            Self::Brightness(amt) => ([1.; 4], rgb(*amt as f32 / 255., 0.)),
+            Self::Contrast(val) => {
+                let factor: f32 = (1.015_686_3 * (*val as f32 / 255. + 1.0))
+                    / (1.0 * (1.015_686_3 - *val as f32 / 255.));
+                (rgb(factor, 1.), [0.; 4])
+            }
+            Self::Mult(amt) => (
+                [
+                    amt[0] as f32 / 255.,
+                    amt[1] as f32 / 255.,
+                    amt[2] as f32 / 255.,
+                    1.,
+                ],
+                [0.; 4],
+            ),
+            Self::Add(amt) => (
+                [1.; 4],
+                [
+                    amt[0] as f32 / 255.,
+                    amt[1] as f32 / 255.,
+                    amt[2] as f32 / 255.,
+                    0.,
+                ],
+            ),
+            Self::Invert => (rgb(-1., 1.), rgb(1., 0.)),
+            Self::Erase => ([0.; 4], [0.; 4]),
+            _ => return None,
+        })
+    }
+
+    /// Color operators that can be combined with their input using a blend mode
+    pub fn supports_blend_mode(&self) -> bool {
+        matches!(self, Self::Fill(_) | Self::Add(_) | Self::Mult(_))
+    }
+
+    /// Whether the parameters are absolute pixel positions or sizes, which don't carry over to a smaller copy
+    pub fn uses_pixel_positions(&self) -> bool {
+        matches!(
+            self,
+            Self::Resize { .. } | Self::CropPerspective { .. } | Self::RedEye { .. }
+        )
+    }
+
+    /// Scale sizes in pixels, to process an image downscaled by `scale` like the full image
+    pub fn scale_radius(&mut self, scale: f32) {
+        match self {
+            Self::Blur(amt) | Self::ChromaticAberration(amt) if *amt > 0 => {
+                *amt = ((*amt as f32 * scale).round() as u8).max(1);
+            }
+            _ => (),
+        }
+    }
+
+    /// Whether this operator works on a number of pixels that can't be scaled, such as a kernel
+    /// or per pixel noise, see `scale_radius`
+    pub fn has_fixed_radius(&self) -> bool {
+        match self {
+            Self::Filter3x3(_) | Self::Noise { .. } | Self::Plugin { .. } => true,
+            _ => self.needs_source(),
+        }
+    }
+
     pub fn is_per_pixel(&self) -> bool {
         match self {
             Self::Blur(_) => false,
@@ -191,8 +1364,11 @@ impl ImageOperation {
             Self::Crop(_) => false,
             Self::CropPerspective { .. } => false,
             Self::Rotate(_) => false,
+            Self::AutoOrient(_) => false,
             Self::Flip(_) => false,
             Self::ChromaticAberration(_) => false,
+            Self::Plugin { .. } => false,
+            Self::PixelShader { .. } => false,
             Self::LUT(_) => false,
             Self::Filter3x3(_) => false,
             Self::ScaleImageMinMax => false,
@@ -207,6 +1383,23 @@ impl ImageOperation {
             Self::Brightness(val) => ui.slider_styled(val, -255..=255),
             Self::Exposure(val) => ui.slider_styled(val, -100..=100),
             Self::ChromaticAberration(val) => ui.slider_styled(val, 0..=255),
+            Self::Plugin { params, .. } => {
+                let mut r = ui.allocate_response(Vec2::ZERO, Sense::hover());
+                ui.vertical(|ui| {
+                    for param in params {
+                        ui.horizontal(|ui| {
+                            ui.label(&param.name);
+                            if ui
+                                .slider_styled(&mut param.value, param.min..=param.max)
+                                .changed()
+                            {
+                                r.mark_changed();
+                            }
+                        });
+                    }
+                });
+                r
+            }
             Self::Filter3x3(val) => {
                 let mut x = ui.allocate_response(vec2(0.0, 0.0), Sense::click_and_drag());
 
@@ -235,7 +1428,153 @@ impl ImageOperation {
                 x
             }
             Self::Posterize(val) => ui.slider_styled(val, 1..=255),
-            Self::Expression(expr) => ui.text_edit_singleline(expr),
+            Self::PixelShader {
+                fragment,
+                params,
+                path,
+            } => {
+                ui.vertical(|ui| {
+                    let error = ui
+                        .ctx()
+                        .data(|r| r.get_temp::<Option<String>>(shader_error_id(fragment)))
+                        .flatten();
+                    // a linked file is the source of truth, so it is only edited there
+                    let mut r = ui
+                        .add(
+                            egui::TextEdit::multiline(fragment)
+                                .code_editor()
+                                .desired_rows(6)
+                                .interactive(path.is_none())
+                                .text_color_opt(error.as_ref().map(|_| Color32::RED)),
+                        )
+                        .on_hover_text(
+                            "Runs on the GPU after all other operators.\nAvailable: v_uv (texture coordinate), u_texture (the edited image), iResolution, iTime, iMouse, iZoom and the parameters by name. Write the output to color.\nShadertoy shaders defining mainImage() work as well.",
+                        );
+                    if let Some(e) = error {
+                        ui.label(egui::RichText::new(e).small().color(Color32::RED));
+                    }
+
+                    if let Some(p) = path.clone() {
+                        ui.horizontal(|ui| {
+                            ui.label(
+                                p.file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                                    .to_string(),
+                            )
+                            .on_hover_text(p.to_string_lossy());
+                            if ui
+                                .button(LINK_BREAK)
+                                .on_hover_text("Stop reloading the shader from this file")
+                                .clicked()
+                            {
+                                *path = None;
+                            }
+                        });
+                    } else {
+                        #[cfg(not(feature = "file_open"))]
+                        {
+                            if ui.button("Load from file").clicked() {
+                                ui.ctx()
+                                    .memory_mut(|w| w.open_popup(Id::new("shader_file")));
+                            }
+
+                            if ui.ctx().memory(|w| w.is_popup_open(Id::new("shader_file"))) {
+                                filebrowser::browse_modal(
+                                    false,
+                                    &["frag", "glsl"],
+                                    |p| {
+                                        *path = Some(p.clone());
+                                        r.mark_changed();
+                                    },
+                                    ui.ctx(),
+                                );
+                            }
+                        }
+
+                        #[cfg(feature = "file_open")]
+                        {
+                            if ui
+                                .button("Load from file")
+                                .on_hover_text(
+                                    "Read the shader from a file and reload it when it changes",
+                                )
+                                .clicked()
+                            {
+                                if let Some(p) = rfd::FileDialog::new()
+                                    .add_filter("Fragment shader", &["frag", "glsl"])
+                                    .pick_file()
+                                {
+                                    *path = Some(p);
+                                    r.mark_changed();
+                                }
+                            }
+                        }
+                    }
+
+                    egui::ComboBox::from_id_source("shader presets")
+                        .selected_text(
+                            gpu::SHADER_PRESETS
+                                .iter()
+                                .find(|p| p.fragment == fragment.as_str())
+                                .map(|p| p.name)
+                                .unwrap_or("Presets"),
+                        )
+                        .show_ui(ui, |ui| {
+                            for preset in gpu::SHADER_PRESETS {
+                                if ui.selectable_label(false, preset.name).clicked() {
+                                    *fragment = preset.fragment.to_string();
+                                    *params = preset.params();
+                                    *path = None;
+                                    r.mark_changed();
+                                }
+                            }
+                        });
+
+                    for param in params {
+                        ui.horizontal(|ui| {
+                            ui.label(&param.name);
+                            if ui
+                                .slider_styled(&mut param.value, param.min..=param.max)
+                                .changed()
+                            {
+                                r.mark_changed();
+                            }
+                        });
+                    }
+                    r
+                })
+                .inner
+            }
+            Self::Expression(expr) => {
+                ui.vertical(|ui| {
+                    let error = expression_error(expr);
+                    let mut r = ui
+                        .add(
+                            egui::TextEdit::singleline(expr)
+                                .text_color_opt(error.as_ref().map(|_| Color32::RED)),
+                        )
+                        .on_hover_text(
+                            "Available variables: r, g, b, a (0-1), x, y (pixel position), width, height\nsample(dx, dy, channel) reads a neighboring pixel of the input (channel 0-3)",
+                        );
+                    if let Some(e) = error {
+                        ui.label(egui::RichText::new(e).small().color(Color32::RED));
+                    }
+
+                    egui::ComboBox::from_id_source("expression snippets")
+                        .selected_text("Examples")
+                        .show_ui(ui, |ui| {
+                            for (name, snippet) in EXPRESSION_SNIPPETS {
+                                if ui.selectable_label(false, *name).clicked() {
+                                    *expr = snippet.to_string();
+                                    r.mark_changed();
+                                }
+                            }
+                        });
+                    r
+                })
+                .inner
+            }
             Self::LUT(lut_name) => {
                 ui.scope(|ui| {
                     let mut x = ui.allocate_response(vec2(0.0, 0.0), Sense::click_and_drag());
@@ -385,14 +1724,192 @@ impl ImageOperation {
                 }
                 r
             }
-            Self::Blur(val) => ui.slider_styled(val, 0..=20),
-            Self::Noise { amt, mono } => {
-                let mut r = ui.slider_styled(amt, 0..=100);
-                if ui.checkbox(mono, "Grey").changed() {
-                    r.changed = true
+            Self::HslBands(bands) => {
+                let mut r = ui.allocate_response(vec2(0.0, 0.0), Sense::hover());
+                egui::Grid::new("hsl bands").show(ui, |ui| {
+                    ui.label("");
+                    ui.label("Hue");
+                    ui.label("Sat");
+                    ui.label("Light");
+                    ui.end_row();
+                    for ((name, hue), band) in HSL_BANDS.iter().zip(bands.iter_mut()) {
+                        let rgb: Rgb = Hsl::new(*hue, 1.0, 0.5).into_color();
+                        ui.colored_label(
+                            Color32::from_rgb(
+                                (rgb.red * 255.) as u8,
+                                (rgb.green * 255.) as u8,
+                                (rgb.blue * 255.) as u8,
+                            ),
+                            *name,
+                        );
+                        for (i, val) in band.iter_mut().enumerate() {
+                            let range = if i == 0 { -30..=30 } else { -100..=100 };
+                            if ui.add(DragValue::new(val).clamp_range(range)).changed() {
+                                r.changed = true;
+                            }
+                        }
+                        ui.end_row();
+                    }
+                });
+                r
+            }
+            Self::RedEye { points, radius } => {
+                let picking_id = ui.id().with("picking");
+                let mut picking = ui.data(|r| r.get_temp::<bool>(picking_id)).unwrap_or_default();
+
+                let r = ui
+                    .vertical(|ui| {
+                        let mut r = ui.slider_styled(radius, 1..=100);
+                        ui.horizontal(|ui| {
+                            if ui
+                                .selectable_label(picking, format!("{CROSSHAIR} Pick eyes"))
+                                .on_hover_text("Click on the eyes in the image")
+                                .clicked()
+                            {
+                                picking = !picking;
+                            }
+                            if ui
+                                .add_enabled(
+                                    !points.is_empty(),
+                                    egui::Button::new(format!("{ARROW_U_UP_LEFT} Clear")),
+                                )
+                                .clicked()
+                            {
+                                points.clear();
+                                r.mark_changed();
+                            }
+                        });
+
+                        let to_screen = |pt: &(u32, u32)| {
+                            Pos2::new(
+                                geo.offset.x
+                                    + pt.0 as f32 / 10000. * geo.dimensions.0 as f32 * geo.scale,
+                                geo.offset.y
+                                    + pt.1 as f32 / 10000. * geo.dimensions.1 as f32 * geo.scale,
+                            )
+                        };
+                        let screen_radius = *radius as f32 / 1000.
+                            * geo.dimensions.0.max(geo.dimensions.1) as f32
+                            * geo.scale;
+
+                        if picking {
+                            for pt in points.iter() {
+                                ui.painter().circle_stroke(
+                                    to_screen(pt),
+                                    screen_radius,
+                                    Stroke::new(1., Color32::GOLD),
+                                );
+                            }
+
+                            // only pick if the cursor is over the image, not the ui
+                            if !ui.ctx().is_pointer_over_area()
+                                && ui.input(|i| i.pointer.primary_clicked())
+                            {
+                                if let Some(cursor_abs) = ui.input(|i| i.pointer.hover_pos()) {
+                                    let cursor_relative = pos_from_coord(
+                                        geo.offset,
+                                        Vector2::new(cursor_abs.x, cursor_abs.y),
+                                        Vector2::new(
+                                            geo.dimensions.0 as f32,
+                                            geo.dimensions.1 as f32,
+                                        ),
+                                        geo.scale,
+                                    );
+                                    points.push((
+                                        (cursor_relative.x / geo.dimensions.0 as f32 * 10000.)
+                                            as u32,
+                                        (cursor_relative.y / geo.dimensions.1 as f32 * 10000.)
+                                            as u32,
+                                    ));
+                                    r.mark_changed();
+                                }
+                            }
+                        }
+                        r
+                    })
+                    .inner;
+
+                ui.data_mut(|w| w.insert_temp(picking_id, picking));
+                r
+            }
+            Self::AutoLevels(params) => {
+                let mut r = ui
+                    .button(format!("{ARROW_CLOCKWISE} Re-analyze"))
+                    .on_hover_text("Compute the adjustment from the current image again");
+                if r.clicked() {
+                    *params = None;
+                    r.mark_changed();
+                }
+                r
+            }
+            Self::AutoContrast(params) => {
+                let mut r = ui
+                    .button(format!("{ARROW_CLOCKWISE} Re-analyze"))
+                    .on_hover_text("Compute the adjustment from the current image again");
+                if r.clicked() {
+                    *params = None;
+                    r.mark_changed();
                 }
                 r
             }
+            Self::AutoWhiteBalance(params) => {
+                let mut r = ui
+                    .button(format!("{ARROW_CLOCKWISE} Re-analyze"))
+                    .on_hover_text("Compute the adjustment from the current image again");
+                if r.clicked() {
+                    *params = None;
+                    r.mark_changed();
+                }
+                r
+            }
+            Self::Blur(val) => ui.slider_styled(val, 0..=20),
+            Self::Noise {
+                amt,
+                mono,
+                seed,
+                distribution,
+            } => {
+                ui.vertical(|ui| {
+                    let mut r = ui.slider_styled(amt, 0..=100);
+                    ui.horizontal(|ui| {
+                        if ui.checkbox(mono, "Grey").changed() {
+                            r.changed = true
+                        }
+                        egui::ComboBox::from_id_source("noise distribution")
+                            .selected_text(format!("{distribution:?}"))
+                            .show_ui(ui, |ui| {
+                                for d in [
+                                    NoiseDistribution::Uniform,
+                                    NoiseDistribution::Gaussian,
+                                    NoiseDistribution::SaltPepper,
+                                ] {
+                                    if ui
+                                        .selectable_value(distribution, d, format!("{d:?}"))
+                                        .clicked()
+                                    {
+                                        r.changed = true;
+                                    }
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Seed");
+                        if ui.add(DragValue::new(seed)).changed() {
+                            r.changed = true;
+                        }
+                        if ui
+                            .button(DICE_FIVE)
+                            .on_hover_text("Randomize seed")
+                            .clicked()
+                        {
+                            *seed = thread_rng().gen();
+                            r.changed = true;
+                        }
+                    });
+                    r
+                })
+                .inner
+            }
 
             Self::GradientMap(pts) => {
                 ui.vertical(|ui| {
@@ -582,6 +2099,16 @@ impl ImageOperation {
                 }
                 r
             }
+            Self::AutoOrient(orientation) => ui.label(match orientation {
+                2 => "Flip horizontal",
+                3 => "Rotate 180°",
+                4 => "Flip vertical",
+                5 => "Transpose",
+                6 => "Rotate 90°",
+                7 => "Transverse",
+                8 => "Rotate -90°",
+                _ => "No orientation needed",
+            }),
             Self::Desaturate(val) => ui.slider_styled(val, 0..=100),
             Self::Contrast(val) => ui.slider_styled(val, -128..=128),
             Self::CropPerspective {
@@ -700,53 +2227,160 @@ impl ImageOperation {
                 let mut float_bounds = bounds.map(|b| b as f32 / 10000.);
                 // debug!("Float bounds {:?}", float_bounds);
 
+                // The crop is stored normalized, pixel units refer to the unedited image
+                let source_size = ui
+                    .ctx()
+                    .data(|r| r.get_temp::<(u32, u32)>(Id::new("edit_source_size")))
+                    .unwrap_or(geo.dimensions);
+                let (w, h) = (source_size.0.max(1) as f32, source_size.1.max(1) as f32);
+
+                let settings_id = ui.id().with("crop_settings");
+                let (mut pixel_units, mut aspect) = ui
+                    .ctx()
+                    .data(|r| r.get_temp::<(bool, CropAspect)>(settings_id))
+                    .unwrap_or_default();
+
                 let available_w_single_spacing =
                     ui.available_width() - 60. - ui.style().spacing.item_spacing.x * 3.;
-                ui.horizontal(|ui| {
-                    let mut r1 = ui.add_sized(
-                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
-                        egui::DragValue::new(&mut float_bounds[0])
-                            .speed(0.004)
-                            .clamp_range(0.0..=1.0)
-                            // X
-                            .prefix("⏵ "),
-                    );
-                    let r2 = ui.add_sized(
-                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
-                        egui::DragValue::new(&mut float_bounds[2])
-                            .speed(0.004)
-                            .clamp_range(0.0..=1.0)
-                            // WIDTH
-                            .prefix("⏴ "),
-                    );
-                    let r3 = ui.add_sized(
-                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
-                        egui::DragValue::new(&mut float_bounds[1])
-                            .speed(0.004)
-                            .clamp_range(0.0..=1.0)
-                            // Y
-                            .prefix("⏷ "),
-                    );
-                    let r4 = ui.add_sized(
-                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
-                        egui::DragValue::new(&mut float_bounds[3])
-                            .speed(0.004)
-                            .clamp_range(0.0..=1.0)
-                            // HEIGHT
-                            .prefix("⏶ "),
-                    );
-                    // TODO rewrite with any
-                    if r2.changed() || r3.changed() || r4.changed() {
-                        r1.changed = true;
-                    }
-                    if r1.changed() {
-                        // commit back changed vals
-                        *bounds = float_bounds.map(|b| (b * 10000.) as u32);
-                        debug!("changed bounds {:?}", bounds);
+                let mut r = ui
+                    .vertical(|ui| {
+                        let mut r1 = ui
+                            .horizontal(|ui| {
+                                if pixel_units {
+                                    let mut px = [
+                                        float_bounds[0] * w,
+                                        float_bounds[1] * h,
+                                        (1. - float_bounds[0] - float_bounds[2]) * w,
+                                        (1. - float_bounds[1] - float_bounds[3]) * h,
+                                    ]
+                                    .map(|v| v.round() as u32);
+
+                                    let mut r1 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut px[0])
+                                            .clamp_range(0..=source_size.0.saturating_sub(1))
+                                            .prefix("x "),
+                                    );
+                                    let r2 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut px[1])
+                                            .clamp_range(0..=source_size.1.saturating_sub(1))
+                                            .prefix("y "),
+                                    );
+                                    let r3 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut px[2])
+                                            .clamp_range(1..=source_size.0.saturating_sub(px[0]).max(1))
+                                            .prefix("w "),
+                                    );
+                                    let r4 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut px[3])
+                                            .clamp_range(1..=source_size.1.saturating_sub(px[1]).max(1))
+                                            .prefix("h "),
+                                    );
+                                    if r2.changed() || r3.changed() || r4.changed() {
+                                        r1.changed = true;
+                                    }
+                                    if r1.changed() {
+                                        let px = px.map(|v| v as f32);
+                                        float_bounds = [
+                                            px[0] / w,
+                                            px[1] / h,
+                                            (1. - (px[0] + px[2]) / w).max(0.),
+                                            (1. - (px[1] + px[3]) / h).max(0.),
+                                        ];
+                                    }
+                                    r1
+                                } else {
+                                    let mut r1 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut float_bounds[0])
+                                            .speed(0.004)
+                                            .clamp_range(0.0..=1.0)
+                                            // X
+                                            .prefix("⏵ "),
+                                    );
+                                    let r2 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut float_bounds[2])
+                                            .speed(0.004)
+                                            .clamp_range(0.0..=1.0)
+                                            // WIDTH
+                                            .prefix("⏴ "),
+                                    );
+                                    let r3 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut float_bounds[1])
+                                            .speed(0.004)
+                                            .clamp_range(0.0..=1.0)
+                                            // Y
+                                            .prefix("⏷ "),
+                                    );
+                                    let r4 = ui.add_sized(
+                                        egui::vec2(available_w_single_spacing / 4., ui.available_height()),
+                                        egui::DragValue::new(&mut float_bounds[3])
+                                            .speed(0.004)
+                                            .clamp_range(0.0..=1.0)
+                                            // HEIGHT
+                                            .prefix("⏶ "),
+                                    );
+                                    // TODO rewrite with any
+                                    if r2.changed() || r3.changed() || r4.changed() {
+                                        r1.changed = true;
+                                    }
+                                    r1
+                                }
+                            })
+                            .inner;
+
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut pixel_units, "Pixels");
+                            egui::ComboBox::from_id_source("crop aspect")
+                                .selected_text(aspect.label())
+                                .show_ui(ui, |ui| {
+                                    for a in [
+                                        CropAspect::Free,
+                                        CropAspect::Ratio(1, 1),
+                                        CropAspect::Ratio(4, 3),
+                                        CropAspect::Ratio(16, 9),
+                                        CropAspect::Custom(3, 2),
+                                    ] {
+                                        let selected = match (aspect, a) {
+                                            (CropAspect::Custom(..), CropAspect::Custom(..)) => true,
+                                            _ => aspect == a,
+                                        };
+                                        if ui.selectable_label(selected, a.label()).clicked() && !selected {
+                                            aspect = a;
+                                            r1.changed = true;
+                                        }
+                                    }
+                                });
+                            if let CropAspect::Custom(aw, ah) = &mut aspect {
+                                let r_w = ui.add(DragValue::new(aw).clamp_range(1..=100));
+                                ui.label(":");
+                                let r_h = ui.add(DragValue::new(ah).clamp_range(1..=100));
+                                if r_w.changed() || r_h.changed() {
+                                    r1.changed = true;
+                                }
+                            }
+                        });
+                        r1
+                    })
+                    .inner;
+
+                if r.changed() {
+                    if let Some(ratio) = aspect.ratio() {
+                        constrain_crop_aspect(&mut float_bounds, ratio, (w, h));
                     }
-                    r1
-                })
-                .inner
+                    // commit back changed vals
+                    *bounds = float_bounds.map(|b| (b * 10000.) as u32);
+                    debug!("changed bounds {:?}", bounds);
+                    r.changed = true;
+                }
+                ui.ctx()
+                    .data_mut(|w| w.insert_temp(settings_id, (pixel_units, aspect)));
+                r
             }
             Self::Equalize(bounds) => {
                 let available_w_single_spacing =
@@ -826,50 +2460,95 @@ impl ImageOperation {
                 dimensions,
                 aspect,
                 filter,
+                mode,
             } => {
                 let ratio = dimensions.1 as f32 / dimensions.0 as f32;
 
                 ui.horizontal(|ui| {
-                    let mut r0 = ui.add(
-                        egui::DragValue::new(&mut dimensions.0)
-                            .speed(4.)
-                            .clamp_range(1..=10000)
-                            .prefix("X "),
-                    );
-                    let r1 = ui.add(
-                        egui::DragValue::new(&mut dimensions.1)
-                            .speed(4.)
-                            .clamp_range(1..=10000)
-                            .prefix("Y "),
-                    );
+                    let mut r_mode = ui.allocate_response(vec2(0.0, 0.0), Sense::hover());
+                    egui::ComboBox::from_id_source("resize mode")
+                        .selected_text(match mode {
+                            ResizeMode::Absolute => "Size",
+                            ResizeMode::Percentage(_) => "%",
+                            ResizeMode::Fit => "Fit",
+                        })
+                        .width(40.)
+                        .show_ui(ui, |ui| {
+                            for (m, label) in [
+                                (ResizeMode::Absolute, "Size"),
+                                (ResizeMode::Percentage(50), "%"),
+                                (ResizeMode::Fit, "Fit"),
+                            ] {
+                                let selected =
+                                    std::mem::discriminant(mode) == std::mem::discriminant(&m);
+                                if ui.selectable_label(selected, label).clicked() && !selected {
+                                    *mode = m;
+                                    r_mode.mark_changed();
+                                }
+                            }
+                        });
 
-                    if r0.changed() && *aspect {
-                        dimensions.1 = (dimensions.0 as f32 * ratio) as u32
-                    }
+                    let mut r0 = if let ResizeMode::Percentage(percent) = mode {
+                        let mut r = ui.add(
+                            egui::DragValue::new(percent)
+                                .clamp_range(1..=1000)
+                                .suffix("%"),
+                        );
+                        // same as below, only update on release
+                        r.changed = r.drag_released() || (r.changed() && !r.dragged());
+                        r
+                    } else {
+                        let mut r0 = ui.add(
+                            egui::DragValue::new(&mut dimensions.0)
+                                .speed(4.)
+                                .clamp_range(1..=10000)
+                                .prefix("X "),
+                        );
+                        let r1 = ui.add(
+                            egui::DragValue::new(&mut dimensions.1)
+                                .speed(4.)
+                                .clamp_range(1..=10000)
+                                .prefix("Y "),
+                        );
+
+                        // Fit always keeps the aspect, the dimensions are the bounding box
+                        let lock = *aspect && *mode == ResizeMode::Absolute;
+
+                        if r0.changed() && lock {
+                            dimensions.1 = (dimensions.0 as f32 * ratio) as u32
+                        }
+
+                        if r1.changed() {
+                            r0.changed = true;
+                            if lock {
+                                dimensions.0 = (dimensions.1 as f32 / ratio) as u32
+                            }
+                        }
+
+                        let r2 = ui
+                            .add_enabled(*mode == ResizeMode::Absolute, egui::Checkbox::new(aspect, "🔗"))
+                            .on_hover_text("Lock aspect ratio");
+
+                        if r2.changed() {
+                            r0.changed = true;
 
-                    if r1.changed() {
-                        r0.changed = true;
-                        if *aspect {
-                            dimensions.0 = (dimensions.1 as f32 / ratio) as u32
+                            if *aspect {
+                                dimensions.1 = (dimensions.0 as f32 * ratio) as u32;
+                            }
                         }
-                    }
 
-                    let r2 = ui.checkbox(aspect, "🔗").on_hover_text("Lock aspect ratio");
+                        // For this operator, we want to update on release, not on change.
+                        // Since all operators are processed the same, we use the hack to emit `changed` just on release.
+                        // Users dragging the resize values will now only trigger a resize on release, which feels
+                        // more snappy.
+                        r0.changed = r0.drag_released() || r1.drag_released() || r2.changed();
+                        r0
+                    };
 
-                    if r2.changed() {
+                    if r_mode.changed() {
                         r0.changed = true;
-
-                        if *aspect {
-                            dimensions.1 = (dimensions.0 as f32 * ratio) as u32;
-                        }
                     }
 
-                    // For this operator, we want to update on release, not on change.
-                    // Since all operators are processed the same, we use the hack to emit `changed` just on release.
-                    // Users dragging the resize values will now only trigger a resize on release, which feels
-                    // more snappy.
-                    r0.changed = r0.drag_released() || r1.drag_released() || r2.changed();
-
                     egui::ComboBox::from_id_source("filter")
                         .selected_text(format!("{filter:?}"))
                         .show_ui(ui, |ui| {
@@ -900,7 +2579,15 @@ impl ImageOperation {
         match self {
             Self::Blur(amt) => {
                 if *amt != 0 {
-                    *img = imageops::blur(img, *amt as f32);
+                    let (width, height) = (img.width() as usize, img.height() as usize);
+                    gaussian_blur(
+                        &mut **img,
+                        width,
+                        height,
+                        *amt as f32,
+                        |v| v as f32,
+                        |v| v.round().clamp(0., 255.) as u8,
+                    );
                 }
             }
             Self::Filter3x3(amt) => {
@@ -973,9 +2660,13 @@ impl ImageOperation {
                 }
             }
             Self::Resize {
-                dimensions, filter, ..
+                dimensions,
+                filter,
+                mode,
+                ..
             } => {
-                if *dimensions != Default::default() {
+                let dimensions = &mode.target_dimensions(*dimensions, img.dimensions());
+                if *dimensions != Default::default() && *dimensions != img.dimensions() {
                     let filter = match filter {
                         ScaleFilter::Box => fr::FilterType::Box,
                         ScaleFilter::Bilinear => fr::FilterType::Bilinear,
@@ -1039,6 +2730,16 @@ impl ImageOperation {
                     _ => (),
                 }
             }
+            Self::AutoOrient(orientation) => match orientation {
+                2 => *img = image::imageops::flip_horizontal(img),
+                3 => *img = image::imageops::rotate180(img),
+                4 => *img = image::imageops::flip_vertical(img),
+                5 => *img = image::imageops::flip_horizontal(&image::imageops::rotate90(img)),
+                6 => *img = image::imageops::rotate90(img),
+                7 => *img = image::imageops::flip_horizontal(&image::imageops::rotate270(img)),
+                8 => *img = image::imageops::rotate270(img),
+                _ => (),
+            },
             Self::Flip(vert) => {
                 if *vert {
                     *img = image::imageops::flip_vertical(img);
@@ -1080,23 +2781,34 @@ impl ImageOperation {
                     });
                 }
             }
+            Self::Plugin { id, params, .. } => {
+                plugins::run(id, params, img)?;
+            }
+            // needs a graphics context, the editor renders it once the other operators are done
+            Self::PixelShader { .. } => (),
             Self::ChromaticAberration(amt) => {
                 let center = (img.width() as i32 / 2, img.height() as i32 / 2);
                 let img_c = img.clone();
-
-                for (x, y, p) in img.enumerate_pixels_mut() {
-                    let dist_to_center = (x as i32 - center.0, y as i32 - center.1);
-                    let dist_to_center = (
-                        (dist_to_center.0 as f32 / center.0 as f32) * *amt as f32 / 10.,
-                        (dist_to_center.1 as f32 / center.1 as f32) * *amt as f32 / 10.,
-                    );
-                    if let Some(l) = img_c.get_pixel_checked(
-                        (x as i32 + dist_to_center.0 as i32).max(0) as u32,
-                        (y as i32 + dist_to_center.1 as i32).max(0) as u32,
-                    ) {
-                        p[0] = l[0];
-                    }
-                }
+                let width = img.width() as usize;
+
+                // rows are independent, as only the copy is sampled
+                img.par_chunks_mut(width * 4)
+                    .enumerate()
+                    .for_each(|(y, row)| {
+                        for (x, p) in row.chunks_mut(4).enumerate() {
+                            let dist_to_center = (x as i32 - center.0, y as i32 - center.1);
+                            let dist_to_center = (
+                                (dist_to_center.0 as f32 / center.0 as f32) * *amt as f32 / 10.,
+                                (dist_to_center.1 as f32 / center.1 as f32) * *amt as f32 / 10.,
+                            );
+                            if let Some(l) = img_c.get_pixel_checked(
+                                (x as i32 + dist_to_center.0 as i32).max(0) as u32,
+                                (y as i32 + dist_to_center.1 as i32).max(0) as u32,
+                            ) {
+                                p[0] = l[0];
+                            }
+                        }
+                    });
             }
 
             _ => (),
@@ -1104,8 +2816,86 @@ impl ImageOperation {
         Ok(())
     }
 
-    /// Process a single pixel.
-    pub fn process_pixel(&self, p: &mut Vector4<f32>) -> Result<()> {
+    /// Like `process_image`, on an unclamped float buffer. Operators without a float
+    /// implementation run on a quantized copy, which clips highlights.
+    pub fn process_image_f32(&self, img: &mut Rgba32FImage) -> Result<()> {
+        match self {
+            Self::Blur(amt) => {
+                if *amt != 0 {
+                    let (width, height) = (img.width() as usize, img.height() as usize);
+                    gaussian_blur(&mut **img, width, height, *amt as f32, |v| v, |v| v);
+                }
+            }
+            Self::Crop(dim) => {
+                if *dim != [0, 0, 0, 0] {
+                    let window = cropped_range(dim, &(img.width(), img.height()));
+                    let sub_img =
+                        image::imageops::crop_imm(img, window[0], window[1], window[2], window[3]);
+                    *img = sub_img.to_image();
+                }
+            }
+            Self::Resize {
+                dimensions,
+                filter,
+                mode,
+                ..
+            } => {
+                let dimensions = mode.target_dimensions(*dimensions, img.dimensions());
+                if dimensions != Default::default() && dimensions != img.dimensions() {
+                    let filter = match filter {
+                        ScaleFilter::Box => imageops::FilterType::Nearest,
+                        ScaleFilter::Bilinear | ScaleFilter::Hamming => {
+                            imageops::FilterType::Triangle
+                        }
+                        ScaleFilter::CatmullRom | ScaleFilter::Mitchell => {
+                            imageops::FilterType::CatmullRom
+                        }
+                        ScaleFilter::Lanczos3 => imageops::FilterType::Lanczos3,
+                    };
+                    *img = imageops::resize(img, dimensions.0, dimensions.1, filter);
+                }
+            }
+            Self::Rotate(angle) => match angle {
+                90 => *img = image::imageops::rotate90(img),
+                -90 | 270 => *img = image::imageops::rotate270(img),
+                180 => *img = image::imageops::rotate180(img),
+                _ => (),
+            },
+            Self::AutoOrient(orientation) => match orientation {
+                2 => *img = image::imageops::flip_horizontal(img),
+                3 => *img = image::imageops::rotate180(img),
+                4 => *img = image::imageops::flip_vertical(img),
+                5 => *img = image::imageops::flip_horizontal(&image::imageops::rotate90(img)),
+                6 => *img = image::imageops::rotate90(img),
+                7 => *img = image::imageops::flip_horizontal(&image::imageops::rotate270(img)),
+                8 => *img = image::imageops::rotate270(img),
+                _ => (),
+            },
+            Self::Flip(vert) => {
+                if *vert {
+                    *img = image::imageops::flip_vertical(img);
+                }
+                *img = image::imageops::flip_horizontal(img);
+            }
+            Self::PixelShader { .. } => (),
+            _ => {
+                let mut quantized = quantize(img);
+                self.process_image(&mut quantized)?;
+                *img = DynamicImage::ImageRgba8(quantized).into_rgba32f();
+            }
+        }
+        Ok(())
+    }
+
+    /// Process a single pixel. `pos` is the pixel's x/y coordinate within an image of size `dimensions`.
+    /// `source` is the buffer before any pixel operations ran, if an operator requested it.
+    pub fn process_pixel(
+        &self,
+        p: &mut Vector4<f32>,
+        pos: (u32, u32),
+        dimensions: (u32, u32),
+        source: Option<&Arc<RgbaImage>>,
+    ) -> Result<()> {
         match self {
             Self::Brightness(amt) => {
                 let amt = *amt as f32 / 255.;
@@ -1140,35 +2930,8 @@ impl ImageOperation {
                 p[2] = res[2] as f32 / 255.;
             }
             Self::Expression(expr) => {
-                let mut context = context_map! {
-                    "r" => p[0] as f64,
-                    "g" => p[1] as f64,
-                    "b" => p[2] as f64,
-                    "a" => p[3] as f64,
-                }?;
-
-                if eval_empty_with_context_mut(expr, &mut context).is_ok() {
-                    if let Some(r) = context.get_value("r") {
-                        if let Ok(r) = r.as_float() {
-                            p[0] = r as f32
-                        }
-                    }
-                    if let Some(g) = context.get_value("g") {
-                        if let Ok(g) = g.as_float() {
-                            p[1] = g as f32
-                        }
-                    }
-                    if let Some(b) = context.get_value("b") {
-                        if let Ok(b) = b.as_float() {
-                            p[2] = b as f32
-                        }
-                    }
-                    if let Some(a) = context.get_value("a") {
-                        if let Ok(a) = a.as_float() {
-                            p[3] = a as f32
-                        }
-                    }
-                }
+                // Errors are surfaced in the ui, so they are not logged for every pixel
+                _ = eval_expression(expr, p, pos, dimensions, source);
             }
             Self::Posterize(levels) => {
                 p[0] = (p[0] * *levels as f32).round() / *levels as f32;
@@ -1176,17 +2939,56 @@ impl ImageOperation {
                 p[2] = (p[2] * *levels as f32).round() / *levels as f32;
                 // 0.65 * 10.0 = 6.5 / 10
             }
-            Self::Noise { amt, mono } => {
+            Self::Noise {
+                amt,
+                mono,
+                seed,
+                distribution,
+            } => {
                 let amt = *amt as f32 / 100.;
 
-                let mut rng = thread_rng();
-                let n_r: f32 = rng.gen();
-                let n_g: f32 = if *mono { n_r } else { rng.gen() };
-                let n_b: f32 = if *mono { n_r } else { rng.gen() };
+                // seeded by pixel index so the result does not change between runs
+                let index = pos.1 as u64 * dimensions.0 as u64 + pos.0 as u64;
+                let mut noise = PixelNoise::new(*seed, index);
+                let channels = if *mono { 1 } else { 3 };
 
-                p[0] = egui::lerp(p[0]..=n_r, amt);
-                p[1] = egui::lerp(p[1]..=n_g, amt);
-                p[2] = egui::lerp(p[2]..=n_b, amt);
+                match distribution {
+                    NoiseDistribution::Uniform => {
+                        let mut n = [0.; 3];
+                        for v in &mut n[..channels] {
+                            *v = noise.sample();
+                        }
+                        for c in 0..3 {
+                            p[c] = egui::lerp(p[c]..=n[c % channels], amt);
+                        }
+                    }
+                    NoiseDistribution::Gaussian => {
+                        // Box-Muller transform
+                        let mut n = [0.; 3];
+                        for v in &mut n[..channels] {
+                            let u1 = noise.sample().max(f32::EPSILON);
+                            let u2 = noise.sample();
+                            *v = (-2. * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+                        }
+                        for c in 0..3 {
+                            p[c] = (p[c] + n[c % channels] * amt * 0.5).clamp(0., 1.);
+                        }
+                    }
+                    NoiseDistribution::SaltPepper => {
+                        for c in 0..channels {
+                            if noise.sample() < amt {
+                                let v = if noise.sample() < 0.5 { 1. } else { 0. };
+                                if *mono {
+                                    p[0] = v;
+                                    p[1] = v;
+                                    p[2] = v;
+                                } else {
+                                    p[c] = v;
+                                }
+                            }
+                        }
+                    }
+                }
             }
             Self::Fill(col) => {
                 let target =
@@ -1228,6 +3030,60 @@ impl ImageOperation {
                 p[1] = rgb.green;
                 p[2] = rgb.blue;
             }
+            Self::HslBands(bands) => {
+                let rgb: Rgb = Rgb::from_components((p.x, p.y, p.z));
+                let mut hsl: Hsl = rgb.into_color();
+                let shift = hsl_band_shift(bands, hsl.hue.into_positive_degrees());
+
+                hsl.hue += shift[0];
+                hsl.saturation = (hsl.saturation * (1. + shift[1] / 100.)).clamp(0., 1.);
+                // weigh lightness by saturation so greys stay untouched
+                hsl.lightness = (hsl.lightness + shift[2] / 200. * hsl.saturation).clamp(0., 1.);
+                let rgb: Rgb = hsl.into_color();
+
+                p[0] = rgb.red;
+                p[1] = rgb.green;
+                p[2] = rgb.blue;
+            }
+            Self::RedEye { points, radius } => {
+                let radius = *radius as f32 / 1000. * dimensions.0.max(dimensions.1) as f32;
+                for pt in points {
+                    let center = Vector2::new(
+                        pt.0 as f32 / 10000. * dimensions.0 as f32,
+                        pt.1 as f32 / 10000. * dimensions.1 as f32,
+                    );
+                    let d = (Vector2::new(pos.0 as f32, pos.1 as f32) - center).norm();
+                    if d > radius {
+                        continue;
+                    }
+                    // how much more red than the other channels
+                    let redness = p[0] - p[1].max(p[2]);
+                    if redness > 0.1 {
+                        // feather towards the edge of the radius
+                        let amt = (1. - d / radius).min(0.5) * 2.;
+                        let target = (p[1] + p[2]) / 2. * 0.8;
+                        p[0] = lerp(p[0]..=target, amt);
+                    }
+                }
+            }
+            Self::AutoLevels(Some(levels)) => {
+                for c in 0..3 {
+                    let (black, white) = levels[c];
+                    let range = (white as f32 - black as f32).max(1.) / 255.;
+                    p[c] = ((p[c] - black as f32 / 255.) / range).clamp(0., 1.);
+                }
+            }
+            Self::AutoContrast(Some((black, white))) => {
+                let range = (*white as f32 - *black as f32).max(1.) / 255.;
+                for c in 0..3 {
+                    p[c] = ((p[c] - *black as f32 / 255.) / range).clamp(0., 1.);
+                }
+            }
+            Self::AutoWhiteBalance(Some(gains)) => {
+                for c in 0..3 {
+                    p[c] = (p[c] * gains[c] as f32 / 1000.).clamp(0., 1.);
+                }
+            }
             Self::Invert => {
                 p[0] = 1. - p[0];
                 p[1] = 1. - p[1];
@@ -1243,6 +3099,9 @@ impl ImageOperation {
                 p[1] /= p[3];
                 p[2] /= p[3];
             }
+            Self::Erase => {
+                *p = Vector4::zeros();
+            }
             Self::Contrast(val) => {
                 let factor: f32 = (1.015_686_3 * (*val as f32 / 255. + 1.0))
                     / (1.0 * (1.015_686_3 - *val as f32 / 255.));
@@ -1256,6 +3115,156 @@ impl ImageOperation {
     }
 }
 
+/// Example expressions users can pick from
+pub const EXPRESSION_SNIPPETS: &[(&str, &str)] = &[
+    ("Invert", "r = 1 - r; g = 1 - g; b = 1 - b"),
+    (
+        "Sepia",
+        "nr = 0.393 * r + 0.769 * g + 0.189 * b; ng = 0.349 * r + 0.686 * g + 0.168 * b; nb = 0.272 * r + 0.534 * g + 0.131 * b; r = min(nr, 1.0); g = min(ng, 1.0); b = min(nb, 1.0)",
+    ),
+    ("Swap red and blue", "t = r; r = b; b = t"),
+    ("Grayscale", "l = 0.299 * r + 0.587 * g + 0.114 * b; r = l; g = l; b = l"),
+    (
+        "Horizontal gradient",
+        "f = x / width; r = r * f; g = g * f; b = b * f",
+    ),
+    (
+        "Edge detect",
+        "r = 4 * r - sample(1, 0, 0) - sample(-1, 0, 0) - sample(0, 1, 0) - sample(0, -1, 0); g = r; b = r",
+    ),
+];
+
+/// Evaluate a user expression for a single pixel, writing back the resulting channels
+fn eval_expression(
+    expr: &str,
+    p: &mut Vector4<f32>,
+    pos: (u32, u32),
+    dimensions: (u32, u32),
+    source: Option<&Arc<RgbaImage>>,
+) -> Result<()> {
+    let mut context = context_map! {
+        "r" => p[0] as f64,
+        "g" => p[1] as f64,
+        "b" => p[2] as f64,
+        "a" => p[3] as f64,
+        "x" => pos.0 as f64,
+        "y" => pos.1 as f64,
+        "width" => dimensions.0 as f64,
+        "height" => dimensions.1 as f64,
+    }?;
+
+    if let Some(source) = source {
+        let source = source.clone();
+        context.set_function(
+            "sample".into(),
+            Function::new(move |argument| {
+                let args = argument.as_fixed_len_tuple(3)?;
+                let dx = args[0].as_number()? as i64;
+                let dy = args[1].as_number()? as i64;
+                let channel = args[2].as_int()?.clamp(0, 3) as usize;
+                // clamp to the image edges
+                let x = (pos.0 as i64 + dx).clamp(0, source.width() as i64 - 1);
+                let y = (pos.1 as i64 + dy).clamp(0, source.height() as i64 - 1);
+                let value = source.get_pixel(x as u32, y as u32)[channel];
+                Ok(Value::Float(value as f64 / 255.))
+            }),
+        )?;
+    }
+
+    eval_empty_with_context_mut(expr, &mut context)?;
+    if let Some(r) = context.get_value("r") {
+        if let Ok(r) = r.as_number() {
+            p[0] = r as f32
+        }
+    }
+    if let Some(g) = context.get_value("g") {
+        if let Ok(g) = g.as_number() {
+            p[1] = g as f32
+        }
+    }
+    if let Some(b) = context.get_value("b") {
+        if let Ok(b) = b.as_number() {
+            p[2] = b as f32
+        }
+    }
+    if let Some(a) = context.get_value("a") {
+        if let Ok(a) = a.as_number() {
+            p[3] = a as f32
+        }
+    }
+    Ok(())
+}
+
+/// Check an expression by evaluating it on a dummy pixel. Returns a description of the error, if any.
+pub fn expression_error(expr: &str) -> Option<String> {
+    let source = Arc::new(RgbaImage::new(1, 1));
+    let mut p = Vector4::new(0.5, 0.5, 0.5, 1.0);
+    eval_expression(expr, &mut p, (0, 0), (1, 1), Some(&source))
+        .err()
+        .map(|e| e.to_string())
+}
+
+/// Find the lowest and highest value of a histogram, ignoring `clip` of the pixels at each end
+fn histogram_bounds(hist: &[u64; 256], clip: f32) -> (u8, u8) {
+    let total: u64 = hist.iter().sum();
+    let threshold = (total as f32 * clip) as u64;
+
+    let mut acc = 0;
+    let low = hist
+        .iter()
+        .position(|count| {
+            acc += count;
+            acc > threshold
+        })
+        .unwrap_or(0);
+    acc = 0;
+    let high = 255
+        - hist
+            .iter()
+            .rev()
+            .position(|count| {
+                acc += count;
+                acc > threshold
+            })
+            .unwrap_or(0);
+    (low as u8, high.max(low) as u8)
+}
+
+/// Names and center hues (in degrees) of the bands of the `HslBands` operator
+pub const HSL_BANDS: [(&str, f32); 8] = [
+    ("Red", 0.),
+    ("Orange", 30.),
+    ("Yellow", 60.),
+    ("Green", 120.),
+    ("Aqua", 180.),
+    ("Blue", 240.),
+    ("Purple", 270.),
+    ("Magenta", 300.),
+];
+
+/// Interpolate the shift of the two bands surrounding `hue`
+pub fn hsl_band_shift(bands: &[[i32; 3]; 8], hue: f32) -> [f32; 3] {
+    let next = HSL_BANDS
+        .iter()
+        .position(|(_, center)| *center > hue)
+        .unwrap_or(0);
+    let prev = (next + HSL_BANDS.len() - 1) % HSL_BANDS.len();
+
+    let start = HSL_BANDS[prev].1;
+    let mut end = HSL_BANDS[next].1;
+    if end <= start {
+        // wrap around from magenta to red
+        end += 360.;
+    }
+    let t = ((hue - start).rem_euclid(360.) / (end - start)).clamp(0., 1.);
+
+    let mut shift = [0.; 3];
+    for (i, s) in shift.iter_mut().enumerate() {
+        *s = lerp(bands[prev][i] as f32..=bands[next][i] as f32, t);
+    }
+    shift
+}
+
 pub fn desaturate(p: &mut Vector4<f32>, factor: f32) {
     // G*.59+R*.3+B*.11
     let val = p[0] * 0.59 + p[1] * 0.3 + p[2] * 0.11;
@@ -1285,43 +3294,491 @@ pub fn builtin_luts() -> HashMap<String, Vec<u8>> {
 }
 
 pub fn process_pixels(buffer: &mut RgbaImage, operators: &Vec<ImageOperation>) {
-    // use pulp::Arch;
-    // let arch = Arch::new();
+    PixelStack {
+        operators: operators.clone(),
+        masks: vec![],
+        blend_modes: vec![],
+    }
+    .process(buffer)
+}
 
-    // arch.dispatch(|| {
-    //         for x in &mut buffer.into_vec() {
-    //             *x = 12 as u8;
-    //         }
-    //     });
+/// Process all active pixel operators of a stack, limiting each one to its mask
+pub fn process_pixel_stack(buffer: &mut RgbaImage, stack: &[ImgOpItem]) {
+    PixelStack::new(stack, buffer.dimensions()).process(buffer)
+}
 
-    buffer
-        // .chunks_mut(4)
-        .par_chunks_mut(4)
-        .for_each(|px| {
-            // let mut float_pixel = image::Rgba([
-            //     px[0] as f32 / 255.,
-            //     px[1] as f32 / 255.,
-            //     px[2] as f32 / 255.,
-            //     px[3] as f32 / 255.,
-            // ]);
-
-            let mut float_pixel =
-                Vector4::new(px[0] as f32, px[1] as f32, px[2] as f32, px[3] as f32) / 255.;
-
-            // run pixel operations
-            for operation in operators {
-                if let Err(e) = operation.process_pixel(&mut float_pixel) {
+/// Process all active pixel operators of a stack on an unclamped float buffer
+pub fn process_pixel_stack_f32(buffer: &mut Rgba32FImage, stack: &[ImgOpItem]) {
+    PixelStack::new(stack, buffer.dimensions()).process_f32(buffer)
+}
+
+/// Move the operator at `from` to `to`, shifting the ones in between. Out of range indices are ignored.
+pub fn move_operator(stack: &mut Vec<ImgOpItem>, from: usize, to: usize) {
+    if from < stack.len() && to < stack.len() {
+        let operation = stack.remove(from);
+        stack.insert(to, operation);
+    }
+}
+
+/// Intermediate results of an operator stack. When an operator changes,
+/// processing restarts from its input if that was kept.
+#[derive(Debug, Clone)]
+pub struct StackCache<T> {
+    /// The stack as it was last processed
+    items: Vec<ImgOpItem>,
+    /// The input of the operator at this index. Only one is kept to bound memory, which is
+    /// enough while the same operator is edited.
+    input: Option<(usize, T)>,
+}
+
+impl<T> Default for StackCache<T> {
+    fn default() -> Self {
+        Self {
+            items: vec![],
+            input: None,
+        }
+    }
+}
+
+impl<T: Clone> StackCache<T> {
+    /// Forget all results, for example because the input of the stack changed
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.input = None;
+    }
+
+    /// Run `stack` on `buffer`, which must be the same input as in previous calls.
+    /// `process` runs a slice of the stack on a buffer. The input of the first changed operator
+    /// is kept, so consecutive operators are still handled in one pass.
+    pub fn process(
+        &mut self,
+        buffer: &mut T,
+        stack: &[ImgOpItem],
+        process: impl Fn(&[ImgOpItem], &mut T),
+    ) {
+        // operators sampling the stack input can't be split from the operators before them
+        if stack.is_empty() || stack.iter().any(|i| i.active && i.operation.needs_source()) {
+            self.clear();
+            process(stack, buffer);
+            return;
+        }
+
+        let unchanged = self
+            .items
+            .iter()
+            .zip(stack)
+            .take_while(|(cached, item)| cached == item)
+            .count();
+        self.items = stack.to_vec();
+        // without changes, the last operator is run again
+        let split = unchanged.min(stack.len() - 1);
+
+        // restart from the kept input, unless an operator before it changed
+        let start = match self.input.take() {
+            Some((i, input)) if i <= split => {
+                *buffer = input;
+                i
+            }
+            _ => 0,
+        };
+        if split > start {
+            process(&stack[start..split], buffer);
+        }
+        self.input = Some((split, buffer.clone()));
+        process(&stack[split..], buffer);
+    }
+}
+
+/// Caches of both operator stacks, for 8 bit and float sources
+#[derive(Debug, Clone, Default)]
+pub struct EditCaches {
+    pub image_ops: StackCache<RgbaImage>,
+    pub image_ops_f32: StackCache<Rgba32FImage>,
+    pub pixel_ops: StackCache<RgbaImage>,
+    pub pixel_ops_f32: StackCache<Rgba32FImage>,
+    pub image_op_thumbnails: ThumbnailCache,
+    pub pixel_op_thumbnails: ThumbnailCache,
+    /// The EXIF orientation of the current file, read once for `AutoOrient`
+    pub orientation: Option<u8>,
+}
+
+impl EditCaches {
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+
+    /// The pixel stack input changed
+    pub fn clear_pixel_ops(&mut self) {
+        self.pixel_ops.clear();
+        self.pixel_ops_f32.clear();
+        self.pixel_op_thumbnails.clear();
+    }
+}
+
+/// Longest side of the image while an operator is dragged
+pub const PROXY_SIZE: u32 = 1500;
+
+/// Longest side of the operator preview thumbnails
+pub const THUMBNAIL_SIZE: u32 = 64;
+
+/// Downscale `img` to fit into `size`, keeping the aspect ratio
+pub fn proxy(img: &RgbaImage, size: u32) -> RgbaImage {
+    let (w, h) = img.dimensions();
+    if w <= size && h <= size {
+        return img.clone();
+    }
+    let scale = size as f32 / w.max(h) as f32;
+    imageops::thumbnail(
+        img,
+        ((w as f32 * scale) as u32).max(1),
+        ((h as f32 * scale) as u32).max(1),
+    )
+}
+
+/// Thumbnails of the cumulative result after each operator of a stack. Only the thumbnails from
+/// the first changed operator on are made again, from the result of the operator before it.
+#[derive(Debug, Clone, Default)]
+pub struct ThumbnailCache {
+    /// The stack the thumbnails were made for
+    items: Vec<ImgOpItem>,
+    /// Whether the stack was processed per pixel
+    per_pixel: bool,
+    thumbnails: Vec<RgbaImage>,
+    /// The unquantized results of a per pixel stack, which the next operator continues from
+    results: Vec<Rgba32FImage>,
+}
+
+impl ThumbnailCache {
+    /// Forget all thumbnails, for example because the input of the stack changed
+    pub fn clear(&mut self) {
+        *self = Default::default();
+    }
+
+    pub fn thumbnails(&self) -> &[RgbaImage] {
+        &self.thumbnails
+    }
+
+    /// Make the thumbnails of `stack` on `img`, which must be the same input as in previous calls.
+    /// Returns the index of the first thumbnail that changed. Operators that use pixel positions
+    /// are skipped, as they would not match the thumbnail.
+    pub fn update(&mut self, img: &RgbaImage, stack: &[ImgOpItem]) -> usize {
+        let skipped = |item: &ImgOpItem| !item.active || item.operation.uses_pixel_positions();
+        let per_pixel = stack.iter().all(|i| i.operation.is_per_pixel());
+        let mut start = self
+            .items
+            .iter()
+            .zip(stack)
+            .take_while(|(cached, item)| cached == item)
+            .count();
+        if per_pixel != self.per_pixel {
+            start = 0;
+        }
+        self.items = stack.to_vec();
+        self.per_pixel = per_pixel;
+        self.thumbnails.truncate(start);
+        self.results.truncate(start);
+
+        if per_pixel {
+            // operators sampling the stack input need all operators before them in the same pass
+            let from_source = stack
+                .iter()
+                .any(|i| !skipped(i) && i.operation.needs_source());
+            let active = |items: &[ImgOpItem]| {
+                items
+                    .iter()
+                    .cloned()
+                    .map(|mut item| {
+                        item.active = !skipped(&item);
+                        item
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let mut source = None;
+            for i in start..stack.len() {
+                let result = match self.results.last() {
+                    Some(previous) if !from_source => {
+                        let mut result = previous.clone();
+                        process_pixel_stack_f32(&mut result, &active(&stack[i..=i]));
+                        result
+                    }
+                    _ => {
+                        let mut result = source
+                            .get_or_insert_with(|| {
+                                DynamicImage::ImageRgba8(proxy(img, THUMBNAIL_SIZE)).into_rgba32f()
+                            })
+                            .clone();
+                        process_pixel_stack_f32(&mut result, &active(&stack[..=i]));
+                        result
+                    }
+                };
+                self.thumbnails.push(quantize(&result));
+                self.results.push(result);
+            }
+            return start;
+        }
+
+        let mut thumbnail = match self.thumbnails.last() {
+            Some(previous) => previous.clone(),
+            None => proxy(img, THUMBNAIL_SIZE),
+        };
+        for item in &stack[start..] {
+            if !skipped(item) {
+                if let Err(e) = item.process_image(&mut thumbnail) {
                     error!("{e}")
                 }
+                // keep the following operators cheap if this one enlarged the image
+                thumbnail = proxy(&thumbnail, THUMBNAIL_SIZE);
+            }
+            self.thumbnails.push(thumbnail.clone());
+        }
+        start
+    }
+}
+
+/// Separable gaussian blur of an RGBA buffer, with rows processed in parallel.
+/// `sigma` matches `imageops::blur`, `to_f32` and `from_f32` convert the channel values.
+pub fn gaussian_blur<S: Copy + Send + Sync>(
+    data: &mut [S],
+    width: usize,
+    height: usize,
+    sigma: f32,
+    to_f32: impl Fn(S) -> f32 + Sync,
+    from_f32: impl Fn(f32) -> S + Sync,
+) {
+    let sigma = if sigma <= 0. { 1. } else { sigma };
+    let radius = (2. * sigma).ceil() as i64;
+    let stride = width * 4;
+    if width == 0 || height == 0 || data.len() < stride * height {
+        return;
+    }
+
+    let kernel = (-radius..=radius)
+        .map(|x| (-(x * x) as f32 / (2. * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let sum = kernel.iter().sum::<f32>();
+    let kernel = kernel.iter().map(|k| k / sum).collect::<Vec<_>>();
+
+    // horizontal pass into a float buffer, so values are only quantized once
+    let mut tmp = vec![0_f32; stride * height];
+    tmp.par_chunks_mut(stride)
+        .zip(data.par_chunks(stride))
+        .for_each(|(out, row)| {
+            for x in 0..width {
+                let mut acc = [0_f32; 4];
+                for (k, weight) in kernel.iter().enumerate() {
+                    let sx = (x as i64 + k as i64 - radius).clamp(0, width as i64 - 1) as usize;
+                    for c in 0..4 {
+                        acc[c] += to_f32(row[sx * 4 + c]) * weight;
+                    }
+                }
+                out[x * 4..x * 4 + 4].copy_from_slice(&acc);
+            }
+        });
+
+    // vertical pass, accumulating whole rows
+    data.par_chunks_mut(stride)
+        .enumerate()
+        .for_each(|(y, out)| {
+            let mut acc = vec![0_f32; stride];
+            for (k, weight) in kernel.iter().enumerate() {
+                let sy = (y as i64 + k as i64 - radius).clamp(0, height as i64 - 1) as usize;
+                let row = &tmp[sy * stride..(sy + 1) * stride];
+                for (a, v) in acc.iter_mut().zip(row) {
+                    *a += v * weight;
+                }
+            }
+            for (o, a) in out.iter_mut().zip(acc) {
+                *o = from_f32(a);
+            }
+        });
+}
+
+/// Quantize an unclamped float buffer for display or export
+pub fn quantize(img: &Rgba32FImage) -> RgbaImage {
+    let raw = img
+        .as_raw()
+        .par_iter()
+        .map(|v| (v.clamp(0., 1.) * 255.) as u8)
+        .collect::<Vec<_>>();
+    RgbaImage::from_raw(img.width(), img.height(), raw).unwrap_or_default()
+}
+
+/// Operators ready to run per pixel. Masks and blend modes are matched to operators by index.
+/// Missing masks mean full coverage, missing blend modes are `Normal`.
+struct PixelStack {
+    operators: Vec<ImageOperation>,
+    masks: Vec<Option<GrayImage>>,
+    blend_modes: Vec<BlendMode>,
+}
+
+impl PixelStack {
+    fn new(stack: &[ImgOpItem], dimensions: (u32, u32)) -> Self {
+        let active = stack.iter().filter(|i| i.active).collect::<Vec<_>>();
+        Self {
+            operators: active.iter().map(|i| i.operation.clone()).collect(),
+            masks: active
+                .iter()
+                .map(|i| i.mask.as_ref().map(|m| m.render(dimensions)))
+                .collect(),
+            blend_modes: active.iter().map(|i| i.blend_mode).collect(),
+        }
+    }
+
+    fn needs_source(&self) -> bool {
+        self.operators.iter().any(|op| op.needs_source())
+    }
+
+    /// Run all operators on pixel number `i`
+    fn apply(
+        &self,
+        p: &mut Vector4<f32>,
+        i: usize,
+        pos: (u32, u32),
+        dimensions: (u32, u32),
+        source: Option<&Arc<RgbaImage>>,
+    ) {
+        for (j, operation) in self.operators.iter().enumerate() {
+            let before = *p;
+            if let Err(e) = operation.process_pixel(p, pos, dimensions, source) {
+                error!("{e}")
+            }
+            if let Some(mode) = self.blend_modes.get(j).filter(|m| **m != BlendMode::Normal) {
+                for c in 0..3 {
+                    p[c] = mode.blend(before[c], p[c].clamp(0., 1.));
+                }
+            }
+            if let Some(Some(mask)) = self.masks.get(j) {
+                let coverage = mask.as_raw()[i] as f32 / 255.;
+                *p = before.lerp(p, coverage);
+            }
+        }
+    }
+
+    /// All operators combined into a single `p * mul + add` transform, if possible
+    fn affine(&self) -> Option<([f32; 4], [f32; 4])> {
+        if self.masks.iter().any(|m| m.is_some())
+            || self.blend_modes.iter().any(|m| *m != BlendMode::Normal)
+        {
+            return None;
+        }
+        let mut mul = [1_f32; 4];
+        let mut add = [0_f32; 4];
+        for operation in &self.operators {
+            let (m, a) = operation.affine()?;
+            for c in 0..4 {
+                mul[c] *= m[c];
+                add[c] = add[c] * m[c] + a[c];
             }
+        }
+        Some((mul, add))
+    }
+
+    fn process(&self, buffer: &mut RgbaImage) {
+        if let Some((mul, add)) = self.affine() {
+            process_affine_simd(buffer, mul, add);
+            return;
+        }
+
+        let dimensions = buffer.dimensions();
+        let width = dimensions.0 as usize;
+
+        // Operators that sample neighbors need the unmodified input, as pixels are processed in place
+        let source = if self.needs_source() {
+            Some(Arc::new(buffer.clone()))
+        } else {
+            None
+        };
+
+        buffer
+            // .chunks_mut(4)
+            .par_chunks_mut(4)
+            .enumerate()
+            .for_each(|(i, px)| {
+                let pos = ((i % width) as u32, (i / width) as u32);
+
+                let mut float_pixel =
+                    Vector4::new(px[0] as f32, px[1] as f32, px[2] as f32, px[3] as f32) / 255.;
 
-            float_pixel *= 255.;
+                // run pixel operations
+                self.apply(&mut float_pixel, i, pos, dimensions, source.as_ref());
 
-            px[0] = (float_pixel[0]) as u8;
-            px[1] = (float_pixel[1]) as u8;
-            px[2] = (float_pixel[2]) as u8;
-            px[3] = (float_pixel[3]) as u8;
+                float_pixel *= 255.;
+
+                px[0] = (float_pixel[0]) as u8;
+                px[1] = (float_pixel[1]) as u8;
+                px[2] = (float_pixel[2]) as u8;
+                px[3] = (float_pixel[3]) as u8;
+            });
+    }
+
+    /// Like `process`, but values are kept outside of 0-1
+    fn process_f32(&self, buffer: &mut Rgba32FImage) {
+        let dimensions = buffer.dimensions();
+        let width = dimensions.0 as usize;
+
+        // sampling operators read the quantized input
+        let source = if self.needs_source() {
+            Some(Arc::new(quantize(buffer)))
+        } else {
+            None
+        };
+
+        buffer.par_chunks_mut(4).enumerate().for_each(|(i, px)| {
+            let pos = ((i % width) as u32, (i / width) as u32);
+            let mut float_pixel = Vector4::new(px[0], px[1], px[2], px[3]);
+            self.apply(&mut float_pixel, i, pos, dimensions, source.as_ref());
+            px.copy_from_slice(float_pixel.as_slice());
         });
+    }
+}
+
+/// Pixels per SIMD chunk
+const SIMD_PIXELS: usize = 16;
+
+/// Apply `p * mul + add` to all pixels, two pixels per SIMD vector.
+/// Values are converted like the scalar path, saturating and truncating.
+fn process_affine_simd(buffer: &mut RgbaImage, mul: [f32; 4], add: [f32; 4]) {
+    // work in 0-255, so the scale to 0-1 and back is folded into the offset
+    let add = add.map(|a| a * 255.);
+    let mul = f32x8::from([
+        mul[0], mul[1], mul[2], mul[3], mul[0], mul[1], mul[2], mul[3],
+    ]);
+    let add = f32x8::from([
+        add[0], add[1], add[2], add[3], add[0], add[1], add[2], add[3],
+    ]);
+    let min = f32x8::splat(0.);
+    let max = f32x8::splat(255.);
+
+    let transform = |values: &mut [u8]| {
+        let mut lanes = [0_f32; 8];
+        for (lane, v) in lanes.iter_mut().zip(values.iter()) {
+            *lane = *v as f32;
+        }
+        let result = (f32x8::from(lanes) * mul + add).max(min).min(max);
+        for (v, r) in values.iter_mut().zip(result.to_array()) {
+            *v = r as u8;
+        }
+    };
+
+    buffer
+        .par_chunks_mut(SIMD_PIXELS * 4)
+        .for_each(|chunk| chunk.chunks_mut(8).for_each(transform));
+}
+
+/// Shrink a normalized crop (left, top, right margin, bottom margin) so that its window
+/// matches `ratio` (width / height) on an image of size `img_dim`.
+pub fn constrain_crop_aspect(crop: &mut [f32; 4], ratio: f32, img_dim: (f32, f32)) {
+    let crop_w = (1. - crop[0] - crop[2]).max(0.) * img_dim.0;
+    let crop_h = (1. - crop[1] - crop[3]).max(0.) * img_dim.1;
+    if crop_w <= 0. || crop_h <= 0. {
+        return;
+    }
+    if crop_w / crop_h > ratio {
+        // too wide, shrink width
+        crop[2] = 1. - crop[0] - crop_h * ratio / img_dim.0;
+    } else {
+        crop[3] = 1. - crop[1] - crop_w / ratio / img_dim.1;
+    }
 }
 
 /// Crop a left,top (x,y) plus x/y window safely into absolute pixel units.
@@ -1360,23 +3817,11 @@ pub fn lossless_tx(p: &std::path::Path, transform: turbojpeg::Transform) -> anyh
 
     // read the JPEG header
     let header = decompressor.read_header(&jpeg_data)?;
-    let mcu_h = header.subsamp.mcu_height();
-    let mcu_w = header.subsamp.mcu_width();
-
-    debug!("h {mcu_h} w {mcu_w}");
 
     // make sure crop is aligned to mcu bounds
     let mut transform = transform;
     if let Some(c) = transform.crop.as_mut() {
-        c.x = (c.x as f32 / mcu_w as f32) as usize * mcu_w;
-        c.y = (c.y as f32 / mcu_h as f32) as usize * mcu_h;
-        // the start point may have shifted, make sure we don't go over bounds
-        // if let Some(crop_w) = c.width.as_mut() {
-        //     *crop_w = *crop_w;
-        // }
-        // if let Some(crop_h) = c.height.as_mut() {
-        //     // *crop_h = (*crop_h + c.y).min(header.height - c.y);
-        // }
+        *c = mcu_aligned_crop(c, &header);
         debug!("jpg crop transform {:#?}", c);
     }
 
@@ -1388,6 +3833,97 @@ pub fn lossless_tx(p: &std::path::Path, transform: turbojpeg::Transform) -> anyh
     Ok(())
 }
 
+/// Move the start of a crop to the MCU grid of the JPEG, growing it so the end stays put
+#[cfg(feature = "turbo")]
+fn mcu_aligned_crop(
+    crop: &turbojpeg::TransformCrop,
+    header: &turbojpeg::DecompressHeader,
+) -> turbojpeg::TransformCrop {
+    let mcu_w = header.subsamp.mcu_width();
+    let mcu_h = header.subsamp.mcu_height();
+    debug!("h {mcu_h} w {mcu_w}");
+    let x = crop.x / mcu_w * mcu_w;
+    let y = crop.y / mcu_h * mcu_h;
+    turbojpeg::TransformCrop {
+        x,
+        y,
+        width: crop.width.map(|w| (w + crop.x - x).min(header.width - x)),
+        height: crop.height.map(|h| (h + crop.y - y).min(header.height - y)),
+    }
+}
+
+/// The lossless JPEG transforms that do the same as `op`, or None if it needs to decode the image.
+/// A crop maps to `TransformOp::None`, its region depends on the size of the image at that point.
+#[cfg(feature = "turbo")]
+pub fn lossless_ops(op: &ImageOperation) -> Option<Vec<turbojpeg::TransformOp>> {
+    use turbojpeg::TransformOp as Op;
+    Some(match op {
+        ImageOperation::Crop(_) => vec![Op::None],
+        ImageOperation::Rotate(90) => vec![Op::Rot90],
+        ImageOperation::Rotate(180) => vec![Op::Rot180],
+        ImageOperation::Rotate(-90 | 270) => vec![Op::Rot270],
+        // the same order as `process_image`
+        ImageOperation::Flip(true) => vec![Op::Vflip, Op::Hflip],
+        ImageOperation::Flip(false) => vec![Op::Hflip],
+        ImageOperation::AutoOrient(orientation) => vec![match orientation {
+            2 => Op::Hflip,
+            3 => Op::Rot180,
+            4 => Op::Vflip,
+            5 => Op::Transpose,
+            6 => Op::Rot90,
+            7 => Op::Transverse,
+            8 => Op::Rot270,
+            _ => Op::None,
+        }],
+        _ => return None,
+    })
+}
+
+/// Apply the active operators of `stack` to a JPEG without recompressing it, and write it back.
+/// Crops are aligned to the MCU grid, and partial MCUs at the edges are trimmed when rotating.
+/// Nothing is written if one of the operators can't be applied losslessly.
+#[cfg(feature = "turbo")]
+pub fn lossless_apply(p: &Path, stack: &[ImgOpItem]) -> Result<()> {
+    let mut data = std::fs::read(p)?;
+    for item in stack.iter().filter(|i| i.active) {
+        if item.mask.is_some() {
+            anyhow::bail!("{} is limited to a selection", item.operation);
+        }
+        let Some(ops) = lossless_ops(&item.operation) else {
+            anyhow::bail!("{} can't be applied losslessly", item.operation);
+        };
+        for op in ops {
+            let crop = match &item.operation {
+                ImageOperation::Crop(amount) if *amount != [0, 0, 0, 0] => {
+                    let header = turbojpeg::Decompressor::new()?.read_header(&data)?;
+                    let range = cropped_range(amount, &(header.width as u32, header.height as u32));
+                    let crop = turbojpeg::TransformCrop {
+                        x: range[0] as usize,
+                        y: range[1] as usize,
+                        width: Some(range[2] as usize),
+                        height: Some(range[3] as usize),
+                    };
+                    Some(mcu_aligned_crop(&crop, &header))
+                }
+                _ => None,
+            };
+            if matches!(op, turbojpeg::TransformOp::None) && crop.is_none() {
+                continue;
+            }
+            let transform = turbojpeg::Transform {
+                op,
+                crop,
+                trim: true,
+                ..turbojpeg::Transform::default()
+            };
+            debug!("lossless transform {:?}", transform);
+            data = turbojpeg::transform(&transform, &data)?;
+        }
+    }
+    std::fs::write(p, data)?;
+    Ok(())
+}
+
 fn interpolate_u8(data: &Vec<GradientStop>, pt: u8) -> [u8; 3] {
     // debug!("Pt is {pt}");
 
@@ -1,5 +1,11 @@
+use crate::archive;
+use crate::dicom::DicomImage;
+use crate::fits::FitsImage;
+use crate::heif;
 use crate::ktx2_loader::CompressedImageFormats;
+use crate::tiled::TiledImage;
 use crate::utils::{fit, Frame, FrameSource};
+use crate::video::{Video, VIDEO_EXTENSIONS};
 use crate::{ktx2_loader, FONT};
 use log::{debug, error, info};
 use psd::Psd;
@@ -9,7 +15,8 @@ use dds::DDS;
 use exr::prelude as exrs;
 use exr::prelude::*;
 use image::{
-    DynamicImage, EncodableLayout, GrayAlphaImage, GrayImage, Rgb32FImage, RgbImage, RgbaImage,
+    DynamicImage, GrayAlphaImage, GrayImage, Rgb32FImage, Rgba32FImage, RgbImage,
+    RgbaImage,
 };
 use jxl_oxide::{JxlImage, PixelFormat};
 use quickraw::{data, DemosaicingMethod, Export, Input, Output, OutputType};
@@ -19,81 +26,210 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
 use tiff::decoder::Limits;
 use usvg::{TreeParsing, TreeTextToPath};
 use webp_animation::prelude::*;
 use zune_png::zune_core::options::DecoderOptions;
 use zune_png::zune_core::result::DecodingResult;
 
-/// Open an image from disk and send it somewhere
-pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
-    let (sender, receiver): (Sender<Frame>, Receiver<Frame>) = channel();
-    let img_location = (*img_location).to_owned();
+/// Formats with mip levels, which can be decoded with `texture_mips`
+pub const TEXTURE_EXTENSIONS: &[&str] = &["dds", "ktx2", "basis"];
 
-    match img_location
+/// Decode every mip level of a texture, largest first
+pub fn texture_mips(img_location: &Path) -> Result<Vec<RgbaImage>> {
+    let extension = img_location
         .extension()
         .unwrap_or_default()
-        .to_str()
-        .unwrap_or_default()
-        .to_lowercase()
-        .as_str()
-    {
+        .to_string_lossy()
+        .to_lowercase();
+    let mips = match extension.as_str() {
         "dds" => {
             let file = File::open(img_location)?;
             let mut reader = BufReader::new(file);
             let dds = DDS::decode(&mut reader).map_err(|e| anyhow!("{:?}", e))?;
-            if let Some(main_layer) = dds.layers.get(0) {
-                let buf = main_layer.as_bytes();
-                let buf =
-                    image::ImageBuffer::from_raw(dds.header.width, dds.header.height, buf.into())
-                        .context("Can't create DDS ImageBuffer with given res")?;
-                _ = sender.send(Frame::new_still(buf));
-                return Ok(receiver);
-            }
+            // the layers of a DDS file are its mip levels
+            dds.layers
+                .iter()
+                .enumerate()
+                .map(|(level, layer)| {
+                    image::ImageBuffer::from_raw(
+                        (dds.header.width >> level).max(1),
+                        (dds.header.height >> level).max(1),
+                        layer.as_bytes().into(),
+                    )
+                    .context("Can't create DDS ImageBuffer with given res")
+                })
+                .collect::<Result<Vec<_>>>()?
         }
-        "ktx2" => {
-            // let file = File::open(img_location)?;
+        "ktx2" | "basis" => {
             let data = std::fs::read(img_location)?;
-
-            // let c = Cursor::new(file);
-            // let b = BufReader::new(file);
-            // file.re
-            // let mut reader = ktx2::Reader::new(reader.).expect("Can't create reader"); // Crate instance of reader.
-
-            let ktx = ktx2_loader::ktx2_buffer_to_image(
-                data.as_bytes(),
+            ktx2_loader::Image::from_buffer(
+                &data,
+                ktx2_loader::ImageType::Extension(&extension),
                 CompressedImageFormats::all(),
                 true,
-            )?;
-            let d = ktx.try_into_dynamic()?;
-            _ = sender.send(Frame::new_still(d.into_rgba8()));
+            )?
+            .mip_levels()?
+        }
+        _ => bail!("{} is not a texture format", extension),
+    };
+    if mips.is_empty() {
+        bail!("The texture has no image data");
+    }
+    Ok(mips)
+}
+
+/// All mip levels side by side: the largest on the left, the smaller ones stacked to its right
+pub fn mip_chain(mips: &[RgbaImage]) -> RgbaImage {
+    let Some((first, rest)) = mips.split_first() else {
+        return RgbaImage::default();
+    };
+    let width = first.width() + rest.first().map(|m| m.width()).unwrap_or_default();
+    let height = first
+        .height()
+        .max(rest.iter().map(|m| m.height()).sum::<u32>());
+    let mut chain = RgbaImage::new(width, height);
+    image::imageops::replace(&mut chain, first, 0, 0);
+    let mut y = 0;
+    for mip in rest {
+        image::imageops::replace(&mut chain, mip, first.width() as i64, y);
+        y += mip.height() as i64;
+    }
+    chain
+}
+
+/// Formats that store an image in several sizes, which can be decoded with `icon_sizes`
+pub const ICON_EXTENSIONS: &[&str] = &["ico", "icns"];
+
+/// Decode every size stored in an icon, largest first
+pub fn icon_sizes(img_location: &Path) -> Result<Vec<RgbaImage>> {
+    let data = std::fs::read(img_location)?;
+    let extension = img_location
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let mut sizes = match extension.as_str() {
+        "ico" => {
+            let count = data
+                .get(4..6)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]) as usize)
+                .context("Not an icon file")?;
+            let entries = data
+                .get(6..6 + count * 16)
+                .context("The icon directory is cut off")?;
+            let mut sizes = vec![];
+            for entry in entries.chunks_exact(16) {
+                let len = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+                let offset =
+                    u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as usize;
+                let Some(image_data) = data.get(offset..offset + len) else {
+                    continue;
+                };
+                // an icon with only this entry, so the decoder picks it
+                let single = [
+                    &data[..4],
+                    &1_u16.to_le_bytes()[..],
+                    &entry[..12],
+                    &22_u32.to_le_bytes()[..],
+                    image_data,
+                ]
+                .concat();
+                match image::load_from_memory_with_format(&single, image::ImageFormat::Ico) {
+                    Ok(d) => sizes.push(d.into_rgba8()),
+                    Err(e) => debug!("Skipping icon entry: {e}"),
+                }
+            }
+            sizes
+        }
+        "icns" => {
+            let icon_family = icns::IconFamily::read(std::io::Cursor::new(data))?;
+            icon_family
+                .available_icons()
+                .into_iter()
+                .filter_map(|icon_type| {
+                    let image = icon_family.get_icon_with_type(icon_type).ok()?;
+                    let mut target = vec![];
+                    image.write_png(&mut target).ok()?;
+                    image::load_from_memory(&target).ok().map(|d| d.into_rgba8())
+                })
+                .collect()
+        }
+        _ => bail!("{} is not an icon format", extension),
+    };
+    if sizes.is_empty() {
+        bail!("The icon has no image it can show");
+    }
+    sizes.sort_by_key(|i| std::cmp::Reverse(i.width() * i.height()));
+    Ok(sizes)
+}
+
+/// Open an image from disk and send it somewhere
+pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
+    let (sender, receiver): (Sender<Frame>, Receiver<Frame>) = channel();
+    let img_location = (*img_location).to_owned();
+
+    // images in archives are decoded from memory
+    if archive::split(&img_location).is_some() {
+        _ = sender.send(Frame::new_still(archive::decode(&img_location)?));
+        return Ok(receiver);
+    }
+
+    // image sequences play like animations, or show their primary image if ffmpeg is missing
+    let extension = img_location
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if ["avif", "avifs", "heif", "heic", "heics"].contains(&extension.as_str()) {
+        if let Some(sequence) = heif::Sequence::read(&std::fs::read(&img_location)?) {
+            match sequence.play(&img_location, sender.clone()) {
+                Ok(()) => return Ok(receiver),
+                Err(e) => error!("Can't play the image sequence: {e}"),
+            }
+        }
+    }
+
+    match extension.as_str() {
+        "dds" | "ktx2" | "basis" => {
+            let mut mips = texture_mips(&img_location)?;
+            _ = sender.send(Frame::new_still(mips.swap_remove(0)));
             return Ok(receiver);
         }
         #[cfg(feature = "dav1d")]
-        "avif" => {
+        "avif" | "avifs" => {
             let mut file = File::open(img_location)?;
             let mut buf = vec![];
             file.read_to_end(&mut buf)?;
             let i = libavif_image::read(buf.as_slice())?;
-            _ = sender.send(Frame::new_still(i.to_rgba8()));
+            let mut img = i.to_rgba8();
+            heif::orient(&buf, &mut img);
+            heif::to_srgb(&buf, &mut img);
+            _ = sender.send(Frame::new_still(img));
             return Ok(receiver);
 
             // col.add_still(i.to_rgba8());
         }
         #[cfg(feature = "j2k")]
-        "jp2" => {
-            let jp2_image = jpeg2k::Image::from_file(img_location)?;
-
-            let image_buffer = RgbaImage::from_raw(
-                jp2_image.width(),
-                jp2_image.height(),
-                jp2_image.get_pixels(Some(255))?.data,
-            ).context("Can't decode jp2k buffer")?;
-            _ = sender.send(Frame::new_still(image_buffer));
+        "jp2" | "j2k" | "j2c" | "jpc" | "jpf" | "jpx" => {
+            // the codestream format is detected from the data, raw codestreams have no container
+            let jp2_image = jpeg2k::Image::from_bytes(&std::fs::read(&img_location)?)?;
+            let (width, height) = (jp2_image.width(), jp2_image.height());
+            let pixels = jp2_image.get_pixels(Some(255))?.data;
+            let channels = pixels.len() / (width as usize * height as usize).max(1);
+            let image = match channels {
+                1 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+                2 => GrayAlphaImage::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+                3 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+                _ => RgbaImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+            }
+            .context("Can't decode jp2k buffer")?;
+            _ = sender.send(Frame::new_still(image.into_rgba8()));
             return Ok(receiver);
         }
         #[cfg(feature = "heif")]
-        "heif" | "heic" => {
+        "heif" | "heic" | "heics" => {
             // Built on work in https://github.com/rsuu/rmg - thanks!
             use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
 
@@ -125,134 +261,98 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
                     step += 4;
                 }
             }
-            let buf = image::ImageBuffer::from_vec(width as u32, height as u32, res)
+            let mut buf = image::ImageBuffer::from_vec(width as u32, height as u32, res)
                 .context("Can't create HEIC/HEIF ImageBuffer with given res")?;
+            heif::to_srgb(&std::fs::read(&img_location)?, &mut buf);
             _ = sender.send(Frame::new_still(buf));
             return Ok(receiver);
         }
         #[cfg(feature = "avif_native")]
         #[cfg(not(feature = "dav1d"))]
-        "avif" => {
-            let mut file = File::open(img_location)?;
-            let avif = avif_decode::Decoder::from_reader(&mut file)?.to_image()?;
-            match avif {
-                avif_decode::Image::Rgb8(img) => {
-                    let mut img_buffer = vec![];
+        "avif" | "avifs" => {
+            use avif_decode::Image;
+            let data = std::fs::read(&img_location)?;
+            let avif = avif_decode::Decoder::from_reader(&mut data.as_slice())?.to_image()?;
+            let (buf, width, height) = match avif {
+                Image::Rgb8(img) => {
                     let (buf, width, height) = img.into_contiguous_buf();
-                    for b in buf {
-                        img_buffer.push(b.r);
-                        img_buffer.push(b.g);
-                        img_buffer.push(b.b);
-                        img_buffer.push(255);
-                    }
-                    let buf = image::ImageBuffer::from_vec(width as u32, height as u32, img_buffer)
-                        .context("Can't create avif ImageBuffer with given res")?;
-                    _ = sender.send(Frame::new_still(buf));
-                    return Ok(receiver);
+                    let buf = buf.iter().flat_map(|b| [b.r, b.g, b.b, 255]).collect();
+                    (buf, width, height)
                 }
-                avif_decode::Image::Rgba8(img) => {
-                    let mut img_buffer = vec![];
+                Image::Rgba8(img) => {
                     let (buf, width, height) = img.into_contiguous_buf();
-                    for b in buf {
-                        img_buffer.push(b.r);
-                        img_buffer.push(b.g);
-                        img_buffer.push(b.b);
-                        img_buffer.push(b.a);
-                    }
-                    let buf = image::ImageBuffer::from_vec(width as u32, height as u32, img_buffer)
-                        .context("Can't create avif ImageBuffer with given res")?;
-                    _ = sender.send(Frame::new_still(buf));
-                    return Ok(receiver);
+                    let buf = buf.iter().flat_map(|b| [b.r, b.g, b.b, b.a]).collect();
+                    (buf, width, height)
                 }
-                avif_decode::Image::Rgb16(img) => {
-                    let mut img_buffer = vec![];
+                Image::Rgb16(img) => {
                     let (buf, width, height) = img.into_contiguous_buf();
-                    for b in buf {
-                        img_buffer.push(u16_to_u8(b.r));
-                        img_buffer.push(u16_to_u8(b.g));
-                        img_buffer.push(u16_to_u8(b.b));
-                        img_buffer.push(255);
-                    }
-                    let buf = image::ImageBuffer::from_vec(width as u32, height as u32, img_buffer)
-                        .context("Can't create avif ImageBuffer with given res")?;
-                    _ = sender.send(Frame::new_still(buf));
-                    return Ok(receiver);
+                    let buf = buf
+                        .iter()
+                        .flat_map(|b| [u16_to_u8(b.r), u16_to_u8(b.g), u16_to_u8(b.b), 255])
+                        .collect();
+                    (buf, width, height)
                 }
-                avif_decode::Image::Rgba16(_) => {
-                    anyhow::bail!("This avif is not yet supported (Rgba16).")
+                Image::Rgba16(img) => {
+                    let (buf, width, height) = img.into_contiguous_buf();
+                    let buf = buf
+                        .iter()
+                        .flat_map(|b| [b.r, b.g, b.b, b.a].map(u16_to_u8))
+                        .collect();
+                    (buf, width, height)
                 }
-                avif_decode::Image::Gray8(_) => {
-                    anyhow::bail!("This avif is not yet supported (Gray8).")
+                Image::Gray8(img) => {
+                    let (buf, width, height) = img.into_contiguous_buf();
+                    let buf = buf.iter().flat_map(|b| [b.0, b.0, b.0, 255]).collect();
+                    (buf, width, height)
                 }
-                avif_decode::Image::Gray16(_) => {
-                    anyhow::bail!("This avif is not yet supported (Gray16).")
+                Image::Gray16(img) => {
+                    let (buf, width, height) = img.into_contiguous_buf();
+                    let buf = buf
+                        .iter()
+                        .flat_map(|b| {
+                            let v = u16_to_u8(b.0);
+                            [v, v, v, 255]
+                        })
+                        .collect();
+                    (buf, width, height)
                 }
-            }
+            };
+            let mut buf = RgbaImage::from_vec(width as u32, height as u32, buf)
+                .context("Can't create avif ImageBuffer with given res")?;
+            // the decoder ignores the rotation of the container
+            heif::orient(&data, &mut buf);
+            heif::to_srgb(&data, &mut buf);
+            _ = sender.send(Frame::new_still(buf));
+            return Ok(receiver);
         }
         "svg" => {
-            // TODO: Should the svg be scaled? if so by what number?
-            // This should be specified in a smarter way, maybe resolution * x?
-
-            let render_scale = 2.;
-            let mut opt = usvg::Options::default();
-            opt.font_family = "Inter".into();
-            opt.font_size = 6.;
-
             let svg_data = std::fs::read(img_location)?;
-            if let Ok(mut tree) = usvg::Tree::from_data(&svg_data, &opt) {
-                let pixmap_size = resvg::IntSize::from_usvg(tree.size);
-
-                let scaled_size = (
-                    (pixmap_size.width() as f32 * render_scale) as u32,
-                    (pixmap_size.height() as f32 * render_scale) as u32,
-                );
-
-                if let Some(mut pixmap) = tiny_skia::Pixmap::new(scaled_size.0, scaled_size.1) {
-                    let mut fontdb = usvg::fontdb::Database::new();
-                    fontdb.load_system_fonts();
-                    fontdb.load_font_data(FONT.to_vec());
-                    fontdb.set_cursive_family("Inter");
-                    fontdb.set_sans_serif_family("Inter");
-                    fontdb.set_serif_family("Inter");
-                    tree.convert_text(&fontdb);
-
-                    let rtree = resvg::Tree::from_usvg(&tree);
-
-                    rtree.render(
-                        tiny_skia::Transform::from_scale(render_scale, render_scale),
-                        &mut pixmap.as_mut(),
-                    );
-                    let buf: RgbaImage = image::ImageBuffer::from_raw(
-                        scaled_size.0,
-                        scaled_size.1,
-                        pixmap.data().to_vec(),
-                    )
-                    .context("Can't create image buffer from SVG render")?;
-
-                    _ = sender.send(Frame::new_still(buf));
-                    return Ok(receiver);
-                }
-            }
+            let buf = render_svg(&svg_data, SVG_RENDER_SCALE)?;
+            _ = sender.send(Frame::new_still(buf));
+            return Ok(receiver);
         }
         "exr" => {
             let reader = exrs::read()
                 .no_deep_data()
                 .largest_resolution_level()
                 .rgba_channels(
-                    |resolution, _channels: &RgbaChannels| -> RgbaImage {
+                    |resolution, _channels: &RgbaChannels| -> Rgba32FImage {
                         image::ImageBuffer::new(
                             resolution.width() as u32,
                             resolution.height() as u32,
                         )
                     },
-                    // set each pixel in the png buffer from the exr file
-                    |png_pixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
-                        png_pixels.put_pixel(
+                    // keep the full range for editing, gamma encoded like the display buffer
+                    |hdr_pixels, position, (r, g, b, a): (f32, f32, f32, f32)| {
+                        hdr_pixels.put_pixel(
                             position.x() as u32,
                             position.y() as u32,
-                            // exr's tonemap:
-                            // image::Rgba([tone_map(r), tone_map(g), tone_map(b), (a * 255.0) as u8]),
-                            image::Rgba(tonemap_rgba([r, g, b, a])),
+                            image::Rgba([
+                                gamma_encode_f32(r),
+                                gamma_encode_f32(g),
+                                gamma_encode_f32(b),
+                                a,
+                            ]),
                         );
                     },
                 )
@@ -261,13 +361,22 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
 
             // an image that contains a single layer containing an png rgba buffer
             let maybe_image: Result<
-                Image<Layer<SpecificChannels<RgbaImage, RgbaChannels>>>,
+                Image<Layer<SpecificChannels<Rgba32FImage, RgbaChannels>>>,
                 exrs::Error,
             > = reader.from_file(&img_location);
             match maybe_image {
                 Ok(image) => {
-                    let buf = image.layer_data.channel_data.pixels;
-                    _ = sender.send(Frame::new_still(buf));
+                    let hdr = image.layer_data.channel_data.pixels;
+                    let buf = RgbaImage::from_fn(hdr.width(), hdr.height(), |x, y| {
+                        let p = hdr.get_pixel(x, y);
+                        image::Rgba([
+                            (p[0].min(1.0) * 255.0) as u8,
+                            (p[1].min(1.0) * 255.0) as u8,
+                            (p[2].min(1.0) * 255.0) as u8,
+                            tonemap_f32(p[3]),
+                        ])
+                    });
+                    _ = sender.send(Frame::new_still_hdr(buf, hdr));
                     return Ok(receiver);
                 }
                 Err(e) => {
@@ -307,7 +416,7 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
         "jxl" => {
             //TODO this needs to be a thread
 
-            fn foo(img_location: &Path, frame_sender: Sender<Frame>) -> Result<()> {
+            fn decode_jxl(img_location: &Path, frame_sender: Sender<Frame>) -> Result<()> {
                 let image = JxlImage::builder()
                     .open(img_location)
                     .map_err(|e| anyhow!("{e}"))?;
@@ -338,6 +447,32 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
                     debug!("duration {frame_duration} ms");
                     let framebuffer = render.image();
                     debug!("{:?}", image.pixel_format());
+
+                    // HDR images have values above 1. Keep them for editing, like EXR.
+                    let channels = framebuffer.channels();
+                    if !is_jxl_anim
+                        && channels >= 3
+                        && framebuffer.buf().par_iter().any(|x| *x > 1.0)
+                    {
+                        let (width, height) =
+                            (framebuffer.width() as u32, framebuffer.height() as u32);
+                        let buf = framebuffer.buf();
+                        let hdr = Rgba32FImage::from_fn(width, height, |x, y| {
+                            let i = (y * width + x) as usize * channels;
+                            image::Rgba([
+                                buf[i],
+                                buf[i + 1],
+                                buf[i + 2],
+                                if channels > 3 { buf[i + 3] } else { 1.0 },
+                            ])
+                        });
+                        let ldr = RgbaImage::from_fn(width, height, |x, y| {
+                            let p = hdr.get_pixel(x, y);
+                            image::Rgba(p.0.map(|v| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8))
+                        });
+                        _ = frame_sender.send(Frame::new_still_hdr(ldr, hdr));
+                        continue;
+                    }
                     match image.pixel_format() {
                         PixelFormat::Graya => {
                             let float_image = GrayAlphaImage::from_raw(
@@ -419,7 +554,7 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
             }
 
             std::thread::spawn(move || {
-                if let Err(e) = foo(&img_location, sender) {
+                if let Err(e) = decode_jxl(&img_location, sender) {
                     error!("{e}");
                 }
             });
@@ -436,12 +571,70 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
                 _ => bail!("expected rgb32f image"),
             };
 
-            let rgba_image = RgbaImage::from_fn(meta.width, meta.height, |x, y| {
-                let pixel = hdr_img.get_pixel(x, y);
-                image::Rgba(tonemap_rgb(pixel.0))
-            });
-
-            _ = sender.send(Frame::new_still(rgba_image));
+            debug!("Radiance image of {}x{}", meta.width, meta.height);
+            _ = sender.send(linear_frame(&hdr_img));
+            return Ok(receiver);
+        }
+        "pfm" => {
+            let hdr_img = load_pfm(&std::fs::read(&img_location)?)?;
+            _ = sender.send(linear_frame(&hdr_img));
+            return Ok(receiver);
+        }
+        "pnm" | "pbm" | "pgm" | "ppm" | "pam" | "ff" => {
+            let img = image::open(img_location)?;
+            // 16 bit images keep their full precision for editing
+            if img.color().bytes_per_pixel() > img.color().channel_count() {
+                _ = sender.send(Frame::new_still_hdr(img.to_rgba8(), img.to_rgba32f()));
+            } else {
+                _ = sender.send(Frame::new_still(img.to_rgba8()));
+            }
+            return Ok(receiver);
+        }
+        "dcm" | "dicom" => {
+            let dicom = DicomImage::read(&std::fs::read(&img_location)?)?;
+            _ = sender.send(Frame::new_still(dicom.render(&dicom.default_window())));
+            return Ok(receiver);
+        }
+        ext if VIDEO_EXTENSIONS.contains(&ext) => {
+            let video = Video::probe(img_location)?;
+            let mut frame = Frame::new_still(video.decode()?);
+            // the video is probed here, off the UI thread
+            frame.video = Some(video);
+            _ = sender.send(frame);
+            return Ok(receiver);
+        }
+        "zip" | "cbz" | "cbr" => {
+            // the first image, the others are browsed like a folder
+            let first = archive::images(&img_location)?
+                .into_iter()
+                .next()
+                .context("The archive has no images")?;
+            _ = sender.send(Frame::new_still(archive::decode(&first)?));
+            return Ok(receiver);
+        }
+        "fits" | "fit" | "fts" => {
+            let fits = FitsImage::read(&std::fs::read(&img_location)?)?;
+            _ = sender.send(fits.frame(&Default::default()));
+            return Ok(receiver);
+        }
+        "ora" | "kra" => {
+            use std::io::Read;
+            // OpenRaster and Krita files are zip archives with a composited PNG inside
+            let file = BufReader::new(File::open(&img_location)?);
+            let mut archive = zip::ZipArchive::new(file)?;
+            let mut data = vec![];
+            // older Krita files only have a small preview
+            for name in ["mergedimage.png", "preview.png"] {
+                if let Ok(mut entry) = archive.by_name(name) {
+                    entry.read_to_end(&mut data)?;
+                    break;
+                }
+            }
+            if data.is_empty() {
+                bail!("There is no merged image in this file");
+            }
+            let img = image::load_from_memory_with_format(&data, image::ImageFormat::Png)?;
+            _ = sender.send(Frame::new_still(img.into_rgba8()));
             return Ok(receiver);
         }
         "psd" => {
@@ -699,6 +892,18 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
         #[cfg(feature = "turbo")]
         "jpg" | "jpeg" => {
             let jpeg_data = std::fs::read(img_location)?;
+            // show the coarse scans of large progressive files while the rest decodes
+            if let Some((preview, full_size)) = jpeg_preview(&jpeg_data) {
+                _ = sender.send(Frame::new(preview, 0, FrameSource::Preview(full_size)));
+                std::thread::spawn(move || match turbojpeg::decompress_image(&jpeg_data) {
+                    Ok(buf) => {
+                        let d = DynamicImage::ImageRgb8(buf);
+                        _ = sender.send(Frame::new_still(d.to_rgba8()));
+                    }
+                    Err(e) => error!("Could not decode JPEG: {e}"),
+                });
+                return Ok(receiver);
+            }
             let buf: RgbImage = turbojpeg::decompress_image(&jpeg_data)?;
             let d = DynamicImage::ImageRgb8(buf);
 
@@ -725,22 +930,35 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
                 return Ok(receiver);
             }
         }
-        "tif" | "tiff" => match load_tiff(&img_location) {
-            Ok(tiff) => {
-                _ = sender.send(Frame::new_still(tiff));
-                return Ok(receiver);
-            }
-            Err(tiff_error) => match load_raw(&img_location) {
-                Ok(raw) => {
-                    info!("This image is a raw image with tiff format.");
-                    _ = sender.send(Frame::new_still(raw));
+        "tif" | "tiff" => {
+            // very large tiled files are never decoded whole
+            match TiledImage::open(img_location) {
+                Ok(Some(tiled)) => {
+                    let mut frame = Frame::new_still(tiled.overview());
+                    frame.tiled = Some(Arc::new(tiled));
+                    _ = sender.send(frame);
                     return Ok(receiver);
                 }
-                Err(raw_error) => {
-                    bail!("Could not load tiff: {tiff_error}, tried as raw and still got error: {raw_error}")
+                Ok(None) => (),
+                Err(e) => debug!("Could not open as tiled image: {e}"),
+            }
+            match load_tiff(&img_location) {
+                Ok(tiff) => {
+                    _ = sender.send(Frame::new_still(tiff));
+                    return Ok(receiver);
                 }
-            },
-        },
+                Err(tiff_error) => match load_raw(&img_location) {
+                    Ok(raw) => {
+                        info!("This image is a raw image with tiff format.");
+                        _ = sender.send(Frame::new_still(raw));
+                        return Ok(receiver);
+                    }
+                    Err(raw_error) => {
+                        bail!("Could not load tiff: {tiff_error}, tried as raw and still got error: {raw_error}")
+                    }
+                },
+            }
+        }
         _ => {
             // All other supported image files are handled by using `image`
             debug!("Loading using image library");
@@ -754,6 +972,162 @@ pub fn open_image(img_location: &Path) -> Result<Receiver<Frame>> {
     Ok(receiver)
 }
 
+/// A frame of a linear float image, tone mapped for display
+fn linear_frame(img: &Rgb32FImage) -> Frame {
+    let rgba_image = RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        image::Rgba(tonemap_rgb(img.get_pixel(x, y).0))
+    });
+    let hdr_buffer = Rgba32FImage::from_fn(img.width(), img.height(), |x, y| {
+        let pixel = img.get_pixel(x, y);
+        image::Rgba([
+            gamma_encode_f32(pixel[0]),
+            gamma_encode_f32(pixel[1]),
+            gamma_encode_f32(pixel[2]),
+            1.0,
+        ])
+    });
+    Frame::new_still_hdr(rgba_image, hdr_buffer)
+}
+
+/// Decode a portable float map, a color (`PF`) or grayscale (`Pf`) image of linear floats. A
+/// negative scale means the data is little endian. Rows are stored from the bottom up.
+pub fn load_pfm(data: &[u8]) -> Result<Rgb32FImage> {
+    // the header is three values separated by whitespace, then a single whitespace character
+    let mut header = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while header.len() < 4 {
+        let c = *data.get(i).context("The float map header is incomplete")?;
+        if c.is_ascii_whitespace() {
+            if i > start {
+                header.push(std::str::from_utf8(&data[start..i])?);
+            }
+            start = i + 1;
+        }
+        i += 1;
+    }
+    let channels = match header[0] {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => bail!("Not a float map"),
+    };
+    let width: u32 = header[1].parse()?;
+    let height: u32 = header[2].parse()?;
+    let little_endian = header[3].parse::<f32>()? < 0.0;
+
+    let row_len = width as usize * channels;
+    let floats = data[start..]
+        .chunks_exact(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            if little_endian {
+                f32::from_le_bytes(b)
+            } else {
+                f32::from_be_bytes(b)
+            }
+        })
+        .collect::<Vec<_>>();
+    if row_len == 0 || floats.len() < row_len * height as usize {
+        bail!("The float map has less data than its size");
+    }
+    let pixels = floats
+        .chunks_exact(row_len)
+        .take(height as usize)
+        .rev()
+        .flat_map(|row| row.chunks_exact(channels))
+        .flat_map(|p| match p {
+            [v] => [*v, *v, *v],
+            p => [p[0], p[1], p[2]],
+        })
+        .collect();
+    Rgb32FImage::from_raw(width, height, pixels).context("Invalid float map size")
+}
+
+/// The extension of the format of a file, guessed from the start of its data
+pub fn guess_extension(data: &[u8]) -> Option<&'static str> {
+    let signatures: &[(&[u8], &str)] = &[
+        (b"8BPS", "psd"),
+        (b"icns", "icns"),
+        (b"SIMPLE  =", "fits"),
+        (b"\xABKTX 20\xBB", "ktx2"),
+        (b"\xFF\x0A", "jxl"),
+        (b"\0\0\0\x0CJXL ", "jxl"),
+        (b"\0\0\0\x0CjP  ", "jp2"),
+        (b"\xFF\x4F\xFF\x51", "j2k"),
+        (b"PF\n", "pfm"),
+        (b"Pf\n", "pfm"),
+    ];
+    if let Some((_, extension)) = signatures.iter().find(|(s, _)| data.starts_with(s)) {
+        return Some(*extension);
+    }
+    if data.get(128..132) == Some(&b"DICM"[..]) {
+        return Some("dcm");
+    }
+    // ISO media files name their brand after the size of the first box
+    match data.get(4..12) {
+        Some(b"ftypavif") | Some(b"ftypavis") => return Some("avif"),
+        Some(b"ftypheic") | Some(b"ftypheix") | Some(b"ftypmif1") => return Some("heic"),
+        _ => (),
+    }
+    if let Ok(format) = image::guess_format(data) {
+        return format.extensions_str().first().copied();
+    }
+    let start = String::from_utf8_lossy(&data[..data.len().min(1024)]);
+    if start.contains("<svg") {
+        return Some("svg");
+    }
+    None
+}
+
+/// Whether the frame header of a JPEG is progressive
+#[cfg(feature = "turbo")]
+fn is_progressive_jpeg(data: &[u8]) -> bool {
+    let mut i = 2;
+    while i + 4 <= data.len() && data[i] == 0xFF {
+        match data[i + 1] {
+            // padding before a marker
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            0xC2 | 0xC6 | 0xCA | 0xCE => return true,
+            // other frame headers or the start of the scans
+            0xC0 | 0xC1 | 0xC3 | 0xC5 | 0xC7 | 0xC9 | 0xCB | 0xCD | 0xCF | 0xDA => return false,
+            _ => (),
+        }
+        i += 2 + u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize;
+    }
+    false
+}
+
+/// An eighth size decode of a large progressive JPEG, which only needs the low frequencies of
+/// the first scans, and the size of the full image
+#[cfg(feature = "turbo")]
+fn jpeg_preview(data: &[u8]) -> Option<(RgbaImage, (u32, u32))> {
+    if !is_progressive_jpeg(data) {
+        return None;
+    }
+    let mut decompressor = turbojpeg::Decompressor::new().ok()?;
+    let header = decompressor.read_header(data).ok()?;
+    // smaller images are decoded quickly enough
+    if header.width * header.height < 16_000_000 {
+        return None;
+    }
+    let factor = turbojpeg::ScalingFactor::ONE_EIGHTH;
+    decompressor.set_scaling_factor(factor).ok()?;
+    let scaled = header.scaled(factor);
+    let mut image = turbojpeg::Image {
+        pixels: vec![0; 4 * scaled.width * scaled.height],
+        width: scaled.width,
+        pitch: 4 * scaled.width,
+        height: scaled.height,
+        format: turbojpeg::PixelFormat::RGBA,
+    };
+    decompressor.decompress(data, image.as_deref_mut()).ok()?;
+    let preview = RgbaImage::from_raw(scaled.width as u32, scaled.height as u32, image.pixels)?;
+    Some((preview, (header.width as u32, header.height as u32)))
+}
+
 fn tonemap_rgba(px: [f32; 4]) -> [u8; 4] {
     [
         tonemap_f32(px[0]),
@@ -764,10 +1138,15 @@ fn tonemap_rgba(px: [f32; 4]) -> [u8; 4] {
 }
 
 pub fn tonemap_f32(px: f32) -> u8 {
-    (px.powf(1.0 / 2.2).max(0.0).min(1.0) * 255.0) as u8
+    (gamma_encode_f32(px).min(1.0) * 255.0) as u8
     // (px.filmic() * 255.) as u8
 }
 
+/// Gamma encode a linear value without clamping, so highlights survive editing
+pub fn gamma_encode_f32(px: f32) -> f32 {
+    px.max(0.0).powf(1.0 / 2.2)
+}
+
 fn tonemap_rgb(px: [f32; 3]) -> [u8; 4] {
     let mut tm = tonemap_rgba([px[0], px[1], px[2], 1.0]);
     tm[3] = 255;
@@ -775,6 +1154,43 @@ fn tonemap_rgb(px: [f32; 3]) -> [u8; 4] {
 }
 
 #[allow(unused)]
+/// SVGs are first shown at this multiple of their size, then rendered again when zooming
+pub const SVG_RENDER_SCALE: f32 = 2.;
+
+/// Render an SVG document at `scale` times its size
+pub fn render_svg(svg_data: &[u8], scale: f32) -> Result<RgbaImage> {
+    let mut opt = usvg::Options::default();
+    opt.font_family = "Inter".into();
+    opt.font_size = 6.;
+
+    let mut tree = usvg::Tree::from_data(svg_data, &opt)?;
+    let pixmap_size = resvg::IntSize::from_usvg(tree.size);
+
+    let scaled_size = (
+        ((pixmap_size.width() as f32 * scale) as u32).max(1),
+        ((pixmap_size.height() as f32 * scale) as u32).max(1),
+    );
+
+    let mut pixmap = tiny_skia::Pixmap::new(scaled_size.0, scaled_size.1)
+        .context("Can't create SVG pixmap")?;
+    let mut fontdb = usvg::fontdb::Database::new();
+    fontdb.load_system_fonts();
+    fontdb.load_font_data(FONT.to_vec());
+    fontdb.set_cursive_family("Inter");
+    fontdb.set_sans_serif_family("Inter");
+    fontdb.set_serif_family("Inter");
+    tree.convert_text(&fontdb);
+
+    let rtree = resvg::Tree::from_usvg(&tree);
+
+    rtree.render(
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    image::ImageBuffer::from_raw(scaled_size.0, scaled_size.1, pixmap.data().to_vec())
+        .context("Can't create image buffer from SVG render")
+}
+
 fn u16_to_u8(p: u16) -> u8 {
     ((p as f32 / u16::MAX as f32) * u8::MAX as f32) as u8
 }
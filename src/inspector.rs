@@ -0,0 +1,78 @@
+//! The pixel inspector: the pixels around the cursor magnified, and the value of the one under
+//! it in the notations colors are usually given in
+
+use image::{Rgba32FImage, RgbaImage};
+use palette::{Hsl, IntoColor, LinSrgb, Srgb};
+
+/// The pixels on each side of the one under the cursor that are magnified
+pub const RADIUS: i64 = 5;
+
+/// The names of the values `notations` returns
+pub const NOTATIONS: [&str; 5] = ["RGBA", "Float", "Hex", "HSL", "Linear"];
+
+/// A pixel in every notation, with `float` its value from 0-1 or unclamped
+pub fn notations(p: [u8; 4], float: [f32; 4]) -> [String; 5] {
+    let [h, s, l] = hsl(p);
+    let [r, g, b] = linear(p);
+    [
+        format!("{}, {}, {}, {}", p[0], p[1], p[2], p[3]),
+        format!(
+            "{:.3}, {:.3}, {:.3}, {:.3}",
+            float[0], float[1], float[2], float[3]
+        ),
+        hex(p),
+        format!("{h:.0}°, {:.0}%, {:.0}%", s * 100., l * 100.),
+        format!("{r:.4}, {g:.4}, {b:.4}"),
+    ]
+}
+
+/// The pixels around `(x, y)` row by row, `None` outside the image
+pub fn neighbourhood(img: &RgbaImage, x: i64, y: i64) -> Vec<Option<[u8; 4]>> {
+    let mut pixels = vec![];
+    for ny in y - RADIUS..=y + RADIUS {
+        for nx in x - RADIUS..=x + RADIUS {
+            pixels.push(pixel(img, nx, ny));
+        }
+    }
+    pixels
+}
+
+pub fn pixel(img: &RgbaImage, x: i64, y: i64) -> Option<[u8; 4]> {
+    if x < 0 || y < 0 {
+        return None;
+    }
+    img.get_pixel_checked(x as u32, y as u32).map(|p| p.0)
+}
+
+/// The unclamped value of a high dynamic range image, or the 8 bit value from 0-1
+pub fn float(p: [u8; 4], hdr: Option<&Rgba32FImage>, x: i64, y: i64) -> [f32; 4] {
+    if let Some(hdr) = hdr.filter(|_| x >= 0 && y >= 0) {
+        if let Some(hdr) = hdr.get_pixel_checked(x as u32, y as u32) {
+            return hdr.0;
+        }
+    }
+    p.map(|c| c as f32 / 255.)
+}
+
+pub fn hex(p: [u8; 4]) -> String {
+    format!("#{:02X}{:02X}{:02X}{:02X}", p[0], p[1], p[2], p[3])
+}
+
+/// Hue in degrees, saturation and lightness from 0-1
+pub fn hsl(p: [u8; 4]) -> [f32; 3] {
+    let rgb = Srgb::new(p[0], p[1], p[2]).into_format::<f32>();
+    let hsl: Hsl = rgb.into_color();
+    [
+        hsl.hue.into_positive_degrees(),
+        hsl.saturation,
+        hsl.lightness,
+    ]
+}
+
+/// The light intensity of the sRGB channels, without the transfer curve
+pub fn linear(p: [u8; 4]) -> [f32; 3] {
+    let linear: LinSrgb = Srgb::new(p[0], p[1], p[2])
+        .into_format::<f32>()
+        .into_linear();
+    [linear.red, linear.green, linear.blue]
+}
@@ -3,10 +3,10 @@ use std::convert::TryInto;
 use crate::ktx2_loader::Image;
 
 use exr::prelude::f16;
-use image::{DynamicImage, ImageBuffer, Rgba32FImage};
+use image::{DynamicImage, ImageBuffer, Rgba32FImage, RgbaImage};
 use log::debug;
 use thiserror::Error;
-use wgpu::TextureFormat;
+use wgpu::{TextureDimension, TextureFormat};
 
 impl Image {
     /// Converts a [`DynamicImage`] to an [`Image`].
@@ -84,6 +84,115 @@ impl Image {
             self.texture_descriptor.format,
         ))
     }
+
+    /// Decode the mip levels of the first layer, largest first. Block compressed formats are
+    /// decompressed on the cpu, so textures meant for the gpu can be inspected as well.
+    pub fn mip_levels(&self) -> Result<Vec<RgbaImage>, IntoDynamicImageError> {
+        let format = self.texture_descriptor.format;
+        let (block_width, block_height) = format.block_dimensions();
+        let block_bytes = format
+            .block_copy_size(None)
+            .ok_or(IntoDynamicImageError::UnsupportedFormat(format))?;
+        // the levels of volume textures hold every slice, only the first level is split up here
+        let level_count = match self.texture_descriptor.dimension {
+            TextureDimension::D3 => 1,
+            _ => self.texture_descriptor.mip_level_count.max(1),
+        };
+
+        let mut levels = vec![];
+        let mut offset = 0;
+        for level in 0..level_count {
+            let (width, height) = (
+                (self.width() >> level).max(1),
+                (self.height() >> level).max(1),
+            );
+            let len = ((width + block_width - 1) / block_width
+                * ((height + block_height - 1) / block_height)
+                * block_bytes) as usize;
+            let Some(data) = self.data.get(offset..offset + len) else {
+                break;
+            };
+            offset += len;
+            levels.push(
+                decode_level(format, data, width, height)
+                    .ok_or(IntoDynamicImageError::UnsupportedFormat(format))?,
+            );
+        }
+        Ok(levels)
+    }
+}
+
+/// Decode one mip level to RGBA, if the format is supported
+fn decode_level(format: TextureFormat, data: &[u8], width: u32, height: u32) -> Option<RgbaImage> {
+    let unorm = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+    let rgba: Vec<u8> = match format {
+        TextureFormat::R8Unorm => data.iter().flat_map(|v| [*v, *v, *v, 255]).collect(),
+        TextureFormat::Rg8Unorm => data
+            .chunks_exact(2)
+            .flat_map(|c| [c[0], c[1], 0, 255])
+            .collect(),
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => data.to_vec(),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => data
+            .chunks_exact(4)
+            .flat_map(|c| [c[2], c[1], c[0], c[3]])
+            .collect(),
+        TextureFormat::Rgba16Float => data
+            .chunks_exact(2)
+            .map(|c| unorm(f16::from_le_bytes([c[0], c[1]]).to_f32()))
+            .collect(),
+        TextureFormat::Rgba32Float => data
+            .chunks_exact(4)
+            .map(|c| unorm(f32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+            .collect(),
+        _ => {
+            let (w, h) = (width as usize, height as usize);
+            let mut pixels = vec![0_u32; w * h];
+            match format {
+                TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => {
+                    texture2ddecoder::decode_bc1(data, w, h, &mut pixels)
+                }
+                TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => {
+                    texture2ddecoder::decode_bc3(data, w, h, &mut pixels)
+                }
+                TextureFormat::Bc4RUnorm => texture2ddecoder::decode_bc4(data, w, h, &mut pixels),
+                TextureFormat::Bc5RgUnorm => texture2ddecoder::decode_bc5(data, w, h, &mut pixels),
+                TextureFormat::Bc7RgbaUnorm | TextureFormat::Bc7RgbaUnormSrgb => {
+                    texture2ddecoder::decode_bc7(data, w, h, &mut pixels)
+                }
+                TextureFormat::Etc2Rgb8Unorm | TextureFormat::Etc2Rgb8UnormSrgb => {
+                    texture2ddecoder::decode_etc2_rgb(data, w, h, &mut pixels)
+                }
+                TextureFormat::Etc2Rgb8A1Unorm | TextureFormat::Etc2Rgb8A1UnormSrgb => {
+                    texture2ddecoder::decode_etc2_rgba1(data, w, h, &mut pixels)
+                }
+                TextureFormat::Etc2Rgba8Unorm | TextureFormat::Etc2Rgba8UnormSrgb => {
+                    texture2ddecoder::decode_etc2_rgba8(data, w, h, &mut pixels)
+                }
+                TextureFormat::EacR11Unorm => {
+                    texture2ddecoder::decode_eacr(data, w, h, &mut pixels)
+                }
+                TextureFormat::EacRg11Unorm => {
+                    texture2ddecoder::decode_eacrg(data, w, h, &mut pixels)
+                }
+                TextureFormat::Astc { .. } => {
+                    let (bw, bh) = format.block_dimensions();
+                    texture2ddecoder::decode_astc(data, w, h, bw as usize, bh as usize, &mut pixels)
+                }
+                _ => return None,
+            }
+            .map_err(|e| debug!("Can't decode {format:?}: {e}"))
+            .ok()?;
+            // the decoder writes BGRA
+            pixels
+                .into_iter()
+                .flat_map(|p| {
+                    let [b, g, r, a] = p.to_le_bytes();
+                    [r, g, b, a]
+                })
+                .collect()
+        }
+    };
+    RgbaImage::from_raw(width, height, rgba)
 }
 
 /// Errors that occur while converting an [`Image`] into a [`DynamicImage`]
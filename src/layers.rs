@@ -0,0 +1,235 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use image::RgbaImage;
+use notan::egui::{self, DragValue, Response, Sense, Ui, Vec2};
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::paint::{Brush, PaintStroke};
+
+/// How a layer is combined with what is below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Subtract,
+    Darken,
+    Lighten,
+    Difference,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 9] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Overlay,
+        BlendMode::Add,
+        BlendMode::Subtract,
+        BlendMode::Darken,
+        BlendMode::Lighten,
+        BlendMode::Difference,
+    ];
+
+    /// Blend a single channel of `top` onto `base`, both in 0-1
+    pub fn blend(&self, base: f32, top: f32) -> f32 {
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Multiply => base * top,
+            BlendMode::Screen => 1. - (1. - base) * (1. - top),
+            BlendMode::Overlay => {
+                if base < 0.5 {
+                    2. * base * top
+                } else {
+                    1. - 2. * (1. - base) * (1. - top)
+                }
+            }
+            BlendMode::Add => (base + top).min(1.),
+            BlendMode::Subtract => (base - top).max(0.),
+            BlendMode::Darken => base.min(top),
+            BlendMode::Lighten => base.max(top),
+            BlendMode::Difference => (base - top).abs(),
+        }
+    }
+}
+
+impl BlendMode {
+    /// A compact selector, `id_source` must be unique among its siblings
+    pub fn ui(&mut self, ui: &mut Ui, id_source: impl std::hash::Hash) -> Response {
+        let mut changed = false;
+        let mut r = egui::ComboBox::from_id_source(id_source)
+            .selected_text(self.to_string())
+            .width(70.)
+            .show_ui(ui, |ui| {
+                for mode in BlendMode::ALL {
+                    if ui.selectable_value(self, mode, mode.to_string()).changed() {
+                        changed = true;
+                    }
+                }
+            })
+            .response
+            .on_hover_text("Blend mode");
+        if changed {
+            r.mark_changed();
+        }
+        r
+    }
+}
+
+impl fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Where the pixels of a layer come from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LayerSource {
+    /// An image file, placed at the layer offset
+    File(PathBuf),
+    /// Paint strokes, rendered at the size of the base image
+    Strokes(Vec<PaintStroke>),
+}
+
+/// An image composited on top of the edited photo
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+    pub name: String,
+    pub source: LayerSource,
+    pub visible: bool,
+    /// From 0 to 1
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+    /// Position of the top left corner in pixels of the base image
+    pub offset: (i32, i32),
+    /// The rendered source, refreshed when empty
+    #[serde(skip)]
+    pub image: Arc<RgbaImage>,
+}
+
+/// The image is derived from the source, so it does not take part in comparisons
+impl PartialEq for Layer {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.source == other.source
+            && self.visible == other.visible
+            && self.opacity == other.opacity
+            && self.blend_mode == other.blend_mode
+            && self.offset == other.offset
+    }
+}
+
+impl Layer {
+    pub fn new(name: &str, source: LayerSource) -> Self {
+        Self {
+            name: name.into(),
+            source,
+            visible: true,
+            opacity: 1.,
+            blend_mode: Default::default(),
+            offset: (0, 0),
+            image: Default::default(),
+        }
+    }
+
+    pub fn needs_refresh(&self) -> bool {
+        self.image.width() == 0
+    }
+
+    /// Render the layer source. `dimensions` is the size of the base image.
+    pub fn refresh(&mut self, dimensions: (u32, u32), brushes: &[Brush]) -> Result<()> {
+        let image = match &self.source {
+            LayerSource::File(path) => image::open(path)?.into_rgba8(),
+            LayerSource::Strokes(strokes) => {
+                let mut canvas = RgbaImage::new(dimensions.0, dimensions.1);
+                for stroke in strokes.iter().filter(|s| !s.is_empty()) {
+                    stroke.render(&mut canvas, brushes);
+                }
+                canvas
+            }
+        };
+        self.image = Arc::new(image);
+        Ok(())
+    }
+
+    /// Blend this layer onto `base`
+    pub fn composite(&self, base: &mut RgbaImage) {
+        if !self.visible || self.opacity <= 0. {
+            return;
+        }
+        let top = &self.image;
+        let width = base.width() as usize;
+
+        base.par_chunks_mut(4).enumerate().for_each(|(i, px)| {
+            let x = (i % width) as i64 - self.offset.0 as i64;
+            let y = (i / width) as i64 - self.offset.1 as i64;
+            if x < 0 || y < 0 || x >= top.width() as i64 || y >= top.height() as i64 {
+                return;
+            }
+            let t = top.get_pixel(x as u32, y as u32);
+            let alpha = t[3] as f32 / 255. * self.opacity;
+            if alpha <= 0. {
+                return;
+            }
+            for c in 0..3 {
+                let b = px[c] as f32 / 255.;
+                let blended = self.blend_mode.blend(b, t[c] as f32 / 255.);
+                px[c] = ((b + (blended - b) * alpha) * 255.) as u8;
+            }
+            let base_alpha = px[3] as f32 / 255.;
+            px[3] = ((base_alpha + alpha * (1. - base_alpha)) * 255.) as u8;
+        });
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui) -> Response {
+        ui.horizontal(|ui| {
+            let mut r = ui.allocate_response(Vec2::ZERO, Sense::hover());
+
+            if ui.checkbox(&mut self.visible, "").changed() {
+                r.mark_changed();
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.name).desired_width(60.));
+
+            let id = ui.id().with("blend");
+            if self.blend_mode.ui(ui, id).changed() {
+                r.mark_changed();
+            }
+
+            if ui
+                .add(
+                    DragValue::new(&mut self.opacity)
+                        .speed(0.01)
+                        .clamp_range(0.0..=1.0)
+                        .prefix("α "),
+                )
+                .changed()
+            {
+                r.mark_changed();
+            }
+            if ui
+                .add(DragValue::new(&mut self.offset.0).prefix("x "))
+                .changed()
+            {
+                r.mark_changed();
+            }
+            if ui
+                .add(DragValue::new(&mut self.offset.1).prefix("y "))
+                .changed()
+            {
+                r.mark_changed();
+            }
+            r
+        })
+        .inner
+    }
+}
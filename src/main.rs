@@ -13,11 +13,12 @@ use notan::app::Event;
 use notan::draw::*;
 use notan::egui::{self, *};
 use notan::prelude::*;
-use shortcuts::key_pressed;
+use shortcuts::{key_held, key_pressed};
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::time::Duration;
+use strum::IntoEnumIterator;
 pub mod cache;
 pub mod scrubber;
 pub mod settings;
@@ -25,16 +26,35 @@ pub mod shortcuts;
 #[cfg(feature = "turbo")]
 use crate::image_editing::lossless_tx;
 use crate::scrubber::find_first_image_in_directory;
+use crate::tiled::TileView;
+use crate::xmp::ColorLabel;
 use crate::settings::set_system_theme;
 use crate::settings::ColorTheme;
 use crate::shortcuts::InputEvent::*;
 mod utils;
 use utils::*;
+mod annotation;
 mod appstate;
+mod archive;
+mod background;
+mod capture;
+mod compare;
 mod image_loader;
+mod histogram;
+mod inspector;
+mod scopes;
 use appstate::*;
+mod convert;
+mod dicom;
+mod download;
+mod export;
+mod fits;
+mod folders;
 #[cfg(not(feature = "file_open"))]
 mod filebrowser;
+mod gallery;
+mod grid;
+mod hotfolder;
 
 pub mod ktx2_loader;
 // mod events;
@@ -49,13 +69,123 @@ mod ui;
 mod update;
 use ui::*;
 
-use crate::image_editing::EditState;
+use crate::image_editing::{legacy_sidecar_path, sidecar_path, EditState};
 
+mod gpu;
+mod heif;
+mod icc;
 mod image_editing;
+mod layers;
+mod mask;
+mod metadata;
 pub mod paint;
+mod plugins;
+mod psd_layers;
+mod redaction;
+mod scripting;
+mod selection;
+mod thumbnails;
+mod tiled;
+mod tonemap;
+mod video;
+mod xmp;
 
 pub const FONT: &[u8; 309828] = include_bytes!("../res/fonts/Inter-Regular.ttf");
 
+/// The command line arguments
+fn cli() -> Command<'static> {
+    Command::new("Oculante")
+        .arg(
+            Arg::new("INPUT")
+                .help("Display this image, or the image piped to STDIN if it is -")
+                .index(1),
+        )
+        .arg(
+            Arg::new("l")
+                .short('l')
+                .help("Listen on port")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("watch")
+                .short('w')
+                .long("watch")
+                .takes_value(true)
+                .value_name("FOLDER")
+                .help("Show the newest image of a folder as images appear in it"),
+        )
+        .arg(
+            Arg::new("stdin")
+                .short('s')
+                .id("stdin")
+                .takes_value(false)
+                .help("Load data from STDIN"),
+        )
+        .arg(
+            Arg::new("chainload")
+                .required(false)
+                .takes_value(false)
+                .short('c')
+                .help("Chainload on Mac"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .takes_value(true)
+                .help("Run a Rhai script to edit images, without opening a window"),
+        )
+        .arg(
+            Arg::new("convert")
+                .long("convert")
+                .takes_value(true)
+                .value_name("FORMAT")
+                .help("Convert INPUT, an image or a folder, to this format without opening a window"),
+        )
+        .arg(
+            Arg::new("quality")
+                .long("quality")
+                .takes_value(true)
+                .requires("convert")
+                .help("Quality of lossy formats from 1-100"),
+        )
+        .arg(
+            Arg::new("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .requires("convert")
+                .help("Scale converted images down to fit this many pixels"),
+        )
+}
+
+/// Convert images from the command line, with the saved export settings
+fn convert_cli(matches: &clap::ArgMatches) -> anyhow::Result<()> {
+    use anyhow::Context as _;
+    let input = matches.value_of("INPUT").context("No input to convert")?;
+    let format = matches.value_of("convert").unwrap_or_default();
+    let mut export = settings::PersistentSettings::load()
+        .map(|s| s.export)
+        .unwrap_or_default();
+    export.format = export::ExportFormat::from_extension(format)
+        .with_context(|| format!("Unsupported format {format}"))?;
+    if let Some(quality) = matches.value_of("quality") {
+        let quality: u8 = quality.parse()?;
+        export.jpeg_quality = quality;
+        export.avif_quality = quality;
+        export.webp_lossless = false;
+        export.webp_quality = quality as f32;
+    }
+    // full size, unless a size is given
+    export.size_preset = usize::MAX;
+    if let Some(size) = matches.value_of("max-size") {
+        let size: u32 = size.parse()?;
+        export.size_preset = export.size_presets.len();
+        export
+            .size_presets
+            .push(export::SizePreset::new("", size, size));
+    }
+    convert::run_cli(Path::new(input), &export)
+}
+
 #[notan_main]
 fn main() -> Result<(), String> {
     if std::env::var("RUST_LOG").is_err() {
@@ -68,6 +198,15 @@ fn main() -> Result<(), String> {
     }
     let _ = env_logger::try_init();
 
+    let args: Vec<String> = std::env::args().filter(|a| !a.contains("psn_")).collect();
+    let matches = cli().get_matches_from(args);
+    if let Some(script) = matches.value_of("script") {
+        return scripting::run_file(Path::new(script)).map_err(|e| e.to_string());
+    }
+    if matches.contains_id("convert") {
+        return convert_cli(&matches).map_err(|e| e.to_string());
+    }
+
     let icon_data = include_bytes!("../icon.ico");
 
     let mut window_config = WindowConfig::new()
@@ -160,34 +299,17 @@ fn init(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSta
     // Filter out strange mac args
     let args: Vec<String> = std::env::args().filter(|a| !a.contains("psn_")).collect();
 
-    let matches = Command::new("Oculante")
-        .arg(Arg::new("INPUT").help("Display this image").index(1))
-        .arg(
-            Arg::new("l")
-                .short('l')
-                .help("Listen on port")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::new("stdin")
-                .short('s')
-                .id("stdin")
-                .takes_value(false)
-                .help("Load data from STDIN"),
-        )
-        .arg(
-            Arg::new("chainload")
-                .required(false)
-                .takes_value(false)
-                .short('c')
-                .help("Chainload on Mac"),
-        )
-        .get_matches_from(args);
+    let matches = cli().get_matches_from(args);
 
     debug!("Completed argument parsing.");
 
     // #[cfg(target_os = "windows")]
-    let maybe_img_location = matches.value_of("INPUT").map(PathBuf::from);
+    let read_stdin = matches.contains_id("stdin") || matches.value_of("INPUT") == Some("-");
+    let url = matches.value_of("INPUT").filter(|input| download::is_url(input));
+    let maybe_img_location = matches
+        .value_of("INPUT")
+        .filter(|input| *input != "-" && url.is_none())
+        .map(PathBuf::from);
 
     // #[cfg(not(target_os = "windows"))]
     // let maybe_img_location = matches
@@ -222,6 +344,7 @@ fn init(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSta
         state.persistent_settings.max_cache,
         gfx.limits().max_texture_size,
     );
+    state.player.watch(state.reload_channel.0.clone());
 
     debug!("Image is: {:?}", maybe_img_location);
 
@@ -232,7 +355,9 @@ fn init(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSta
         if let Ok(maybe_location_metadata) = location.metadata() {
             if maybe_location_metadata.is_dir() {
                 // Folder - Pick first image from the folder...
-                if let Ok(first_img_location) = find_first_image_in_directory(location) {
+                if let Ok(first_img_location) = find_first_image_in_directory(location, &state.persistent_settings.listing) {
+                    // the folder is browsed from where it was opened, in case subfolders are walked
+                    state.scrubber = scrubber::Scrubber::new(location, &state.persistent_settings.listing);
                     start_img_location = Some(first_img_location);
                 }
             } else if is_ext_compatible(location) {
@@ -257,27 +382,19 @@ fn init(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSta
         }
     }
 
-    if matches.contains_id("stdin") {
-        debug!("Trying to read from pipe");
-        let mut input = vec![];
-        if let Ok(bytes_read) = std::io::stdin().read_to_end(&mut input) {
-            if bytes_read > 0 {
-                debug!("There was stdin");
-
-                match image::load_from_memory(input.as_ref()) {
-                    Ok(i) => {
-                        // println!("got image");
-                        debug!("Sending image!");
-                        let _ = state
-                            .texture_channel
-                            .0
-                            .clone()
-                            .send(utils::Frame::new_reset(i.to_rgba8()));
-                    }
-                    Err(e) => error!("ERR loading from stdin: {e} - for now, oculante only supports data that can be decoded by the image crate."),
-                }
-            }
-        }
+    if let Some(url) = url {
+        open_url(&mut state, url);
+    }
+
+    if read_stdin {
+        load_stdin(
+            state.load_channel.0.clone(),
+            state.message_channel.0.clone(),
+        );
+    }
+
+    if let Some(folder) = matches.value_of("watch") {
+        watch_folder(&mut state, Path::new(folder));
     }
 
     if let Some(port) = matches.value_of("l") {
@@ -346,19 +463,11 @@ fn init(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins) -> OculanteSta
         ctx.set_style(style);
     });
 
-    // load checker texture
-    if let Ok(checker_image) = image::load_from_memory(include_bytes!("../res/checker.png")) {
-        // state.checker_texture = checker_image.into_rgba8().to_texture(gfx);
-        // No mipmaps for the checker pattern!
-        let img = checker_image.into_rgba8();
-        state.checker_texture = gfx
-            .create_texture()
-            .from_bytes(&img, img.width(), img.height())
-            .with_mipmaps(false)
-            .with_format(notan::prelude::TextureFormat::SRgba8)
-            .build()
-            .ok();
-    }
+    state.checker_texture = state.persistent_settings.checker.texture(gfx);
+
+    state.tone_mapper = tonemap::ToneMapper::new(gfx)
+        .map_err(|e| error!("Can't create tone mapping pipeline: {e}"))
+        .ok();
 
     // force a frame to render so ctx() has a size (important for centering the image)
     gfx.render(&plugins.egui(|_| {}));
@@ -377,6 +486,62 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
         Event::KeyDown { .. } => {
             debug!("key down");
 
+            if key_pressed(app, state, Gallery) {
+                toggle_gallery(state);
+            }
+            if key_pressed(app, state, Histogram) {
+                state.persistent_settings.show_histogram =
+                    !state.persistent_settings.show_histogram;
+            }
+            if key_pressed(app, state, Scopes) {
+                state.persistent_settings.show_scopes = !state.persistent_settings.show_scopes;
+            }
+            if key_pressed(app, state, PixelInspector) {
+                state.persistent_settings.show_inspector =
+                    !state.persistent_settings.show_inspector;
+            }
+            if key_pressed(app, state, FolderPanel) {
+                state.persistent_settings.show_folder_panel =
+                    !state.persistent_settings.show_folder_panel;
+            }
+            if key_pressed(app, state, CycleBackground) {
+                let preset = state.persistent_settings.background_preset.next();
+                state.persistent_settings.background_preset = preset;
+                state.send_message_info(&format!("Background: {preset}"));
+            }
+            if key_pressed(app, state, RecentFiles) {
+                state.recent_filter = Some(Default::default());
+            }
+            // the gallery has its own keys to move through the folder
+            if state.gallery.is_some() {
+                return;
+            }
+            if key_pressed(app, state, Favourite) {
+                toggle_favourite(state);
+            }
+            if key_pressed(app, state, FavouritesOnly) {
+                toggle_favourites_only(state);
+            }
+            for (stars, rate) in [RateNone, RateOne, RateTwo, RateThree, RateFour, RateFive]
+                .into_iter()
+                .enumerate()
+            {
+                if key_pressed(app, state, rate) {
+                    set_rating(state, stars as u8);
+                }
+            }
+            for (label, event) in ColorLabel::iter().zip([
+                LabelRed,
+                LabelYellow,
+                LabelGreen,
+                LabelBlue,
+                LabelPurple,
+            ]) {
+                if key_pressed(app, state, event) {
+                    toggle_label(state, label);
+                }
+            }
+
             // return;
             // pan image with keyboard
             let delta = 40.;
@@ -426,22 +591,19 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                     state.send_message_info("Image copied");
                 }
             }
+            if key_pressed(app, state, CopyEdited) {
+                // the edit result is empty until edits have been processed once
+                if state.edit_state.result_pixel_op.width() > 0 {
+                    clipboard_copy(&state.edit_state.result_pixel_op);
+                    state.send_message_info("Edited image copied");
+                } else if let Some(img) = &state.current_image {
+                    clipboard_copy(img);
+                    state.send_message_info("Image copied");
+                }
+            }
 
             if key_pressed(app, state, Paste) {
-                match clipboard_to_image() {
-                    Ok(img) => {
-                        state.current_path = None;
-                        // Stop in the even that an animation is running
-                        state.player.stop();
-                        _ = state
-                            .player
-                            .image_sender
-                            .send(crate::utils::Frame::new_still(img));
-                        // Since pasted data has no path, make sure it's not set
-                        state.send_message_info("Image pasted");
-                    }
-                    Err(e) => state.send_message_err(&e.to_string()),
-                }
+                paste(state);
             }
             if key_pressed(app, state, Quit) {
                 state.persistent_settings.save_blocking();
@@ -500,14 +662,39 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                 }
             }
 
-            if key_pressed(app, state, NextImage) {
-                if state.is_loaded {
-                    next_image(state)
+            // the arrow keys step through the frames of a video instead of the folder
+            if state.video.is_some() {
+                let step = if key_pressed(app, state, NextImage) || key_pressed(app, state, NextFrame) {
+                    1
+                } else if key_pressed(app, state, PreviousImage) || key_pressed(app, state, PreviousFrame) {
+                    -1
+                } else {
+                    0
+                };
+                if let Some(video) = state.video.as_mut().filter(|_| step != 0) {
+                    video.step(step, &state.texture_channel.0, &state.message_channel.0);
+                }
+            } else {
+                if key_pressed(app, state, NextImage) {
+                    if state.is_loaded {
+                        next_image(state)
+                    }
+                }
+                if key_pressed(app, state, PreviousImage) {
+                    if state.is_loaded {
+                        prev_image(state)
+                    }
                 }
             }
-            if key_pressed(app, state, PreviousImage) {
-                if state.is_loaded {
-                    prev_image(state)
+            if state.is_animation {
+                if key_pressed(app, state, PauseAnimation) {
+                    state.player.toggle_pause();
+                }
+                if key_pressed(app, state, NextFrame) {
+                    state.player.step(1);
+                }
+                if key_pressed(app, state, PreviousFrame) {
+                    state.player.step(-1);
                 }
             }
             if key_pressed(app, state, FirstImage) {
@@ -528,6 +715,25 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
                     &state.extended_info_channel,
                 );
             }
+            if state.persistent_settings.edit_enabled {
+                if key_pressed(app, state, Undo) {
+                    state.edit_state.undo();
+                }
+                if key_pressed(app, state, Redo) {
+                    state.edit_state.redo();
+                }
+            }
+            if key_pressed(app, state, CopyEdits) {
+                state.copied_edits = Some(state.edit_state.copy_stacks());
+                state.send_message_info("Edits copied");
+            }
+            if key_pressed(app, state, PasteEdits) {
+                if let Some(stacks) = &state.copied_edits {
+                    state.edit_state.paste_stacks(stacks);
+                    state.persistent_settings.edit_enabled = true;
+                    state.send_message_info("Edits pasted");
+                }
+            }
             if key_pressed(app, state, EditMode) {
                 state.persistent_settings.edit_enabled = !state.persistent_settings.edit_enabled;
             }
@@ -535,6 +741,7 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
             if key_pressed(app, state, DeleteFile) {
                 if let Some(p) = &state.current_path {
                     _ = trash::delete(p);
+                    state.scrubber.entries.retain(|e| e != p);
                     state.send_message_info("Deleted image");
                 }
             }
@@ -641,9 +848,13 @@ fn event(app: &mut App, state: &mut OculanteState, evt: Event) {
         }
 
         Event::Drop(file) => {
-            if let Some(p) = file.path {
+            // links may be dropped from browsers
+            let link = file.path.as_ref().map(|p| p.to_string_lossy().to_string());
+            if let Some(url) = link.as_deref().filter(|l| download::is_url(l)) {
+                open_url(state, url);
+            } else if let Some(p) = file.path {
                 if let Some(ext) = p.extension() {
-                    if SUPPORTED_EXTENSIONS.contains(&ext.to_string_lossy().to_string().as_str()) {
+                    if supported_extensions().contains(&ext.to_string_lossy().to_string().as_str()) {
                         state.is_loaded = false;
                         state.current_image = None;
                         state.player.load(&p, state.message_channel.0.clone());
@@ -685,13 +896,12 @@ fn update(app: &mut App, state: &mut OculanteState) {
 
     // dbg!(format!("upg {}", app.timer.elapsed_f32()));
 
-    if let Some(p) = &state.current_path {
-        let t = app.timer.elapsed_f32() % 0.8;
-        if t <= 0.05 {
-            trace!("chk mod {}", t);
-            state
-                .player
-                .check_modified(p, state.message_channel.0.clone());
+    // the image shown changed on disk, see `Player::watch`
+    if let Ok(p) = state.reload_channel.1.try_recv() {
+        if state.persistent_settings.auto_reload && state.current_path.as_ref() == Some(&p) {
+            state.player.cache.data.remove(&p);
+            state.player.load(&p, state.message_channel.0.clone());
+            state.reloaded = true;
         }
     }
 
@@ -765,6 +975,7 @@ fn update(app: &mut App, state: &mut OculanteState) {
                 state.current_image = None;
                 state.is_loaded = true;
                 state.current_texture = None;
+                state.tiles = None;
             }
             Message::Info(m) => {
                 state
@@ -793,15 +1004,33 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         state.is_loaded = false;
         state.current_image = None;
         state.player.load(&p, state.message_channel.0.clone());
-        if let Some(dir) = p.parent() {
+        // downloads and STDIN are stored in the temporary folder, which is no place to browse
+        if let Some(dir) = p.parent().filter(|d| !d.starts_with(std::env::temp_dir())) {
             state.persistent_settings.last_open_directory = dir.to_path_buf();
         }
         state.current_path = Some(p);
     }
 
     // check if a new texture has been sent
-    if let Ok(frame) = state.texture_channel.1.try_recv() {
+    let frame = state.texture_channel.1.try_recv().ok().filter(|f| match &f.source {
+        // another image may have been opened while this was rendered
+        FrameSource::Rerender(path) => state.current_path.as_ref() == Some(path),
+        // don't replace the previous image if its view is kept
+        FrameSource::Preview(_) => {
+            !state.persistent_settings.keep_view || state.current_texture.is_none()
+        }
+        // the capture may have been stopped since
+        FrameSource::Capture => state.capture.is_some(),
+        _ => true,
+    });
+    if let Some(frame) = frame {
+        // previews of the image that changed are still shown like a new image
+        let reloaded = matches!(frame.source, FrameSource::Still | FrameSource::AnimationStart)
+            && std::mem::take(&mut state.reloaded);
+        let keep_edits = state.persistent_settings.keep_edits
+            || reloaded && state.persistent_settings.reload_keeps_edits;
         let img = frame.buffer;
+        let previous_dimensions = state.image_geometry.dimensions;
         debug!("Received image buffer: {:?}", img.dimensions());
         state.image_geometry.dimensions = img.dimensions();
         // state.current_texture = img.to_texture(gfx);
@@ -812,27 +1041,72 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
 
         // fill image sequence
         if let Some(p) = &state.current_path {
-            state.scrubber = scrubber::Scrubber::new(p);
-            state.scrubber.wrap = state.persistent_settings.wrap_folder;
+            if matches!(frame.source, FrameSource::Still | FrameSource::AnimationStart) {
+                state.rating = xmp::Rating::read(p);
+            }
+            // opening an image that is not a favourite goes back to its folder
+            if !(state.favourites_only && state.scrubber.entries.contains(p)) {
+                state.favourites_only = false;
+                state.scrubber.open(p, &state.persistent_settings.listing);
+                state.scrubber.wrap = state.persistent_settings.wrap_folder;
+            }
+            if state.persistent_settings.prefetch
+                && matches!(frame.source, FrameSource::Still | FrameSource::AnimationStart)
+            {
+                state.player.prefetch(&state.scrubber.neighbours());
+            }
 
             // debug!("{:#?} from {}", &state.scrubber, p.display());
-            if !state.persistent_settings.recent_images.contains(p) {
-                state.persistent_settings.recent_images.insert(0, p.clone());
-                state.persistent_settings.recent_images.truncate(10);
+            let recent = &mut state.persistent_settings.recent_images;
+            if recent.first() != Some(p) {
+                recent.retain(|r| r != p);
+                recent.insert(0, p.clone());
+                recent.truncate(settings::MAX_RECENT_IMAGES);
             }
         }
 
         match frame.source {
             FrameSource::Still => {
                 debug!("Received still");
+                state.is_animation = false;
+                state.vector_rendering = false;
+                state.vector_scale = state
+                    .current_path
+                    .as_ref()
+                    .filter(|p| {
+                        p.extension().map(|e| e.to_ascii_lowercase()) == Some("svg".into())
+                    })
+                    .map(|_| image_loader::SVG_RENDER_SCALE);
+                state.texture_mips.clear();
+                state.mip_level = 0;
+                state.show_mip_chain = false;
+                state.icon_sizes.clear();
+                state.icon_size = 0;
+                state.psd_tree = None;
+                state.fits_stretch = Default::default();
+                state.dicom = None;
+                state.tiles = frame.tiled.clone().map(TileView::new);
+                state.capture = None;
+                state.video = frame.video.clone();
                 state.edit_state.result_image_op = Default::default();
                 state.edit_state.result_pixel_op = Default::default();
 
-                if !state.persistent_settings.keep_view {
+                // the view was fitted to a preview
+                if state.preview_of.take().is_some() {
+                    state.reset_image = true;
+                }
+
+                // an image that changed on disk keeps its view, like a live preview
+                if !state.persistent_settings.keep_view && !reloaded {
                     state.reset_image = true;
 
                     if let Some(p) = state.current_path.clone() {
-                        if state.persistent_settings.max_cache != 0 {
+                        // the overview of a tiled image would be opened without its tiles, and a
+                        // video frame without its video
+                        if state.persistent_settings.max_cache != 0
+                            && state.tiles.is_none()
+                            && state.video.is_none()
+                        {
                             state.player.cache.insert(&p, img.clone());
                         }
                     }
@@ -842,7 +1116,7 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                     state.reset_image = true;
                 }
 
-                if !state.persistent_settings.keep_edits {
+                if !keep_edits {
                     state.edit_state = Default::default();
                 } else {
                     state.edit_state.result_pixel_op = Default::default();
@@ -850,12 +1124,18 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 }
 
                 // Load edit information if any
-                if let Some(p) = &state.current_path {
-                    if p.with_extension("oculante").is_file() {
-                        if let Ok(f) = std::fs::File::open(p.with_extension("oculante")) {
+                if let Some(p) = state.current_path.as_ref().filter(|_| !(reloaded && keep_edits)) {
+                    // prefer sidecars named after the full file name, fall back to the old naming
+                    let sidecar = [sidecar_path(p), legacy_sidecar_path(p)]
+                        .iter()
+                        .find(|s| s.is_file())
+                        .cloned();
+                    if let Some(sidecar) = sidecar {
+                        if let Ok(f) = std::fs::File::open(sidecar) {
                             if let Ok(edit_state) = serde_json::from_reader::<_, EditState>(f) {
                                 state.send_message_info("Edits have been loaded for this image.");
                                 state.edit_state = edit_state;
+                                state.edit_state.reset_history();
                                 state.persistent_settings.edit_enabled = true;
                                 state.reset_image = true;
                             }
@@ -871,6 +1151,7 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                                         "Directory edits have been loaded for this image.",
                                     );
                                     state.edit_state = edit_state;
+                                    state.edit_state.reset_history();
                                     state.persistent_settings.edit_enabled = true;
                                     state.reset_image = true;
                                 }
@@ -887,16 +1168,59 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
             FrameSource::AnimationStart => {
                 state.redraw = true;
-                state.reset_image = true
+                state.reset_image = true;
+                state.is_animation = true;
+                state.vector_scale = None;
+                state.tiles = None;
+                state.capture = None;
             }
             FrameSource::Animation => {
                 state.redraw = true;
+                state.is_animation = true;
             }
             FrameSource::CompareResult => {
                 state.redraw = false;
             }
+            FrameSource::Preview(full_size) => {
+                state.is_animation = false;
+                state.vector_scale = None;
+                state.redraw = false;
+                state.preview_of = Some(full_size);
+                state.reset_image = true;
+                state.tiles = None;
+                state.capture = None;
+            }
+            FrameSource::Capture => {
+                state.redraw = true;
+                state.is_animation = false;
+                state.vector_scale = None;
+                state.tiles = None;
+                state.video = None;
+                if previous_dimensions != img.dimensions() {
+                    state.reset_image = true;
+                }
+                // the edits are applied to every frame
+                state.edit_state.result_pixel_op = Default::default();
+                state.edit_state.result_image_op = Default::default();
+                if let Some(capture) = &state.capture {
+                    capture.shown();
+                }
+            }
+            FrameSource::Rerender(_) => {
+                state.vector_rendering = false;
+                // keep the size of vector images on screen
+                if let Some(vector_scale) = &mut state.vector_scale {
+                    if previous_dimensions.0 != 0 {
+                        let factor = img.width() as f32 / previous_dimensions.0 as f32;
+                        state.image_geometry.scale /= factor;
+                        *vector_scale *= factor;
+                    }
+                }
+            }
         }
 
+        state.histogram = None;
+        state.scope_texture = None;
         if let Some(tex) = &mut state.current_texture {
             if tex.width() as u32 == img.width() && tex.height() as u32 == img.height() {
                 img.update_texture(gfx, tex);
@@ -928,6 +1252,14 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
         }
         state.current_image = Some(img);
+        state.compare_blend.invalidate();
+        state.current_hdr_image = frame.hdr_buffer;
+        state.hdr_texture = state.current_hdr_image.as_ref().and_then(|hdr| {
+            tonemap::hdr_texture(gfx, hdr, state.persistent_settings.linear_mag_filter)
+        });
+        state.original_texture = None;
+        // cached edit results are based on the previous image
+        state.edit_state.caches.clear();
         if state.persistent_settings.info_enabled {
             debug!("Sending extended info");
             send_extended_info(
@@ -938,6 +1270,8 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         }
     }
 
+    rerender_vector(state);
+
     if state.redraw {
         trace!("Force redraw");
         app.window().request_frame();
@@ -965,7 +1299,7 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             if ctx.memory(|w| w.is_popup_open(Id::new("OPEN_SHORTCUT"))) {
                 filebrowser::browse_modal(
                     false,
-                    SUPPORTED_EXTENSIONS,
+                    supported_extensions(),
                     |p| {
                         let _ = state.load_channel.0.clone().send(p.to_path_buf());
                     },
@@ -1006,6 +1340,13 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 });
         }
 
+        if state.persistent_settings.show_folder_panel
+            && !state.settings_enabled
+            && !state.persistent_settings.zen_mode
+        {
+            folder_panel_ui(ctx, state);
+        }
+
         if state.persistent_settings.info_enabled
             && !state.settings_enabled
             && !state.persistent_settings.zen_mode
@@ -1020,6 +1361,17 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             edit_ui(app, ctx, state, gfx);
         }
 
+        if !state.persistent_settings.zen_mode {
+            inspector_ui(ctx, state);
+            histogram_ui(ctx, state);
+            scopes_ui(ctx, state, gfx);
+        }
+
+        // last, as it fills the space left by the panels
+        gallery_ui(ctx, state, gfx);
+
+        recent_ui(ctx, state);
+
         state.pointer_over_ui = ctx.is_pointer_over_area();
         // ("using pointer {}", ctx.is_using_pointer());
 
@@ -1027,6 +1379,7 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         // we don't want zoom & pan to work, so we "grab" the pointer
         if ctx.is_using_pointer()
             || state.edit_state.painting
+            || state.edit_state.selection_tool.is_some()
             || ctx.is_pointer_over_area()
             || state.edit_state.block_panning
         {
@@ -1049,11 +1402,13 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                     draw_area.height().min(app.window().height() as f32),
                 );
                 let img_size = current_image.size_vec();
-                state.image_geometry.scale = (window_size.x / img_size.x)
-                    .min(window_size.y / img_size.y)
+                // a preview is fitted like the full image it stands in for
+                let fit_size = state.preview_of.map(|s| s.size_vec()).unwrap_or(img_size);
+                let fit = (window_size.x / fit_size.x)
+                    .min(window_size.y / fit_size.y)
                     .min(1.0);
-                state.image_geometry.offset =
-                    window_size / 2.0 - (img_size * state.image_geometry.scale) / 2.0;
+                state.image_geometry.scale = fit * fit_size.x / img_size.x;
+                state.image_geometry.offset = window_size / 2.0 - (fit_size * fit) / 2.0;
                 // offset by left UI elements
                 state.image_geometry.offset.x += draw_area.left();
                 // offset by top UI elements
@@ -1064,27 +1419,81 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
             }
         }
 
+        convert_ui(ctx, state);
+        overwrite_ui(ctx, state);
+        brush_overwrite_ui(ctx, state);
+
         // Settings come last, as they block keyboard grab (for hotkey assigment)
         settings_ui(app, ctx, state, gfx);
     });
 
+    state.compare_blend.update(
+        gfx,
+        state.current_image.as_ref(),
+        state.persistent_settings.linear_mag_filter,
+    );
+
+    let split = if key_held(app, state, HoldCompare) {
+        Some(1.)
+    } else {
+        state.edit_state.split_compare
+    };
+
+    // the checker settings changed
+    if state.checker_texture.is_none() {
+        state.checker_texture = state.persistent_settings.checker.texture(gfx);
+    }
+
     if let Some(texture) = &state.current_texture {
         if state.persistent_settings.show_checker_background {
             if let Some(checker) = &state.checker_texture {
                 draw.pattern(checker)
                     // .size(texture.width() as f32, texture.height() as f32)
                     .size(texture.width() as f32 * state.image_geometry.scale * state.tiling as f32, texture.height() as f32 * state.image_geometry.scale* state.tiling as f32)
-                    .blend_mode(BlendMode::ADD)
+                    .blend_mode(BlendMode::NORMAL)
                     .translate(state.image_geometry.offset.x, state.image_geometry.offset.y)
                     // .scale(state.image_geometry.scale, state.image_geometry.scale)
                     ;
             }
         }
-        if state.tiling < 2 {
-            draw.image(texture)
+        // unedited HDR images are tone mapped from their full range
+        let hdr = state
+            .hdr_texture
+            .as_ref()
+            .zip(state.tone_mapper.as_ref())
+            .filter(|(hdr, _)| {
+                !state.persistent_settings.edit_enabled
+                    && state.persistent_settings.current_channel == ColorChannel::Rgba
+                    && state.tiling < 2
+                    && hdr.size() == texture.size()
+            });
+        if let Some((hdr, tone_mapper)) = hdr {
+            tone_mapper.set(
+                gfx,
+                state.persistent_settings.tone_map,
+                state.persistent_settings.exposure,
+            );
+            draw.image_pipeline()
+                .pipeline(&tone_mapper.pipeline)
+                .uniform_buffer(&tone_mapper.uniforms);
+            draw.image(hdr)
+                .blend_mode(BlendMode::NORMAL)
+                .scale(state.image_geometry.scale, state.image_geometry.scale)
+                .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+            draw.image_pipeline().remove();
+        } else if state.tiling < 2 {
+            let mut image = draw.image(texture);
+            image
                 .blend_mode(BlendMode::NORMAL)
                 .scale(state.image_geometry.scale, state.image_geometry.scale)
                 .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+            // a downscaled edit preview is stretched to the size of the full result
+            if state.edit_state.proxy_scale.is_some() {
+                image.size(
+                    state.image_geometry.dimensions.0 as f32,
+                    state.image_geometry.dimensions.1 as f32,
+                );
+            }
         } else {
             draw.pattern(texture)
                 .scale(state.image_geometry.scale, state.image_geometry.scale)
@@ -1095,6 +1504,62 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 );
         }
 
+        // the part in view of very large images, in more detail than the overview
+        let show_tiles = state.tiling < 2
+            && !state.persistent_settings.edit_enabled
+            && state.persistent_settings.current_channel == ColorChannel::Rgba;
+        if let Some(tiles) = state.tiles.as_mut().filter(|_| show_tiles) {
+            let window_size = app.window().size();
+            // decoded tiles are shown in the next frames, even if nothing else happens
+            if tiles.draw(
+                gfx,
+                &mut draw,
+                texture.width(),
+                &state.image_geometry,
+                Vector2::new(window_size.0 as f32, window_size.1 as f32),
+                state.persistent_settings.linear_mag_filter,
+            ) {
+                app.window().request_frame();
+            }
+        }
+
+        // another image of the compare list, on top and at the same scale
+        if let Some(overlay) = state.compare_blend.texture.as_ref().filter(|_| state.tiling < 2) {
+            let alpha = match state.compare_blend.mode {
+                compare::CompareMode::OnionSkin => 0.5,
+                _ => 1.0,
+            };
+            draw.image(overlay)
+                .alpha(alpha)
+                .blend_mode(BlendMode::NORMAL)
+                .scale(state.image_geometry.scale, state.image_geometry.scale)
+                .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+        }
+
+        // show the unedited image left of the split line, or entirely while the compare key is held
+        if let Some(split) =
+            split.filter(|_| state.persistent_settings.edit_enabled && state.tiling < 2)
+        {
+            if state.original_texture.is_none() {
+                if let Some(img) = &state.current_image {
+                    state.original_texture =
+                        img.to_texture(gfx, state.persistent_settings.linear_mag_filter);
+                }
+            }
+            if let Some(original) = &state.original_texture {
+                // stretched over the result, in case the edits changed its size
+                draw.image(original)
+                    .crop((0., 0.), (original.width() * split, original.height()))
+                    .size(
+                        state.image_geometry.dimensions.0 as f32 * split,
+                        state.image_geometry.dimensions.1 as f32,
+                    )
+                    .blend_mode(BlendMode::NORMAL)
+                    .scale(state.image_geometry.scale, state.image_geometry.scale)
+                    .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
+            }
+        }
+
         if state.persistent_settings.show_frame {
             draw.rect((0.0, 0.0), texture.size())
                 .stroke(1.0)
@@ -1109,6 +1574,15 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
                 .translate(state.image_geometry.offset.x, state.image_geometry.offset.y);
         }
 
+        if state.tiling < 2 {
+            let window_size = app.window().size();
+            state.persistent_settings.pixel_grid.draw(
+                &mut draw,
+                &state.image_geometry,
+                (window_size.0 as f32, window_size.1 as f32),
+            );
+        }
+
         if state.persistent_settings.show_minimap {
             // let offset_x = app.window().size().0 as f32 - state.dimensions.0 as f32;
             let offset_x = 0.0;
@@ -1156,13 +1630,17 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
         }
     }
 
-    if state.network_mode {
+    // keep drawing without input, so images from the network or the watched folder are shown
+    if state.network_mode || state.hot_folder.is_some() {
         app.window().request_frame();
     }
     // if state.edit_state.is_processing {
     //     app.window().request_frame();
     // }
-    let c = state.persistent_settings.background_color;
+    let c = state
+        .persistent_settings
+        .background_preset
+        .color(state.persistent_settings.background_color);
     // draw.clear(Color:: from_bytes(c[0], c[1], c[2], 255));
     draw.clear(Color::from_rgb(
         c[0] as f32 / 255.,
@@ -1173,6 +1651,90 @@ fn drawe(app: &mut App, gfx: &mut Graphics, plugins: &mut Plugins, state: &mut O
     gfx.render(&egui_output);
 }
 
+/// Render SVGs again when the zoom changed a lot, so they stay sharp
+fn rerender_vector(state: &mut OculanteState) {
+    let (Some(scale), Some(path)) = (state.vector_scale, state.current_path.clone()) else {
+        return;
+    };
+    // edits are based on the pixels of the current render
+    if state.vector_rendering || !state.is_loaded || state.persistent_settings.edit_enabled {
+        return;
+    }
+    let zoom = state.image_geometry.scale;
+    // leave some room, so small zoom steps don't render every time
+    if (0.5..=1.5).contains(&zoom) {
+        return;
+    }
+    let (width, height) = state.image_geometry.dimensions;
+    let largest_side = width.max(height) as f32 / scale;
+    let max_scale = state.player.max_texture_size as f32 / largest_side.max(1.);
+    let new_scale = (scale * zoom).min(max_scale);
+    if (new_scale / scale - 1.).abs() < 0.1 {
+        return;
+    }
+    debug!("Rendering {} at {new_scale}", path.display());
+    state.vector_rendering = true;
+    let texture_sender = state.texture_channel.0.clone();
+    let message_sender = state.message_channel.0.clone();
+    std::thread::spawn(move || {
+        let render = std::fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| image_loader::render_svg(&data, new_scale));
+        match render {
+            Ok(buf) => {
+                _ = texture_sender.send(Frame::new(buf, 0, FrameSource::Rerender(path)));
+            }
+            Err(e) => {
+                _ = message_sender.send(Message::err(&format!("Could not render SVG: {e}")));
+            }
+        }
+    });
+}
+
+/// Download an image from a link and open it when it is complete
+fn open_url(state: &mut OculanteState, url: &str) {
+    state.download = Some(download::Download::start(
+        url,
+        state.load_channel.0.clone(),
+        state.message_channel.0.clone(),
+    ));
+}
+
+/// Read an image piped to STDIN in the background. It is opened from a temporary file, so every
+/// format can be loaded.
+fn load_stdin(load_sender: mpsc::Sender<PathBuf>, message_sender: mpsc::Sender<Message>) {
+    std::thread::spawn(move || {
+        debug!("Trying to read from pipe");
+        let mut data = vec![];
+        if let Err(e) = std::io::stdin().read_to_end(&mut data) {
+            _ = message_sender.send(Message::err(&format!("Could not read STDIN: {e}")));
+            return;
+        }
+        if data.is_empty() {
+            return;
+        }
+        let Some(extension) = image_loader::guess_extension(&data) else {
+            _ = message_sender.send(Message::err(
+                "The format of the data from STDIN was not recognized",
+            ));
+            return;
+        };
+        // a folder of its own, so there is nothing else to browse, and one per process so
+        // several instances reading from pipes don't remove each other's images
+        let dir = std::env::temp_dir().join(format!("oculante-stdin-{}", std::process::id()));
+        let path = dir.join(format!("stdin.{extension}"));
+        _ = std::fs::remove_dir_all(&dir);
+        match std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, &data)) {
+            Ok(_) => {
+                _ = load_sender.send(path);
+            }
+            Err(e) => {
+                _ = message_sender.send(Message::err(&format!("Could not store STDIN: {e}")));
+            }
+        }
+    });
+}
+
 // Show file browser to select image to load
 #[cfg(feature = "file_open")]
 fn browse_for_image_path(state: &mut OculanteState) {
@@ -1181,12 +1743,12 @@ fn browse_for_image_path(state: &mut OculanteState) {
     state.redraw = true;
     std::thread::spawn(move || {
         let uppercase_lowercase_ext = [
-            utils::SUPPORTED_EXTENSIONS
-                .into_iter()
+            utils::supported_extensions()
+                .iter()
                 .map(|e| e.to_ascii_lowercase())
                 .collect::<Vec<_>>(),
-            utils::SUPPORTED_EXTENSIONS
-                .into_iter()
+            utils::supported_extensions()
+                .iter()
                 .map(|e| e.to_ascii_uppercase())
                 .collect::<Vec<_>>(),
         ]
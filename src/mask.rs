@@ -0,0 +1,379 @@
+use image::{GrayImage, Luma, RgbaImage};
+use nalgebra::Vector2;
+use notan::egui::{self, Color32, DragValue, Pos2, Rect, Response, Sense, Stroke, Ui, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::image_editing::bundled_brushes;
+use crate::paint::PaintStroke;
+use crate::{pos_from_coord, ImageGeometry};
+
+/// Limits the effect of an operator to a part of the image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpMask {
+    pub shape: MaskShape,
+    pub invert: bool,
+    /// Width of the soft edge, relative to the larger image side
+    pub feather: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum MaskShape {
+    /// x, y, width, height from 0-1
+    Rectangle([f32; 4]),
+    /// The bounding box as x, y, width, height from 0-1
+    Ellipse([f32; 4]),
+    /// Full effect at `start`, fading out towards `end` (0-1)
+    Gradient {
+        start: (f32, f32),
+        end: (f32, f32),
+    },
+    Painted(Vec<PaintStroke>),
+    /// A closed outline from 0-1, as drawn with the lasso
+    Polygon(Vec<(f32, f32)>),
+    /// A selection of individual pixels, as alternating runs of unselected and selected pixels
+    Pixels {
+        width: u32,
+        height: u32,
+        runs: Vec<u32>,
+    },
+}
+
+impl Default for OpMask {
+    fn default() -> Self {
+        Self {
+            shape: MaskShape::Ellipse([0.25, 0.25, 0.5, 0.5]),
+            invert: false,
+            feather: 0.05,
+        }
+    }
+}
+
+impl MaskShape {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Rectangle(_) => "Rectangle",
+            Self::Ellipse(_) => "Ellipse",
+            Self::Gradient { .. } => "Gradient",
+            Self::Painted(_) => "Painted",
+            Self::Polygon(_) => "Polygon",
+            Self::Pixels { .. } => "Pixels",
+        }
+    }
+
+    /// Pixels of an image of `width` x `height` which are selected, row by row
+    pub fn from_pixels(width: u32, height: u32, selected: &[bool]) -> Self {
+        let mut runs = vec![];
+        let mut current = false;
+        let mut len = 0;
+        for s in selected {
+            if *s != current {
+                runs.push(len);
+                current = *s;
+                len = 0;
+            }
+            len += 1;
+        }
+        runs.push(len);
+        Self::Pixels {
+            width,
+            height,
+            runs,
+        }
+    }
+}
+
+impl OpMask {
+    /// Render the coverage of this mask for an image of `dimensions`. 255 means full effect.
+    pub fn render(&self, dimensions: (u32, u32)) -> GrayImage {
+        let (w, h) = (dimensions.0.max(1) as f32, dimensions.1.max(1) as f32);
+        // feather in pixels
+        let feather = (self.feather * w.max(h)).max(f32::EPSILON);
+
+        let mut mask = match &self.shape {
+            MaskShape::Painted(strokes) => {
+                let mut canvas = RgbaImage::new(dimensions.0, dimensions.1);
+                // mask strokes always use the first brush
+                for stroke in strokes.iter().filter(|s| !s.is_empty()) {
+                    stroke.render(&mut canvas, bundled_brushes());
+                }
+                GrayImage::from_fn(dimensions.0, dimensions.1, |x, y| {
+                    Luma([canvas.get_pixel(x, y)[3]])
+                })
+            }
+            MaskShape::Polygon(points) => {
+                let mut mask = GrayImage::new(dimensions.0, dimensions.1);
+                // fill the spans between crossings of the outline with each row (even-odd rule)
+                for y in 0..dimensions.1 {
+                    let py = (y as f32 + 0.5) / h;
+                    let mut crossings = points
+                        .iter()
+                        .zip(points.iter().cycle().skip(1))
+                        .filter(|(a, b)| (a.1 <= py) != (b.1 <= py))
+                        .map(|(a, b)| (a.0 + (py - a.1) / (b.1 - a.1) * (b.0 - a.0)) * w)
+                        .collect::<Vec<_>>();
+                    crossings.sort_by(|a, b| a.total_cmp(b));
+                    for span in crossings.chunks_exact(2) {
+                        let x0 = (span[0] - 0.5).ceil().clamp(0., w) as u32;
+                        let x1 = (span[1] - 0.5).ceil().clamp(0., w) as u32;
+                        for x in x0..x1 {
+                            mask.put_pixel(x, y, Luma([255]));
+                        }
+                    }
+                }
+                mask
+            }
+            MaskShape::Pixels {
+                width,
+                height,
+                runs,
+            } => {
+                let mut raw = Vec::with_capacity((width * height) as usize);
+                for (i, len) in runs.iter().enumerate() {
+                    let value = if i % 2 == 0 { 0 } else { 255 };
+                    raw.extend(std::iter::repeat(value).take(*len as usize));
+                }
+                raw.resize((width * height) as usize, 0);
+                let pixels = GrayImage::from_raw(*width, *height, raw).unwrap_or_default();
+                if pixels.dimensions() == dimensions {
+                    pixels
+                } else {
+                    // the selection was made on an image of another size
+                    image::imageops::resize(
+                        &pixels,
+                        dimensions.0,
+                        dimensions.1,
+                        image::imageops::Nearest,
+                    )
+                }
+            }
+            shape => GrayImage::from_fn(dimensions.0, dimensions.1, |x, y| {
+                let p = Vector2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let coverage = shape_coverage(shape, p, (w, h), feather);
+                Luma([(coverage.clamp(0., 1.) * 255.) as u8])
+            }),
+        };
+
+        if self.invert {
+            for p in mask.pixels_mut() {
+                p[0] = 255 - p[0];
+            }
+        }
+        mask
+    }
+
+    pub fn ui(&mut self, ui: &mut Ui, geo: &ImageGeometry, block_panning: &mut bool) -> Response {
+        ui.vertical(|ui| {
+            let mut r = ui.allocate_response(Vec2::ZERO, Sense::hover());
+
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source(ui.id().with("mask shape"))
+                    .selected_text(self.shape.name())
+                    .width(80.)
+                    .show_ui(ui, |ui| {
+                        for shape in [
+                            MaskShape::Rectangle([0.25, 0.25, 0.5, 0.5]),
+                            MaskShape::Ellipse([0.25, 0.25, 0.5, 0.5]),
+                            MaskShape::Gradient {
+                                start: (0.5, 0.),
+                                end: (0.5, 1.),
+                            },
+                            MaskShape::Painted(vec![]),
+                        ] {
+                            let selected = shape.name() == self.shape.name();
+                            if ui.selectable_label(selected, shape.name()).clicked() && !selected {
+                                self.shape = shape;
+                                r.mark_changed();
+                            }
+                        }
+                    });
+                if ui.checkbox(&mut self.invert, "Invert").changed() {
+                    r.mark_changed();
+                }
+                if ui
+                    .add(
+                        DragValue::new(&mut self.feather)
+                            .speed(0.002)
+                            .clamp_range(0.0..=0.5)
+                            .prefix("feather "),
+                    )
+                    .changed()
+                {
+                    r.mark_changed();
+                }
+            });
+
+            let to_screen = |p: (f32, f32)| {
+                Pos2::new(
+                    geo.offset.x + p.0 * geo.dimensions.0 as f32 * geo.scale,
+                    geo.offset.y + p.1 * geo.dimensions.1 as f32 * geo.scale,
+                )
+            };
+            let overlay = Stroke::new(1., Color32::GOLD);
+
+            match &mut self.shape {
+                MaskShape::Rectangle(bounds) | MaskShape::Ellipse(bounds) => {
+                    ui.horizontal(|ui| {
+                        for (value, prefix) in bounds.iter_mut().zip(["x ", "y ", "w ", "h "]) {
+                            if ui
+                                .add(
+                                    DragValue::new(value)
+                                        .speed(0.004)
+                                        .clamp_range(0.0..=1.0)
+                                        .prefix(prefix),
+                                )
+                                .changed()
+                            {
+                                r.mark_changed();
+                            }
+                        }
+                    });
+                    let rect = Rect::from_min_max(
+                        to_screen((bounds[0], bounds[1])),
+                        to_screen((bounds[0] + bounds[2], bounds[1] + bounds[3])),
+                    );
+                    if matches!(self.shape, MaskShape::Rectangle(_)) {
+                        ui.painter().rect_stroke(rect, 0., overlay);
+                    } else {
+                        let points = (0..64)
+                            .map(|i| {
+                                let a = i as f32 / 64. * std::f32::consts::TAU;
+                                rect.center()
+                                    + Vec2::new(a.cos() * rect.width(), a.sin() * rect.height())
+                                        / 2.
+                            })
+                            .collect();
+                        ui.painter().add(egui::Shape::closed_line(points, overlay));
+                    }
+                }
+                MaskShape::Gradient { start, end } => {
+                    ui.horizontal(|ui| {
+                        for (value, prefix) in [
+                            (&mut start.0, "⏵ x "),
+                            (&mut start.1, "y "),
+                            (&mut end.0, "⏹ x "),
+                            (&mut end.1, "y "),
+                        ] {
+                            if ui
+                                .add(
+                                    DragValue::new(value)
+                                        .speed(0.004)
+                                        .clamp_range(0.0..=1.0)
+                                        .prefix(prefix),
+                                )
+                                .changed()
+                            {
+                                r.mark_changed();
+                            }
+                        }
+                    });
+                    ui.painter().arrow(
+                        to_screen(*start),
+                        to_screen(*end) - to_screen(*start),
+                        overlay,
+                    );
+                }
+                MaskShape::Painted(strokes) => {
+                    let painting_id = ui.id().with("painting");
+                    let mut painting = ui
+                        .data(|r| r.get_temp::<bool>(painting_id))
+                        .unwrap_or_default();
+
+                    if strokes.is_empty() {
+                        strokes.push(PaintStroke::new());
+                    }
+
+                    ui.horizontal(|ui| {
+                        if ui.selectable_label(painting, "Paint").clicked() {
+                            painting = !painting;
+                        }
+                        if let Some(stroke) = strokes.last_mut() {
+                            ui.add(
+                                DragValue::new(&mut stroke.width)
+                                    .speed(0.001)
+                                    .clamp_range(0.01..=0.5)
+                                    .prefix("width "),
+                            );
+                        }
+                        if ui.button("Clear").clicked() {
+                            strokes.clear();
+                            r.mark_changed();
+                        }
+                    });
+
+                    if painting {
+                        *block_panning = true;
+                        if let Some(stroke) = strokes.last_mut() {
+                            let pointer_down = ui.input(|i| i.pointer.primary_down());
+                            if pointer_down && !ui.ctx().is_pointer_over_area() {
+                                if let Some(cursor_abs) = ui.input(|i| i.pointer.hover_pos()) {
+                                    let cursor_relative = pos_from_coord(
+                                        geo.offset,
+                                        Vector2::new(cursor_abs.x, cursor_abs.y),
+                                        Vector2::new(
+                                            geo.dimensions.0 as f32,
+                                            geo.dimensions.1 as f32,
+                                        ),
+                                        geo.scale,
+                                    );
+                                    stroke.points.push((
+                                        cursor_relative.x / geo.dimensions.0 as f32,
+                                        cursor_relative.y / geo.dimensions.1 as f32,
+                                    ));
+                                    r.mark_changed();
+                                }
+                            } else if !pointer_down && !stroke.is_empty() {
+                                // start a new stroke with the same settings
+                                let next = stroke.without_points();
+                                strokes.push(next);
+                            }
+                        }
+                    }
+                    ui.data_mut(|w| w.insert_temp(painting_id, painting));
+                }
+                MaskShape::Polygon(points) => {
+                    let points = points.iter().map(|p| to_screen(*p)).collect();
+                    ui.painter().add(egui::Shape::closed_line(points, overlay));
+                }
+                MaskShape::Pixels { .. } => {
+                    ui.label("Made with the magic wand");
+                }
+            }
+            r
+        })
+        .inner
+    }
+}
+
+/// Coverage (0-1) of an analytic mask shape at pixel position `p` of an image of `dim`
+fn shape_coverage(shape: &MaskShape, p: Vector2<f32>, dim: (f32, f32), feather: f32) -> f32 {
+    let (w, h) = dim;
+    match shape {
+        MaskShape::Rectangle(b) => {
+            let min = Vector2::new(b[0] * w, b[1] * h);
+            let max = Vector2::new((b[0] + b[2]) * w, (b[1] + b[3]) * h);
+            let dx = (min.x - p.x).max(p.x - max.x).max(0.);
+            let dy = (min.y - p.y).max(p.y - max.y).max(0.);
+            1. - (dx * dx + dy * dy).sqrt() / feather
+        }
+        MaskShape::Ellipse(b) => {
+            let radius = Vector2::new(b[2] * w / 2., b[3] * h / 2.);
+            if radius.x <= 0. || radius.y <= 0. {
+                return 0.;
+            }
+            let center = Vector2::new(b[0] * w, b[1] * h) + radius;
+            let d = p - center;
+            let r = ((d.x / radius.x).powi(2) + (d.y / radius.y).powi(2)).sqrt();
+            // approximate the distance to the outline
+            1. - (r - 1.).max(0.) * radius.x.min(radius.y) / feather
+        }
+        MaskShape::Gradient { start, end } => {
+            let start = Vector2::new(start.0 * w, start.1 * h);
+            let v = Vector2::new(end.0 * w, end.1 * h) - start;
+            if v.norm_squared() == 0. {
+                return 1.;
+            }
+            1. - (p - start).dot(&v) / v.norm_squared()
+        }
+        MaskShape::Painted(_) | MaskShape::Polygon(_) | MaskShape::Pixels { .. } => 1.,
+    }
+}
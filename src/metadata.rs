@@ -0,0 +1,594 @@
+//! All the metadata of an image: EXIF including the maker notes, XMP and IPTC, as a flat list of
+//! tags to search in, and the position the image was taken at
+
+use std::convert::TryInto;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+
+use anyhow::Result;
+use exif::{In, Tag as ExifTag, Value};
+use image::RgbaImage;
+
+/// The zoom level of the map preview, where streets can be told apart
+pub const MAP_ZOOM: u32 = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// Where the tag comes from, like "EXIF", "GPS" or "IPTC"
+    pub group: String,
+    pub name: String,
+    pub value: String,
+}
+
+impl Tag {
+    /// If the group, name or value contain `query`, ignoring case
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        [&self.group, &self.name, &self.value]
+            .iter()
+            .any(|s| s.to_lowercase().contains(&query))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub tags: Vec<Tag>,
+    pub gps: Option<Gps>,
+}
+
+impl Metadata {
+    /// The metadata of the file at `path`, of which `data` is the content
+    pub fn read(path: &Path, data: &[u8]) -> Self {
+        let mut metadata = Self::default();
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut Cursor::new(data)) {
+            metadata.tags = exif_tags(&exif);
+            metadata.gps = Gps::from_exif(&exif);
+        }
+        metadata.tags.extend(iptc_tags(data));
+        if let Some(xmp) = embedded_xmp(data) {
+            metadata.tags.extend(xmp_tags(&xmp, "XMP"));
+        }
+        for sidecar in crate::xmp::sidecars(path) {
+            if let Ok(xmp) = std::fs::read_to_string(sidecar) {
+                metadata.tags.extend(xmp_tags(&xmp, "XMP sidecar"));
+            }
+        }
+        metadata
+    }
+}
+
+/// Add a tag, or the value to the tag before it if it has the same name, as lists are stored
+fn push(tags: &mut Vec<Tag>, group: &str, name: &str, value: String) {
+    if let Some(last) = tags.last_mut() {
+        if last.group == group && last.name == name {
+            last.value = format!("{}, {value}", last.value);
+            return;
+        }
+    }
+    tags.push(Tag {
+        group: group.to_string(),
+        name: name.to_string(),
+        value,
+    });
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|w| w == needle)
+}
+
+pub fn exif_tags(exif: &exif::Exif) -> Vec<Tag> {
+    let mut tags = vec![];
+    for field in exif.fields() {
+        let group = match (field.tag.context(), field.ifd_num) {
+            (_, In::THUMBNAIL) => "Thumbnail",
+            (exif::Context::Tiff, _) => "Image",
+            (exif::Context::Exif, _) => "EXIF",
+            (exif::Context::Gps, _) => "GPS",
+            (exif::Context::Interop, _) => "Interoperability",
+        };
+        if let (ExifTag::MakerNote, Value::Undefined(note, offset)) = (field.tag, &field.value) {
+            let make = exif
+                .get_field(ExifTag::Make, In::PRIMARY)
+                .map(|m| m.display_value().to_string().trim_matches('"').to_string());
+            tags.extend(maker_notes(
+                exif.buf(),
+                *offset as usize,
+                note,
+                exif.little_endian(),
+                make.as_deref().unwrap_or("Unknown"),
+            ));
+            continue;
+        }
+        tags.push(Tag {
+            group: group.to_string(),
+            name: field.tag.to_string(),
+            value: field.display_value().with_unit(exif).to_string(),
+        });
+    }
+    tags
+}
+
+/// The entries of the maker note at `offset` of the EXIF data `tiff`. Maker notes are not
+/// documented by the manufacturers, so the known layouts are read as an IFD and the entries are
+/// listed by their number.
+pub fn maker_notes(
+    tiff: &[u8],
+    offset: usize,
+    note: &[u8],
+    little_endian: bool,
+    make: &str,
+) -> Vec<Tag> {
+    let byte_order = |data: &[u8], at: usize| match data.get(at..at + 2) {
+        Some(b"II") => Some(true),
+        Some(b"MM") => Some(false),
+        _ => None,
+    };
+    let note_data = tiff.get(offset..).unwrap_or_default();
+    // the data offsets are relative to, where the IFD starts in it, and its byte order
+    let layout = if note.starts_with(b"Nikon\0\x02") {
+        let data = tiff.get(offset + 10..).unwrap_or_default();
+        byte_order(data, 0).and_then(|le| Some((data, read_u32(data, 4, le)? as usize, le)))
+    } else if note.starts_with(b"Apple iOS\0") {
+        byte_order(note, 12).map(|le| (note_data, 14, le))
+    } else if note.starts_with(b"FUJIFILM") {
+        read_u32(note, 8, true).map(|ifd| (note_data, ifd as usize, true))
+    } else if note.starts_with(b"OLYMPUS\0") || note.starts_with(b"OM SYSTEM\0") {
+        let header = if note.starts_with(b"OLYMPUS") { 8 } else { 12 };
+        byte_order(note, header).map(|le| (note_data, header + 4, le))
+    } else if note.starts_with(b"Panasonic\0") || note.starts_with(b"SONY ") {
+        Some((tiff, offset + 12, little_endian))
+    } else {
+        // Canon and others start with the IFD, with offsets like the rest of the EXIF data
+        Some((tiff, offset, little_endian))
+    };
+
+    let group = format!("Maker notes ({make})");
+    let entries = layout
+        .map(|(data, ifd, le)| ifd_entries(data, ifd, le))
+        .unwrap_or_default();
+    if entries.is_empty() {
+        return vec![Tag {
+            group,
+            name: "MakerNote".into(),
+            value: format!("{} bytes", note.len()),
+        }];
+    }
+    entries
+        .into_iter()
+        .map(|(number, value)| Tag {
+            group: group.clone(),
+            name: format!("0x{number:04x}"),
+            value,
+        })
+        .collect()
+}
+
+fn read_u16(data: &[u8], at: usize, little_endian: bool) -> Option<u16> {
+    let bytes = data.get(at..at + 2)?.try_into().ok()?;
+    Some(match little_endian {
+        true => u16::from_le_bytes(bytes),
+        false => u16::from_be_bytes(bytes),
+    })
+}
+
+fn read_u32(data: &[u8], at: usize, little_endian: bool) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?.try_into().ok()?;
+    Some(match little_endian {
+        true => u32::from_le_bytes(bytes),
+        false => u32::from_be_bytes(bytes),
+    })
+}
+
+/// The tag numbers and values of the IFD at `ifd` in `data`. Entries pointing outside of the
+/// data are left out.
+fn ifd_entries(data: &[u8], ifd: usize, little_endian: bool) -> Vec<(u16, String)> {
+    let Some(count) = read_u16(data, ifd, little_endian) else {
+        return vec![];
+    };
+    // more entries than any maker note has, this is not an IFD
+    if count == 0 || count > 500 {
+        return vec![];
+    }
+    let mut entries = vec![];
+    for i in 0..count as usize {
+        let entry = ifd + 2 + i * 12;
+        let (Some(number), Some(kind), Some(len)) = (
+            read_u16(data, entry, little_endian),
+            read_u16(data, entry + 2, little_endian),
+            read_u32(data, entry + 4, little_endian),
+        ) else {
+            break;
+        };
+        let size = match kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => continue,
+        };
+        let Some(total) = (len as usize).checked_mul(size) else {
+            continue;
+        };
+        let start = match total {
+            0..=4 => entry + 8,
+            _ => match read_u32(data, entry + 8, little_endian) {
+                Some(offset) => offset as usize,
+                None => continue,
+            },
+        };
+        let Some(bytes) = data.get(start..start.saturating_add(total)) else {
+            continue;
+        };
+        entries.push((
+            number,
+            format_value(kind, bytes, len as usize, little_endian),
+        ));
+    }
+    entries
+}
+
+fn format_value(kind: u16, bytes: &[u8], len: usize, little_endian: bool) -> String {
+    const SHOWN: usize = 16;
+    let join = |values: Vec<String>| {
+        let mut text = values.join(", ");
+        if len > SHOWN {
+            text.push_str(&format!(", … ({len} values)"));
+        }
+        text
+    };
+    let u16s = || (0..len.min(SHOWN)).filter_map(move |i| read_u16(bytes, i * 2, little_endian));
+    let u32s = || (0..len.min(SHOWN)).filter_map(move |i| read_u32(bytes, i * 4, little_endian));
+    match kind {
+        2 => String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string(),
+        3 => join(u16s().map(|v| v.to_string()).collect()),
+        8 => join(u16s().map(|v| (v as i16).to_string()).collect()),
+        4 => join(u32s().map(|v| v.to_string()).collect()),
+        9 => join(u32s().map(|v| (v as i32).to_string()).collect()),
+        11 => join(u32s().map(|v| f32::from_bits(v).to_string()).collect()),
+        12 => join(
+            (0..len.min(SHOWN))
+                .filter_map(|i| {
+                    let bytes = bytes.get(i * 8..i * 8 + 8)?.try_into().ok()?;
+                    Some(match little_endian {
+                        true => f64::from_le_bytes(bytes),
+                        false => f64::from_be_bytes(bytes),
+                    })
+                })
+                .map(|v| v.to_string())
+                .collect(),
+        ),
+        5 | 10 => join(
+            (0..len.min(SHOWN))
+                .filter_map(|i| {
+                    let n = read_u32(bytes, i * 8, little_endian)?;
+                    let d = read_u32(bytes, i * 8 + 4, little_endian)?;
+                    Some(match kind {
+                        5 => format!("{n}/{d}"),
+                        _ => format!("{}/{}", n as i32, d as i32),
+                    })
+                })
+                .collect(),
+        ),
+        _ if len <= SHOWN => bytes
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => format!("{len} bytes"),
+    }
+}
+
+/// The XMP packet stored in an image file
+pub fn embedded_xmp(data: &[u8]) -> Option<String> {
+    let start = find(data, b"<x:xmpmeta")?;
+    let end = start + find(&data[start..], b"</x:xmpmeta>")? + "</x:xmpmeta>".len();
+    Some(String::from_utf8_lossy(&data[start..end]).to_string())
+}
+
+/// The properties of an XMP packet, written as attributes or elements. The items of lists and
+/// the fields of structures are joined into one value.
+pub fn xmp_tags(xmp: &str, group: &str) -> Vec<Tag> {
+    let is_property = |name: &str| {
+        name.contains(':')
+            && !["rdf:", "x:", "xml:", "xmlns"]
+                .iter()
+                .any(|prefix| name.starts_with(prefix))
+    };
+    let mut tags = vec![];
+    // the elements the text is in
+    let mut open: Vec<&str> = vec![];
+    let mut rest = xmp;
+    while let Some(start) = rest.find('<') {
+        let text = rest[..start].trim();
+        if !text.is_empty() {
+            if let Some(name) = open.iter().rev().find(|n| is_property(n)) {
+                push(&mut tags, group, name, unescape(text));
+            }
+        }
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let element = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        if element.starts_with('?') || element.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = element.strip_prefix('/') {
+            if let Some(i) = open.iter().rposition(|n| *n == name.trim()) {
+                open.truncate(i);
+            }
+            continue;
+        }
+        let empty = element.ends_with('/');
+        let element = element.trim_end_matches('/');
+        let name = element.split_whitespace().next().unwrap_or_default();
+        for (key, value) in attributes(&element[name.len()..]) {
+            if is_property(key) {
+                push(&mut tags, group, key, unescape(value));
+            }
+        }
+        if !empty {
+            open.push(name);
+        }
+    }
+    tags
+}
+
+/// The `name="value"` pairs of an element
+fn attributes(mut s: &str) -> Vec<(&str, &str)> {
+    let mut attributes = vec![];
+    while let Some(equals) = s.find('=') {
+        let name = s[..equals].trim();
+        let value = s[equals + 1..].trim_start();
+        let Some(quote) = value.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            break;
+        };
+        let Some(end) = value[1..].find(quote) else {
+            break;
+        };
+        attributes.push((name, &value[1..end + 1]));
+        s = &value[end + 2..];
+    }
+    attributes
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The name of an IPTC dataset of the application record
+fn iptc_name(dataset: u8) -> Option<&'static str> {
+    Some(match dataset {
+        5 => "Object Name",
+        7 => "Edit Status",
+        10 => "Urgency",
+        15 => "Category",
+        20 => "Supplemental Category",
+        25 => "Keywords",
+        40 => "Special Instructions",
+        55 => "Date Created",
+        60 => "Time Created",
+        65 => "Originating Program",
+        80 => "By-line",
+        85 => "By-line Title",
+        90 => "City",
+        92 => "Sub-location",
+        95 => "Province/State",
+        100 => "Country Code",
+        101 => "Country",
+        103 => "Original Transmission Reference",
+        105 => "Headline",
+        110 => "Credit",
+        115 => "Source",
+        116 => "Copyright Notice",
+        118 => "Contact",
+        120 => "Caption/Abstract",
+        122 => "Writer/Editor",
+        _ => return None,
+    })
+}
+
+/// The IPTC datasets in the Photoshop resources of a JPEG file
+pub fn iptc_tags(data: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+    let Some(start) = find(data, b"Photoshop 3.0\0") else {
+        return tags;
+    };
+    let mut pos = start + 14;
+    while data.get(pos..pos + 4) == Some(&b"8BIM"[..]) {
+        let (Some(id), Some(name_len)) = (read_u16(data, pos + 4, false), data.get(pos + 6)) else {
+            break;
+        };
+        // the name is a pascal string, padded to an even length
+        let size_pos = pos + 6 + ((*name_len as usize + 2) & !1);
+        let Some(size) = read_u32(data, size_pos, false).map(|s| s as usize) else {
+            break;
+        };
+        let Some(content) = data.get(size_pos + 4..size_pos + 4 + size) else {
+            break;
+        };
+        if id == 0x0404 {
+            tags.extend(iim_tags(content));
+        }
+        pos = size_pos + 4 + size + (size & 1);
+    }
+    tags
+}
+
+/// The datasets of IPTC-IIM data, which are all in a row
+fn iim_tags(data: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+    let mut pos = 0;
+    while data.get(pos) == Some(&0x1c) {
+        let (Some(record), Some(dataset), Some(size)) = (
+            data.get(pos + 1),
+            data.get(pos + 2),
+            read_u16(data, pos + 3, false),
+        ) else {
+            break;
+        };
+        // extended datasets are only used for binary data, which is not shown
+        if size & 0x8000 != 0 {
+            break;
+        }
+        let Some(value) = data.get(pos + 5..pos + 5 + size as usize) else {
+            break;
+        };
+        if *record == 2 && *dataset != 0 {
+            let name = iptc_name(*dataset)
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| format!("2:{dataset}"));
+            push(
+                &mut tags,
+                "IPTC",
+                &name,
+                String::from_utf8_lossy(value).trim().to_string(),
+            );
+        }
+        pos += 5 + size as usize;
+    }
+    tags
+}
+
+/// The position an image was taken at, in degrees north and east
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gps {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level
+    pub altitude: Option<f64>,
+}
+
+impl Gps {
+    pub fn from_exif(exif: &exif::Exif) -> Option<Self> {
+        let reference = |tag: ExifTag| {
+            exif.get_field(tag, In::PRIMARY)
+                .map(|f| f.display_value().to_string())
+        };
+        let degrees = |tag: ExifTag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(dms) => {
+                let degrees = dms
+                    .iter()
+                    .zip([1., 60., 3600.])
+                    .map(|(v, unit)| v.to_f64() / unit)
+                    .sum::<f64>();
+                degrees.is_finite().then_some(degrees)
+            }
+            _ => None,
+        };
+        let mut latitude = degrees(ExifTag::GPSLatitude)?;
+        let mut longitude = degrees(ExifTag::GPSLongitude)?;
+        if reference(ExifTag::GPSLatitudeRef).map_or(false, |r| r.contains('S')) {
+            latitude = -latitude;
+        }
+        if reference(ExifTag::GPSLongitudeRef).map_or(false, |r| r.contains('W')) {
+            longitude = -longitude;
+        }
+        let below_sea_level = exif
+            .get_field(ExifTag::GPSAltitudeRef, In::PRIMARY)
+            .and_then(|f| f.value.get_uint(0))
+            == Some(1);
+        let altitude = exif
+            .get_field(ExifTag::GPSAltitude, In::PRIMARY)
+            .and_then(|f| match &f.value {
+                Value::Rational(a) => a.first().map(|a| a.to_f64()),
+                _ => None,
+            })
+            .filter(|a| a.is_finite())
+            .map(|a| if below_sea_level { -a } else { a });
+        Some(Self {
+            latitude,
+            longitude,
+            altitude,
+        })
+    }
+
+    /// The position on OpenStreetMap
+    pub fn map_url(&self) -> String {
+        format!(
+            "https://www.openstreetmap.org/?mlat={lat:.6}&mlon={lon:.6}#map={MAP_ZOOM}/{lat:.6}/{lon:.6}",
+            lat = self.latitude,
+            lon = self.longitude
+        )
+    }
+
+    /// The map tile the position is on at `zoom`, and where in the tile it is from 0-1
+    pub fn tile(&self, zoom: u32) -> (u32, u32, [f32; 2]) {
+        let n = 2_f64.powi(zoom as i32);
+        let x = (self.longitude + 180.) / 360. * n;
+        let latitude = self.latitude.clamp(-85.05, 85.05).to_radians();
+        let y = (1. - latitude.tan().asinh() / std::f64::consts::PI) / 2. * n;
+        let (tx, ty) = (x.floor().clamp(0., n - 1.), y.floor().clamp(0., n - 1.));
+        (tx as u32, ty as u32, [(x - tx) as f32, (y - ty) as f32])
+    }
+}
+
+impl std::fmt::Display for Gps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.6}, {:.6}", self.latitude, self.longitude)
+    }
+}
+
+/// The map around a position, downloaded from OpenStreetMap once it is asked for, as it reveals
+/// where the image was taken
+#[derive(Debug)]
+pub struct MapPreview {
+    pub gps: Gps,
+    pub tile: Option<RgbaImage>,
+    pub error: Option<String>,
+    receiver: Receiver<Result<RgbaImage, String>>,
+}
+
+impl MapPreview {
+    pub fn fetch(gps: Gps) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            _ = sender.send(fetch_tile(&gps).map_err(|e| e.to_string()));
+        });
+        Self {
+            gps,
+            tile: None,
+            error: None,
+            receiver,
+        }
+    }
+
+    /// If the tile arrived since the last call
+    pub fn poll(&mut self) -> bool {
+        match self.receiver.try_recv() {
+            Ok(Ok(tile)) => {
+                self.tile = Some(tile);
+                true
+            }
+            Ok(Err(e)) => {
+                self.error = Some(e);
+                false
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+fn fetch_tile(gps: &Gps) -> Result<RgbaImage> {
+    let (x, y, _) = gps.tile(MAP_ZOOM);
+    let url = format!("https://tile.openstreetmap.org/{MAP_ZOOM}/{x}/{y}.png");
+    // the tile servers ask for programs to name themselves
+    let response = ureq::get(&url)
+        .set(
+            "User-Agent",
+            concat!("oculante/", env!("CARGO_PKG_VERSION")),
+        )
+        .call()?;
+    let mut data = vec![];
+    response.into_reader().read_to_end(&mut data)?;
+    Ok(image::load_from_memory(&data)?.to_rgba8())
+}
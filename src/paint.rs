@@ -1,27 +1,180 @@
+use std::fmt;
+
 use image::{Pixel, Rgba, RgbaImage};
-use notan::egui::{Color32, Pos2};
+use notan::egui::{lerp, Pos2};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::annotation::AnnotationKind;
+use crate::layers::BlendMode;
+use crate::redaction::RedactionStyle;
+
+/// What a click or drag does in paint mode
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PaintTool {
+    #[default]
+    Brush,
+    Fill,
+    Shape(AnnotationKind),
+    /// Drag a rectangle which hides what is below it
+    Redact(RedactionStyle),
+}
+
+/// What a brush stroke does to the pixels under it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BrushMode {
+    #[default]
+    Paint,
+    /// Soften the pixels under the brush
+    Blur,
+    /// Drag the pixels under the brush along the stroke
+    Smudge,
+}
+
+impl BrushMode {
+    pub const ALL: [BrushMode; 3] = [BrushMode::Paint, BrushMode::Blur, BrushMode::Smudge];
+}
+
+impl fmt::Display for BrushMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Which pixels around a point are filled by the bucket, or selected by the magic wand
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FloodFill {
+    /// Largest difference of any channel to the clicked pixel that is still filled
+    pub tolerance: u8,
+    /// Only fill pixels connected to the clicked pixel, otherwise all similar pixels
+    pub contiguous: bool,
+}
+
+impl FloodFill {
+    /// The pixels similar to the one at `seed` (0-1), row by row
+    pub fn mask(&self, img: &RgbaImage, seed: (f32, f32)) -> Vec<bool> {
+        let (w, h) = img.dimensions();
+        let mut mask = vec![false; (w * h) as usize];
+        if w == 0 || h == 0 {
+            return mask;
+        }
+        let seed = (
+            ((seed.0 * w as f32) as u32).min(w - 1),
+            ((seed.1 * h as f32) as u32).min(h - 1),
+        );
+        let target = *img.get_pixel(seed.0, seed.1);
+        let matches = |p: &Rgba<u8>| {
+            p.0.iter()
+                .zip(target.0)
+                .all(|(a, b)| a.abs_diff(b) <= self.tolerance)
+        };
+
+        if self.contiguous {
+            let mut queue = vec![seed];
+            mask[(seed.1 * w + seed.0) as usize] = true;
+            while let Some((x, y)) = queue.pop() {
+                let neighbours = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                for (nx, ny) in neighbours {
+                    if nx >= w || ny >= h {
+                        continue;
+                    }
+                    let i = (ny * w + nx) as usize;
+                    if !mask[i] && matches(img.get_pixel(nx, ny)) {
+                        mask[i] = true;
+                        queue.push((nx, ny));
+                    }
+                }
+            }
+        } else {
+            for (m, p) in mask.iter_mut().zip(img.pixels()) {
+                *m = matches(p);
+            }
+        }
+        mask
+    }
+}
+
+impl Default for FloodFill {
+    fn default() -> Self {
+        Self {
+            tolerance: 32,
+            contiguous: true,
+        }
+    }
+}
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct PaintStroke {
     pub points: Vec<(f32, f32)>,
     pub fade: bool,
     pub color: [f32; 4],
     /// brush width from 0-1. 1 is equal to 1/10th of the smallest image dimension.
     pub width: f32,
-    pub brush_index: usize,
+    /// The name of the brush. Unknown brushes, for example removed ones, paint with the first.
+    #[serde(default, alias = "brush_index", deserialize_with = "deserialize_brush")]
+    pub brush: String,
     /// For ui preview: if highlit, paint brush stroke differently
     pub highlight: bool,
     pub committed: bool,
     pub flip_random: bool,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    /// Alpha of the whole stroke from 0-1, overlapping stamps don't exceed it
+    #[serde(default = "default_one")]
+    pub opacity: f32,
+    /// Alpha of each stamp from 0-1, which builds up where stamps overlap
+    #[serde(default = "default_one")]
+    pub flow: f32,
+    /// Distance between stamps, relative to the brush width
+    #[serde(default = "default_spacing")]
+    pub spacing: f32,
+    /// Set if this is a bucket fill at the first point
+    #[serde(default)]
+    pub flood_fill: Option<FloodFill>,
+    #[serde(default)]
+    pub mode: BrushMode,
+}
+
+/// A brush tip, named after its file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brush {
+    pub name: String,
+    pub image: RgbaImage,
+}
+
+/// Strokes used to refer to brushes by index, when there were only the bundled ones
+fn deserialize_brush<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BrushRef {
+        Index(usize),
+        Name(String),
+    }
+    Ok(match BrushRef::deserialize(deserializer)? {
+        BrushRef::Index(i) => format!("brush{}", i + 1),
+        BrushRef::Name(name) => name,
+    })
+}
+
+fn default_one() -> f32 {
+    1.
+}
+
+fn default_spacing() -> f32 {
+    0.25
 }
 
 impl PaintStroke {
     pub fn without_points(&self) -> Self {
         Self {
             points: vec![],
+            flood_fill: None,
             ..self.clone()
         }
     }
@@ -30,6 +183,10 @@ impl PaintStroke {
         Self {
             color: [1., 1., 1., 1.],
             width: 0.05,
+            brush: "brush1".into(),
+            opacity: 1.,
+            flow: 1.,
+            spacing: default_spacing(),
             ..Default::default()
         }
     }
@@ -39,16 +196,27 @@ impl PaintStroke {
     }
 
     // render brush stroke
-    pub fn render(&self, img: &mut RgbaImage, brushes: &[RgbaImage]) {
+    pub fn render(&self, img: &mut RgbaImage, brushes: &[Brush]) {
+        if let Some(fill) = self.flood_fill {
+            self.render_fill(img, fill);
+            return;
+        }
+
         // Calculate the brush: use a fraction of the smallest image size
         let max_brush_size = img.width().min(img.height());
 
-        let mut brush = image::imageops::resize(
-            &brushes[self.brush_index],
-            (self.width * max_brush_size as f32) as u32,
-            (self.width * max_brush_size as f32) as u32,
-            image::imageops::Triangle,
-        );
+        // the brush may have been removed from the brush directory
+        let Some(source_brush) = brushes
+            .iter()
+            .find(|b| b.name == self.brush)
+            .or(brushes.first())
+            .map(|b| &b.image)
+        else {
+            return;
+        };
+        let size = ((self.width * max_brush_size as f32) as u32).max(1);
+        let mut brush =
+            image::imageops::resize(source_brush, size, size, image::imageops::Triangle);
 
         // transform points from UV into image space
         let abs_points = self
@@ -57,20 +225,40 @@ impl PaintStroke {
             .map(|p| Pos2::new(img.width() as f32 * p.0, img.height() as f32 * p.1))
             .collect::<Vec<_>>();
 
-        let points = notan::egui::Shape::dotted_line(
-            &abs_points,
-            Color32::DARK_RED,
-            (brush.width() as f32 / 4.0).max(1.5), // .min(60.)
-            0.,
-        );
+        let stamps = stamp_positions(&abs_points, (size as f32 * self.spacing).max(1.5));
+        if stamps.is_empty() {
+            return;
+        }
+
+        if self.mode != BrushMode::Paint {
+            self.render_filter(img, &stamps, &brush);
+            return;
+        }
 
-        for (i, p) in points.iter().enumerate() {
-            let pos_on_line = p.visual_bounding_rect().center();
+        // Stamps build up on their own layer with the flow, which is then composited with the opacity
+        let margin = size as f32;
+        let (min, max) = abs_points.iter().fold(
+            (Pos2::new(f32::MAX, f32::MAX), Pos2::new(f32::MIN, f32::MIN)),
+            |(min, max), p| (min.min(*p), max.max(*p)),
+        );
+        let origin = (
+            (min.x - margin).clamp(0., img.width() as f32) as u32,
+            (min.y - margin).clamp(0., img.height() as f32) as u32,
+        );
+        let end = (
+            (max.x + margin).clamp(0., img.width() as f32) as u32,
+            (max.y + margin).clamp(0., img.height() as f32) as u32,
+        );
+        if end.0 <= origin.0 || end.1 <= origin.1 {
+            return;
+        }
+        let mut layer = RgbaImage::new(end.0 - origin.0, end.1 - origin.1);
+        let layer_offset = notan::egui::vec2(origin.0 as f32, origin.1 as f32);
 
+        for (i, pos) in stamps.iter().enumerate() {
             if self.flip_random {
                 // seed by brush position so randomness only changes per brush instance
-                let mut rng =
-                    ChaCha8Rng::seed_from_u64(pos_on_line.x as u64 + pos_on_line.y as u64);
+                let mut rng = ChaCha8Rng::seed_from_u64(pos.x as u64 + pos.y as u64);
 
                 let flip_x: bool = rng.gen();
                 let flip_y: bool = rng.gen();
@@ -84,9 +272,10 @@ impl PaintStroke {
             }
 
             let mut stroke_color = self.color;
+            stroke_color[3] *= self.flow;
 
             if self.fade {
-                let fraction = 1.0 - i as f32 / points.len() as f32;
+                let fraction = 1.0 - i as f32 / stamps.len() as f32;
                 stroke_color[3] *= fraction;
             }
 
@@ -96,12 +285,124 @@ impl PaintStroke {
                 stroke_color[2] *= 2.5;
                 stroke_color[3] *= 2.5;
             }
-            paint_at(img, &brush, &pos_on_line, stroke_color);
+            paint_at(
+                &mut layer,
+                &brush,
+                &(*pos - layer_offset),
+                stroke_color,
+                BlendMode::Normal,
+            );
+        }
+
+        for (x, y, l) in layer.enumerate_pixels() {
+            if l[3] == 0 {
+                continue;
+            }
+            let p = img.get_pixel_mut(origin.0 + x, origin.1 + y);
+            let alpha = (l[3] as f32 * self.opacity.clamp(0., 1.)) as u8;
+            blend_pixel(p, Rgba([l[0], l[1], l[2], alpha]), self.blend_mode);
+        }
+    }
+
+    /// Blur or smudge the pixels under each stamp. The brush alpha sets the strength of the effect.
+    fn render_filter(&self, img: &mut RgbaImage, stamps: &[Pos2], brush: &RgbaImage) {
+        if img.width() == 0 || img.height() == 0 {
+            return;
+        }
+        let size = brush.width();
+        // the pixels picked up by the smudge brush, in brush coordinates
+        let mut carried: Option<RgbaImage> = None;
+
+        for (i, pos) in stamps.iter().enumerate() {
+            let x0 = pos.x as i64 - size as i64 / 2;
+            let y0 = pos.y as i64 - size as i64 / 2;
+
+            let mut strength = self.flow * self.opacity;
+            if self.fade {
+                strength *= 1.0 - i as f32 / stamps.len() as f32;
+            }
+
+            // the pixels under the brush, repeating the image edge
+            let under = RgbaImage::from_fn(size, size, |bx, by| {
+                let x = (x0 + bx as i64).clamp(0, img.width() as i64 - 1);
+                let y = (y0 + by as i64).clamp(0, img.height() as i64 - 1);
+                *img.get_pixel(x as u32, y as u32)
+            });
+
+            let source = match self.mode {
+                BrushMode::Blur => image::imageops::blur(&under, (size as f32 / 8.).max(0.5)),
+                // the first stamp only picks up pixels
+                _ => carried.take().unwrap_or_else(|| under.clone()),
+            };
+            let mut picked_up = under;
+
+            for (bx, by, b_pixel) in brush.enumerate_pixels() {
+                let (x, y) = (x0 + bx as i64, y0 + by as i64);
+                if x < 0 || y < 0 || x >= img.width() as i64 || y >= img.height() as i64 {
+                    continue;
+                }
+                let t = (strength * b_pixel[3] as f32 / 255.).clamp(0., 1.);
+                let p = img.get_pixel_mut(x as u32, y as u32);
+                let s = source.get_pixel(bx, by);
+                for c in 0..4 {
+                    p[c] = lerp(p[c] as f32..=s[c] as f32, t).round() as u8;
+                }
+                picked_up.put_pixel(bx, by, *p);
+            }
+            if self.mode == BrushMode::Smudge {
+                carried = Some(picked_up);
+            }
+        }
+    }
+
+    /// Bucket fill around the first point with the stroke color
+    fn render_fill(&self, img: &mut RgbaImage, fill: FloodFill) {
+        let Some(seed) = self.points.first() else {
+            return;
+        };
+        // find all pixels to fill before changing any of them
+        let mask = fill.mask(img, *seed);
+
+        let color = Rgba([
+            (self.color[0] * 255.) as u8,
+            (self.color[1] * 255.) as u8,
+            (self.color[2] * 255.) as u8,
+            (self.color[3] * self.opacity.clamp(0., 1.) * 255.) as u8,
+        ]);
+        for (m, p) in mask.into_iter().zip(img.pixels_mut()) {
+            if m {
+                blend_pixel(p, color, self.blend_mode);
+            }
+        }
+    }
+}
+
+/// Positions every `spacing` pixels along the path
+pub fn stamp_positions(points: &[Pos2], spacing: f32) -> Vec<Pos2> {
+    let Some(first) = points.first() else {
+        return vec![];
+    };
+    let mut stamps = vec![*first];
+    // distance from the start of a segment to its first stamp
+    let mut next = spacing;
+    for segment in points.windows(2) {
+        let len = segment[0].distance(segment[1]);
+        while next <= len {
+            stamps.push(segment[0].lerp(segment[1], next / len));
+            next += spacing;
         }
+        next -= len;
     }
+    stamps
 }
 
-pub fn paint_at(img: &mut RgbaImage, brush: &RgbaImage, pos: &Pos2, color: [f32; 4]) {
+pub fn paint_at(
+    img: &mut RgbaImage,
+    brush: &RgbaImage,
+    pos: &Pos2,
+    color: [f32; 4],
+    blend_mode: BlendMode,
+) {
     // To test
     // img.put_pixel(pos.x as u32, pos.y as u32, color_to_pixel(color));
     // return;
@@ -121,7 +422,23 @@ pub fn paint_at(img: &mut RgbaImage, brush: &RgbaImage, pos: &Pos2, color: [f32;
                 (color[3] * b_pixel[3] as f32) as u8,
             ]);
             // colored_pixel.blend(&color_to_pixel(color));
-            p.blend(&colored_pixel);
+            blend_pixel(p, colored_pixel, blend_mode);
+        }
+    }
+}
+
+/// Put `color` on top of `p`, combining them with `blend_mode`
+fn blend_pixel(p: &mut Rgba<u8>, color: Rgba<u8>, blend_mode: BlendMode) {
+    if blend_mode == BlendMode::Normal {
+        p.blend(&color);
+    } else {
+        let alpha = color[3] as f32 / 255.;
+        for c in 0..3 {
+            let base = p[c] as f32 / 255.;
+            let blended = blend_mode.blend(base, color[c] as f32 / 255.);
+            p[c] = ((base + (blended - base) * alpha) * 255.) as u8;
         }
+        let base_alpha = p[3] as f32 / 255.;
+        p[3] = ((base_alpha + alpha * (1. - base_alpha)) * 255.) as u8;
     }
 }
@@ -0,0 +1,184 @@
+//! Image filters loaded from WebAssembly modules in the plugin directory.
+//!
+//! A plugin is a `.wasm` file with a `.json` manifest of the same name next to it, which
+//! names the filter and its parameters. The module must export its `memory` and
+//!
+//! - `alloc(len: i32) -> i32`, returning a pointer to `len` free bytes
+//! - `process(pixels: i32, width: i32, height: i32, params: i32, params_len: i32) -> i32`,
+//!   which edits the RGBA pixels in place and returns 0 on success. The parameters are
+//!   a JSON object of parameter names to values.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Result};
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use wasmi::{Config, Engine, Linker, Module, Store};
+
+/// How many instructions a plugin may run for each pixel, plus a fixed amount for setting up
+const FUEL_PER_PIXEL: u64 = 1000;
+const FUEL_BASE: u64 = 100_000_000;
+
+/// An integer parameter of a plugin or shader, shown as a slider
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Serialize, Deserialize)]
+pub struct PluginParam {
+    pub name: String,
+    pub min: i32,
+    pub max: i32,
+    /// The default in the manifest, the current value in an operator
+    pub value: i32,
+}
+
+/// The description of a plugin, read from its `.json` file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginManifest {
+    /// The file name of the module, without extension. Set when listing plugins.
+    #[serde(skip)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub params: Vec<PluginParam>,
+}
+
+pub fn dir() -> Result<PathBuf> {
+    Ok(dirs::config_local_dir()
+        .ok_or(anyhow!("Can't get config_local dir"))?
+        .join("oculante")
+        .join("plugins"))
+}
+
+/// The file of plugin `id` with `extension`. Ids are file names, so they can't lead out of `dir`.
+pub fn file(id: &str, extension: &str) -> Result<PathBuf> {
+    if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+        bail!("Invalid plugin name {id}");
+    }
+    Ok(dir()?.join(id).with_extension(extension))
+}
+
+/// All plugins with a readable manifest, sorted by name
+pub fn list() -> Vec<PluginManifest> {
+    let mut plugins = dir()
+        .and_then(|d| Ok(std::fs::read_dir(d)?))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.path())
+                .filter(|p| p.extension() == Some(std::ffi::OsStr::new("wasm")))
+                .filter_map(|p| {
+                    let id = p.file_stem()?.to_string_lossy().to_string();
+                    manifest(&id).ok()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    plugins.sort_by(|a, b| a.name.cmp(&b.name));
+    plugins
+}
+
+pub fn manifest(id: &str) -> Result<PluginManifest> {
+    let f = std::fs::File::open(file(id, "json")?)?;
+    let mut manifest: PluginManifest = serde_json::from_reader(f)?;
+    manifest.id = id.to_string();
+    Ok(manifest)
+}
+
+/// Compile a plugin module. Running it uses fuel, so a plugin stuck in a loop is stopped.
+pub fn compile(wasm: &[u8]) -> Result<Module> {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    let engine = ENGINE.get_or_init(|| {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    });
+    Module::new(engine, wasm).map_err(|e| anyhow!(e))
+}
+
+/// Compiled modules by id, with the modification time of their file
+type ModuleCache = HashMap<String, (SystemTime, Arc<Module>)>;
+
+/// The compiled module of plugin `id`, compiled again if its file changed
+fn module(id: &str) -> Result<Arc<Module>> {
+    static MODULES: OnceLock<Mutex<ModuleCache>> = OnceLock::new();
+    let path = file(id, "wasm")?;
+    let modified = std::fs::metadata(&path)?.modified()?;
+    let mut modules = MODULES
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow!("Plugin cache is poisoned"))?;
+    match modules.get(id) {
+        Some((time, module)) if *time == modified => Ok(module.clone()),
+        _ => {
+            let module = Arc::new(compile(&std::fs::read(&path)?)?);
+            modules.insert(id.to_string(), (modified, module.clone()));
+            Ok(module)
+        }
+    }
+}
+
+/// Run the plugin `id` on `img`
+pub fn run(id: &str, params: &[PluginParam], img: &mut RgbaImage) -> Result<()> {
+    run_module(&*module(id)?, params, img).map_err(|e| anyhow!("Plugin {id}: {e}"))
+}
+
+/// Run a compiled plugin on `img`
+pub fn run_module(module: &Module, params: &[PluginParam], img: &mut RgbaImage) -> Result<()> {
+    let params = serde_json::to_vec(
+        &params
+            .iter()
+            .map(|p| (p.name.clone(), p.value))
+            .collect::<BTreeMap<_, _>>(),
+    )?;
+    // the module addresses memory with 32 bit integers
+    let too_large = |_| anyhow!("The image is too large");
+    let len = i32::try_from(img.len()).map_err(too_large)?;
+    let width = i32::try_from(img.width()).map_err(too_large)?;
+    let height = i32::try_from(img.height()).map_err(too_large)?;
+    let params_len = i32::try_from(params.len()).map_err(too_large)?;
+
+    let mut store = Store::new(module.engine(), ());
+    store
+        .add_fuel(FUEL_PER_PIXEL * (img.len() as u64 / 4) + FUEL_BASE)
+        .map_err(|e| anyhow!(e))?;
+    let linker = <Linker<()>>::new(module.engine());
+    let instance = linker
+        .instantiate(&mut store, module)
+        .and_then(|i| i.start(&mut store))
+        .map_err(|e| anyhow!(e))?;
+
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or(anyhow!("The module does not export its memory"))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&store, "alloc")
+        .map_err(|e| anyhow!(e))?;
+    let process = instance
+        .get_typed_func::<(i32, i32, i32, i32, i32), i32>(&store, "process")
+        .map_err(|e| anyhow!(e))?;
+
+    let pixels = alloc.call(&mut store, len).map_err(|e| anyhow!(e))?;
+    memory
+        .write(&mut store, pixels as usize, img)
+        .map_err(|e| anyhow!(e))?;
+    let params_ptr = alloc.call(&mut store, params_len).map_err(|e| anyhow!(e))?;
+    memory
+        .write(&mut store, params_ptr as usize, &params)
+        .map_err(|e| anyhow!(e))?;
+
+    let status = process
+        .call(&mut store, (pixels, width, height, params_ptr, params_len))
+        .map_err(|e| anyhow!(e))?;
+    if status != 0 {
+        bail!("Failed with status {status}");
+    }
+
+    memory
+        .read(&store, pixels as usize, img)
+        .map_err(|e| anyhow!(e))?;
+    Ok(())
+}
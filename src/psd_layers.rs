@@ -0,0 +1,94 @@
+//! The layer structure of PSD files, so layers and groups can be hidden and the image composited
+//! again. Files open with the composite Photoshop stored in them.
+
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use image::RgbaImage;
+use psd::Psd;
+
+/// A layer or group of a PSD file
+#[derive(Debug, Clone, PartialEq)]
+pub struct PsdNode {
+    pub name: String,
+    /// The id of a group, or the index of a layer
+    pub id: u32,
+    /// The group this is in
+    pub parent: Option<u32>,
+    pub visible: bool,
+}
+
+/// The groups and layers of a PSD file. Layers are ordered from the bottom up.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PsdTree {
+    pub groups: Vec<PsdNode>,
+    pub layers: Vec<PsdNode>,
+}
+
+impl PsdTree {
+    pub fn read(path: &Path) -> Result<Self> {
+        let psd = open(path)?;
+        Ok(Self {
+            groups: psd
+                .groups()
+                .iter()
+                .map(|g| PsdNode {
+                    name: g.name().to_string(),
+                    id: g.id(),
+                    parent: g.parent_id(),
+                    visible: g.visible(),
+                })
+                .collect(),
+            layers: psd
+                .layers()
+                .iter()
+                .enumerate()
+                .map(|(i, l)| PsdNode {
+                    name: l.name().to_string(),
+                    id: i as u32,
+                    parent: l.parent_id(),
+                    visible: l.visible(),
+                })
+                .collect(),
+        })
+    }
+
+    /// A group is shown if it and all groups around it are visible
+    fn group_shown(&self, id: u32) -> bool {
+        let mut id = Some(id);
+        // the depth is limited, in case a broken file has a cycle
+        for _ in 0..self.groups.len() + 1 {
+            let Some(group) = id.and_then(|id| self.groups.iter().find(|g| g.id == id)) else {
+                return true;
+            };
+            if !group.visible {
+                return false;
+            }
+            id = group.parent;
+        }
+        true
+    }
+
+    /// Which layers end up in the composite, by index
+    pub fn shown_layers(&self) -> Vec<bool> {
+        self.layers
+            .iter()
+            .map(|l| l.visible && l.parent.map(|p| self.group_shown(p)).unwrap_or(true))
+            .collect()
+    }
+}
+
+fn open(path: &Path) -> Result<Psd> {
+    let contents = std::fs::read(path)?;
+    Psd::from_bytes(&contents).map_err(|e| anyhow!("{:?}", e))
+}
+
+/// Composite the layers of the PSD file at `path` where `shown` is true
+pub fn composite(path: &Path, shown: &[bool]) -> Result<RgbaImage> {
+    let psd = open(path)?;
+    let pixels = psd
+        .flatten_layers_rgba(&|(i, _)| shown.get(i).copied().unwrap_or(true))
+        .map_err(|e| anyhow!("{:?}", e))?;
+    image::ImageBuffer::from_raw(psd.width(), psd.height(), pixels)
+        .context("Can't create imagebuffer from PSD")
+}
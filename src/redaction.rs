@@ -0,0 +1,91 @@
+//! Rectangles which hide what is below them. Unlike paint strokes they replace the pixels
+//! completely, so nothing of the original can be recovered from the saved image.
+
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RedactionStyle {
+    /// Replace blocks of pixels with their average
+    Pixelate,
+    /// Paint over the area with solid black
+    Fill,
+}
+
+impl RedactionStyle {
+    pub const ALL: [Self; 2] = [Self::Pixelate, Self::Fill];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Pixelate => "Pixelate",
+            Self::Fill => "Fill",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Redaction {
+    pub style: RedactionStyle,
+    /// From 0-1, the rectangle spans the box between `start` and `end`
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+}
+
+impl Redaction {
+    pub fn new(style: RedactionStyle, at: (f32, f32)) -> Self {
+        Self {
+            style,
+            start: at,
+            end: at,
+        }
+    }
+
+    /// The covered pixels as (x, y, width, height)
+    fn pixel_rect(&self, img: &RgbaImage) -> (u32, u32, u32, u32) {
+        let (w, h) = (img.width() as f32, img.height() as f32);
+        // round outwards, so partially covered pixels are hidden as well
+        let x0 = (self.start.0.min(self.end.0) * w).floor().clamp(0., w) as u32;
+        let y0 = (self.start.1.min(self.end.1) * h).floor().clamp(0., h) as u32;
+        let x1 = (self.start.0.max(self.end.0) * w).ceil().clamp(0., w) as u32;
+        let y1 = (self.start.1.max(self.end.1) * h).ceil().clamp(0., h) as u32;
+        (x0, y0, x1 - x0, y1 - y0)
+    }
+
+    pub fn render(&self, img: &mut RgbaImage) {
+        let (x0, y0, width, height) = self.pixel_rect(img);
+        match self.style {
+            RedactionStyle::Fill => {
+                for y in y0..y0 + height {
+                    for x in x0..x0 + width {
+                        img.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+                    }
+                }
+            }
+            RedactionStyle::Pixelate => {
+                // large enough blocks that text can't be made out, relative to the image
+                let block = (img.width().max(img.height()) / 60).max(8);
+                for by in (y0..y0 + height).step_by(block as usize) {
+                    for bx in (x0..x0 + width).step_by(block as usize) {
+                        let bw = block.min(x0 + width - bx);
+                        let bh = block.min(y0 + height - by);
+                        let mut sum = [0u64; 4];
+                        for y in by..by + bh {
+                            for x in bx..bx + bw {
+                                for (s, c) in sum.iter_mut().zip(img.get_pixel(x, y).0) {
+                                    *s += c as u64;
+                                }
+                            }
+                        }
+                        let count = (bw * bh) as u64;
+                        let average = Rgba(sum.map(|s| (s / count) as u8));
+                        for y in by..by + bh {
+                            for x in bx..bx + bw {
+                                img.put_pixel(x, y, average);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Video scopes of the image on screen: a luma waveform, an RGB parade and a vectorscope. They
+//! are rendered from a downsampled copy of the image, which is enough to read them.
+
+use image::{imageops, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter};
+
+use crate::histogram::luma;
+
+/// The widest the image is downsampled to, which is also the width of the waveform
+pub const SAMPLE_WIDTH: u32 = 360;
+/// The height of the waveform and parade, one row per 8 bit value, and the size of the vectorscope
+pub const SCOPE_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Display, EnumIter)]
+pub enum Scope {
+    #[default]
+    Waveform,
+    #[strum(to_string = "RGB parade")]
+    Parade,
+    Vectorscope,
+}
+
+/// A copy of `img` at most `SAMPLE_WIDTH` wide
+pub fn downsample(img: &RgbaImage) -> RgbaImage {
+    if img.width() <= SAMPLE_WIDTH {
+        return img.clone();
+    }
+    let height = (img.height() as u64 * SAMPLE_WIDTH as u64 / img.width() as u64).max(1) as u32;
+    imageops::thumbnail(img, SAMPLE_WIDTH, height)
+}
+
+/// The blue and red difference of a pixel from -0.5 to 0.5, as BT.709 Cb and Cr
+pub fn chroma(p: [u8; 4]) -> (f32, f32) {
+    let [r, g, b] = [p[0], p[1], p[2]].map(|c| c as f32 / 255.);
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    ((b - y) / 1.8556, (r - y) / 1.5748)
+}
+
+impl Scope {
+    /// Draw the scope of `img`, which should already be downsampled
+    pub fn render(&self, img: &RgbaImage) -> RgbaImage {
+        match self {
+            Scope::Waveform => {
+                let mut traces = Traces::new(img.width(), SCOPE_SIZE);
+                for (x, _, p) in img.enumerate_pixels().filter(|(_, _, p)| p[3] > 0) {
+                    traces.add(0, x, 255 - luma(p[0], p[1], p[2]) as u32);
+                }
+                traces.image([[210, 255, 210]])
+            }
+            Scope::Parade => {
+                // the channels side by side, each as wide as the image
+                let mut traces = Traces::new(img.width() * 3, SCOPE_SIZE);
+                for (x, _, p) in img.enumerate_pixels().filter(|(_, _, p)| p[3] > 0) {
+                    for c in 0..3 {
+                        traces.add(c, x + img.width() * c as u32, 255 - p[c] as u32);
+                    }
+                }
+                traces.image([[255, 70, 70], [70, 255, 70], [90, 120, 255]])
+            }
+            Scope::Vectorscope => {
+                let mut traces = Traces::new(SCOPE_SIZE, SCOPE_SIZE);
+                let center = SCOPE_SIZE as f32 / 2.;
+                for p in img.pixels().filter(|p| p[3] > 0) {
+                    let (cb, cr) = chroma(p.0);
+                    let x = (center + cb * SCOPE_SIZE as f32).clamp(0., SCOPE_SIZE as f32 - 1.);
+                    let y = (center - cr * SCOPE_SIZE as f32).clamp(0., SCOPE_SIZE as f32 - 1.);
+                    traces.add(0, x as u32, y as u32);
+                }
+                traces.image([[210, 255, 210]])
+            }
+        }
+    }
+}
+
+/// How many pixels landed on each point of a scope, for each color they are drawn in
+struct Traces {
+    width: u32,
+    height: u32,
+    counts: Vec<Vec<u32>>,
+}
+
+impl Traces {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            counts: vec![vec![0; (width * height) as usize]; 3],
+        }
+    }
+
+    fn add(&mut self, color: usize, x: u32, y: u32) {
+        self.counts[color][(y * self.width + x) as usize] += 1;
+    }
+
+    /// The traces on black, brighter where more pixels landed. The brightness is logarithmic so
+    /// single pixels stay visible next to large flat areas.
+    fn image<const N: usize>(&self, colors: [[u8; 3]; N]) -> RgbaImage {
+        let peak = self.counts.iter().flatten().max().copied().unwrap_or(0);
+        let scale = (peak as f32).ln_1p().max(1.);
+        let mut img = RgbaImage::from_pixel(self.width, self.height, Rgba([0, 0, 0, 255]));
+        for (counts, color) in self.counts.iter().zip(colors) {
+            for (p, count) in img.pixels_mut().zip(counts).filter(|(_, c)| **c > 0) {
+                // at least a bit visible, however few pixels there are
+                let intensity = ((*count as f32).ln_1p() / scale).max(0.25);
+                for c in 0..3 {
+                    p[c] = p[c].saturating_add((color[c] as f32 * intensity) as u8);
+                }
+            }
+        }
+        img
+    }
+}
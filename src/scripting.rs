@@ -0,0 +1,246 @@
+//! Automate edits with Rhai scripts, from the edit panel or with `--script`.
+//!
+//! ```text
+//! open("photo.jpg");
+//! add_op("brightness", 20);
+//! add_op("invert");
+//! save("out.png");
+//! ```
+//!
+//! `add_op` builds up operator stacks, which are applied to the open image by `save`.
+//! `apply` bakes them into the image and starts with empty stacks again.
+
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+use anyhow::{anyhow, bail, Result};
+use image::RgbaImage;
+use log::info;
+use rhai::{Array, Dynamic, Engine, EvalAltResult};
+
+use crate::image_editing::{
+    apply_preset, EditPreset, ImageOperation, ImgOpItem, NoiseDistribution, ResizeMode, ScaleFilter,
+};
+use crate::image_loader::open_image;
+use crate::utils::is_ext_compatible;
+
+/// How many operations a script may run, and how deeply functions may call each other, so a
+/// script stuck in a loop ends with an error
+const MAX_OPERATIONS: u64 = 50_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+
+/// What a script works on, and what it leaves behind
+#[derive(Debug, Clone, Default)]
+pub struct ScriptContext {
+    pub image: Option<RgbaImage>,
+    pub path: Option<PathBuf>,
+    pub stacks: EditPreset,
+    /// Whether `apply` or `open` replaced the image
+    pub image_replaced: bool,
+}
+
+impl ScriptContext {
+    fn add(&mut self, operation: ImageOperation) {
+        if operation.is_per_pixel() {
+            self.stacks.pixel_op_stack.push(ImgOpItem::new(operation));
+        } else {
+            self.stacks.image_op_stack.push(ImgOpItem::new(operation));
+        }
+    }
+
+    /// The open image with the current stacks applied
+    fn result(&self) -> Result<RgbaImage> {
+        let mut img = self.image.clone().ok_or(anyhow!("No image is open"))?;
+        apply_preset(&mut img, &self.stacks)?;
+        Ok(img)
+    }
+}
+
+/// An operator from its script name and value
+fn operation(name: &str, value: i64) -> Result<ImageOperation> {
+    let byte = value.clamp(0, 255) as u8;
+    let int = value.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    Ok(match name {
+        "brightness" => ImageOperation::Brightness(int),
+        "contrast" => ImageOperation::Contrast(int),
+        "exposure" => ImageOperation::Exposure(int),
+        "desaturate" => ImageOperation::Desaturate(byte),
+        "posterize" => ImageOperation::Posterize(byte),
+        "blur" => ImageOperation::Blur(byte),
+        "chromatic_aberration" => ImageOperation::ChromaticAberration(byte),
+        "rotate" => ImageOperation::Rotate(value.clamp(-360, 360) as i16),
+        "noise" => ImageOperation::Noise {
+            amt: byte,
+            mono: false,
+            seed: 0,
+            distribution: NoiseDistribution::Uniform,
+        },
+        "resize_percent" => ImageOperation::Resize {
+            dimensions: (0, 0),
+            aspect: true,
+            filter: ScaleFilter::Hamming,
+            mode: ResizeMode::Percentage(value.max(1) as u32),
+        },
+        "invert" => ImageOperation::Invert,
+        "flip_horizontal" => ImageOperation::Flip(false),
+        "auto_levels" => ImageOperation::AutoLevels(None),
+        "auto_contrast" => ImageOperation::AutoContrast(None),
+        "auto_white_balance" => ImageOperation::AutoWhiteBalance(None),
+        _ => bail!("Unknown operator '{name}'"),
+    })
+}
+
+fn script_err(e: anyhow::Error) -> Box<EvalAltResult> {
+    e.to_string().into()
+}
+
+/// Run `script`, starting from `context`. Returns the context as the script left it.
+pub fn run(script: &str, context: ScriptContext) -> Result<ScriptContext> {
+    let context = Rc::new(RefCell::new(context));
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine.on_print(|s| info!("{s}"));
+
+    let c = context.clone();
+    engine.register_fn(
+        "open",
+        move |path: &str| -> Result<(), Box<EvalAltResult>> {
+            let img = open_image(Path::new(path))
+                .and_then(|r| Ok(r.recv()?.buffer))
+                .map_err(script_err)?;
+            let mut c = c.borrow_mut();
+            c.image = Some(img);
+            c.path = Some(PathBuf::from(path));
+            c.image_replaced = true;
+            Ok(())
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn(
+        "add_op",
+        move |name: &str, value: i64| -> Result<(), Box<EvalAltResult>> {
+            c.borrow_mut()
+                .add(operation(name, value).map_err(script_err)?);
+            Ok(())
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn(
+        "add_op",
+        move |name: &str| -> Result<(), Box<EvalAltResult>> {
+            c.borrow_mut().add(operation(name, 0).map_err(script_err)?);
+            Ok(())
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn("clear_ops", move || {
+        c.borrow_mut().stacks = Default::default();
+    });
+
+    let c = context.clone();
+    engine.register_fn(
+        "load_preset",
+        move |name: &str| -> Result<(), Box<EvalAltResult>> {
+            let preset = EditPreset::load(name).map_err(script_err)?;
+            let mut c = c.borrow_mut();
+            c.stacks.image_op_stack.extend(preset.image_op_stack);
+            c.stacks.pixel_op_stack.extend(preset.pixel_op_stack);
+            Ok(())
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn("apply", move || -> Result<(), Box<EvalAltResult>> {
+        let mut c = c.borrow_mut();
+        let img = c.result().map_err(script_err)?;
+        c.image = Some(img);
+        c.stacks = Default::default();
+        c.image_replaced = true;
+        Ok(())
+    });
+
+    let c = context.clone();
+    engine.register_fn(
+        "save",
+        move |path: &str| -> Result<(), Box<EvalAltResult>> {
+            let img = c.borrow().result().map_err(script_err)?;
+            img.save(path).map_err(|e| script_err(e.into()))?;
+            info!("Saved {path}");
+            Ok(())
+        },
+    );
+
+    let c = context.clone();
+    engine.register_fn("path", move || -> String {
+        c.borrow()
+            .path
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+
+    // all images in a directory, to process them in a loop
+    engine.register_fn("files", |dir: &str| -> Array {
+        let mut files = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_file() && is_ext_compatible(p))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+            .into_iter()
+            .map(|p| Dynamic::from(p.to_string_lossy().to_string()))
+            .collect()
+    });
+
+    engine.run(script).map_err(|e| anyhow!("{e}"))?;
+    drop(engine);
+
+    let context = Rc::try_unwrap(context)
+        .map_err(|_| anyhow!("Script state is still in use"))?
+        .into_inner();
+    Ok(context)
+}
+
+/// A script running in the background, so the window stays responsive while it works
+pub struct ScriptJob {
+    receiver: Receiver<Result<ScriptContext>>,
+}
+
+impl ScriptJob {
+    pub fn start(script: String, context: ScriptContext) -> Self {
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            _ = sender.send(run(&script, context));
+        });
+        Self { receiver }
+    }
+
+    /// The result once the script is done
+    pub fn poll(&self) -> Option<Result<ScriptContext>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                Some(Err(anyhow!("The script stopped unexpectedly")))
+            }
+        }
+    }
+}
+
+/// Run a script file from the command line, without opening a window
+pub fn run_file(path: &Path) -> Result<()> {
+    let script = std::fs::read_to_string(path)?;
+    run(&script, Default::default())?;
+    Ok(())
+}
@@ -1,24 +1,241 @@
+use crate::archive;
 use crate::utils::is_ext_compatible;
+use crate::xmp::Rating;
 use anyhow::{bail, Context, Result};
 use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+use strum::Display;
+use strum_macros::EnumIter;
+
+/// The order images of a folder are browsed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Display, EnumIter)]
+pub enum SortOrder {
+    #[default]
+    Name,
+    #[strum(to_string = "Date modified")]
+    Modified,
+    #[strum(to_string = "File size")]
+    Size,
+    /// The EXIF capture date. Images without one come last, by name.
+    #[strum(to_string = "Date taken")]
+    Captured,
+}
+
+/// How the images of a folder are listed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Listing {
+    pub sort: SortOrder,
+    pub descending: bool,
+    /// File name patterns like `*.jpg` or `IMG_????.*`, or extensions, separated by spaces or
+    /// commas. Empty to list every image.
+    pub filter: String,
+    /// Walk into the subfolders of the folder opened, depth-first
+    pub recursive: bool,
+    /// Skip images rated lower than this in their XMP sidecar. 0 to list every image.
+    pub min_rating: u8,
+}
+
+impl Listing {
+    /// Filter and sort `images`, which are in name order
+    pub fn apply(&self, mut images: Vec<PathBuf>) -> Vec<PathBuf> {
+        let patterns = self
+            .filter
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let p = p.to_lowercase();
+                // a bare extension
+                if p.contains(&['*', '?', '.'][..]) {
+                    p
+                } else {
+                    format!("*.{p}")
+                }
+            })
+            .map(|p| p.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if !patterns.is_empty() {
+            images.retain(|image| {
+                let name = image
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_lowercase())
+                    .unwrap_or_default()
+                    .chars()
+                    .collect::<Vec<_>>();
+                patterns.iter().any(|p| glob_match(p, &name))
+            });
+        }
+
+        if self.min_rating > 0 {
+            images.retain(|image| Rating::read(image).stars >= self.min_rating);
+        }
+
+        // sorts are stable, so images that sort equal stay in name order
+        match self.sort {
+            SortOrder::Name => (),
+            SortOrder::Modified => images.sort_by_cached_key(|p| {
+                p.metadata()
+                    .and_then(|m| m.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH)
+            }),
+            SortOrder::Size => {
+                images.sort_by_cached_key(|p| p.metadata().map(|m| m.len()).unwrap_or_default())
+            }
+            SortOrder::Captured => images.sort_by_cached_key(|p| {
+                let captured = capture_date(p);
+                (captured.is_none(), captured)
+            }),
+        }
+        if self.descending {
+            images.reverse();
+        }
+        images
+    }
+}
+
+/// Subfolders deeper than this are not walked into, in case links lead in a circle
+const MAX_DEPTH: usize = 16;
+
+/// The images of `folder`, then those of its subfolders, ordered by `listing` in every folder
+pub fn images_recursive(folder: &Path, listing: &Listing) -> Vec<PathBuf> {
+    fn walk(folder: &Path, listing: &Listing, depth: usize, images: &mut Vec<PathBuf>) {
+        images.extend(listing.apply(get_image_filenames_for_directory(folder).unwrap_or_default()));
+        if depth < MAX_DEPTH {
+            for subfolder in crate::folders::subfolders(folder) {
+                walk(&subfolder, listing, depth + 1, images);
+            }
+        }
+    }
+    let mut images = vec![];
+    walk(folder, listing, 0, &mut images);
+    images
+}
+
+/// Whether `name` matches `pattern`, with `*` for any text and `?` for any character
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Capture dates by path, with the modification time of their file
+type CaptureDates = HashMap<PathBuf, (SystemTime, Option<String>)>;
+
+/// The EXIF capture date, formatted so it sorts by time. It is read once and read again only if
+/// the file changed, as listings are sorted again on every filter keystroke.
+pub fn capture_date(path: &Path) -> Option<String> {
+    static DATES: OnceLock<Mutex<CaptureDates>> = OnceLock::new();
+    let modified = path.metadata().and_then(|m| m.modified()).ok()?;
+    let mut dates = DATES.get_or_init(Default::default).lock().ok()?;
+    match dates.get(path) {
+        Some((time, date)) if *time == modified => date.clone(),
+        _ => {
+            let date = read_capture_date(path);
+            dates.insert(path.to_path_buf(), (modified, date.clone()));
+            date
+        }
+    }
+}
+
+/// The EXIF capture date, read from the file
+fn read_capture_date(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::BufReader::new(file))
+        .ok()?;
+    let field = exif
+        .get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
+        .or_else(|| exif.get_field(exif::Tag::DateTime, exif::In::PRIMARY))?;
+    Some(field.display_value().to_string())
+}
 
 #[derive(Debug, Default)]
 pub struct Scrubber {
     pub index: usize,
     pub entries: Vec<PathBuf>,
     pub wrap: bool,
+    /// How the entries were listed
+    listing: Listing,
 }
 
 impl Scrubber {
-    pub fn new(path: &Path) -> Self {
-        let entries = get_image_filenames_for_directory(path).unwrap_or_default();
+    pub fn new(path: &Path, listing: &Listing) -> Self {
+        // the images in an archive are browsed instead of the folder it is in
+        let entries = if archive::is_archive(path) {
+            archive::images(path).map(|entries| listing.apply(entries))
+        } else if let Some((zip, _)) = archive::split(path) {
+            archive::images(zip).map(|entries| listing.apply(entries))
+        } else if listing.recursive {
+            // the folder opened, or the folder of the image opened
+            let folder = if path.is_dir() {
+                Some(path)
+            } else {
+                path.parent()
+            };
+            folder
+                .map(|f| images_recursive(f, listing))
+                .context("Can't get parent")
+        } else {
+            get_image_filenames_for_directory(path).map(|entries| listing.apply(entries))
+        }
+        .unwrap_or_default();
         let index = entries.iter().position(|p| p == path).unwrap_or_default();
         Self {
             index,
             entries,
             wrap: true,
+            listing: listing.clone(),
+        }
+    }
+
+    /// The favourite images that still exist, ordered by `listing`
+    pub fn favourites(
+        favourites: &HashSet<PathBuf>,
+        current: Option<&Path>,
+        listing: &Listing,
+    ) -> Self {
+        let mut entries = favourites
+            .iter()
+            .filter(|p| p.is_file() || archive::split(p).is_some())
+            .cloned()
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| {
+            lexical_sort::natural_lexical_cmp(&a.to_string_lossy(), &b.to_string_lossy())
+        });
+        let entries = listing.apply(entries);
+        let index = current
+            .and_then(|c| entries.iter().position(|p| p == c))
+            .unwrap_or_default();
+        Self {
+            index,
+            entries,
+            wrap: true,
+            listing: listing.clone(),
+        }
+    }
+
+    /// Move to `path`, listing its folder again if it is not one of the entries. When walking
+    /// subfolders, the folder listed stays the same while `path` is one of its images.
+    pub fn open(&mut self, path: &Path, listing: &Listing) {
+        if &self.listing == listing {
+            if let Some(index) = self.entries.iter().position(|p| p == path) {
+                self.index = index;
+                return;
+            }
         }
+        let wrap = self.wrap;
+        *self = Self::new(path, listing);
+        self.wrap = wrap;
     }
     pub fn next(&mut self) -> PathBuf {
         self.index += 1;
@@ -56,6 +273,29 @@ impl Scrubber {
     pub fn len(&mut self) -> usize {
         self.entries.len()
     }
+
+    /// The entries `next` and `prev` would move to, without moving
+    pub fn neighbours(&self) -> Vec<PathBuf> {
+        let last = self.entries.len().saturating_sub(1);
+        let next = match self.index {
+            i if i < last => Some(i + 1),
+            _ if self.wrap => Some(0),
+            _ => None,
+        };
+        let prev = match self.index {
+            0 if self.wrap => Some(last),
+            0 => None,
+            i => Some(i - 1),
+        };
+        let mut neighbours = [next, prev]
+            .into_iter()
+            .flatten()
+            .filter(|&i| i != self.index)
+            .filter_map(|i| self.entries.get(i).cloned())
+            .collect::<Vec<_>>();
+        neighbours.dedup();
+        neighbours
+    }
 }
 
 // Get sorted list of files in a folder
@@ -94,11 +334,16 @@ pub fn get_image_filenames_for_directory(folder_path: &Path) -> Result<Vec<PathB
 
 /// Find first valid image from the directory
 /// Assumes the given path is a directory and not a file
-pub fn find_first_image_in_directory(folder_path: &PathBuf) -> Result<PathBuf> {
+pub fn find_first_image_in_directory(folder_path: &PathBuf, listing: &Listing) -> Result<PathBuf> {
     if !folder_path.is_dir() {
         bail!("This is not a folder");
     };
-    get_image_filenames_for_directory(folder_path).map(|x| {
+    let images = if listing.recursive {
+        Ok(images_recursive(folder_path, listing))
+    } else {
+        get_image_filenames_for_directory(folder_path).map(|x| listing.apply(x))
+    };
+    images.map(|x| {
         x.first()
             .cloned()
             .context("Folder does not have any supported images in it")
@@ -0,0 +1,304 @@
+//! Selections limit operators to a part of the image. Operators added while something is selected
+//! get the selection as their mask, so they keep their area when the selection changes later.
+
+use image::RgbaImage;
+use notan::egui::{self, Color32, Context, Id, LayerId, Order, Pos2, Stroke, Vec2};
+
+use crate::mask::{MaskShape, OpMask};
+use crate::paint::FloodFill;
+use crate::ImageGeometry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionTool {
+    Rectangle,
+    Ellipse,
+    /// Select pixels of a similar color by clicking
+    MagicWand,
+    /// Draw the outline of the selection freehand
+    Lasso,
+}
+
+impl SelectionTool {
+    pub const ALL: [Self; 4] = [Self::Rectangle, Self::Ellipse, Self::MagicWand, Self::Lasso];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rectangle => "Rectangle",
+            Self::Ellipse => "Ellipse",
+            Self::MagicWand => "Magic wand",
+            Self::Lasso => "Lasso",
+        }
+    }
+}
+
+/// The image position under the pointer, from 0-1
+fn pointer_uv(ctx: &Context, geo: &ImageGeometry) -> Option<(f32, f32)> {
+    let pos = ctx.input(|i| i.pointer.hover_pos())?;
+    let size = Vec2::new(
+        geo.dimensions.0 as f32 * geo.scale,
+        geo.dimensions.1 as f32 * geo.scale,
+    );
+    // not clamped to the last pixel, so a selection can reach the edge of the image
+    Some((
+        ((pos.x - geo.offset.x) / size.x).clamp(0., 1.),
+        ((pos.y - geo.offset.y) / size.y).clamp(0., 1.),
+    ))
+}
+
+/// Drag out a new selection with `tool`. `anchor` keeps where the drag started between frames.
+/// A click without dragging clears the selection. Returns true if the selection changed.
+pub fn drag_ui(
+    ctx: &Context,
+    tool: SelectionTool,
+    selection: &mut Option<OpMask>,
+    anchor: &mut Option<(f32, f32)>,
+    geo: &ImageGeometry,
+) -> bool {
+    if geo.dimensions.0 == 0 || geo.dimensions.1 == 0 {
+        return false;
+    }
+    let Some(uv) = pointer_uv(ctx, geo) else {
+        return false;
+    };
+    let (pressed, down) = ctx.input(|i| (i.pointer.primary_pressed(), i.pointer.primary_down()));
+    if pressed && !ctx.is_pointer_over_area() {
+        *anchor = Some(uv);
+    }
+    let Some(start) = *anchor else {
+        return false;
+    };
+    if !down {
+        *anchor = None;
+    }
+
+    let bounds = [
+        start.0.min(uv.0),
+        start.1.min(uv.1),
+        (uv.0 - start.0).abs(),
+        (uv.1 - start.1).abs(),
+    ];
+    if bounds[2] == 0. || bounds[3] == 0. {
+        let changed = selection.is_some();
+        *selection = None;
+        return changed;
+    }
+    let shape = match tool {
+        SelectionTool::Ellipse => MaskShape::Ellipse(bounds),
+        _ => MaskShape::Rectangle(bounds),
+    };
+    let new_selection = OpMask {
+        shape,
+        invert: selection.as_ref().map(|s| s.invert).unwrap_or_default(),
+        feather: 0.,
+    };
+    if selection.as_ref() == Some(&new_selection) {
+        return false;
+    }
+    *selection = Some(new_selection);
+    true
+}
+
+/// Draw a freehand outline to select. `anchor` is where the outline started, while it is drawn.
+/// Returns true if the selection changed.
+pub fn lasso_ui(
+    ctx: &Context,
+    selection: &mut Option<OpMask>,
+    anchor: &mut Option<(f32, f32)>,
+    geo: &ImageGeometry,
+) -> bool {
+    if geo.dimensions.0 == 0 || geo.dimensions.1 == 0 {
+        return false;
+    }
+    let Some(uv) = pointer_uv(ctx, geo) else {
+        return false;
+    };
+    let (pressed, down) = ctx.input(|i| (i.pointer.primary_pressed(), i.pointer.primary_down()));
+    if pressed && !ctx.is_pointer_over_area() {
+        *anchor = Some(uv);
+        *selection = Some(OpMask {
+            shape: MaskShape::Polygon(vec![uv]),
+            invert: selection.as_ref().map(|s| s.invert).unwrap_or_default(),
+            feather: 0.,
+        });
+        return true;
+    }
+    if anchor.is_none() {
+        return false;
+    }
+    let Some(MaskShape::Polygon(points)) = selection.as_mut().map(|s| &mut s.shape) else {
+        *anchor = None;
+        return false;
+    };
+    if !down {
+        *anchor = None;
+        // a click or a line doesn't enclose anything
+        if points.len() < 3 {
+            *selection = None;
+            return true;
+        }
+        return false;
+    }
+    // skip points closer than a screen pixel to the last one
+    let min_distance = 1. / (geo.dimensions.0.max(geo.dimensions.1) as f32 * geo.scale);
+    match points.last() {
+        Some(last) if (last.0 - uv.0).hypot(last.1 - uv.1) < min_distance => false,
+        _ => {
+            points.push(uv);
+            true
+        }
+    }
+}
+
+/// Select the pixels similar to the clicked one in `img`, which is shown with `geo`.
+/// Returns true if the selection changed.
+pub fn wand_ui(
+    ctx: &Context,
+    img: &RgbaImage,
+    settings: FloodFill,
+    selection: &mut Option<OpMask>,
+    geo: &ImageGeometry,
+) -> bool {
+    if geo.dimensions.0 == 0 || geo.dimensions.1 == 0 {
+        return false;
+    }
+    let Some(uv) = pointer_uv(ctx, geo) else {
+        return false;
+    };
+    if !ctx.input(|i| i.pointer.primary_pressed()) || ctx.is_pointer_over_area() {
+        return false;
+    }
+    let selected = settings.mask(img, uv);
+    *selection = Some(OpMask {
+        shape: MaskShape::from_pixels(img.width(), img.height(), &selected),
+        invert: selection.as_ref().map(|s| s.invert).unwrap_or_default(),
+        feather: 0.,
+    });
+    true
+}
+
+/// The selected part of `img`, cropped to the bounds of the selection. Pixels outside of the
+/// selection are transparent. Returns None if nothing is selected.
+pub fn crop_to_selection(img: &RgbaImage, selection: &OpMask) -> Option<RgbaImage> {
+    let mask = selection.render(img.dimensions());
+    let (mut x0, mut y0, mut x1, mut y1) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, p) in mask.enumerate_pixels() {
+        if p[0] > 0 {
+            x0 = x0.min(x);
+            y0 = y0.min(y);
+            x1 = x1.max(x + 1);
+            y1 = y1.max(y + 1);
+        }
+    }
+    if x0 >= x1 || y0 >= y1 {
+        return None;
+    }
+    let mut cropped = image::imageops::crop_imm(img, x0, y0, x1 - x0, y1 - y0).to_image();
+    for (x, y, p) in cropped.enumerate_pixels_mut() {
+        let coverage = mask.get_pixel(x0 + x, y0 + y)[0] as u32;
+        p[3] = (p[3] as u32 * coverage / 255) as u8;
+    }
+    Some(cropped)
+}
+
+/// The outline of a selection from 0-1, if it has one
+fn outline(selection: &OpMask) -> Option<Vec<(f32, f32)>> {
+    match &selection.shape {
+        MaskShape::Rectangle(b) => Some(vec![
+            (b[0], b[1]),
+            (b[0] + b[2], b[1]),
+            (b[0] + b[2], b[1] + b[3]),
+            (b[0], b[1] + b[3]),
+        ]),
+        MaskShape::Ellipse(b) => Some(
+            (0..64)
+                .map(|i| {
+                    let a = i as f32 / 64. * std::f32::consts::TAU;
+                    (
+                        b[0] + b[2] * (1. + a.cos()) / 2.,
+                        b[1] + b[3] * (1. + a.sin()) / 2.,
+                    )
+                })
+                .collect(),
+        ),
+        MaskShape::Polygon(points) => Some(points.clone()),
+        _ => None,
+    }
+}
+
+/// Edges between selected and unselected cells of a coarse grid over a pixel selection, from 0-1
+fn pixel_edges(selection: &OpMask) -> Vec<[(f32, f32); 2]> {
+    let MaskShape::Pixels { width, height, .. } = &selection.shape else {
+        return vec![];
+    };
+    let scale = (256. / *width.max(height) as f32).min(1.);
+    let (w, h) = (
+        ((*width as f32 * scale) as u32).max(1),
+        ((*height as f32 * scale) as u32).max(1),
+    );
+    let mask = selection.render((w, h));
+    let selected = |x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && x < w as i64
+            && y < h as i64
+            && mask.get_pixel(x as u32, y as u32)[0] > 127
+    };
+    let uv = |x: i64, y: i64| (x as f32 / w as f32, y as f32 / h as f32);
+    let mut edges = vec![];
+    for y in -1..h as i64 {
+        for x in -1..w as i64 {
+            if selected(x, y) != selected(x + 1, y) {
+                edges.push([uv(x + 1, y), uv(x + 1, y + 1)]);
+            }
+            if selected(x, y) != selected(x, y + 1) {
+                edges.push([uv(x, y + 1), uv(x + 1, y + 1)]);
+            }
+        }
+    }
+    edges
+}
+
+/// Draw the outline of the selection on top of the image
+pub fn draw_outline(ctx: &Context, selection: &OpMask, geo: &ImageGeometry) {
+    let size = Vec2::new(
+        geo.dimensions.0 as f32 * geo.scale,
+        geo.dimensions.1 as f32 * geo.scale,
+    );
+    let to_screen =
+        |p: (f32, f32)| Pos2::new(geo.offset.x + p.0 * size.x, geo.offset.y + p.1 * size.y);
+    let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("selection")));
+    // dark and light, so it can be seen on any image
+    let strokes = [
+        Stroke::new(3., Color32::BLACK),
+        Stroke::new(1., Color32::WHITE),
+    ];
+
+    if let MaskShape::Pixels { runs, .. } = &selection.shape {
+        // finding the edges is slow, so they are only updated when the selection changes
+        let id = Id::new("selection edges");
+        let key = Id::new(runs);
+        let cached = ctx.data(|d| d.get_temp::<(Id, Vec<[(f32, f32); 2]>)>(id));
+        let edges = match cached {
+            Some((k, edges)) if k == key => edges,
+            _ => {
+                let edges = pixel_edges(selection);
+                ctx.data_mut(|d| d.insert_temp(id, (key, edges.clone())));
+                edges
+            }
+        };
+        for stroke in strokes {
+            for edge in &edges {
+                painter.line_segment([to_screen(edge[0]), to_screen(edge[1])], stroke);
+            }
+        }
+        return;
+    }
+
+    let Some(points) = outline(selection) else {
+        return;
+    };
+    let points = points.into_iter().map(to_screen).collect::<Vec<_>>();
+    for stroke in strokes {
+        painter.add(egui::Shape::closed_line(points.clone(), stroke));
+    }
+}
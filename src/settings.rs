@@ -1,4 +1,8 @@
-use crate::{shortcuts::*, utils::ColorChannel};
+use crate::{
+    background::{BackgroundPreset, Checker},
+    export::ExportSettings, grid::PixelGrid, scopes::Scope, scrubber::Listing, shortcuts::*, tonemap::ToneMap,
+    utils::ColorChannel,
+};
 use anyhow::{anyhow, Result};
 use notan::egui::{Context, Visuals};
 use serde::{Deserialize, Serialize};
@@ -8,6 +12,9 @@ use std::{
     path::PathBuf,
 };
 
+/// How many images are remembered as recently opened
+pub const MAX_RECENT_IMAGES: usize = 50;
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum ColorTheme {
     /// Light Theme
@@ -23,8 +30,12 @@ pub enum ColorTheme {
 pub struct PersistentSettings {
     /// The UI accent color
     pub accent_color: [u8; 3],
-    /// The BG color
+    /// The custom color around the image
     pub background_color: [u8; 3],
+    /// The color around the image, `Custom` uses `background_color`
+    pub background_preset: BackgroundPreset,
+    /// The checkerboard behind transparent pixels
+    pub checker: Checker,
     /// Should we sync to monitor rate? This makes the app snappier, but also more resource intensive.
     pub vsync: bool,
     pub force_redraw: bool,
@@ -34,11 +45,36 @@ pub struct PersistentSettings {
     pub keep_view: bool,
     /// How many images to keep in cache
     pub max_cache: usize,
+    /// Decode the next and previous image of the folder in the background
+    pub prefetch: bool,
     pub show_scrub_bar: bool,
+    /// Show the folder tree next to the image
+    pub show_folder_panel: bool,
+    pub show_inspector: bool,
+    pub show_histogram: bool,
+    /// Draw the histogram on a log scale
+    pub histogram_log: bool,
+    pub show_scopes: bool,
+    /// Show the scopes as a panel instead of a window
+    pub dock_scopes: bool,
+    pub scope: Scope,
+    /// Show the pixel inspector as a panel instead of a window
+    pub dock_inspector: bool,
     pub wrap_folder: bool,
+    /// The order and filter of the images browsed in a folder
+    pub listing: Listing,
     /// Whether to keep the image edit stack
     pub keep_edits: bool,
+    /// Load the current image again when it changes on disk
+    pub auto_reload: bool,
+    /// Keep the edits when the current image is loaded again after it changed
+    pub reload_keeps_edits: bool,
+    /// Automatically save edits next to the image
+    pub auto_sidecar: bool,
+    /// Preview supported edits on the GPU while adjusting them
+    pub gpu_edits: bool,
     pub favourite_images: HashSet<PathBuf>,
+    /// The images opened last, most recent first
     pub recent_images: Vec<PathBuf>,
     pub title_format: String,
     pub info_enabled: bool,
@@ -49,6 +85,7 @@ pub struct PersistentSettings {
     pub show_checker_background: bool,
     pub show_minimap: bool,
     pub show_frame: bool,
+    pub pixel_grid: PixelGrid,
     pub current_channel: ColorChannel,
     /// How much to scale SVG images when rendering
     pub svg_scale: f32,
@@ -58,7 +95,13 @@ pub struct PersistentSettings {
     pub fit_image_on_window_resize: bool,
     pub zoom_multiplier: f32,
     pub borderless: bool,
-    pub min_window_size: (u32,u32)
+    pub min_window_size: (u32,u32),
+    /// Encoder settings for saving images
+    pub export: ExportSettings,
+    /// How high dynamic range images are fitted to the display
+    pub tone_map: ToneMap,
+    /// Display exposure of high dynamic range images, in stops
+    pub exposure: f32,
 }
 
 impl Default for PersistentSettings {
@@ -66,14 +109,30 @@ impl Default for PersistentSettings {
         PersistentSettings {
             accent_color: [255, 0, 75],
             background_color: [51, 51, 51],
+            background_preset: Default::default(),
+            checker: Default::default(),
             vsync: true,
             force_redraw: false,
             shortcuts: Shortcuts::default_keys(),
             keep_view: Default::default(),
             max_cache: 30,
+            prefetch: true,
             show_scrub_bar: Default::default(),
+            show_folder_panel: Default::default(),
+            show_inspector: Default::default(),
+            show_histogram: Default::default(),
+            histogram_log: Default::default(),
+            show_scopes: Default::default(),
+            dock_scopes: Default::default(),
+            scope: Default::default(),
+            dock_inspector: Default::default(),
             wrap_folder: true,
+            listing: Default::default(),
             keep_edits: Default::default(),
+            auto_reload: true,
+            reload_keeps_edits: Default::default(),
+            auto_sidecar: Default::default(),
+            gpu_edits: Default::default(),
             favourite_images: Default::default(),
             recent_images: Default::default(),
             title_format: "{APP} | {VERSION} | {FULLPATH}".into(),
@@ -84,6 +143,7 @@ impl Default for PersistentSettings {
             show_checker_background: Default::default(),
             show_minimap: Default::default(),
             show_frame: Default::default(),
+            pixel_grid: Default::default(),
             current_channel: ColorChannel::Rgba,
             svg_scale: 1.0,
             zen_mode: false,
@@ -92,7 +152,10 @@ impl Default for PersistentSettings {
             fit_image_on_window_resize: false,
             zoom_multiplier: 1.0,
             borderless: false,
-            min_window_size: (100,100)
+            min_window_size: (100,100),
+            export: Default::default(),
+            tone_map: ToneMap::Clamp,
+            exposure: 0.,
         }
     }
 }
@@ -112,9 +175,13 @@ impl PersistentSettings {
                     .join(".oculante"),
             );
 
-        Ok(serde_json::from_reader::<_, PersistentSettings>(
-            File::open(config_path)?,
-        )?)
+        let mut settings =
+            serde_json::from_reader::<_, PersistentSettings>(File::open(config_path)?)?;
+        // add shortcuts introduced after the config was written
+        for (event, keys) in Shortcuts::default_keys() {
+            settings.shortcuts.entry(event).or_insert(keys);
+        }
+        Ok(settings)
     }
 
     // save settings in a thread so we don't block
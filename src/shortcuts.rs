@@ -40,10 +40,47 @@ pub enum InputEvent {
     LosslessRotateRight,
     LosslessRotateLeft,
     Copy,
+    /// Copy the edited image instead of the original
+    CopyEdited,
     Paste,
     Browse,
     Quit,
     ZenMode,
+    Undo,
+    Redo,
+    CopyEdits,
+    PasteEdits,
+    HoldCompare,
+    PauseAnimation,
+    NextFrame,
+    PreviousFrame,
+    /// Show the thumbnails of the folder
+    Gallery,
+    FolderPanel,
+    PixelInspector,
+    Histogram,
+    /// Show the waveform, parade or vectorscope
+    Scopes,
+    /// Switch between the background presets
+    CycleBackground,
+    /// Find a recently opened image
+    RecentFiles,
+    /// Mark the current image as a favourite
+    Favourite,
+    /// Only go through the favourite images
+    FavouritesOnly,
+    /// Remove the star rating of the current image
+    RateNone,
+    RateOne,
+    RateTwo,
+    RateThree,
+    RateFour,
+    RateFive,
+    LabelRed,
+    LabelYellow,
+    LabelGreen,
+    LabelBlue,
+    LabelPurple,
 }
 
 pub type Shortcuts = BTreeMap<InputEvent, SimultaneousKeypresses>;
@@ -138,7 +175,36 @@ impl ShortcutExt for Shortcuts {
             .add_keys(InputEvent::PanDown, &["LShift", "Down"])
             .add_keys(InputEvent::PanUp, &["LShift", "Up"])
             .add_keys(InputEvent::Paste, &["LControl", "V"])
-            .add_keys(InputEvent::Copy, &["LControl", "C"]);
+            .add_keys(InputEvent::Copy, &["LControl", "C"])
+            .add_keys(InputEvent::CopyEdited, &["LControl", "LAlt", "C"])
+            .add_keys(InputEvent::Undo, &["LControl", "Z"])
+            .add_keys(InputEvent::Redo, &["LControl", "LShift", "Z"])
+            .add_keys(InputEvent::CopyEdits, &["LControl", "LShift", "C"])
+            .add_keys(InputEvent::PasteEdits, &["LControl", "LShift", "V"])
+            .add_key(InputEvent::HoldCompare, "Backslash")
+            .add_key(InputEvent::PauseAnimation, "Space")
+            .add_key(InputEvent::NextFrame, "Period")
+            .add_key(InputEvent::PreviousFrame, "Comma")
+            .add_keys(InputEvent::Gallery, &["LShift", "G"])
+            .add_keys(InputEvent::FolderPanel, &["LShift", "D"])
+            .add_keys(InputEvent::PixelInspector, &["LShift", "I"])
+            .add_keys(InputEvent::Histogram, &["LShift", "K"])
+            .add_keys(InputEvent::Scopes, &["LShift", "W"])
+            .add_keys(InputEvent::RecentFiles, &["LShift", "R"])
+            .add_keys(InputEvent::CycleBackground, &["LShift", "B"])
+            .add_key(InputEvent::Favourite, "H")
+            .add_keys(InputEvent::FavouritesOnly, &["LShift", "H"])
+            .add_keys(InputEvent::RateNone, &["LControl", "Key0"])
+            .add_keys(InputEvent::RateOne, &["LControl", "Key1"])
+            .add_keys(InputEvent::RateTwo, &["LControl", "Key2"])
+            .add_keys(InputEvent::RateThree, &["LControl", "Key3"])
+            .add_keys(InputEvent::RateFour, &["LControl", "Key4"])
+            .add_keys(InputEvent::RateFive, &["LControl", "Key5"])
+            .add_keys(InputEvent::LabelRed, &["LControl", "Key6"])
+            .add_keys(InputEvent::LabelYellow, &["LControl", "Key7"])
+            .add_keys(InputEvent::LabelGreen, &["LControl", "Key8"])
+            .add_keys(InputEvent::LabelBlue, &["LControl", "Key9"])
+            .add_keys(InputEvent::LabelPurple, &["LControl", "LShift", "Key6"]);
         #[cfg(target_os = "macos")]
         {
             for (_, keys) in s.iter_mut() {
@@ -219,47 +285,57 @@ pub fn key_pressed(app: &mut App, state: &mut OculanteState, command: InputEvent
 
         // debug!("Down {:?}", app.keyboard.down);
 
+        // Workaround macos fullscreen double press bug: fullscreen toggles when its key is released
+        if command == InputEvent::Fullscreen {
+            let down: SimultaneousKeypresses = app
+                .keyboard
+                .down
+                .keys()
+                .map(|dn| format!("{:?}", dn))
+                .collect();
+            let released: SimultaneousKeypresses = app
+                .keyboard
+                .released
+                .iter()
+                .map(|r| format!("{:?}", r))
+                .collect();
+            if shortcut_released(keys, &down, &released) {
+                debug!("Fullscreen received");
+                return true;
+            }
+            return false;
+        }
+
         for key in keys.alphanumeric() {
-            // Workaround macos fullscreen double press bug
-            if command == InputEvent::Fullscreen {
-                for pressed in &app.keyboard.released {
-                    if format!("{:?}", pressed) == key {
-                        debug!("Fullscreen received");
+            // List of "repeating" keys. Basically "early out" before checking if there were pressed keys
+            if [
+                InputEvent::NextImage,
+                InputEvent::PreviousImage,
+                InputEvent::PanRight,
+                InputEvent::PanLeft,
+                InputEvent::PanDown,
+                InputEvent::PanUp,
+                InputEvent::ZoomIn,
+                InputEvent::ZoomOut,
+            ]
+            .contains(&command)
+            {
+                for (dn, _) in &app.keyboard.down {
+                    if format!("{:?}", dn) == key {
+                        debug!("REPEAT: Number of keys down: {}", app.keyboard.down.len());
                         debug!("Matched {:?} / {:?}", command, key);
+                        debug!("d {}", app.system_timer.delta_f32());
                         return true;
                     }
                 }
-            } else {
-                // List of "repeating" keys. Basically "early out" before checking if there were pressed keys
-                if [
-                    InputEvent::NextImage,
-                    InputEvent::PreviousImage,
-                    InputEvent::PanRight,
-                    InputEvent::PanLeft,
-                    InputEvent::PanDown,
-                    InputEvent::PanUp,
-                    InputEvent::ZoomIn,
-                    InputEvent::ZoomOut,
-                ]
-                .contains(&command)
-                {
-                    for (dn, _) in &app.keyboard.down {
-                        if format!("{:?}", dn) == key {
-                            debug!("REPEAT: Number of keys down: {}", app.keyboard.down.len());
-                            debug!("Matched {:?} / {:?}", command, key);
-                            debug!("d {}", app.system_timer.delta_f32());
-                            return true;
-                        }
-                    }
-                }
+            }
 
-                for pressed in &app.keyboard.pressed {
-                    // debug!("{:?}", pressed);
-                    if format!("{:?}", pressed) == key {
-                        debug!("Number of keys pressed: {}", app.keyboard.down.len());
-                        debug!("Matched {:?} / {:?}", command, key);
-                        return true;
-                    }
+            for pressed in &app.keyboard.pressed {
+                // debug!("{:?}", pressed);
+                if format!("{:?}", pressed) == key {
+                    debug!("Number of keys pressed: {}", app.keyboard.down.len());
+                    debug!("Matched {:?} / {:?}", command, key);
+                    return true;
                 }
             }
         }
@@ -276,6 +352,54 @@ pub fn key_pressed(app: &mut App, state: &mut OculanteState, command: InputEvent
     false
 }
 
+/// Whether all keys of a shortcut are currently held down
+pub fn key_held(app: &App, state: &mut OculanteState, command: InputEvent) -> bool {
+    if state.key_grab {
+        return false;
+    }
+    let Some(keys) = state.persistent_settings.shortcuts.get(&command) else {
+        // update missing shortcut
+        if let Some(default_shortcut) = Shortcuts::default_keys().get(&command) {
+            state
+                .persistent_settings
+                .shortcuts
+                .insert(command, default_shortcut.clone());
+        }
+        return false;
+    };
+    let down: SimultaneousKeypresses = app
+        .keyboard
+        .down
+        .keys()
+        .map(|dn| format!("{:?}", dn))
+        .collect();
+    shortcut_held(keys, &down)
+}
+
+/// Whether a shortcut is held, given the names of the keys that are down. Either side of a
+/// modifier will do.
+pub fn shortcut_held(keys: &SimultaneousKeypresses, down: &SimultaneousKeypresses) -> bool {
+    let modifiers_down = keys.modifiers().iter().all(|m| {
+        ["Shift", "Alt", "Control", "Win"]
+            .iter()
+            .filter(|kind| m.contains(*kind))
+            .all(|kind| down.contains(&format!("L{kind}")) || down.contains(&format!("R{kind}")))
+    });
+    let alphanumeric = keys.alphanumeric();
+    modifiers_down && !alphanumeric.is_empty() && alphanumeric.iter().all(|key| down.contains(key))
+}
+
+/// Whether a key of a shortcut was released while no other keys are held, so shortcuts that
+/// include it don't trigger this one as well
+pub fn shortcut_released(
+    keys: &SimultaneousKeypresses,
+    down: &SimultaneousKeypresses,
+    released: &SimultaneousKeypresses,
+) -> bool {
+    down.iter().all(|dn| keys.contains(dn))
+        && keys.alphanumeric().iter().any(|key| released.contains(key))
+}
+
 pub fn lookup(shortcuts: &Shortcuts, command: &InputEvent) -> String {
     if let Some(keys) = shortcuts.get(&command) {
         return keypresses_as_string(keys);
@@ -2,7 +2,9 @@ use crate::image_loader::*;
 use cmd_lib::run_cmd;
 
 use crate::{
-    image_editing::{process_pixels, ImageOperation, ScaleFilter},
+    image_editing::{
+        process_pixels, ImageOperation, ImgOpItem, NoiseDistribution, ResizeMode, ScaleFilter,
+    },
     shortcuts::{keypresses_as_markdown, ShortcutExt, Shortcuts},
 };
 
@@ -144,6 +146,88 @@ fn bench_process_bright() {
     info!("24 simd");
 }
 
+#[test]
+fn bench_process_simd() {
+    std::env::set_var("RUST_LOG", "info");
+    let _ = env_logger::try_init();
+    let iters = 5;
+    info!("SIMD: benching this with {iters} iterations...");
+
+    let ops = vec![
+        ImageOperation::Brightness(10),
+        ImageOperation::Contrast(10),
+        ImageOperation::Mult([250, 240, 255]),
+        ImageOperation::Add([5, 0, 0]),
+        ImageOperation::Invert,
+    ];
+    // a mask covering everything forces the scalar path
+    let scalar_stack = ops
+        .iter()
+        .map(|op| {
+            let mut item = ImgOpItem::new(op.clone());
+            item.mask = Some(crate::mask::OpMask {
+                shape: crate::mask::MaskShape::Rectangle([0., 0., 1., 1.]),
+                invert: false,
+                feather: 0.,
+            });
+            item
+        })
+        .collect::<Vec<_>>();
+
+    let f = open_image(&PathBuf::from(
+        "tests/mohsen-karimi-f_2B1vBMaQQ-unsplash.jpg",
+    ))
+    .unwrap();
+    let buffer = f.recv().unwrap().buffer;
+
+    let mut total_simd = 0;
+    let mut total_scalar = 0;
+    for _i in 0..iters {
+        let mut simd = buffer.clone();
+        let start = Instant::now();
+        process_pixels(&mut simd, &ops);
+        total_simd += start.elapsed().as_millis();
+
+        let mut scalar = buffer.clone();
+        let start = Instant::now();
+        crate::image_editing::process_pixel_stack(&mut scalar, &scalar_stack);
+        total_scalar += start.elapsed().as_millis();
+    }
+    info!("{} ms mean SIMD", total_simd / iters);
+    info!("{} ms mean scalar (masked)", total_scalar / iters);
+}
+
+#[test]
+fn simd_matches_scalar() {
+    let ops = vec![
+        ImageOperation::Brightness(-20),
+        ImageOperation::Contrast(30),
+        ImageOperation::Mult([200, 255, 128]),
+        ImageOperation::Add([10, 0, 30]),
+        ImageOperation::Invert,
+    ];
+    let img = image::RgbaImage::from_fn(33, 7, |x, y| {
+        image::Rgba([(x * 7) as u8, (y * 30) as u8, (x * y) as u8, 200])
+    });
+    let mut simd = img.clone();
+    process_pixels(&mut simd, &ops);
+
+    let mut scalar = img.clone();
+    for (x, y, p) in scalar.enumerate_pixels_mut() {
+        let mut v =
+            nalgebra::Vector4::new(p[0] as f32, p[1] as f32, p[2] as f32, p[3] as f32) / 255.;
+        for op in &ops {
+            op.process_pixel(&mut v, (x, y), (33, 7), None).unwrap();
+        }
+        v *= 255.;
+        *p = image::Rgba([v[0] as u8, v[1] as u8, v[2] as u8, v[3] as u8]);
+    }
+    // the combined transform rounds differently
+    for (s, c) in simd.as_raw().iter().zip(scalar.as_raw()) {
+        assert!((*s as i32 - *c as i32).abs() <= 1);
+    }
+}
+
 #[test]
 fn dump_shortcuts() {
     use std::io::prelude::*;
@@ -178,6 +262,7 @@ fn bench_process_all() {
                 dimensions: (300, 200),
                 aspect: true,
                 filter: ScaleFilter::Hamming,
+                mode: ResizeMode::Absolute,
             },
             // ImageOperation::
         ];
@@ -202,3 +287,2049 @@ fn bench_process_all() {
     }
     info!("{} ms mean", total / iters);
 }
+
+#[test]
+fn expression_coordinates() {
+    let mut buffer = image::RgbaImage::new(4, 2);
+    let ops = vec![ImageOperation::Expression(
+        "r = x / (width - 1); g = y / (height - 1)".into(),
+    )];
+    process_pixels(&mut buffer, &ops);
+    assert_eq!(buffer.get_pixel(0, 0)[0], 0);
+    assert_eq!(buffer.get_pixel(3, 0)[0], 255);
+    assert_eq!(buffer.get_pixel(3, 1)[1], 255);
+}
+
+#[test]
+fn expression_sample_neighbor() {
+    let mut buffer = image::RgbaImage::new(2, 1);
+    buffer.put_pixel(1, 0, image::Rgba([255, 0, 0, 255]));
+    let ops = vec![ImageOperation::Expression("g = sample(1, 0, 0)".into())];
+    process_pixels(&mut buffer, &ops);
+    // the left pixel reads its right neighbor, the right one is clamped to itself
+    assert_eq!(buffer.get_pixel(0, 0)[1], 255);
+    assert_eq!(buffer.get_pixel(1, 0)[1], 255);
+}
+
+#[test]
+fn noise_is_reproducible() {
+    let ops = vec![ImageOperation::Noise {
+        amt: 50,
+        mono: false,
+        seed: 42,
+        distribution: NoiseDistribution::Gaussian,
+    }];
+    let mut a = image::RgbaImage::from_pixel(8, 8, image::Rgba([128, 128, 128, 255]));
+    let mut b = a.clone();
+    process_pixels(&mut a, &ops);
+    process_pixels(&mut b, &ops);
+    assert_eq!(a, b);
+}
+
+#[test]
+fn resize_modes() {
+    assert_eq!(
+        ResizeMode::Percentage(50).target_dimensions((0, 0), (400, 300)),
+        (200, 150)
+    );
+    assert_eq!(
+        ResizeMode::Fit.target_dimensions((100, 100), (400, 200)),
+        (100, 50)
+    );
+}
+
+#[test]
+fn auto_levels_stretch() {
+    let mut buffer = image::RgbaImage::new(2, 1);
+    buffer.put_pixel(0, 0, image::Rgba([50, 50, 50, 255]));
+    buffer.put_pixel(1, 0, image::Rgba([200, 200, 200, 255]));
+    let mut op = ImageOperation::AutoLevels(None);
+    assert!(op.needs_analysis());
+    op.analyze(&buffer);
+    process_pixels(&mut buffer, &vec![op]);
+    assert_eq!(buffer.get_pixel(0, 0)[0], 0);
+    assert!(buffer.get_pixel(1, 0)[0] >= 254);
+}
+
+#[test]
+fn edit_history_undo_redo() {
+    let mut edit_state = crate::image_editing::EditState::default();
+    edit_state
+        .pixel_op_stack
+        .push(ImgOpItem::new(ImageOperation::Invert));
+    // only marked changes are compared and recorded
+    assert!(!edit_state.record_history());
+    edit_state.history.mark_changed();
+    assert!(edit_state.record_history());
+    edit_state
+        .pixel_op_stack
+        .push(ImgOpItem::new(ImageOperation::Brightness(10)));
+    edit_state.history.mark_changed();
+    assert!(edit_state.record_history());
+    edit_state.history.mark_changed();
+    assert!(!edit_state.record_history());
+
+    assert!(edit_state.undo());
+    assert_eq!(
+        edit_state.pixel_op_stack,
+        vec![ImgOpItem::new(ImageOperation::Invert)]
+    );
+    assert!(edit_state.undo());
+    assert!(edit_state.pixel_op_stack.is_empty());
+    assert!(!edit_state.undo());
+    assert!(edit_state.redo());
+    assert_eq!(
+        edit_state.pixel_op_stack,
+        vec![ImgOpItem::new(ImageOperation::Invert)]
+    );
+}
+
+#[test]
+fn undo_removes_last_stroke() {
+    let mut edit_state = crate::image_editing::EditState::default();
+    for x in [0.2, 0.6] {
+        let mut stroke = paint::PaintStroke::new();
+        stroke.color = [x, 0., 0., 1.];
+        stroke.points = vec![(x, 0.5), (x + 0.1, 0.5)];
+        edit_state.paint_strokes.push(stroke);
+        edit_state.history.mark_changed();
+        edit_state.record_history();
+    }
+    // the empty stroke waiting for input
+    edit_state
+        .paint_strokes
+        .push(edit_state.paint_strokes[1].without_points());
+
+    assert!(edit_state.undo());
+    assert_eq!(edit_state.paint_strokes.len(), 2);
+    assert_eq!(edit_state.paint_strokes[0].points[0], (0.2, 0.5));
+    // painting goes on with a new stroke, not the undone one
+    assert!(edit_state.paint_strokes[1].is_empty());
+    assert_eq!(edit_state.paint_strokes[1].color[0], 0.6);
+}
+
+#[test]
+fn preset_names_stay_in_the_preset_folder() {
+    use crate::image_editing::EditPreset;
+    for name in ["../settings", "a/b", "a\\b", "..", "", "  "] {
+        assert!(EditPreset::path(name).is_err(), "{name}");
+    }
+    // dots inside a name are not taken for an extension
+    let path = EditPreset::path(" warm v1.2 ").unwrap();
+    assert_eq!(path.file_name().unwrap(), "warm v1.2.json");
+    assert_eq!(path.parent().unwrap().file_name().unwrap(), "presets");
+}
+
+#[test]
+fn sidecar_keeps_extension() {
+    assert_eq!(
+        crate::image_editing::sidecar_path(std::path::Path::new("dir/photo.jpg")),
+        PathBuf::from("dir/photo.jpg.oculante")
+    );
+}
+
+#[test]
+fn auto_save_removes_legacy_sidecar() {
+    use crate::image_editing::{legacy_sidecar_path, save_sidecar, sidecar_path, EditState};
+    let dir = std::env::temp_dir().join("oculante_legacy_sidecar");
+    std::fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("photo.jpg");
+    std::fs::write(legacy_sidecar_path(&image), "{}").unwrap();
+
+    let mut edit_state = EditState::default();
+    edit_state
+        .pixel_op_stack
+        .push(ImgOpItem::new(ImageOperation::Invert));
+    save_sidecar(&image, &edit_state).unwrap();
+    assert!(sidecar_path(&image).is_file());
+    assert!(!dir.join("photo.oculante").exists());
+
+    // with no edits left, no sidecar is left to restore them from
+    std::fs::write(legacy_sidecar_path(&image), "{}").unwrap();
+    save_sidecar(&image, &EditState::default()).unwrap();
+    assert!(!sidecar_path(&image).exists());
+    assert!(!legacy_sidecar_path(&image).exists());
+    _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn masked_operator_only_affects_covered_pixels() {
+    use crate::mask::{MaskShape, OpMask};
+    let mut buffer = image::RgbaImage::from_pixel(10, 10, image::Rgba([0, 0, 0, 255]));
+    let mut item = ImgOpItem::new(ImageOperation::Invert);
+    item.mask = Some(OpMask {
+        shape: MaskShape::Rectangle([0., 0., 0.5, 1.]),
+        invert: false,
+        feather: 0.,
+    });
+    crate::image_editing::process_pixel_stack(&mut buffer, &[item]);
+    assert_eq!(buffer.get_pixel(1, 5)[0], 255);
+    assert_eq!(buffer.get_pixel(8, 5)[0], 0);
+}
+
+#[test]
+fn layer_blending() {
+    use crate::layers::{BlendMode, Layer, LayerSource};
+    let mut base = image::RgbaImage::from_pixel(4, 4, image::Rgba([200, 200, 200, 255]));
+    let mut layer = Layer::new("test", LayerSource::Strokes(vec![]));
+    layer.image = std::sync::Arc::new(image::RgbaImage::from_pixel(
+        2,
+        2,
+        image::Rgba([100, 100, 100, 255]),
+    ));
+    layer.offset = (2, 2);
+    layer.blend_mode = BlendMode::Darken;
+    layer.opacity = 0.5;
+    layer.composite(&mut base);
+    assert_eq!(base.get_pixel(0, 0)[0], 200);
+    assert!((base.get_pixel(3, 3)[0] as i32 - 150).abs() <= 1);
+}
+
+#[test]
+fn fill_with_multiply_blend() {
+    let mut buffer = image::RgbaImage::from_pixel(2, 2, image::Rgba([200, 100, 255, 255]));
+    let mut item = ImgOpItem::new(ImageOperation::Fill([255, 0, 255, 255]));
+    item.blend_mode = crate::layers::BlendMode::Multiply;
+    crate::image_editing::process_pixel_stack(&mut buffer, &[item]);
+    let p = buffer.get_pixel(0, 0);
+    assert!((p[0] as i32 - 200).abs() <= 1);
+    assert_eq!(p[1], 0);
+    assert!((p[2] as i32 - 255).abs() <= 1);
+}
+
+#[test]
+fn gpu_program_encoding() {
+    use crate::gpu::{encode_ops, MAX_OPS, PROGRAM_LEN};
+    let program = encode_ops(&[ImageOperation::Invert, ImageOperation::Brightness(51)]).unwrap();
+    assert_eq!(program.len(), PROGRAM_LEN);
+    assert_eq!(program[0], 9.);
+    assert_eq!(program[4], 1.);
+    assert_eq!(program[(MAX_OPS + 1) * 4], 0.2);
+    assert_eq!(program[MAX_OPS * 12], 2.);
+    // operators without a shader version fall back to the CPU
+    assert!(encode_ops(&[ImageOperation::Blur(2)]).is_none());
+}
+
+#[test]
+fn float_pipeline_keeps_highlights() {
+    let mut buffer = image::Rgba32FImage::from_pixel(2, 2, image::Rgba([2.0, 0.5, 0.0, 1.0]));
+    crate::image_editing::process_pixel_stack_f32(
+        &mut buffer,
+        &[ImgOpItem::new(ImageOperation::Exposure(-25))],
+    );
+    let result = crate::image_editing::quantize(&buffer);
+    assert_eq!(result.get_pixel(0, 0)[0], 255);
+    assert_eq!(result.get_pixel(0, 0)[1], 63);
+}
+
+#[test]
+fn paste_edit_stacks() {
+    let mut source = crate::image_editing::EditState::default();
+    source
+        .pixel_op_stack
+        .push(ImgOpItem::new(ImageOperation::Invert));
+    let copied = source.copy_stacks();
+
+    let mut target = crate::image_editing::EditState::default();
+    target
+        .image_op_stack
+        .push(ImgOpItem::new(ImageOperation::Flip(false)));
+    target.paste_stacks(&copied);
+    assert_eq!(target.pixel_op_stack, source.pixel_op_stack);
+    assert!(target.image_op_stack.is_empty());
+}
+
+#[test]
+fn operator_thumbnails() {
+    use crate::image_editing::ThumbnailCache;
+    let img = image::RgbaImage::from_pixel(640, 320, image::Rgba([10, 20, 30, 255]));
+    let mut stack = vec![
+        ImgOpItem::new(ImageOperation::Invert),
+        ImgOpItem::new(ImageOperation::Brightness(10)),
+    ];
+    let mut cache = ThumbnailCache::default();
+    assert_eq!(cache.update(&img, &stack), 0);
+    let thumbnails = cache.thumbnails().to_vec();
+    assert_eq!(thumbnails.len(), 2);
+    assert_eq!(thumbnails[0].dimensions(), (64, 32));
+    assert_eq!(thumbnails[0].get_pixel(0, 0)[0], 245);
+    assert!(thumbnails[1].get_pixel(0, 0)[0] > 245);
+
+    // only the thumbnails from the changed operator on are made again
+    assert_eq!(cache.update(&img, &stack), 2);
+    stack[1] = ImgOpItem::new(ImageOperation::Brightness(-10));
+    stack.push(ImgOpItem::new(ImageOperation::Invert));
+    assert_eq!(cache.update(&img, &stack), 1);
+    assert_eq!(cache.thumbnails()[0], thumbnails[0]);
+    assert!(cache.thumbnails()[1].get_pixel(0, 0)[0] < 245);
+    assert_eq!(cache.thumbnails().len(), 3);
+
+    let mut fresh = ThumbnailCache::default();
+    fresh.update(&img, &stack);
+    assert_eq!(fresh.thumbnails(), cache.thumbnails());
+}
+
+#[test]
+fn stack_cache_matches_full_processing() {
+    use crate::image_editing::{process_pixel_stack_f32, StackCache};
+    let img = image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(32, 32, |x, y| {
+        image::Rgba([x as u8 * 8, y as u8 * 8, 100, 255])
+    }))
+    .into_rgba32f();
+    let mut stack = vec![
+        ImgOpItem::new(ImageOperation::Brightness(10)),
+        ImgOpItem::new(ImageOperation::Contrast(20)),
+        ImgOpItem::new(ImageOperation::Invert),
+    ];
+    let mut cache = StackCache::default();
+    // edit the same operator repeatedly, then operators before and after it
+    for (i, op) in [
+        (1, ImageOperation::Contrast(30)),
+        (1, ImageOperation::Contrast(40)),
+        (1, ImageOperation::Contrast(50)),
+        (0, ImageOperation::Brightness(-20)),
+        (2, ImageOperation::Exposure(15)),
+        (1, ImageOperation::Contrast(60)),
+    ] {
+        stack[i].operation = op;
+        let mut expected = img.clone();
+        process_pixel_stack_f32(&mut expected, &stack);
+        let mut cached = img.clone();
+        cache.process(&mut cached, &stack, |items, buffer| {
+            process_pixel_stack_f32(buffer, items)
+        });
+        assert_eq!(expected, cached);
+    }
+}
+
+#[test]
+fn parallel_blur_matches_imageops() {
+    let img = image::RgbaImage::from_fn(60, 40, |x, y| {
+        image::Rgba([(x * 4) as u8, (y * 6) as u8, ((x + y) % 2 * 255) as u8, 255])
+    });
+    let expected = image::imageops::blur(&img, 3.);
+    let mut blurred = img.clone();
+    ImageOperation::Blur(3).process_image(&mut blurred).unwrap();
+    // the borders are handled differently, so only compare the inside
+    for y in 6..34 {
+        for x in 6..54 {
+            for c in 0..4 {
+                let e = expected.get_pixel(x, y)[c] as i32;
+                let b = blurred.get_pixel(x, y)[c] as i32;
+                assert!((e - b).abs() <= 1);
+            }
+        }
+    }
+}
+
+#[test]
+fn script_builds_stacks() {
+    let img = image::RgbaImage::from_pixel(4, 4, image::Rgba([100, 100, 100, 255]));
+    let context = scripting::ScriptContext {
+        image: Some(img),
+        ..Default::default()
+    };
+    let result = scripting::run(
+        r#"
+        add_op("brightness", 20);
+        add_op("blur", 2);
+        clear_ops();
+        add_op("invert");
+        apply();
+        add_op("contrast", 10);
+        "#,
+        context,
+    )
+    .unwrap();
+    assert!(result.image_replaced);
+    assert_eq!(result.image.unwrap().get_pixel(0, 0)[0], 155);
+    assert_eq!(result.stacks.pixel_op_stack.len(), 1);
+    assert!(scripting::run(r#"add_op("nonsense");"#, Default::default()).is_err());
+    // runaway recursion is stopped
+    assert!(scripting::run("fn f(x) { f(x) } f(1);", Default::default()).is_err());
+}
+
+#[test]
+fn grayscale_brush_paints_dark_areas() {
+    let gray = image::GrayImage::from_fn(2, 1, |x, _| image::Luma([x as u8 * 255]));
+    let brush = image_editing::brush_from_image(image::DynamicImage::ImageLuma8(gray));
+    assert_eq!(brush.get_pixel(0, 0), &image::Rgba([255, 255, 255, 255]));
+    assert_eq!(brush.get_pixel(1, 0), &image::Rgba([255, 255, 255, 0]));
+}
+
+#[test]
+fn annotations_render() {
+    use annotation::{Annotation, AnnotationKind};
+    let mut img = image::RgbaImage::from_pixel(40, 40, image::Rgba([0, 0, 0, 255]));
+    let mut rect = Annotation::new(AnnotationKind::Rectangle, (0.25, 0.25), [1., 0., 0., 1.]);
+    rect.end = (0.75, 0.75);
+    rect.width = 0.05;
+    rect.render(&mut img);
+    // the outline is drawn, the inside is left alone
+    assert_eq!(img.get_pixel(10, 20)[0], 255);
+    assert_eq!(img.get_pixel(20, 20)[0], 0);
+    rect.filled = true;
+    rect.render(&mut img);
+    assert_eq!(img.get_pixel(20, 20)[0], 255);
+    assert_eq!(img.get_pixel(2, 2)[0], 0);
+}
+
+#[test]
+fn flood_fill_modes() {
+    use paint::{FloodFill, PaintStroke};
+    // two black areas, split by a white column
+    let img = image::RgbaImage::from_fn(21, 10, |x, _| match x {
+        10 => image::Rgba([255, 255, 255, 255]),
+        _ => image::Rgba([0, 0, 0, 255]),
+    });
+    let mut stroke = PaintStroke::new();
+    stroke.color = [1., 0., 0., 1.];
+    stroke.points = vec![(0.1, 0.5)];
+    stroke.flood_fill = Some(FloodFill::default());
+
+    let mut contiguous = img.clone();
+    stroke.render(&mut contiguous, &[]);
+    assert_eq!(contiguous.get_pixel(0, 0)[0], 255);
+    assert_eq!(contiguous.get_pixel(10, 0)[1], 255);
+    assert_eq!(contiguous.get_pixel(20, 9)[0], 0);
+
+    stroke.flood_fill = Some(FloodFill {
+        contiguous: false,
+        ..Default::default()
+    });
+    let mut global = img.clone();
+    stroke.render(&mut global, &[]);
+    assert_eq!(global.get_pixel(20, 9)[0], 255);
+    assert_eq!(global.get_pixel(10, 0)[1], 255);
+}
+
+#[test]
+fn redaction_replaces_pixels() {
+    use redaction::{Redaction, RedactionStyle};
+    let img = image::RgbaImage::from_fn(64, 64, |x, y| {
+        image::Rgba([x as u8 * 4, y as u8 * 4, 0, 255])
+    });
+    let mut redaction = Redaction::new(RedactionStyle::Pixelate, (0., 0.));
+    redaction.end = (0.5, 0.5);
+    let mut pixelated = img.clone();
+    redaction.render(&mut pixelated);
+    // a block has a single color, pixels outside are untouched
+    assert_eq!(pixelated.get_pixel(0, 0), pixelated.get_pixel(7, 7));
+    assert_ne!(img.get_pixel(0, 0), pixelated.get_pixel(0, 0));
+    assert_eq!(img.get_pixel(40, 40), pixelated.get_pixel(40, 40));
+
+    redaction.style = RedactionStyle::Fill;
+    let mut filled = img.clone();
+    redaction.render(&mut filled);
+    assert_eq!(filled.get_pixel(31, 31), &image::Rgba([0, 0, 0, 255]));
+    assert_eq!(img.get_pixel(32, 32), filled.get_pixel(32, 32));
+}
+
+#[test]
+fn selection_limits_new_operators() {
+    let mut edit_state = crate::image_editing::EditState::default();
+    edit_state.selection = Some(crate::mask::OpMask {
+        shape: crate::mask::MaskShape::Rectangle([0., 0., 0.5, 1.]),
+        invert: false,
+        feather: 0.,
+    });
+    let item = edit_state.new_item(ImageOperation::Invert);
+    let mut img = image::RgbaImage::from_pixel(4, 1, image::Rgba([0, 0, 0, 255]));
+    crate::image_editing::process_pixel_stack(&mut img, &[item]);
+    assert_eq!(img.get_pixel(0, 0)[0], 255);
+    assert_eq!(img.get_pixel(3, 0)[0], 0);
+}
+
+#[test]
+fn pixel_selection_roundtrip() {
+    use crate::mask::{MaskShape, OpMask};
+    let selected = [false, true, true, false, false, true];
+    let mask = OpMask {
+        shape: MaskShape::from_pixels(3, 2, &selected),
+        invert: false,
+        feather: 0.,
+    };
+    let coverage = mask.render((3, 2));
+    let rendered = coverage.pixels().map(|p| p[0] == 255).collect::<Vec<_>>();
+    assert_eq!(rendered, selected);
+}
+
+#[test]
+fn polygon_selection_fills_inside() {
+    use crate::mask::{MaskShape, OpMask};
+    let mask = OpMask {
+        shape: MaskShape::Polygon(vec![(0., 0.), (1., 0.), (0., 1.)]),
+        invert: false,
+        feather: 0.,
+    };
+    let coverage = mask.render((10, 10));
+    assert_eq!(coverage.get_pixel(1, 1)[0], 255);
+    assert_eq!(coverage.get_pixel(8, 8)[0], 0);
+    assert_eq!(coverage.get_pixel(9, 0)[0], 0);
+    assert_eq!(coverage.get_pixel(8, 0)[0], 255);
+}
+
+#[test]
+fn export_formats_roundtrip() {
+    use crate::export::{ExportFormat, ExportSettings};
+    let img = image::RgbaImage::from_fn(8, 8, |x, y| {
+        image::Rgba([x as u8 * 30, y as u8 * 30, 0, 255])
+    });
+    let settings = ExportSettings::default();
+    for format in [ExportFormat::Png, ExportFormat::Tiff, ExportFormat::Jpeg] {
+        let data = settings.encode(&img, format, None).unwrap();
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!(decoded.width(), 8, "{format:?}");
+    }
+
+    // extensions without encoder settings are written in their own format
+    for ext in ["ico", "qoi", "exr", "ppm"] {
+        let path = std::env::temp_dir().join(format!("oculante_export.{ext}"));
+        settings.save(&img, &path, None).unwrap();
+        let format = image::io::Reader::open(&path)
+            .unwrap()
+            .with_guessed_format()
+            .unwrap()
+            .format();
+        assert_eq!(format, image::ImageFormat::from_path(&path).ok(), "{ext}");
+        _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn size_preset_fits_into_box() {
+    use crate::export::SizePreset;
+    assert_eq!(
+        SizePreset::new("", 2048, 2048).fit((4000, 3000)),
+        (2048, 1536)
+    );
+    assert_eq!(
+        SizePreset::new("", 1920, 1080).fit((1080, 1920)),
+        (608, 1080)
+    );
+    assert_eq!(SizePreset::new("", 256, 256).fit((100, 50)), (100, 50));
+    assert_eq!(SizePreset::new("", 0, 0).fit((4000, 3000)), (4000, 3000));
+}
+
+#[test]
+fn size_preset_is_not_applied_when_overwriting() {
+    use crate::export::ExportSettings;
+    let settings = ExportSettings {
+        size_preset: 3,
+        ..Default::default()
+    };
+    let img = image::RgbaImage::new(1000, 500);
+    let path = std::env::temp_dir().join("oculante_size_preset.png");
+    settings.save(&img, &path, None).unwrap();
+    assert_eq!(image::image_dimensions(&path).unwrap(), (256, 128));
+    settings.save(&img, &path, Some(&path)).unwrap();
+    assert_eq!(image::image_dimensions(&path).unwrap(), (1000, 500));
+    _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn animation_export_keeps_frames() {
+    use crate::export::{ExportFormat, ExportSettings};
+    use image::AnimationDecoder;
+    let frames = (0..3)
+        .map(|i| {
+            let img = image::RgbaImage::from_pixel(4, 4, image::Rgba([i * 80, 0, 0, 255]));
+            (img, 50)
+        })
+        .collect::<Vec<_>>();
+    let settings = ExportSettings::default();
+
+    let gif = settings
+        .encode_animation(&frames, ExportFormat::Gif)
+        .unwrap();
+    let decoded = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(gif))
+        .unwrap()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    assert_eq!(decoded.len(), 3);
+
+    let apng = settings
+        .encode_animation(&frames, ExportFormat::Png)
+        .unwrap();
+    let decoded = image::codecs::png::PngDecoder::new(std::io::Cursor::new(apng))
+        .unwrap()
+        .apng()
+        .unwrap()
+        .into_frames()
+        .collect_frames()
+        .unwrap();
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded[0].delay().numer_denom_ms(), (50, 1));
+
+    assert!(settings
+        .encode_animation(&frames, ExportFormat::Jpeg)
+        .is_err());
+}
+
+#[test]
+fn color_profiles_are_embedded() {
+    use crate::export::{read_profile, ExportFormat, ExportSettings};
+    use crate::icc::ColorProfile;
+    let img = image::RgbaImage::from_pixel(8, 8, image::Rgba([200, 100, 50, 255]));
+    let profile = ColorProfile::DisplayP3.data().unwrap();
+    // the header starts with the size and has the signature at 36
+    assert_eq!(&profile[0..4], &(profile.len() as u32).to_be_bytes());
+    assert_eq!(&profile[36..40], b"acsp");
+
+    let dir = std::env::temp_dir();
+    for format in [ExportFormat::Png, ExportFormat::Jpeg] {
+        let data = ExportSettings::default()
+            .encode(&img, format, Some(&profile))
+            .unwrap();
+        let path = dir
+            .join("oculante_profile")
+            .with_extension(format.extension());
+        std::fs::write(&path, data).unwrap();
+        assert_eq!(read_profile(&path), Some(profile.clone()), "{format:?}");
+        _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn colors_are_converted_between_profiles() {
+    use crate::icc::{convert, ColorProfile};
+    let red = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+
+    // sRGB red is inside the wider Display P3, so it gets less saturated there
+    let mut p3 = red.clone();
+    convert(&mut p3, None, ColorProfile::DisplayP3).unwrap();
+    let [r, g, b, a] = p3.get_pixel(0, 0).0;
+    assert!(r < 255 && g > 0 && b > 0, "{r} {g} {b}");
+    assert_eq!(a, 255);
+
+    // and back to about the same red
+    let profile = ColorProfile::DisplayP3.data().unwrap();
+    convert(&mut p3, Some(&profile), ColorProfile::Srgb).unwrap();
+    let [r, g, b, _] = p3.get_pixel(0, 0).0;
+    assert!(r > 250 && g < 5 && b < 5, "{r} {g} {b}");
+
+    // tagging only, or a profile the pixels are already in, leaves them alone
+    let mut img = red.clone();
+    convert(&mut img, Some(&profile), ColorProfile::Original).unwrap();
+    convert(&mut img, Some(&profile), ColorProfile::DisplayP3).unwrap();
+    convert(&mut img, None, ColorProfile::Srgb).unwrap();
+    assert_eq!(img, red);
+}
+
+#[test]
+fn selection_crop_keeps_selected_pixels() {
+    use crate::mask::{MaskShape, OpMask};
+    let img = image::RgbaImage::from_pixel(100, 50, image::Rgba([255, 0, 0, 255]));
+    let selection = OpMask {
+        shape: MaskShape::Ellipse([0.5, 0.2, 0.3, 0.6]),
+        invert: false,
+        feather: 0.,
+    };
+    let cropped = crate::selection::crop_to_selection(&img, &selection).unwrap();
+    assert_eq!(cropped.dimensions(), (30, 30));
+    // the corners of the bounding box are outside of the ellipse
+    assert_eq!(cropped.get_pixel(0, 0)[3], 0);
+    assert_eq!(cropped.get_pixel(15, 15)[3], 255);
+}
+
+#[test]
+fn data_uri_contains_png() {
+    use base64::Engine;
+    let img = image::RgbaImage::from_pixel(3, 2, image::Rgba([0, 128, 255, 255]));
+    let uri = crate::export::ExportSettings::default()
+        .data_uri(&img)
+        .unwrap();
+    let data = uri.strip_prefix("data:image/png;base64,").unwrap();
+    let png = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .unwrap();
+    let decoded = image::load_from_memory(&png).unwrap().into_rgba8();
+    assert_eq!(decoded, img);
+}
+
+#[test]
+fn templated_names_are_numbered() {
+    use crate::export::ExportSettings;
+    let dir = std::env::temp_dir().join("oculante_template");
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let settings = ExportSettings {
+        name_template: "{name}_{width}x{height}.{ext}".into(),
+        ..Default::default()
+    };
+    let source = dir.join("photo.jpg");
+    let first = settings.templated_path(Some(&source), &dir, (640, 480));
+    assert_eq!(
+        first,
+        dir.join(format!("photo_640x480.{}", settings.format.extension()))
+    );
+    std::fs::write(&first, []).unwrap();
+    let second = settings.templated_path(Some(&source), &dir, (640, 480));
+    assert_eq!(
+        second,
+        dir.join(format!("photo_640x480_1.{}", settings.format.extension()))
+    );
+    _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn rotations_become_exif_orientation() {
+    use crate::image_editing::{EditState, ImageOperation, ImgOpItem};
+    use crate::utils::{exif_orientation, set_exif_orientation};
+    let with_ops = |ops: Vec<ImageOperation>| EditState {
+        image_op_stack: ops.into_iter().map(ImgOpItem::new).collect(),
+        ..Default::default()
+    };
+    assert_eq!(with_ops(vec![]).as_orientation(), None);
+    assert_eq!(
+        with_ops(vec![ImageOperation::Rotate(90)]).as_orientation(),
+        Some(6)
+    );
+    assert_eq!(
+        with_ops(vec![ImageOperation::Rotate(90), ImageOperation::Rotate(90)]).as_orientation(),
+        Some(3)
+    );
+    assert_eq!(
+        with_ops(vec![
+            ImageOperation::Flip(false),
+            ImageOperation::Rotate(90)
+        ])
+        .as_orientation(),
+        Some(7)
+    );
+    assert_eq!(
+        with_ops(vec![ImageOperation::Rotate(90), ImageOperation::Blur(2)]).as_orientation(),
+        None
+    );
+
+    // a file without EXIF data gets new data, then the tag is changed in place
+    let path = std::env::temp_dir().join("oculante_orientation.jpg");
+    image::RgbImage::new(16, 8).save(&path).unwrap();
+    set_exif_orientation(&path, 6).unwrap();
+    assert_eq!(exif_orientation(&path).unwrap(), 6);
+    set_exif_orientation(&path, 3).unwrap();
+    assert_eq!(exif_orientation(&path).unwrap(), 3);
+    assert_eq!(image::open(&path).unwrap().width(), 16);
+    _ = std::fs::remove_file(path);
+}
+
+#[test]
+#[cfg(feature = "turbo")]
+fn lossless_apply_is_blocked_by_other_edits() {
+    use crate::image_editing::{EditState, ImageOperation, ImgOpItem};
+    use crate::redaction::{Redaction, RedactionStyle};
+    let mut state = EditState {
+        image_op_stack: vec![ImgOpItem::new(ImageOperation::Rotate(90))],
+        ..Default::default()
+    };
+    assert_eq!(state.lossless_blocker(), None);
+    state
+        .redactions
+        .push(Redaction::new(RedactionStyle::Fill, (0.2, 0.2)));
+    assert!(state.lossless_blocker().is_some());
+    state.redactions.clear();
+    state
+        .pixel_op_stack
+        .push(ImgOpItem::new(ImageOperation::Blur(2)));
+    assert!(state.lossless_blocker().is_some());
+}
+
+#[test]
+fn heif_properties_are_read() {
+    let boxed = |kind: &[u8; 4], content: &[u8]| {
+        [&(content.len() as u32 + 8).to_be_bytes()[..], kind, content].concat()
+    };
+    let ipco = [
+        boxed(b"irot", &[1]),
+        boxed(b"colr", &[b"prof".as_slice(), b"icc data"].concat()),
+    ]
+    .concat();
+    // item 1 has both properties, version 0 with 16 bit ids and 8 bit indices
+    let ipma = [0, 0, 0, 0, 0, 0, 0, 1, 0, 1, 2, 0x81, 2];
+    let iprp = [boxed(b"ipco", &ipco), boxed(b"ipma", &ipma)].concat();
+    let meta = [
+        vec![0; 4],
+        boxed(b"pitm", &[0, 0, 0, 0, 0, 1]),
+        boxed(b"iprp", &iprp),
+    ]
+    .concat();
+    let file = [boxed(b"ftyp", b"avif"), boxed(b"meta", &meta)].concat();
+
+    assert_eq!(crate::heif::icc_profile(&file), Some(b"icc data".to_vec()));
+    // a quarter turn counter-clockwise
+    let mut img = image::RgbaImage::new(4, 2);
+    img.put_pixel(3, 0, image::Rgba([255; 4]));
+    crate::heif::orient(&file, &mut img);
+    assert_eq!(img.dimensions(), (2, 4));
+    assert_eq!(img.get_pixel(0, 0), &image::Rgba([255; 4]));
+}
+
+#[test]
+fn mip_chain_is_laid_out() {
+    let mips = (0..4)
+        .map(|level| image::RgbaImage::from_pixel(8 >> level, 8 >> level, image::Rgba([level; 4])))
+        .collect::<Vec<_>>();
+    let chain = mip_chain(&mips);
+    assert_eq!(chain.dimensions(), (12, 8));
+    assert_eq!(chain.get_pixel(7, 7), &image::Rgba([0; 4]));
+    assert_eq!(chain.get_pixel(8, 0), &image::Rgba([1; 4]));
+    assert_eq!(chain.get_pixel(8, 4), &image::Rgba([2; 4]));
+    assert_eq!(chain.get_pixel(8, 6), &image::Rgba([3; 4]));
+}
+
+#[test]
+fn every_icon_size_is_read() {
+    let png = |size: u32| {
+        let mut data = std::io::Cursor::new(vec![]);
+        image::RgbaImage::new(size, size)
+            .write_to(&mut data, image::ImageFormat::Png)
+            .unwrap();
+        data.into_inner()
+    };
+    let (small, large) = (png(16), png(32));
+    let entry = |size: u8, len: usize, offset: usize| {
+        [
+            &[size, size, 0, 0, 1, 0, 32, 0][..],
+            &(len as u32).to_le_bytes()[..],
+            &(offset as u32).to_le_bytes()[..],
+        ]
+        .concat()
+    };
+    let ico = [
+        vec![0, 0, 1, 0, 2, 0],
+        entry(16, small.len(), 38),
+        entry(32, large.len(), 38 + small.len()),
+        small,
+        large,
+    ]
+    .concat();
+    let path = std::env::temp_dir().join("oculante_sizes.ico");
+    std::fs::write(&path, ico).unwrap();
+    let sizes = icon_sizes(&path).unwrap();
+    assert_eq!(
+        sizes.iter().map(|i| i.dimensions()).collect::<Vec<_>>(),
+        vec![(32, 32), (16, 16)]
+    );
+}
+
+#[test]
+fn hidden_psd_groups_hide_their_layers() {
+    use crate::psd_layers::{PsdNode, PsdTree};
+    let node = |id: u32, parent: Option<u32>, visible: bool| PsdNode {
+        name: String::new(),
+        id,
+        parent,
+        visible,
+    };
+    let tree = PsdTree {
+        // group 2 is in the hidden group 1
+        groups: vec![node(1, None, false), node(2, Some(1), true)],
+        layers: vec![
+            node(0, None, true),
+            node(1, Some(2), true),
+            node(2, None, false),
+        ],
+    };
+    assert_eq!(tree.shown_layers(), vec![true, false, false]);
+}
+
+#[test]
+fn openraster_merged_image_is_loaded() {
+    let mut png = std::io::Cursor::new(vec![]);
+    image::RgbaImage::new(3, 2)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    let path = std::env::temp_dir().join("oculante_merged.ora");
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    zip.start_file("mergedimage.png", zip::write::FileOptions::default())
+        .unwrap();
+    zip.write_all(png.get_ref()).unwrap();
+    zip.finish().unwrap();
+
+    let frame = open_image(&path).unwrap().recv().unwrap();
+    assert_eq!(frame.buffer.dimensions(), (3, 2));
+}
+
+#[test]
+fn fits_is_read_bottom_up() {
+    let card = |text: &str| format!("{text:<80}");
+    let mut data = [
+        card("SIMPLE  =                    T"),
+        card("BITPIX  =                   16"),
+        card("NAXIS   =                    2"),
+        card("NAXIS1  =                    2"),
+        card("NAXIS2  =                    2"),
+        card("BZERO   =                32768 / unsigned"),
+        card("END"),
+    ]
+    .concat()
+    .into_bytes();
+    data.resize(2880, b' ');
+    // the bottom row is dark, the top row bright
+    for v in [0_u16, 0, 60000, 60000] {
+        data.extend(((v as i32 - 32768) as i16).to_be_bytes());
+    }
+    let fits = crate::fits::FitsImage::read(&data).unwrap();
+    let settings = crate::fits::StretchSettings {
+        stretch: crate::fits::Stretch::Linear,
+        ..Default::default()
+    };
+    let img = fits.stretch(&settings);
+    assert_eq!(img.dimensions(), (2, 2));
+    assert_eq!(img.get_pixel(0, 0)[0], 1.);
+    assert_eq!(img.get_pixel(0, 1)[0], 0.);
+}
+
+#[test]
+fn fits_with_huge_dimensions_is_refused() {
+    let card = |text: &str| format!("{text:<80}");
+    let mut data = [
+        card("SIMPLE  =                    T"),
+        card("BITPIX  =                  -64"),
+        card("NAXIS   =                    3"),
+        card("NAXIS1  =           4294967295"),
+        card("NAXIS2  =           4294967295"),
+        card("NAXIS3  =                    3"),
+        card("END"),
+    ]
+    .concat()
+    .into_bytes();
+    data.resize(2880 + 64, b' ');
+    assert!(crate::fits::FitsImage::read(&data).is_err());
+}
+
+#[test]
+fn dicom_is_windowed() {
+    // implicit VR little endian without a preamble
+    let element = |group: u16, element: u16, value: &[u8]| {
+        [
+            &group.to_le_bytes()[..],
+            &element.to_le_bytes()[..],
+            &(value.len() as u32).to_le_bytes()[..],
+            value,
+        ]
+        .concat()
+    };
+    let pixels = [0_i16, 100, 200, 300]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect::<Vec<_>>();
+    let data = [
+        element(0x0028, 0x0010, &1_u16.to_le_bytes()),
+        element(0x0028, 0x0011, &4_u16.to_le_bytes()),
+        element(0x0028, 0x0100, &16_u16.to_le_bytes()),
+        element(0x0028, 0x0103, &1_u16.to_le_bytes()),
+        element(0x0028, 0x1052, b"-100"),
+        element(0x7FE0, 0x0010, &pixels),
+    ]
+    .concat();
+    let dicom = crate::dicom::DicomImage::read(&data).unwrap();
+    assert_eq!(dicom.values, vec![-100., 0., 100., 200.]);
+    let window = crate::dicom::Window {
+        center: 50.,
+        width: 100.,
+        lut: crate::dicom::Lut::Gray,
+    };
+    let img = dicom.render(&window);
+    let gray = img.pixels().map(|p| p[0]).collect::<Vec<_>>();
+    assert_eq!(gray, vec![0, 0, 255, 255]);
+}
+
+#[test]
+fn stdin_formats_are_detected() {
+    let png = std::fs::read("tests/test.png").unwrap();
+    assert_eq!(guess_extension(&png), Some("png"));
+    let psd = std::fs::read("tests/test.psd").unwrap();
+    assert_eq!(guess_extension(&psd), Some("psd"));
+    let svg = std::fs::read("tests/johnny_automatic_lobster.svg").unwrap();
+    assert_eq!(guess_extension(&svg), Some("svg"));
+    assert_eq!(guess_extension(b"not an image"), None);
+}
+
+#[test]
+fn archive_images_are_browsed_in_order() {
+    let mut png = std::io::Cursor::new(vec![]);
+    image::RgbaImage::new(3, 2)
+        .write_to(&mut png, image::ImageFormat::Png)
+        .unwrap();
+    let path = std::env::temp_dir().join("oculante_comic.cbz");
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    for name in ["pages/page10.png", "pages/page9.png", "notes.txt"] {
+        zip.start_file(name, zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(png.get_ref()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let scrubber = crate::scrubber::Scrubber::new(&path, &Default::default());
+    assert_eq!(
+        scrubber.entries,
+        vec![path.join("pages/page9.png"), path.join("pages/page10.png")]
+    );
+    let frame = open_image(&scrubber.entries[1]).unwrap().recv().unwrap();
+    assert_eq!(frame.buffer.dimensions(), (3, 2));
+}
+
+#[test]
+fn copied_files_are_pasted() {
+    let dir = std::env::temp_dir().join("oculante paste");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("copied image.png");
+    image::RgbaImage::new(1, 1).save(&path).unwrap();
+
+    let uri = format!("file://{}", path.display()).replace(' ', "%20");
+    assert_eq!(
+        clipboard_path(&format!("x-special/nautilus-clipboard\ncopy\n{uri}\n")),
+        Some(path.clone())
+    );
+    assert_eq!(clipboard_path(&path.display().to_string()), Some(path));
+    assert_eq!(clipboard_path("just some text"), None);
+}
+
+#[test]
+fn float_maps_are_flipped() {
+    // two rows of one pixel, the bottom one first
+    let mut pfm = b"Pf\n1 2\n-1.0\n".to_vec();
+    pfm.extend_from_slice(&0.25_f32.to_le_bytes());
+    pfm.extend_from_slice(&1.0_f32.to_le_bytes());
+    assert_eq!(guess_extension(&pfm), Some("pfm"));
+    let img = load_pfm(&pfm).unwrap();
+    assert_eq!(img.get_pixel(0, 0).0, [1.0; 3]);
+    assert_eq!(img.get_pixel(0, 1).0, [0.25; 3]);
+    assert!(load_pfm(&pfm[..pfm.len() - 1]).is_err());
+}
+
+#[test]
+fn sixteen_bit_pnm_keeps_precision() {
+    let path = std::env::temp_dir().join("oculante_16bit.pgm");
+    let mut pgm = b"P5\n1 1\n65535\n".to_vec();
+    pgm.extend_from_slice(&1000_u16.to_be_bytes());
+    std::fs::write(&path, pgm).unwrap();
+    let frame = open_image(&path).unwrap().recv().unwrap();
+    let hdr = frame.hdr_buffer.unwrap();
+    assert_eq!(hdr.get_pixel(0, 0)[0], 1000. / 65535.);
+}
+
+#[test]
+fn heif_sequences_are_timed() {
+    let boxed = |kind: &[u8; 4], content: &[u8]| {
+        [&(content.len() as u32 + 8).to_be_bytes()[..], kind, content].concat()
+    };
+    let words = |values: &[u32]| {
+        values
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect::<Vec<_>>()
+    };
+    // a sample entry of 24 bytes before the size
+    let entry = [vec![0; 24], vec![0, 3, 0, 2]].concat();
+    let stsd = [words(&[0, 1]), boxed(b"av01", &entry)].concat();
+    // a track of three frames with the given timing
+    let track = |stts: &[u32]| {
+        let stbl = [
+            boxed(b"stsd", &stsd),
+            boxed(b"stsz", &words(&[0, 0, 3])),
+            boxed(b"stts", &words(stts)),
+        ]
+        .concat();
+        let mdia = [
+            boxed(b"mdhd", &words(&[0, 0, 0, 1000, 0])),
+            boxed(b"hdlr", &[words(&[0, 0]), b"pict".to_vec()].concat()),
+            boxed(b"minf", &boxed(b"stbl", &stbl)),
+        ]
+        .concat();
+        boxed(b"trak", &boxed(b"mdia", &mdia))
+    };
+    let sequence = |brands: &[u8], moov: &[u8]| {
+        crate::heif::Sequence::read(&[boxed(b"ftyp", brands), boxed(b"moov", moov)].concat())
+    };
+
+    // two frames of 40 ms, then one of 100 ms, in 1/1000 seconds
+    let moov = track(&[0, 2, 2, 40, 1, 100]);
+    assert_eq!(
+        sequence(b"avif\0\0\0\0avismif1", &moov),
+        Some(crate::heif::Sequence {
+            width: 3,
+            height: 2,
+            delays: vec![40, 40, 100],
+        })
+    );
+    // a still image with the same track is not played
+    assert_eq!(sequence(b"avif\0\0\0\0mif1", &moov), None);
+
+    // the timing can't have more frames than the track
+    let huge = track(&[0, 1, u32::MAX, 40]);
+    assert_eq!(
+        sequence(b"avif\0\0\0\0avismif1", &huge).unwrap().delays,
+        vec![40; 3]
+    );
+
+    // frames too large to allocate are refused before decoding
+    let large = crate::heif::Sequence {
+        width: u16::MAX as u32,
+        height: u16::MAX as u32,
+        delays: vec![40; 3],
+    };
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    assert!(large
+        .play(std::path::Path::new("large.avif"), sender)
+        .is_err());
+}
+
+#[test]
+fn compare_difference_is_amplified() {
+    let a = image::RgbaImage::from_pixel(2, 1, image::Rgba([100, 100, 100, 255]));
+    let b = image::RgbaImage::from_pixel(1, 1, image::Rgba([98, 110, 100, 0]));
+    let diff = crate::compare::difference(&a, &b, 10.);
+    assert_eq!(diff.get_pixel(0, 0).0, [20, 100, 0, 255]);
+    // outside of the smaller image
+    assert_eq!(diff.get_pixel(1, 0).0, [255, 255, 255, 255]);
+}
+
+#[test]
+fn gallery_thumbnails_fit() {
+    let path = std::env::temp_dir().join("oculante_wide.png");
+    image::RgbaImage::new(800, 200).save(&path).unwrap();
+    let thumbnail = crate::gallery::make_thumbnail(&path).unwrap();
+    assert_eq!(thumbnail.dimensions(), (crate::gallery::THUMBNAIL_SIZE, 40));
+}
+
+#[test]
+fn folder_tree_lists_folders_in_order() {
+    let root = std::env::temp_dir().join("oculante_folders");
+    for folder in ["shoot 10", "shoot 9", ".hidden"] {
+        std::fs::create_dir_all(root.join(folder)).unwrap();
+    }
+    std::fs::write(root.join("notes.txt"), "").unwrap();
+    assert_eq!(
+        crate::folders::subfolders(&root),
+        vec![root.join("shoot 9"), root.join("shoot 10")]
+    );
+}
+
+#[test]
+fn folder_listing_filters_and_sorts() {
+    use crate::scrubber::{Listing, SortOrder};
+    let dir = std::env::temp_dir().join("oculante_listing");
+    std::fs::create_dir_all(&dir).unwrap();
+    let images = ["IMG_0002.JPG", "IMG_0010.jpg", "edit.png"]
+        .iter()
+        .zip([3, 1, 2])
+        .map(|(name, size)| {
+            let path = dir.join(name);
+            std::fs::write(&path, vec![0; size]).unwrap();
+            path
+        })
+        .collect::<Vec<_>>();
+
+    let mut listing = Listing {
+        filter: "img_*".into(),
+        ..Default::default()
+    };
+    assert_eq!(listing.apply(images.clone()), images[..2].to_vec());
+    listing.filter = "png".into();
+    assert_eq!(listing.apply(images.clone()), vec![images[2].clone()]);
+
+    listing.filter.clear();
+    listing.sort = SortOrder::Size;
+    assert_eq!(
+        listing.apply(images.clone()),
+        vec![images[1].clone(), images[2].clone(), images[0].clone()]
+    );
+    listing.descending = true;
+    assert_eq!(listing.apply(images.clone())[0], images[0]);
+}
+
+#[test]
+fn capture_dates_are_read_once() {
+    use crate::scrubber::capture_date;
+    // a TIFF with only a DateTime tag
+    let tiff = |date: &str| {
+        let mut bytes = b"II*\0\x08\0\0\0\x01\0\x32\x01\x02\0\x14\0\0\0\x1a\0\0\0\0\0\0\0".to_vec();
+        bytes.extend(date.as_bytes());
+        bytes.push(0);
+        bytes
+    };
+    let path = std::env::temp_dir().join("oculante_capture_date.tif");
+    std::fs::write(&path, tiff("2020:01:02 03:04:05")).unwrap();
+    let modified = path.metadata().unwrap().modified().unwrap();
+    assert_eq!(capture_date(&path).as_deref(), Some("2020-01-02 03:04:05"));
+
+    // the date is not read again while the file is unchanged
+    std::fs::write(&path, tiff("2021:01:02 03:04:05")).unwrap();
+    let file = std::fs::File::options().write(true).open(&path).unwrap();
+    file.set_modified(modified).unwrap();
+    assert_eq!(capture_date(&path).as_deref(), Some("2020-01-02 03:04:05"));
+    file.set_modified(modified + std::time::Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(capture_date(&path).as_deref(), Some("2021-01-02 03:04:05"));
+}
+
+#[test]
+fn subfolders_are_walked_depth_first() {
+    use crate::scrubber::{Listing, Scrubber};
+    let root = std::env::temp_dir().join("oculante_dcim");
+    for file in [
+        "b.png",
+        "100CANON/2.png",
+        "100CANON/1.png",
+        "101CANON/3.png",
+    ] {
+        let path = root.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        image::RgbaImage::new(1, 1).save(&path).unwrap();
+    }
+    let listing = Listing {
+        recursive: true,
+        ..Default::default()
+    };
+    let mut scrubber = Scrubber::new(&root, &listing);
+    assert_eq!(
+        scrubber.entries,
+        [
+            "b.png",
+            "100CANON/1.png",
+            "100CANON/2.png",
+            "101CANON/3.png"
+        ]
+        .map(|f| root.join(f))
+    );
+    // moving into a subfolder keeps the folder that was opened
+    scrubber.open(&root.join("101CANON/3.png"), &listing);
+    assert_eq!(scrubber.index, 3);
+    assert_eq!(scrubber.entries.len(), 4);
+}
+
+#[test]
+fn favourites_are_browsed_without_missing_files() {
+    use crate::scrubber::{Listing, Scrubber};
+    let dir = std::env::temp_dir().join("oculante_favourites");
+    std::fs::create_dir_all(&dir).unwrap();
+    let keep = dir.join("keep.png");
+    let also = dir.join("also.png");
+    std::fs::write(&keep, []).unwrap();
+    std::fs::write(&also, []).unwrap();
+    let favourites = [keep.clone(), also.clone(), dir.join("deleted.png")]
+        .into_iter()
+        .collect();
+
+    let scrubber = Scrubber::favourites(&favourites, Some(&keep), &Listing::default());
+    assert_eq!(scrubber.entries, vec![also, keep]);
+    assert_eq!(scrubber.index, 1);
+}
+
+#[test]
+fn ratings_are_kept_in_xmp_sidecars() {
+    use crate::xmp::{ColorLabel, Rating};
+    let dir = std::env::temp_dir().join("oculante_xmp");
+    std::fs::create_dir_all(&dir).unwrap();
+    let image = dir.join("IMG_0001.CR2");
+    let sidecar = dir.join("IMG_0001.xmp");
+    _ = std::fs::remove_file(&sidecar);
+
+    // unrated images get no sidecar
+    Rating::default().write(&image).unwrap();
+    assert!(!sidecar.exists());
+
+    let rating = Rating {
+        stars: 4,
+        label: Some(ColorLabel::Green),
+    };
+    rating.write(&image).unwrap();
+    assert_eq!(Rating::read(&image), rating);
+
+    // other properties are kept, and ratings written as elements are understood
+    std::fs::write(
+        &sidecar,
+        r#"<rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmp:CreatorTool="darktable">
+<xmp:Rating>2</xmp:Rating>
+</rdf:Description>"#,
+    )
+    .unwrap();
+    assert_eq!(Rating::read(&image).stars, 2);
+    Rating {
+        stars: 0,
+        label: Some(ColorLabel::Red),
+    }
+    .write(&image)
+    .unwrap();
+    let xmp = std::fs::read_to_string(&sidecar).unwrap();
+    assert!(xmp.contains(r#"xmp:CreatorTool="darktable""#));
+    assert!(!xmp.contains("xmp:Rating"));
+    assert_eq!(Rating::read(&image).label, Some(ColorLabel::Red));
+}
+
+#[test]
+fn recent_files_are_matched_fuzzily() {
+    use crate::utils::fuzzy_match;
+    assert_eq!(fuzzy_match("", "/home/screenshots/shot.png"), Some(0));
+    assert_eq!(fuzzy_match("shot", "/home/screenshots/Shot.png"), Some(0));
+    assert_eq!(
+        fuzzy_match("scr png", "/home/screenshots/shot.png"),
+        Some(14)
+    );
+    assert_eq!(fuzzy_match("jpg", "/home/screenshots/shot.png"), None);
+    assert!(fuzzy_match("hol", "/photos/holiday.jpg") < fuzzy_match("hol", "/home/lol.jpg"));
+}
+
+#[test]
+fn neighbours_are_prefetched() {
+    use crate::scrubber::Scrubber;
+    use std::path::PathBuf;
+    let mut scrubber = Scrubber::default();
+    scrubber.entries = ["a.png", "b.png", "c.png"].map(PathBuf::from).to_vec();
+    assert_eq!(scrubber.neighbours(), vec![PathBuf::from("b.png")]);
+    scrubber.wrap = true;
+    assert_eq!(
+        scrubber.neighbours(),
+        vec![PathBuf::from("b.png"), PathBuf::from("c.png")]
+    );
+    scrubber.index = 2;
+    assert_eq!(
+        scrubber.neighbours(),
+        vec![PathBuf::from("a.png"), PathBuf::from("b.png")]
+    );
+    scrubber.entries.truncate(1);
+    scrubber.index = 0;
+    assert!(scrubber.neighbours().is_empty());
+}
+
+#[test]
+fn thumbnails_are_named_like_the_freedesktop_spec() {
+    use crate::thumbnails::{file_name, uri};
+    let uri = uri(std::path::Path::new("/home/jens/photos/beach day.jpg"));
+    assert_eq!(uri, "file:///home/jens/photos/beach%20day.jpg");
+    assert_eq!(file_name(&uri).len(), 32);
+    assert_eq!(file_name("abc"), "900150983cd24fb0d6963f7d28e17f72");
+}
+
+#[test]
+fn hot_folder_waits_for_images_to_be_written() {
+    use crate::hotfolder::FolderScanner;
+    let dir = std::env::temp_dir().join("oculante_hot_folder");
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut hot_folder = FolderScanner::new(&dir);
+    assert_eq!(hot_folder.check(), None);
+
+    let render = dir.join("frame_0001.png");
+    std::fs::write(&render, [0; 10]).unwrap();
+    assert_eq!(hot_folder.check(), None);
+    std::fs::write(&render, [0; 20]).unwrap();
+    // still growing
+    assert_eq!(hot_folder.check(), None);
+    assert_eq!(hot_folder.check(), Some(render));
+    assert_eq!(hot_folder.check(), None);
+}
+
+#[test]
+fn pixel_grid_only_draws_lines_in_view() {
+    use crate::grid::visible_lines;
+    // a 100 pixel image at 10x, fully in a 2000 pixel window
+    assert_eq!(visible_lines(50., 10., 100, 2000.), 0..101);
+    // scrolled so pixels 20-39 are in view
+    assert_eq!(visible_lines(-200., 10., 100, 200.), 20..41);
+    assert_eq!(visible_lines(-205., 10., 100, 200.), 21..41);
+}
+
+#[test]
+fn checker_squares_alternate() {
+    use crate::background::Checker;
+    let checker = Checker {
+        size: 4,
+        colors: [[0, 0, 0], [255, 255, 255]],
+    };
+    let img = checker.image();
+    assert_eq!(img.dimensions(), (8, 8));
+    assert_eq!(img.get_pixel(3, 3).0, [0, 0, 0, 255]);
+    assert_eq!(img.get_pixel(4, 3).0, [255, 255, 255, 255]);
+    assert_eq!(img.get_pixel(4, 4).0, [0, 0, 0, 255]);
+}
+
+#[test]
+fn inspector_shows_pixels_in_every_notation() {
+    use crate::inspector::{hex, hsl, linear, neighbourhood, RADIUS};
+    assert_eq!(hex([255, 128, 0, 255]), "#FF8000FF");
+    let [h, s, l] = hsl([255, 0, 0, 255]);
+    assert_eq!((h.round(), s, l), (0., 1., 0.5));
+    let [r, g, b] = linear([255, 188, 0, 255]);
+    assert_eq!(r, 1.);
+    assert!((g - 0.5).abs() < 0.01);
+    assert_eq!(b, 0.);
+
+    let img = image::RgbaImage::from_pixel(3, 3, image::Rgba([1, 2, 3, 4]));
+    let pixels = neighbourhood(&img, 0, 0);
+    assert_eq!(pixels.len(), ((RADIUS * 2 + 1) * (RADIUS * 2 + 1)) as usize);
+    // the pixel under the cursor is in the middle, the ones left of and above it are outside
+    assert_eq!(pixels[pixels.len() / 2], Some([1, 2, 3, 4]));
+    assert_eq!(pixels[pixels.len() / 2 - 1], None);
+}
+
+#[test]
+fn histogram_counts_channels_and_luminance() {
+    use crate::histogram::{height, luma, Histogram};
+    let mut img = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+    img.put_pixel(0, 0, image::Rgba([255, 255, 255, 255]));
+    // fully transparent pixels are not counted
+    img.put_pixel(1, 0, image::Rgba([0, 0, 0, 0]));
+    let hist = Histogram::from_image(&img);
+    assert_eq!(hist.red[255], 15);
+    assert_eq!(hist.green[0], 14);
+    assert_eq!(hist.luma[255], 1);
+    assert_eq!(hist.luma[luma(255, 0, 0) as usize], 14);
+    assert_eq!(hist.blue.iter().sum::<u64>(), 15);
+    assert_eq!(height(0, true), 0.);
+    assert_eq!(height(100, false), 100.);
+}
+
+#[test]
+fn scopes_place_pixels_by_value() {
+    use crate::scopes::{chroma, downsample, Scope, SAMPLE_WIDTH, SCOPE_SIZE};
+    let mut img = image::RgbaImage::from_pixel(4, 2, image::Rgba([0, 0, 0, 255]));
+    img.put_pixel(1, 0, image::Rgba([255, 255, 255, 255]));
+    let waveform = Scope::Waveform.render(&img);
+    assert_eq!(waveform.dimensions(), (4, SCOPE_SIZE));
+    // white at the top of its column, black at the bottom of every column
+    assert_ne!(waveform.get_pixel(1, 0).0, [0, 0, 0, 255]);
+    assert_eq!(waveform.get_pixel(0, 0).0, [0, 0, 0, 255]);
+    assert_ne!(waveform.get_pixel(0, SCOPE_SIZE - 1).0, [0, 0, 0, 255]);
+    assert_eq!(Scope::Parade.render(&img).width(), 12);
+
+    // gray has no color, so it lands in the middle of the vectorscope
+    let (cb, cr) = chroma([128, 128, 128, 255]);
+    assert!(cb.abs() < 0.001 && cr.abs() < 0.001);
+    let (cb, cr) = chroma([255, 0, 0, 255]);
+    assert!(cb < 0. && cr > 0.);
+    let big = image::RgbaImage::new(SAMPLE_WIDTH * 4, 100);
+    assert_eq!(downsample(&big).dimensions(), (SAMPLE_WIDTH, 25));
+}
+
+#[test]
+fn metadata_is_read_from_xmp_iptc_and_maker_notes() {
+    use crate::metadata::{iptc_tags, maker_notes, xmp_tags, Gps};
+    let xmp = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF>
+  <rdf:Description rdf:about="" xmp:Rating="3">
+   <dc:subject><rdf:Bag><rdf:li>cat</rdf:li><rdf:li>Tom &amp; Jerry</rdf:li></rdf:Bag></dc:subject>
+  </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+    let tags = xmp_tags(xmp, "XMP");
+    assert_eq!(tags.len(), 2);
+    assert_eq!(tags[0].value, "3");
+    assert_eq!(tags[1].name, "dc:subject");
+    assert_eq!(tags[1].value, "cat, Tom & Jerry");
+    assert!(tags[1].matches("jerry"));
+
+    // keywords in the Photoshop resources of a JPEG
+    let mut iim = vec![];
+    for (dataset, value) in [(25, "a"), (25, "b"), (80, "Me")] {
+        iim.extend_from_slice(&[0x1c, 2, dataset, 0, value.len() as u8]);
+        iim.extend_from_slice(value.as_bytes());
+    }
+    let mut data = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+    data.extend_from_slice(&(iim.len() as u32).to_be_bytes());
+    data.extend_from_slice(&iim);
+    let tags = iptc_tags(&data);
+    assert_eq!(tags[0].name, "Keywords");
+    assert_eq!(tags[0].value, "a, b");
+    assert_eq!(tags[1].value, "Me");
+
+    // a maker note IFD with a short inline and a string elsewhere
+    let mut tiff = vec![0; 4];
+    tiff.extend_from_slice(&[2, 0]);
+    tiff.extend_from_slice(&[1, 0, 3, 0, 2, 0, 0, 0, 7, 0, 9, 0]);
+    tiff.extend_from_slice(&[2, 0, 2, 0, 6, 0, 0, 0, 34, 0, 0, 0]);
+    tiff.extend_from_slice(&[0; 4]);
+    tiff.extend_from_slice(b"Canon\0");
+    let tags = maker_notes(&tiff, 4, &tiff[4..], true, "Canon");
+    assert_eq!(tags[0].group, "Maker notes (Canon)");
+    assert_eq!(
+        (tags[0].name.as_str(), tags[0].value.as_str()),
+        ("0x0001", "7, 9")
+    );
+    assert_eq!(tags[1].value, "Canon");
+
+    let gps = Gps {
+        latitude: 0.,
+        longitude: 0.,
+        altitude: None,
+    };
+    assert_eq!(gps.tile(1), (1, 1, [0., 0.]));
+    assert!(gps.map_url().contains("mlat=0.000000&mlon=0.000000"));
+}
+
+#[test]
+fn presets_with_shaders_are_refused() {
+    use crate::image_editing::{apply_preset, EditPreset};
+    let mut preset = EditPreset {
+        pixel_op_stack: vec![ImgOpItem::new(ImageOperation::Brightness(10))],
+        image_op_stack: vec![ImgOpItem::new(ImageOperation::PixelShader {
+            fragment: crate::gpu::DEFAULT_SHADER.into(),
+            params: vec![],
+            path: None,
+        })],
+    };
+    let mut img = image::RgbaImage::new(2, 2);
+    assert!(apply_preset(&mut img, &preset).is_err());
+    preset.image_op_stack[0].active = false;
+    apply_preset(&mut img, &preset).unwrap();
+    assert!(img.get_pixel(0, 0)[0] > 0);
+}
+
+#[test]
+fn old_shader_operators_are_read() {
+    let ops: Vec<ImageOperation> =
+        serde_json::from_str(r#"[{"PixelShader": "void main() {}"}]"#).unwrap();
+    assert_eq!(
+        ops,
+        vec![ImageOperation::PixelShader {
+            fragment: "void main() {}".into(),
+            params: vec![],
+            path: None,
+        }]
+    );
+    let json = serde_json::to_string(&ops).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<ImageOperation>>(&json).unwrap(),
+        ops
+    );
+}
+
+#[test]
+fn plugins_are_sandboxed() {
+    use crate::plugins::{compile, file, run_module};
+    // a module exporting its memory, an `alloc` always returning 0 and `process` with `body`
+    let module = |body: &[u8]| {
+        let mut wasm = b"\0asm\x01\0\0\0".to_vec();
+        wasm.extend([1, 15, 2, 0x60, 1, 0x7f, 1, 0x7f, 0x60, 5]);
+        wasm.extend([0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 1, 0x7f]);
+        wasm.extend([3, 3, 2, 0, 1, 5, 3, 1, 0, 1, 7, 28, 3]);
+        wasm.extend(b"\x06memory\x02\0\x05alloc\0\0\x07process\0\x01");
+        wasm.extend([10, 8 + body.len() as u8, 2, 4, 0, 0x41, 0, 0x0b]);
+        wasm.extend([body.len() as u8 + 1, 0]);
+        wasm.extend(body);
+        compile(&wasm).unwrap()
+    };
+    let mut img = image::RgbaImage::new(2, 2);
+    // return 0
+    run_module(&module(&[0x41, 0, 0x0b]), &[], &mut img).unwrap();
+    // loop forever, until the fuel runs out
+    assert!(run_module(
+        &module(&[0x03, 0x40, 0x0c, 0, 0x0b, 0x41, 0, 0x0b]),
+        &[],
+        &mut img
+    )
+    .is_err());
+
+    assert!(file("../config", "wasm").is_err());
+    assert!(file("a\\b", "wasm").is_err());
+    assert!(file("blur", "wasm").unwrap().ends_with("blur.wasm"));
+}
+
+#[test]
+fn strokes_refer_to_brushes_by_name() {
+    use crate::image_editing::bundled_brushes;
+    use crate::paint::PaintStroke;
+    let mut stroke = PaintStroke::new();
+    stroke.brush = "brush3".into();
+    let json = serde_json::to_string(&stroke).unwrap();
+    assert_eq!(serde_json::from_str::<PaintStroke>(&json).unwrap(), stroke);
+    // older strokes stored the index of a bundled brush
+    let old = json.replace(r#""brush":"brush3""#, r#""brush_index":2"#);
+    assert_eq!(serde_json::from_str::<PaintStroke>(&old).unwrap(), stroke);
+    assert_eq!(bundled_brushes()[2].name, "brush3");
+}
+
+#[test]
+fn proxy_scales_pixel_sizes() {
+    let mut blur = ImageOperation::Blur(10);
+    blur.scale_radius(0.5);
+    assert_eq!(blur, ImageOperation::Blur(5));
+    // a small radius doesn't vanish
+    let mut fringe = ImageOperation::ChromaticAberration(2);
+    fringe.scale_radius(0.1);
+    assert_eq!(fringe, ImageOperation::ChromaticAberration(1));
+    assert!(!blur.has_fixed_radius());
+    assert!(ImageOperation::Filter3x3([0, 0, 0, 0, 1, 0, 0, 0, 0]).has_fixed_radius());
+    assert!(ImageOperation::Expression("r = sample(1, 0).r".into()).has_fixed_radius());
+}
+
+#[test]
+fn converted_files_get_unique_names() {
+    use crate::convert::output_paths;
+    let files = ["a/photo.png", "b/photo.jpg", "c/Photo.tif", "d/other.png"].map(PathBuf::from);
+    let (paths, renamed) = output_paths(&files, std::path::Path::new("out"), "webp");
+    assert_eq!(
+        paths,
+        ["photo.webp", "photo_1.webp", "Photo_2.webp", "other.webp"]
+            .map(|name| Some(std::path::Path::new("out").join(name)))
+    );
+    assert_eq!(renamed, 2);
+}
+
+#[test]
+fn video_probe_is_parsed() {
+    use crate::video::{parse_rate, Video};
+    assert!((parse_rate("30000/1001").unwrap() - 29.97).abs() < 0.01);
+    assert_eq!(parse_rate("25"), Some(25.));
+    assert_eq!(parse_rate("fast"), None);
+
+    let path = std::path::Path::new("clip.mkv");
+    let video = Video::from_probe(
+        path,
+        "width=1920\nheight=1080\navg_frame_rate=50/1\nnb_frames=120\nduration=2.400000\n",
+    )
+    .unwrap();
+    assert_eq!(
+        (video.width, video.height, video.frame_count),
+        (1920, 1080, 120)
+    );
+    assert_eq!(video.rate, 50.);
+    // without a frame count or rate, they are estimated
+    let video = Video::from_probe(
+        path,
+        "width=640\nheight=480\navg_frame_rate=0/0\nnb_frames=N/A\nduration=2.000000\n",
+    )
+    .unwrap();
+    assert_eq!((video.rate, video.frame_count), (25., 50));
+    assert!(Video::from_probe(path, "duration=2.000000\n").is_err());
+}
+
+#[test]
+fn changed_files_are_reported() {
+    let path = std::env::temp_dir().join("oculante_watched.png");
+    image::RgbaImage::new(1, 1).save(&path).unwrap();
+    let (image_sender, _image_receiver) = std::sync::mpsc::channel();
+    let (message_sender, _message_receiver) = std::sync::mpsc::channel();
+    let (reload_sender, reload_receiver) = std::sync::mpsc::channel();
+    let mut player = Player::new(image_sender, 0, 16384);
+    player.watch(reload_sender);
+    player.load(&path, message_sender);
+
+    let timeout = std::time::Duration::from_secs(2);
+    assert!(reload_receiver.recv_timeout(timeout).is_err());
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(
+        reload_receiver.recv_timeout(timeout).ok(),
+        Some(path.clone())
+    );
+    // it is only reported once
+    assert!(reload_receiver.recv_timeout(timeout).is_err());
+    _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn capture_frames_are_read_from_the_stream() {
+    use crate::capture::{header_value, read_ppm};
+    assert_eq!(header_value(&mut &b"  640\n"[..]).unwrap(), 640);
+    assert!(header_value(&mut &b"P6"[..]).is_err());
+
+    let mut stream = &b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06P6 1 1 255 \x07\x08\x09"[..];
+    let first = read_ppm(&mut stream).unwrap();
+    assert_eq!(first.dimensions(), (2, 1));
+    assert_eq!(first.get_pixel(1, 0).0, [4, 5, 6]);
+    assert_eq!(read_ppm(&mut stream).unwrap().get_pixel(0, 0).0, [7, 8, 9]);
+    assert!(read_ppm(&mut stream).is_err());
+    // only 8 bit frames
+    assert!(read_ppm(&mut &b"P6 1 1 65535 \x00\x00\x00\x00\x00\x00"[..]).is_err());
+}
+
+#[test]
+fn archive_images_are_decoded_from_memory() {
+    use std::io::Write;
+    let dir = std::env::temp_dir().join("oculante_archive_test");
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let zip_path = dir.join("book.cbz");
+    let mut png = vec![];
+    image::RgbaImage::from_pixel(3, 2, image::Rgba([255, 0, 0, 255]))
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+    for (name, data) in [
+        ("pages/10.png", &png[..]),
+        ("pages/2.jpg", &png[..]),
+        ("notes.txt", &b"notes"[..]),
+    ] {
+        zip.start_file(name, Default::default()).unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let images = crate::archive::images(&zip_path).unwrap();
+    assert_eq!(
+        images,
+        vec![zip_path.join("pages/2.jpg"), zip_path.join("pages/10.png")]
+    );
+    // the format comes from the data, not the name
+    let img = crate::archive::decode(&images[0]).unwrap();
+    assert_eq!(img.dimensions(), (3, 2));
+    assert!(crate::archive::decode(&zip_path.join("notes.txt")).is_err());
+    // nothing is written next to the archive
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn batch_results_with_the_same_name_are_numbered() {
+    use crate::image_editing::{BatchJob, EditPreset};
+    let dir = std::env::temp_dir().join("oculante_batch_names");
+    _ = std::fs::remove_dir_all(&dir);
+    let mut files = vec![];
+    for (i, sub) in ["a", "b"].iter().enumerate() {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+        let file = dir.join(sub).join("shot.png");
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([i as u8 * 200, 0, 0, 255]))
+            .save(&file)
+            .unwrap();
+        files.push(file);
+    }
+    let out_dir = dir.join("edited");
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let job = BatchJob::start(
+        files,
+        out_dir.clone(),
+        EditPreset::default(),
+        Default::default(),
+        sender,
+    );
+    let start = std::time::Instant::now();
+    while !job.is_finished() && start.elapsed().as_secs() < 10 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(job.is_finished());
+    assert!(receiver.try_recv().is_ok());
+
+    // both results are kept, in the order of the files
+    let first = image::open(out_dir.join("shot.png")).unwrap().into_rgba8();
+    let second = image::open(out_dir.join("shot_1.png"))
+        .unwrap()
+        .into_rgba8();
+    assert_eq!(first.get_pixel(0, 0)[0], 0);
+    assert_eq!(second.get_pixel(0, 0)[0], 200);
+    _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn stamps_are_spaced_along_the_stroke() {
+    use crate::paint::stamp_positions;
+    use notan::egui::pos2;
+    let line = [pos2(0., 0.), pos2(10., 0.)];
+    assert_eq!(
+        stamp_positions(&line, 2.5),
+        [0., 2.5, 5., 7.5, 10.].map(|x| pos2(x, 0.)).to_vec()
+    );
+
+    // the spacing carries over from one segment to the next
+    let bent = [pos2(0., 0.), pos2(3., 0.), pos2(3., 3.)];
+    assert_eq!(
+        stamp_positions(&bent, 2.),
+        [pos2(0., 0.), pos2(2., 0.), pos2(3., 1.), pos2(3., 3.)]
+    );
+    assert!(stamp_positions(&[], 2.).is_empty());
+}
+
+#[test]
+fn animations_pause_step_and_change_speed() {
+    use crate::utils::{Playback, Player};
+    let mut playback = Playback::default();
+    assert_eq!(playback.advance(3), Some((1, false)));
+    assert_eq!(playback.advance(3), Some((2, false)));
+    assert_eq!(playback.advance(3), Some((0, true)));
+
+    // paused, frames only move by steps, which wrap around both ways
+    playback.paused = true;
+    assert_eq!(playback.advance(3), None);
+    playback.step = -2;
+    assert_eq!(playback.advance(3), Some((1, false)));
+    assert_eq!(playback.step, 0);
+    assert_eq!(playback.advance(3), None);
+
+    assert_eq!(playback.wait(100).as_millis(), 100);
+    playback.speed = 2.;
+    assert_eq!(playback.wait(100).as_millis(), 50);
+    // frames without a delay, and very fast playback, stay at 60fps at most
+    playback.speed = 8.;
+    assert_eq!(playback.wait(0).as_millis(), 17);
+
+    let (sender, _receiver) = std::sync::mpsc::channel();
+    let player = Player::new(sender, 10, 4096);
+    player.toggle_pause();
+    player.step(1);
+    player.step(1);
+    let playback = player.playback.lock().unwrap().clone();
+    assert!(playback.paused);
+    assert_eq!(playback.step, 2);
+    player.toggle_pause();
+    assert!(!player.playback.lock().unwrap().paused);
+}
+
+#[test]
+fn tiles_come_from_the_smallest_sharp_level() {
+    use crate::tiled::level_for;
+    let widths = [16000_u32, 8000, 4000];
+    assert_eq!(level_for(widths.iter().copied(), 1000.), 2);
+    assert_eq!(level_for(widths.iter().copied(), 4000.), 2);
+    assert_eq!(level_for(widths.iter().copied(), 4001.), 1);
+    assert_eq!(level_for(widths.iter().copied(), 12000.), 0);
+    // zoomed in past the full size
+    assert_eq!(level_for(widths.iter().copied(), 30000.), 0);
+}
+
+#[test]
+fn links_are_named_after_their_file() {
+    use crate::download::{file_name, is_url};
+    assert!(is_url("https://example.com/cat.png"));
+    assert!(is_url("http://example.com/cat.png"));
+    assert!(!is_url("/home/user/https/cat.png"));
+    assert!(!is_url("ftp://example.com/cat.png"));
+
+    assert_eq!(file_name("https://example.com/a/cat.png"), Some("cat.png"));
+    // queries and fragments are not part of the name
+    assert_eq!(
+        file_name("https://example.com/cat.JPG?width=800#top"),
+        Some("cat.JPG")
+    );
+    assert_eq!(file_name("https://example.com/gallery.html"), None);
+    assert_eq!(file_name("https://example.com/"), None);
+    assert_eq!(file_name("https://example.com/image?id=3.png"), None);
+}
+
+#[test]
+fn expression_errors_are_reported() {
+    use crate::image_editing::{expression_error, EXPRESSION_SNIPPETS};
+    for (name, snippet) in EXPRESSION_SNIPPETS {
+        assert_eq!(expression_error(snippet), None, "{name}");
+    }
+    assert!(expression_error("r = ").is_some());
+    assert!(expression_error("r = unknown(1)").is_some());
+    assert!(expression_error("r = sample(1, 0)").is_some());
+
+    let mut buffer = image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 255, 255]));
+    process_pixels(
+        &mut buffer,
+        &vec![ImageOperation::Expression(EXPRESSION_SNIPPETS[0].1.into())],
+    );
+    assert_eq!(buffer.get_pixel(1, 1), &image::Rgba([0, 255, 0, 255]));
+}
+
+#[test]
+fn crop_is_shrunk_to_the_aspect_ratio() {
+    use crate::image_editing::{constrain_crop_aspect, cropped_range};
+    // too wide, the right margin grows
+    let mut crop = [0.; 4];
+    constrain_crop_aspect(&mut crop, 1., (200., 100.));
+    assert_eq!(crop, [0., 0., 0.5, 0.]);
+    let bounds = crop.map(|c| (c * 10000.) as u32);
+    assert_eq!(cropped_range(&bounds, &(200, 100)), [0, 0, 100, 100]);
+
+    // too tall, the bottom margin grows
+    let mut crop = [0.25, 0., 0.25, 0.];
+    constrain_crop_aspect(&mut crop, 16. / 9., (400., 300.));
+    let bounds = crop.map(|c| (c * 10000.).round() as u32);
+    assert_eq!(cropped_range(&bounds, &(400, 300)), [100, 0, 200, 112]);
+
+    // an empty crop is left alone
+    let mut crop = [0.5, 0., 0.5, 0.];
+    constrain_crop_aspect(&mut crop, 1., (100., 100.));
+    assert_eq!(crop, [0.5, 0., 0.5, 0.]);
+}
+
+#[test]
+fn hsl_bands_blend_between_neighbours() {
+    use crate::image_editing::{hsl_band_shift, HSL_BANDS};
+    let mut bands = [[0; 3]; 8];
+    bands[0] = [20, 100, 0];
+    // at the center of a band it applies fully
+    assert_eq!(hsl_band_shift(&bands, HSL_BANDS[0].1), [20., 100., 0.]);
+    assert_eq!(hsl_band_shift(&bands, HSL_BANDS[2].1), [0., 0., 0.]);
+    // halfway to orange
+    assert_eq!(hsl_band_shift(&bands, 15.), [10., 50., 0.]);
+    // halfway from magenta, wrapping around to red
+    assert_eq!(hsl_band_shift(&bands, 330.), [10., 50., 0.]);
+
+    // removing the saturation of blue leaves red alone
+    let mut bands = [[0; 3]; 8];
+    bands[5] = [0, -100, 0];
+    let mut buffer = image::RgbaImage::new(2, 1);
+    buffer.put_pixel(0, 0, image::Rgba([0, 0, 255, 255]));
+    buffer.put_pixel(1, 0, image::Rgba([255, 0, 0, 255]));
+    process_pixels(&mut buffer, &vec![ImageOperation::HslBands(bands)]);
+    let blue = buffer.get_pixel(0, 0);
+    assert!(blue[0] == blue[1] && blue[1] == blue[2]);
+    let red = buffer.get_pixel(1, 0);
+    assert!(red[0] > 250 && red[1] < 5 && red[2] < 5);
+}
+
+#[test]
+fn red_eyes_are_darkened_only_inside_the_radius() {
+    let mut buffer = image::RgbaImage::from_pixel(10, 10, image::Rgba([200, 40, 40, 255]));
+    // skin next to the eye is not red enough to change
+    buffer.put_pixel(5, 6, image::Rgba([120, 110, 100, 255]));
+    process_pixels(
+        &mut buffer,
+        &vec![ImageOperation::RedEye {
+            points: vec![(5000, 5000)],
+            radius: 200,
+        }],
+    );
+    let eye = buffer.get_pixel(5, 5);
+    assert!(eye[0] < 40, "{eye:?}");
+    assert_eq!(eye[1], 40);
+    assert_eq!(buffer.get_pixel(5, 6), &image::Rgba([120, 110, 100, 255]));
+    assert_eq!(buffer.get_pixel(0, 0), &image::Rgba([200, 40, 40, 255]));
+}
+
+#[test]
+fn dragged_operators_shift_the_others() {
+    use crate::image_editing::move_operator;
+    let [invert, posterize, desaturate] = [
+        ImageOperation::Invert,
+        ImageOperation::Posterize(8),
+        ImageOperation::Desaturate(0),
+    ]
+    .map(ImgOpItem::new);
+    let original = vec![invert.clone(), posterize.clone(), desaturate.clone()];
+
+    let mut stack = original.clone();
+    move_operator(&mut stack, 0, 2);
+    assert_eq!(stack, vec![posterize, desaturate, invert]);
+    move_operator(&mut stack, 2, 0);
+    assert_eq!(stack, original);
+    // a stale drag is ignored
+    move_operator(&mut stack, 3, 0);
+    assert_eq!(stack, original);
+}
+
+#[test]
+fn split_line_follows_the_drag() {
+    use crate::{appstate::ImageGeometry, ui::drag_split};
+    let geo = ImageGeometry {
+        scale: 2.,
+        offset: nalgebra::Vector2::new(10., 10.),
+        dimensions: (100, 50),
+    };
+    // the image is shown 200 pixels wide
+    assert_eq!(drag_split(0.5, 50., &geo), 0.75);
+    assert_eq!(drag_split(0.5, -20., &geo), 0.4);
+    // the line stays on the image
+    assert_eq!(drag_split(0.9, 100., &geo), 1.);
+    assert_eq!(drag_split(0.1, -100., &geo), 0.);
+    let empty = ImageGeometry {
+        dimensions: (0, 0),
+        ..geo
+    };
+    assert_eq!(drag_split(0.5, 50., &empty), 0.5);
+}
+
+#[test]
+fn held_shortcuts_need_all_their_keys() {
+    use crate::shortcuts::{shortcut_held, InputEvent, SimultaneousKeypresses};
+    let keys = |k: &[&str]| {
+        k.iter()
+            .map(|k| k.to_string())
+            .collect::<SimultaneousKeypresses>()
+    };
+    let compare = &Shortcuts::default_keys()[&InputEvent::HoldCompare];
+    assert!(shortcut_held(compare, &keys(&["Backslash"])));
+    // other keys may be down as well
+    assert!(shortcut_held(compare, &keys(&["Backslash", "LShift"])));
+    assert!(!shortcut_held(compare, &keys(&["Slash"])));
+    assert!(!shortcut_held(compare, &keys(&[])));
+
+    // either side of a modifier will do
+    let shortcut = keys(&["LControl", "LShift", "V"]);
+    assert!(shortcut_held(
+        &shortcut,
+        &keys(&["RControl", "LShift", "V"])
+    ));
+    assert!(!shortcut_held(&shortcut, &keys(&["LShift", "V"])));
+    // modifiers alone are not a shortcut
+    assert!(!shortcut_held(&keys(&["LShift"]), &keys(&["LShift"])));
+}
+
+#[test]
+fn blur_and_smudge_brushes_move_pixels_around() {
+    use crate::{
+        image_editing::bundled_brushes,
+        paint::{BrushMode, PaintStroke},
+    };
+    // black on the left, white on the right
+    let img = image::RgbaImage::from_fn(64, 64, |x, _| match x {
+        0..=31 => image::Rgba([0, 0, 0, 255]),
+        _ => image::Rgba([255, 255, 255, 255]),
+    });
+    let mut stroke = PaintStroke::new();
+    stroke.width = 0.25;
+    // the color is not painted in these modes
+    stroke.color = [1., 0., 0., 1.];
+
+    // down along the edge
+    stroke.mode = BrushMode::Blur;
+    stroke.points = vec![(0.5, 0.25), (0.5, 0.75)];
+    let mut blurred = img.clone();
+    stroke.render(&mut blurred, bundled_brushes());
+    let (dark, light) = (blurred.get_pixel(31, 32), blurred.get_pixel(32, 32));
+    assert!(dark[0] > 0 && light[0] < 255, "{dark:?} {light:?}");
+    assert_eq!(dark[0], dark[1]);
+    assert_eq!(blurred.get_pixel(31, 2), img.get_pixel(31, 2));
+
+    // from the white into the black half
+    stroke.mode = BrushMode::Smudge;
+    stroke.points = vec![(0.75, 0.5), (0.25, 0.5)];
+    let mut smudged = img.clone();
+    stroke.render(&mut smudged, bundled_brushes());
+    let dragged = smudged.get_pixel(20, 32);
+    assert!(dragged[0] > 0, "{dragged:?}");
+    assert_eq!(dragged[0], dragged[1]);
+    assert_eq!(smudged.get_pixel(20, 2), img.get_pixel(20, 2));
+    assert_eq!(smudged.get_pixel(60, 32), img.get_pixel(60, 32));
+}
+
+#[test]
+fn shift_f_does_not_toggle_fullscreen() {
+    use crate::shortcuts::{shortcut_released, InputEvent, SimultaneousKeypresses};
+    let keys = |k: &[&str]| {
+        k.iter()
+            .map(|k| k.to_string())
+            .collect::<SimultaneousKeypresses>()
+    };
+    let shortcuts = Shortcuts::default_keys();
+    let fullscreen = &shortcuts[&InputEvent::Fullscreen];
+    assert!(shortcut_released(fullscreen, &keys(&[]), &keys(&["F"])));
+    assert!(!shortcut_released(
+        fullscreen,
+        &keys(&["LShift"]),
+        &keys(&["F"])
+    ));
+    assert!(!shortcut_released(fullscreen, &keys(&[]), &keys(&["G"])));
+    // no other shortcut uses the fullscreen key
+    assert!(shortcuts
+        .iter()
+        .filter(|(command, _)| **command != InputEvent::Fullscreen)
+        .all(|(_, k)| !k.contains("F")));
+}
+
+#[test]
+fn downloads_are_cached_per_user_and_limited() {
+    use crate::download::{folder, read_limited};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    let cat = folder("https://example.com/cat.png").unwrap();
+    assert!(cat.starts_with(dirs::cache_dir().unwrap()));
+    assert_eq!(cat, folder("https://example.com/cat.png").unwrap());
+    assert_ne!(cat, folder("https://example.com/dog.png").unwrap());
+    // a sha256 of the link, so the name doesn't depend on the build
+    assert_eq!(cat.file_name().unwrap().len(), 64);
+
+    let received = AtomicU64::new(0);
+    assert_eq!(
+        read_limited(&[1_u8; 10][..], 10, &received).unwrap(),
+        vec![1; 10]
+    );
+    assert_eq!(received.load(Ordering::Relaxed), 10);
+    assert!(read_limited(&[1_u8; 11][..], 10, &received).is_err());
+}
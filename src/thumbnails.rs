@@ -0,0 +1,122 @@
+//! Thumbnails kept on disk following the freedesktop thumbnail specification, so they are shared
+//! with file managers and the gallery opens at once when a folder is visited again. A thumbnail
+//! is named after the MD5 hash of the URI of its image and is only used while the modification
+//! time stored in it matches the one of the image.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use md5::{Digest, Md5};
+
+/// The longest side of thumbnails in the "large" folder
+pub const LARGE_SIZE: u32 = 256;
+
+/// The folder the thumbnails are stored in
+fn folder() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("thumbnails").join("large"))
+}
+
+/// The `file://` URI of an absolute path, percent-encoded
+pub fn uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from("file://");
+    if !path.starts_with('/') {
+        uri.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                uri.push(b as char)
+            }
+            _ => uri.push_str(&format!("%{b:02X}")),
+        }
+    }
+    uri
+}
+
+/// The modification time of a file in seconds, as it is stored in thumbnails
+fn mtime(path: &Path) -> Option<u64> {
+    let modified = path.metadata().ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Where the thumbnail of `path` is stored, if thumbnails of it are kept. Images in archives
+/// and in the temporary folder, like downloads, are left out.
+fn thumbnail_path(path: &Path) -> Option<PathBuf> {
+    let folder = folder()?;
+    let path = path.canonicalize().ok()?;
+    if !path.is_file()
+        || path.starts_with(std::env::temp_dir())
+        || path.starts_with(folder.parent()?)
+    {
+        return None;
+    }
+    Some(folder.join(format!("{}.png", file_name(&uri(&path)))))
+}
+
+/// The stored thumbnail of `path`, if it is there and up to date
+pub fn load(path: &Path) -> Option<RgbaImage> {
+    let thumbnail = thumbnail_path(path)?;
+    let data = std::fs::read(&thumbnail).ok()?;
+    let reader = png::Decoder::new(data.as_slice()).read_info().ok()?;
+    let text = &reader.info().uncompressed_latin1_text;
+    let value = |keyword: &str| {
+        text.iter()
+            .find(|t| t.keyword == keyword)
+            .map(|t| t.text.clone())
+    };
+    let canonical = path.canonicalize().ok()?;
+    if value("Thumb::URI")? != uri(&canonical)
+        || value("Thumb::MTime")?.parse::<u64>().ok()? != mtime(path)?
+    {
+        return None;
+    }
+    Some(image::load_from_memory(&data).ok()?.to_rgba8())
+}
+
+/// Keep `thumbnail` as the thumbnail of `path`
+pub fn store(path: &Path, thumbnail: &RgbaImage) -> Result<()> {
+    let target = thumbnail_path(path).context("Thumbnails of this file are not kept")?;
+    let canonical = path.canonicalize()?;
+    let folder = target.parent().context("No thumbnail folder")?;
+    std::fs::create_dir_all(folder)?;
+
+    // written elsewhere first, so other programs never read half a thumbnail
+    let partial = target.with_extension(format!("{}.part", std::process::id()));
+    {
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(&partial)?),
+            thumbnail.width(),
+            thumbnail.height(),
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk("Thumb::URI".into(), uri(&canonical))?;
+        encoder.add_text_chunk(
+            "Thumb::MTime".into(),
+            mtime(path).context("No modification time")?.to_string(),
+        )?;
+        encoder.add_text_chunk("Software".into(), "oculante".into())?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(thumbnail.as_raw())?;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&partial, std::fs::Permissions::from_mode(0o600))?;
+    }
+    std::fs::rename(&partial, &target)?;
+    Ok(())
+}
+
+/// The file name of the thumbnail of `uri`, the hex MD5 digest of it
+pub fn file_name(uri: &str) -> String {
+    Md5::digest(uri.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+}
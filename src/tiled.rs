@@ -0,0 +1,464 @@
+//! Very large tiled TIFF files, which would not fit in memory decoded. They are shown through a
+//! pyramid of levels: the reduced images stored in pyramidal files, and smaller ones built in
+//! memory while loading. The smallest level is opened like any other image, and when zoomed in,
+//! only the tiles in view of the level matching the zoom are decoded and uploaded.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use image::{imageops, Rgba, RgbaImage};
+use log::{debug, error};
+use nalgebra::Vector2;
+use notan::draw::*;
+use notan::prelude::{BlendMode, Graphics, Texture};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use crate::appstate::ImageGeometry;
+use crate::utils::ImageExt;
+
+/// Images with more pixels than this are opened tiled. It is also the size of the largest level
+/// built in memory.
+pub const TILED_PIXELS: u64 = 64_000_000;
+/// The longest side of the smallest level, which is shown when zoomed out
+const OVERVIEW_SIZE: u32 = 4096;
+/// Levels in memory are uploaded in tiles of this size
+const MEMORY_TILE: u32 = 512;
+/// Tile textures kept for panning back
+const MAX_TEXTURES: usize = 600;
+/// With more tiles in view, only the overview is drawn
+const MAX_VISIBLE: u32 = 400;
+
+type TiffDecoder = Decoder<BufReader<File>>;
+
+#[derive(Debug)]
+enum Source {
+    /// An image of the file, by its index
+    File(usize),
+    Memory(RgbaImage),
+}
+
+#[derive(Debug)]
+struct Level {
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    source: Source,
+}
+
+impl Level {
+    fn tiles_across(&self) -> u32 {
+        (self.width + self.tile_width - 1) / self.tile_width
+    }
+
+    fn tiles_down(&self) -> u32 {
+        (self.height + self.tile_height - 1) / self.tile_height
+    }
+}
+
+/// A tiled TIFF and its levels, from the full size down to the overview
+#[derive(Debug)]
+pub struct TiledImage {
+    path: PathBuf,
+    levels: Vec<Level>,
+}
+
+fn open_decoder(path: &Path, image: usize) -> Result<TiffDecoder> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    for _ in 0..image {
+        decoder.next_image()?;
+    }
+    Ok(decoder)
+}
+
+/// The tile size of the current image, if it is tiled and its tiles can be converted on their
+/// own. Other sample formats are scaled to the range of the whole image.
+fn tile_size(decoder: &mut TiffDecoder) -> Option<(u32, u32)> {
+    let width = decoder.get_tag_u32(Tag::TileWidth).ok()?;
+    let height = decoder.get_tag_u32(Tag::TileLength).ok()?;
+    if decoder.get_tag_u32(Tag::PlanarConfiguration).unwrap_or(1) != 1 || width == 0 || height == 0
+    {
+        return None;
+    }
+    match decoder.colortype().ok()? {
+        ColorType::Gray(8 | 16)
+        | ColorType::GrayA(8 | 16)
+        | ColorType::RGB(8 | 16)
+        | ColorType::RGBA(8 | 16) => Some((width, height)),
+        _ => None,
+    }
+}
+
+fn read_tile(decoder: &mut TiffDecoder, level: &Level, x: u32, y: u32) -> Result<RgbaImage> {
+    let samples = match decoder.colortype()? {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) => 3,
+        ColorType::RGBA(_) => 4,
+        color => bail!("Unsupported color type {color:?}"),
+    };
+    let data = match decoder.read_chunk(y * level.tiles_across() + x)? {
+        DecodingResult::U8(data) => data,
+        DecodingResult::U16(data) => data.iter().map(|v| (v >> 8) as u8).collect(),
+        _ => bail!("Unsupported sample format"),
+    };
+    let width = (level.width - x * level.tile_width).min(level.tile_width);
+    let height = (level.height - y * level.tile_height).min(level.tile_height);
+    // tiles at the edge may be cut to the image, or padded to the full tile size
+    let stride = if data.len() >= (level.tile_width * level.tile_height) as usize * samples {
+        level.tile_width
+    } else {
+        width
+    };
+    Ok(RgbaImage::from_fn(width, height, |px, py| {
+        let i = (py * stride + px) as usize * samples;
+        match data.get(i..i + samples) {
+            Some([v]) => Rgba([*v, *v, *v, 255]),
+            Some([v, a]) => Rgba([*v, *v, *v, *a]),
+            Some([r, g, b]) => Rgba([*r, *g, *b, 255]),
+            Some([r, g, b, a]) => Rgba([*r, *g, *b, *a]),
+            _ => Rgba([0, 0, 0, 0]),
+        }
+    }))
+}
+
+/// All tiles of a level of the file, reduced by `factor`
+fn read_reduced(path: &Path, image: usize, level: &Level, factor: u32) -> Result<RgbaImage> {
+    let mut decoder = open_decoder(path, image)?;
+    let mut reduced = RgbaImage::new(
+        (level.width / factor).max(1),
+        (level.height / factor).max(1),
+    );
+    for y in 0..level.tiles_down() {
+        for x in 0..level.tiles_across() {
+            let mut tile = read_tile(&mut decoder, level, x, y)?;
+            if factor > 1 {
+                tile = imageops::thumbnail(
+                    &tile,
+                    (tile.width() / factor).max(1),
+                    (tile.height() / factor).max(1),
+                );
+            }
+            imageops::replace(
+                &mut reduced,
+                &tile,
+                (x * level.tile_width / factor) as i64,
+                (y * level.tile_height / factor) as i64,
+            );
+        }
+    }
+    Ok(reduced)
+}
+
+impl TiledImage {
+    /// Read the levels of a tiled TIFF. None if the image is small enough to be opened whole.
+    pub fn open(path: &Path) -> Result<Option<Self>> {
+        let mut decoder = open_decoder(path, 0)?;
+        let (width, height) = decoder.dimensions()?;
+        if width as u64 * height as u64 <= TILED_PIXELS {
+            return Ok(None);
+        }
+        let Some((tile_width, tile_height)) = tile_size(&mut decoder) else {
+            return Ok(None);
+        };
+        let color = decoder.colortype()?;
+        let mut levels = vec![Level {
+            width,
+            height,
+            tile_width,
+            tile_height,
+            source: Source::File(0),
+        }];
+
+        // the reduced images of pyramidal files follow the full one
+        let mut image = 0;
+        while decoder.more_images() && decoder.next_image().is_ok() {
+            image += 1;
+            let Ok((width, height)) = decoder.dimensions() else {
+                break;
+            };
+            let smallest = &levels[levels.len() - 1];
+            if width >= smallest.width
+                || height >= smallest.height
+                || decoder.colortype().ok() != Some(color)
+            {
+                continue;
+            }
+            if let Some((tile_width, tile_height)) = tile_size(&mut decoder) {
+                levels.push(Level {
+                    width,
+                    height,
+                    tile_width,
+                    tile_height,
+                    source: Source::File(image),
+                });
+            }
+        }
+        debug!("{} levels stored in {}", levels.len(), path.display());
+
+        // the smallest stored level is read once, as large as fits in memory
+        let smallest = levels.pop().context("No levels")?;
+        let Source::File(image) = smallest.source else {
+            bail!("The smallest level is not in the file");
+        };
+        let mut factor = 1;
+        while (smallest.width / factor) as u64 * (smallest.height / factor) as u64 > TILED_PIXELS {
+            factor *= 2;
+        }
+        let reduced = read_reduced(path, image, &smallest, factor)?;
+        if factor > 1 {
+            levels.push(smallest);
+        }
+
+        // and halved down to the overview
+        let mut images = vec![reduced];
+        while let Some(image) = images
+            .last()
+            .filter(|i| i.width().max(i.height()) > OVERVIEW_SIZE)
+        {
+            let half = imageops::thumbnail(
+                image,
+                (image.width() / 2).max(1),
+                (image.height() / 2).max(1),
+            );
+            images.push(half);
+        }
+        levels.extend(images.into_iter().map(|image| Level {
+            width: image.width(),
+            height: image.height(),
+            tile_width: MEMORY_TILE,
+            tile_height: MEMORY_TILE,
+            source: Source::Memory(image),
+        }));
+
+        Ok(Some(Self {
+            path: path.to_path_buf(),
+            levels,
+        }))
+    }
+
+    /// The size of the full image
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.levels[0].width, self.levels[0].height)
+    }
+
+    /// The smallest level, which is what gets opened
+    pub fn overview(&self) -> RgbaImage {
+        match &self.levels[self.levels.len() - 1].source {
+            Source::Memory(image) => image.clone(),
+            Source::File(_) => RgbaImage::default(),
+        }
+    }
+
+    fn tile(&self, decoders: &mut HashMap<usize, TiffDecoder>, key: TileKey) -> Result<RgbaImage> {
+        let level = &self.levels[key.level];
+        let (x, y) = (key.x * level.tile_width, key.y * level.tile_height);
+        match &level.source {
+            Source::Memory(image) => {
+                Ok(imageops::crop_imm(image, x, y, level.tile_width, level.tile_height).to_image())
+            }
+            Source::File(image) => {
+                let decoder = match decoders.entry(*image) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => e.insert(open_decoder(&self.path, *image)?),
+                };
+                read_tile(decoder, level, key.x, key.y)
+            }
+        }
+    }
+}
+
+/// The smallest of the levels, given by their widths from the largest down, with at least one
+/// pixel for each pixel on screen if `shown` pixels are shown across. The full size is used when
+/// zoomed in further.
+pub fn level_for(
+    widths: impl DoubleEndedIterator<Item = u32> + ExactSizeIterator,
+    shown: f32,
+) -> usize {
+    let count = widths.len();
+    widths
+        .rev()
+        .position(|width| width as f32 >= shown)
+        .map(|i| count - 1 - i)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct TileKey {
+    level: usize,
+    x: u32,
+    y: u32,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Tiles in view that are not decoded yet, taken from the end
+    wanted: Vec<TileKey>,
+    /// Tiles being decoded
+    taken: HashSet<TileKey>,
+}
+
+/// Draws the tiles in view of a `TiledImage`, which are decoded in the background
+pub struct TileView {
+    pub image: Arc<TiledImage>,
+    /// Uploaded tiles and the frame they were last drawn in
+    textures: HashMap<TileKey, (Texture, u64)>,
+    queue: Arc<Mutex<Queue>>,
+    /// Wakes the worker when tiles are wanted
+    wake: Sender<()>,
+    decoded: Receiver<(TileKey, RgbaImage)>,
+    frame: u64,
+}
+
+impl TileView {
+    pub fn new(image: Arc<TiledImage>) -> Self {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let (sender, decoded) = channel();
+        let (wake, woken) = channel::<()>();
+        let worker_image = image.clone();
+        let worker_queue = queue.clone();
+        std::thread::spawn(move || {
+            let mut decoders = HashMap::new();
+            // the worker stops once the view is dropped
+            while woken.recv().is_ok() {
+                // one pass empties the queue, so earlier wake ups are done with
+                for _ in woken.try_iter() {}
+                loop {
+                    let key = worker_queue.lock().ok().and_then(|mut q| {
+                        let key = q.wanted.pop()?;
+                        q.taken.insert(key);
+                        Some(key)
+                    });
+                    let Some(key) = key else {
+                        break;
+                    };
+                    let tile = worker_image.tile(&mut decoders, key).unwrap_or_else(|e| {
+                        error!("Could not read tile {key:?}: {e}");
+                        // a transparent tile, so the overview stays visible and it isn't read again
+                        RgbaImage::new(1, 1)
+                    });
+                    if sender.send((key, tile)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            image,
+            textures: HashMap::new(),
+            queue,
+            wake,
+            decoded,
+            frame: 0,
+        }
+    }
+
+    /// Draw the tiles in view over the overview, which is `shown_width` pixels wide and drawn
+    /// with `geometry` into a window of `window_size`. Returns whether tiles are still being
+    /// decoded or just arrived, so another frame is needed to show them.
+    pub fn draw(
+        &mut self,
+        gfx: &mut Graphics,
+        draw: &mut Draw,
+        shown_width: f32,
+        geometry: &ImageGeometry,
+        window_size: Vector2<f32>,
+        linear_mag_filter: bool,
+    ) -> bool {
+        let (scale, offset) = (geometry.scale, geometry.offset);
+        self.frame += 1;
+        let Ok(mut queue) = self.queue.lock() else {
+            return false;
+        };
+        let mut arrived = false;
+        for (key, tile) in self.decoded.try_iter() {
+            queue.taken.remove(&key);
+            if let Some(texture) = tile.to_texture(gfx, linear_mag_filter) {
+                self.textures.insert(key, (texture, self.frame));
+                arrived = true;
+            }
+        }
+        queue.wanted.clear();
+        let decoding = arrived || !queue.taken.is_empty();
+
+        let widths = self.image.levels.iter().map(|level| level.width);
+        let level_index = level_for(widths, shown_width * scale);
+        if level_index == self.image.levels.len() - 1 {
+            return decoding;
+        }
+        let level = &self.image.levels[level_index];
+        // overview pixels per pixel of the level
+        let k = shown_width / level.width as f32;
+        let tile_size = Vector2::new(level.tile_width as f32, level.tile_height as f32) * k;
+        let start = -offset / scale;
+        let end = (window_size - offset) / scale;
+        let x0 = (start.x / tile_size.x).floor().max(0.) as u32;
+        let y0 = (start.y / tile_size.y).floor().max(0.) as u32;
+        let x1 = ((end.x / tile_size.x).ceil().max(0.) as u32).min(level.tiles_across());
+        let y1 = ((end.y / tile_size.y).ceil().max(0.) as u32).min(level.tiles_down());
+        if x1.saturating_sub(x0) * y1.saturating_sub(y0) > MAX_VISIBLE {
+            return decoding;
+        }
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let key = TileKey {
+                    level: level_index,
+                    x,
+                    y,
+                };
+                match self.textures.get_mut(&key) {
+                    Some((texture, used)) => {
+                        *used = self.frame;
+                        draw.image(texture)
+                            .position(x as f32 * tile_size.x, y as f32 * tile_size.y)
+                            .size(texture.width() * k, texture.height() * k)
+                            .blend_mode(BlendMode::NORMAL)
+                            .scale(scale, scale)
+                            .translate(offset.x, offset.y);
+                    }
+                    None if !queue.taken.contains(&key) => queue.wanted.push(key),
+                    None => (),
+                }
+            }
+        }
+        // the center of the view is decoded first
+        let center = (start + end) / 2.;
+        let distance = |key: &TileKey| {
+            let tile_center = Vector2::new(
+                (key.x as f32 + 0.5) * tile_size.x,
+                (key.y as f32 + 0.5) * tile_size.y,
+            );
+            (tile_center - center).norm()
+        };
+        queue
+            .wanted
+            .sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+        let wanted = !queue.wanted.is_empty();
+        drop(queue);
+        if wanted {
+            _ = self.wake.send(());
+        }
+
+        if self.textures.len() > MAX_TEXTURES {
+            let mut used = self
+                .textures
+                .iter()
+                .map(|(key, (_, used))| (*used, *key))
+                .collect::<Vec<_>>();
+            used.sort_by_key(|(used, _)| *used);
+            for (_, key) in &used[..self.textures.len() - MAX_TEXTURES] {
+                self.textures.remove(key);
+            }
+        }
+        decoding || wanted
+    }
+}
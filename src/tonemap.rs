@@ -0,0 +1,125 @@
+//! Tone mapping of high dynamic range images for display. It only changes how the image is drawn,
+//! the pixels and edits keep their full range.
+
+use image::{EncodableLayout, Rgba32FImage};
+use notan::draw::create_image_pipeline;
+use notan::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMap {
+    /// Cut off everything brighter than white
+    Clamp,
+    Reinhard,
+    /// The filmic curve of the Academy Color Encoding System, as fitted by Krzysztof Narkowicz
+    Aces,
+    /// John Hable's curve from Uncharted 2
+    Filmic,
+}
+
+impl ToneMap {
+    pub const ALL: [Self; 4] = [Self::Clamp, Self::Reinhard, Self::Aces, Self::Filmic];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Clamp => "Linear clamp",
+            Self::Reinhard => "Reinhard",
+            Self::Aces => "ACES",
+            Self::Filmic => "Filmic",
+        }
+    }
+
+    /// Keep in sync with `FRAG`
+    fn code(&self) -> f32 {
+        match self {
+            Self::Clamp => 0.,
+            Self::Reinhard => 1.,
+            Self::Aces => 2.,
+            Self::Filmic => 3.,
+        }
+    }
+}
+
+// The texture holds gamma encoded values, like the other HDR buffers
+const FRAG: ShaderSource = notan::fragment_shader! {
+    r#"
+    #version 450
+    precision highp float;
+
+    layout(location = 0) in vec2 v_uvs;
+    layout(location = 1) in vec4 v_color;
+
+    layout(binding = 0) uniform sampler2D u_texture;
+    layout(set = 0, binding = 1) uniform ToneMap {
+        vec4 u_params;
+    };
+
+    layout(location = 0) out vec4 color;
+
+    vec3 hable(vec3 x) {
+        return ((x * (0.15 * x + 0.05) + 0.004) / (x * (0.15 * x + 0.5) + 0.06)) - 0.02 / 0.3;
+    }
+
+    void main() {
+        vec4 p = texture(u_texture, v_uvs);
+        vec3 v = pow(max(p.rgb, 0.0), vec3(2.2)) * u_params.y;
+        int mode = int(u_params.x);
+        if (mode == 1) {
+            v = v / (1.0 + v);
+        } else if (mode == 2) {
+            v = (v * (2.51 * v + 0.03)) / (v * (2.43 * v + 0.59) + 0.14);
+        } else if (mode == 3) {
+            v = hable(v * 2.0) / hable(vec3(11.2));
+        }
+        v = clamp(v, 0.0, 1.0);
+        color = vec4(pow(v, vec3(1.0 / 2.2)), p.a) * v_color;
+    }
+    "#
+};
+
+/// The draw pipeline that applies a `ToneMap`
+pub struct ToneMapper {
+    pub pipeline: Pipeline,
+    pub uniforms: Buffer,
+}
+
+impl ToneMapper {
+    pub fn new(gfx: &mut Graphics) -> Result<Self, String> {
+        let pipeline = create_image_pipeline(gfx, Some(&FRAG))?;
+        let uniforms = gfx
+            .create_uniform_buffer(1, "ToneMap")
+            .with_data(&[0_f32; 4])
+            .build()?;
+        Ok(Self { pipeline, uniforms })
+    }
+
+    /// Use `tone_map` and `exposure`, in stops, for the next draws
+    pub fn set(&self, gfx: &mut Graphics, tone_map: ToneMap, exposure: f32) {
+        gfx.set_buffer_data(
+            &self.uniforms,
+            &[tone_map.code(), 2_f32.powf(exposure), 0., 0.],
+        );
+    }
+}
+
+/// Upload the full range pixels of an image, to draw them with a `ToneMapper`
+pub fn hdr_texture(
+    gfx: &mut Graphics,
+    img: &Rgba32FImage,
+    linear_mag_filter: bool,
+) -> Option<Texture> {
+    gfx.create_texture()
+        .from_bytes(img.as_raw().as_bytes(), img.width(), img.height())
+        .with_format(TextureFormat::Rgba32Float)
+        .with_filter(
+            TextureFilter::Linear,
+            if linear_mag_filter {
+                TextureFilter::Linear
+            } else {
+                TextureFilter::Nearest
+            },
+        )
+        .build()
+        .map_err(|e| log::error!("Can't create HDR texture: {e}"))
+        .ok()
+}
@@ -1,40 +1,76 @@
 use crate::{
+    annotation::{self, Annotation, AnnotationKind},
     appstate::{ImageGeometry, Message, OculanteState},
-    clipboard_to_image,
-    image_editing::{process_pixels, Channel, GradientStop, ImageOperation, ScaleFilter},
-    paint::PaintStroke,
+    background::BackgroundPreset,
+    compare::CompareMode,
+    convert::{ConvertJob, FileStatus},
+    dicom::{DicomImage, Lut, DICOM_EXTENSIONS},
+    fits::{FitsImage, Stretch, FITS_EXTENSIONS},
+    folders::{self, FolderTree},
+    gallery::THUMBNAIL_SIZE,
+    gpu::{self, GpuEditor, ShaderInputs, ShaderState},
+    histogram,
+    image_editing::{
+        brush_name, bundled_brushes, import_brush, move_operator, process_pixel_stack,
+        process_pixel_stack_f32, quantize, save_animation, save_sidecar, shader_error_id,
+        sidecar_path, BatchJob, Channel, EditPreset, GradientStop, ImageOperation, ImgOpItem,
+        NoiseDistribution, ResizeMode, ScaleFilter, PROXY_SIZE,
+    },
+    image_loader::{icon_sizes, mip_chain, texture_mips, ICON_EXTENSIONS, TEXTURE_EXTENSIONS},
+    inspector,
+    layers::{Layer, LayerSource},
+    metadata::{MapPreview, Tag, MAP_ZOOM},
+    paint::{Brush, BrushMode, PaintStroke, PaintTool},
+    plugins,
+    psd_layers::{self, PsdTree},
+    redaction::{Redaction, RedactionStyle},
+    scopes::{self, Scope},
+    scripting,
+    scrubber::SortOrder,
+    selection::{self, SelectionTool},
     set_zoom,
     settings::{set_system_theme, ColorTheme},
-    shortcuts::{key_pressed, keypresses_as_string, lookup},
+    shortcuts::{key_pressed, keypresses_as_string, lookup, InputEvent},
     utils::{
-        clipboard_copy, disp_col, disp_col_norm, fix_exif, highlight_bleed, highlight_semitrans,
-        load_image_from_path, next_image, prev_image, send_extended_info, set_title, solo_channel,
-        toggle_fullscreen, unpremult, ColorChannel, ImageExt,
+        clipboard_copy, disp_col, disp_col_norm, exif_orientation, fix_exif, fuzzy_match,
+        highlight_bleed, highlight_semitrans, load_image_from_path, next_image, paste, prev_image,
+        send_extended_info, set_rating, set_title, solo_channel, toggle_favourite,
+        toggle_favourites_only, toggle_fullscreen, toggle_gallery, toggle_label, unpremult,
+        watch_folder, ColorChannel, ImageExt, Playback,
     },
+    xmp::ColorLabel,
     FrameSource,
 };
 #[cfg(not(feature = "file_open"))]
-use crate::{filebrowser, SUPPORTED_EXTENSIONS};
+use crate::{filebrowser, supported_extensions};
 
 const ICON_SIZE: f32 = 24.;
+/// How often shaders linked to a file are read again
+const SHADER_CHECK_INTERVAL: Duration = Duration::from_millis(800);
 
 use egui_phosphor::regular::*;
-use egui_plot::{Plot, PlotPoints, Points};
+use egui_plot::{Line, Plot, PlotPoints, Points};
 use image::RgbaImage;
 use log::{debug, error, info};
 #[cfg(not(any(target_os = "netbsd", target_os = "freebsd")))]
 use mouse_position::mouse_position::Mouse;
 use notan::{
     egui::{self, *},
-    prelude::{App, Graphics},
+    prelude::{App, Graphics, Texture},
+};
+use std::{
+    collections::BTreeSet,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
-use std::{collections::BTreeSet, ops::RangeInclusive, path::PathBuf, time::Instant};
 use strum::IntoEnumIterator;
 const PANEL_WIDTH: f32 = 240.0;
 const PANEL_WIDGET_OFFSET: f32 = 10.0;
 
 #[cfg(feature = "turbo")]
-use crate::image_editing::{cropped_range, lossless_tx};
+use crate::image_editing::{lossless_apply, lossless_tx};
+use crate::tonemap::ToneMap;
 pub trait EguiExt {
     fn label_i(&mut self, _text: &str) -> Response {
         unimplemented!()
@@ -221,6 +257,43 @@ pub fn image_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
     // .rect;
 }
 
+/// Checkboxes for the groups and layers in `parent`, the top one first. Returns true if one
+/// was toggled.
+fn psd_tree_ui(ui: &mut Ui, tree: &mut PsdTree, parent: Option<u32>, depth: usize) -> bool {
+    let mut changed = false;
+    let groups = (0..tree.groups.len())
+        .filter(|i| tree.groups[*i].parent == parent)
+        .collect::<Vec<_>>();
+    for i in groups.into_iter().rev() {
+        let id = tree.groups[i].id;
+        egui::collapsing_header::CollapsingState::load_with_default_open(
+            ui.ctx(),
+            ui.make_persistent_id(("psd group", id)),
+            false,
+        )
+        .show_header(ui, |ui| {
+            let group = &mut tree.groups[i];
+            changed |= ui
+                .checkbox(&mut group.visible, format!("{FOLDER} {}", group.name))
+                .changed();
+        })
+        .body(|ui| {
+            // a broken file could nest groups in themselves
+            if depth < 32 {
+                changed |= psd_tree_ui(ui, tree, Some(id), depth + 1);
+            }
+        });
+    }
+    let layers = (0..tree.layers.len())
+        .filter(|i| tree.layers[*i].parent == parent)
+        .collect::<Vec<_>>();
+    for i in layers.into_iter().rev() {
+        let layer = &mut tree.layers[i];
+        changed |= ui.checkbox(&mut layer.visible, &layer.name).changed();
+    }
+    changed
+}
+
 pub fn info_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
     if let Some(img) = &state.current_image {
         let mut img = img;
@@ -273,6 +346,27 @@ pub fn info_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
                     );
                     ui.end_row();
 
+                    // the size above is the overview
+                    if let Some(tiles) = &state.tiles {
+                        let (width, height) = tiles.image.dimensions();
+                        ui.label_i(&format!("{SQUARES_FOUR} Full size"));
+                        ui.label(RichText::new(format!("{width}x{height}")).monospace());
+                        ui.end_row();
+                    }
+
+                    if state.is_animation {
+                        let playback = state.player.playback.lock().map(|p| p.clone()).unwrap_or_default();
+                        ui.label_i(&format!("{FILM_STRIP} Frame"));
+                        ui.label(
+                            RichText::new(format!("{} / {}", playback.frame + 1, playback.frame_count))
+                                .monospace(),
+                        );
+                        ui.end_row();
+
+                        ui.label_i(&format!("{TIMER} Delay"));
+                        ui.label(RichText::new(format!("{} ms", playback.delay)).monospace());
+                        ui.end_row();
+                    }
 
                     if let Some(path) = &state.current_path {
                         // make sure we truncate filenames
@@ -388,12 +482,338 @@ pub fn info_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
                     }
                     if ui.button("Clear").clicked() {
                         state.compare_list.clear();
+                        state.compare_blend = Default::default();
+                    }
+
+                    let file_name = |path: &Path| path.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_default();
+                    let mut others = state.compare_list.keys().filter(|path| *path != p).cloned().collect::<Vec<_>>();
+                    others.sort();
+                    ui.horizontal(|ui| {
+                        for mode in CompareMode::iter() {
+                            if ui.selectable_value(&mut state.compare_blend.mode, mode, mode.to_string()).changed() {
+                                state.compare_blend.invalidate();
+                                if state.compare_blend.reference.is_none() {
+                                    if let Some(other) = others.first() {
+                                        state.compare_blend.set_reference(other);
+                                    }
+                                }
+                            }
+                        }
+                    }).response.on_hover_text(format!(
+                        "Switch between the images with {}, or blend the current one with another",
+                        lookup(&state.persistent_settings.shortcuts, &InputEvent::CompareNext)
+                    ));
+
+                    if state.compare_blend.mode != CompareMode::Toggle {
+                        let selected = state.compare_blend.reference.as_deref().map(file_name).unwrap_or_default();
+                        egui::ComboBox::from_label("Blend with")
+                            .selected_text(selected)
+                            .show_ui(ui, |ui| {
+                                for other in &others {
+                                    let is_reference = state.compare_blend.reference.as_ref() == Some(other);
+                                    if ui.selectable_label(is_reference, file_name(other)).clicked() {
+                                        state.compare_blend.set_reference(other);
+                                    }
+                                }
+                            });
+                    }
+                    if state.compare_blend.mode == CompareMode::Difference {
+                        if ui
+                            .add(egui::Slider::new(&mut state.compare_blend.amplify, 1.0..=100.0).logarithmic(true).text("Amplify"))
+                            .on_hover_text("Multiply differences, so small ones become visible")
+                            .changed()
+                        {
+                            state.compare_blend.invalidate();
+                        }
                     }
                 }
 
             });
             });
 
+            let texture_path = state.current_path.clone().filter(|p| {
+                TEXTURE_EXTENSIONS.contains(&p.extension().unwrap_or_default().to_string_lossy().to_lowercase().as_str())
+            });
+            if let Some(path) = texture_path {
+                ui.collapsing("Mip levels", |ui| {
+                    if state.texture_mips.is_empty() {
+                        if ui.button("Load mip levels").clicked() {
+                            match texture_mips(&path) {
+                                Ok(mips) => state.texture_mips = mips,
+                                Err(e) => state.send_message_err(&format!("Can't read mip levels: {e}")),
+                            }
+                        }
+                        return;
+                    }
+                    let mut changed = false;
+                    ui.add_enabled_ui(!state.persistent_settings.edit_enabled, |ui| {
+                        let last = state.texture_mips.len() - 1;
+                        ui.add_enabled_ui(!state.show_mip_chain, |ui| {
+                            changed |= ui.add(egui::Slider::new(&mut state.mip_level, 0..=last).text("Level")).changed();
+                        });
+                        if let Some(mip) = state.texture_mips.get(state.mip_level) {
+                            ui.label(format!("{} x {} pixels", mip.width(), mip.height()));
+                        }
+                        changed |= ui
+                            .checkbox(&mut state.show_mip_chain, "Show all levels")
+                            .on_hover_text("Every mip level side by side, each at its own size")
+                            .changed();
+                    });
+                    if !changed {
+                        return;
+                    }
+                    let base = &state.texture_mips[0];
+                    let buf = if state.show_mip_chain {
+                        mip_chain(&state.texture_mips)
+                    } else {
+                        // stretched like on a surface, so levels can be compared in place
+                        image::imageops::resize(
+                            &state.texture_mips[state.mip_level],
+                            base.width(),
+                            base.height(),
+                            image::imageops::FilterType::Nearest,
+                        )
+                    };
+                    _ = state
+                        .texture_channel
+                        .0
+                        .send(crate::utils::Frame::new(buf, 0, FrameSource::Rerender(path.clone())));
+                });
+            }
+
+            let icon_path = state.current_path.clone().filter(|p| {
+                ICON_EXTENSIONS.contains(&p.extension().unwrap_or_default().to_string_lossy().to_lowercase().as_str())
+            });
+            if let Some(path) = icon_path {
+                ui.collapsing("Icon sizes", |ui| {
+                    if state.icon_sizes.is_empty() {
+                        if ui.button("Load icon sizes").clicked() {
+                            match icon_sizes(&path) {
+                                Ok(sizes) => state.icon_sizes = sizes,
+                                Err(e) => state.send_message_err(&format!("Can't read icon sizes: {e}")),
+                            }
+                        }
+                        return;
+                    }
+                    let mut selected = None;
+                    ui.add_enabled_ui(!state.persistent_settings.edit_enabled, |ui| {
+                        for (i, icon) in state.icon_sizes.iter().enumerate() {
+                            if ui.selectable_label(state.icon_size == i, format!("{} x {}", icon.width(), icon.height())).clicked() {
+                                selected = Some(i);
+                            }
+                        }
+                    });
+                    let Some(i) = selected.filter(|i| *i != state.icon_size) else {
+                        return;
+                    };
+                    state.icon_size = i;
+                    let buf = state.icon_sizes[i].clone();
+                    // keep the size on screen, so sizes can be compared in place
+                    if buf.width() > 0 {
+                        state.image_geometry.scale *= state.image_geometry.dimensions.0 as f32 / buf.width() as f32;
+                    }
+                    _ = state
+                        .texture_channel
+                        .0
+                        .send(crate::utils::Frame::new(buf, 0, FrameSource::Rerender(path.clone())));
+                });
+            }
+
+            let psd_path = state.current_path.clone().filter(|p| {
+                p.extension().unwrap_or_default().to_string_lossy().to_lowercase() == "psd"
+            });
+            if let Some(path) = psd_path {
+                ui.collapsing("Layers", |ui| {
+                    if state.psd_tree.is_none() {
+                        if ui.button("Load layers").clicked() {
+                            match PsdTree::read(&path) {
+                                Ok(tree) => state.psd_tree = Some(tree),
+                                Err(e) => state.send_message_err(&format!("Can't read layers: {e}")),
+                            }
+                        }
+                        return;
+                    }
+                    let Some(tree) = &mut state.psd_tree else {
+                        return;
+                    };
+                    let changed = ui
+                        .add_enabled_ui(!state.persistent_settings.edit_enabled, |ui| psd_tree_ui(ui, tree, None, 0))
+                        .inner;
+                    if !changed {
+                        return;
+                    }
+                    let shown = tree.shown_layers();
+                    let texture_sender = state.texture_channel.0.clone();
+                    let message_sender = state.message_channel.0.clone();
+                    std::thread::spawn(move || match psd_layers::composite(&path, &shown) {
+                        Ok(buf) => {
+                            _ = texture_sender.send(crate::utils::Frame::new(buf, 0, FrameSource::Rerender(path)));
+                        }
+                        Err(e) => {
+                            _ = message_sender.send(Message::err(&format!("Can't composite layers: {e}")));
+                        }
+                    });
+                });
+            }
+
+            let dicom_path = state.current_path.clone().filter(|p| {
+                DICOM_EXTENSIONS.contains(&p.extension().unwrap_or_default().to_string_lossy().to_lowercase().as_str())
+            });
+            if let Some(path) = dicom_path {
+                ui.collapsing("Window / level", |ui| {
+                    if state.dicom.is_none() {
+                        if ui.button("Adjust window").clicked() {
+                            let dicom = std::fs::read(&path)
+                                .map_err(anyhow::Error::from)
+                                .and_then(|data| DicomImage::read(&data));
+                            match dicom {
+                                Ok(dicom) => {
+                                    let window = dicom.default_window();
+                                    state.dicom = Some((dicom, window));
+                                }
+                                Err(e) => state.send_message_err(&format!("Can't read image: {e}")),
+                            }
+                        }
+                        return;
+                    }
+                    let Some((dicom, window)) = &mut state.dicom else {
+                        return;
+                    };
+                    let (min, max) = dicom.range();
+                    let mut changed = false;
+                    egui::Grid::new("dicom window").show(ui, |ui| {
+                        ui.label("Level").on_hover_text("The value in the middle of the window");
+                        changed |= ui.add(egui::Slider::new(&mut window.center, min..=max)).changed();
+                        ui.end_row();
+
+                        ui.label("Window").on_hover_text("The range of values from black to white");
+                        changed |= ui.add(egui::Slider::new(&mut window.width, 1.0..=(max - min).max(1.))).changed();
+                        ui.end_row();
+
+                        ui.label("Colors");
+                        egui::ComboBox::from_id_source("dicom lut")
+                            .selected_text(window.lut.name())
+                            .show_ui(ui, |ui| {
+                                for lut in Lut::ALL {
+                                    changed |= ui.selectable_value(&mut window.lut, lut, lut.name()).changed();
+                                }
+                            });
+                        ui.end_row();
+                    });
+                    if ui.button("Reset").clicked() {
+                        *window = dicom.default_window();
+                        changed = true;
+                    }
+                    if changed {
+                        let mut frame = crate::utils::Frame::new_still(dicom.render(window));
+                        frame.source = FrameSource::Rerender(path.clone());
+                        _ = state.texture_channel.0.send(frame);
+                    }
+                });
+            }
+
+            let fits_path = state.current_path.clone().filter(|p| {
+                FITS_EXTENSIONS.contains(&p.extension().unwrap_or_default().to_string_lossy().to_lowercase().as_str())
+            });
+            if let Some(path) = fits_path {
+                ui.collapsing("Stretch", |ui| {
+                    let settings = &mut state.fits_stretch;
+                    let mut changed = false;
+                    egui::Grid::new("fits stretch").show(ui, |ui| {
+                        ui.label("Stretch");
+                        egui::ComboBox::from_id_source("stretch")
+                            .selected_text(settings.stretch.name())
+                            .show_ui(ui, |ui| {
+                                for stretch in Stretch::ALL {
+                                    changed |= ui.selectable_value(&mut settings.stretch, stretch, stretch.name()).changed();
+                                }
+                            });
+                        ui.end_row();
+
+                        // only render again once a slider is let go
+                        ui.label("Black point");
+                        let r = ui.add(egui::Slider::new(&mut settings.black, 0.0..=1.0));
+                        changed |= r.drag_released() || (r.changed() && !r.dragged());
+                        ui.end_row();
+
+                        ui.label("White point");
+                        let r = ui.add(egui::Slider::new(&mut settings.white, 0.0..=1.0));
+                        changed |= r.drag_released() || (r.changed() && !r.dragged());
+                        ui.end_row();
+                    });
+                    if ui.button("Reset").clicked() {
+                        *settings = Default::default();
+                        changed = true;
+                    }
+                    if !changed {
+                        return;
+                    }
+                    let settings = *settings;
+                    let texture_sender = state.texture_channel.0.clone();
+                    let message_sender = state.message_channel.0.clone();
+                    std::thread::spawn(move || {
+                        let fits = std::fs::read(&path)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|data| FitsImage::read(&data));
+                        match fits {
+                            Ok(fits) => {
+                                let mut frame = fits.frame(&settings);
+                                frame.source = FrameSource::Rerender(path);
+                                _ = texture_sender.send(frame);
+                            }
+                            Err(e) => {
+                                _ = message_sender.send(Message::err(&format!("Can't stretch image: {e}")));
+                            }
+                        }
+                    });
+                });
+            }
+
+            let texture_sender = state.texture_channel.0.clone();
+            let message_sender = state.message_channel.0.clone();
+            if let Some(video) = &mut state.video {
+                ui.collapsing("Video", |ui| {
+                    // only decode again once the slider is let go
+                    let last = video.frame_count.saturating_sub(1);
+                    let r = ui.add(egui::Slider::new(&mut video.frame, 0..=last).text("Frame"));
+                    if r.drag_released() || (r.changed() && !r.dragged()) {
+                        video.seek(video.frame, &texture_sender, &message_sender);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button(CARET_LEFT).on_hover_text("Previous frame").clicked() {
+                            video.step(-1, &texture_sender, &message_sender);
+                        }
+                        if ui.button(CARET_RIGHT).on_hover_text("Next frame").clicked() {
+                            video.step(1, &texture_sender, &message_sender);
+                        }
+                        ui.label(format!("{:.3} s at {:.2} fps", video.time(), video.rate));
+                    });
+                });
+            }
+
+            if state.current_hdr_image.is_some() {
+                ui.collapsing("HDR display", |ui| {
+                    egui::Grid::new("tone mapping").show(ui, |ui| {
+                        ui.label("Tone mapping").on_hover_text("How brightness beyond white is fitted to the display. This does not change the image.");
+                        egui::ComboBox::from_id_source("tone map")
+                            .selected_text(state.persistent_settings.tone_map.name())
+                            .show_ui(ui, |ui| {
+                                for tone_map in ToneMap::ALL {
+                                    ui.selectable_value(&mut state.persistent_settings.tone_map, tone_map, tone_map.name());
+                                }
+                            });
+                        ui.end_row();
+
+                        ui.label("Exposure");
+                        ui.add(egui::Slider::new(&mut state.persistent_settings.exposure, -8.0..=8.0).suffix(" EV"));
+                        ui.end_row();
+                    });
+                    if state.persistent_settings.edit_enabled {
+                        ui.label("Edits are shown without tone mapping");
+                    }
+                });
+            }
+
             ui.collapsing("Alpha tools", |ui| {
                 ui.vertical_centered_justified(|ui| {
                     if let Some(img) = &state.current_image {
@@ -426,7 +846,7 @@ pub fn info_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
                 ui.label("Tiling");
                 ui.slider_styled(&mut state.tiling, 1..=10);
             });
-            advanced_ui(ui, state);
+            advanced_ui(ui, state, gfx);
 
         });
 
@@ -498,7 +918,22 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx:
                     });
 
                     ui.horizontal(|ui| {
-                        ui.color_edit_button_srgb(&mut state.persistent_settings.background_color);
+                        let preset = &mut state.persistent_settings.background_preset;
+                        egui::ComboBox::from_id_source("background_preset")
+                            .selected_text(preset.to_string())
+                            .show_ui(ui, |ui| {
+                                for p in BackgroundPreset::iter() {
+                                    ui.selectable_value(preset, p, p.to_string());
+                                }
+                            })
+                            .response
+                            .on_hover_text(format!(
+                                "Switch between them with {}",
+                                lookup(&state.persistent_settings.shortcuts, &InputEvent::CycleBackground)
+                            ));
+                        if state.persistent_settings.background_preset == BackgroundPreset::Custom {
+                            ui.color_edit_button_srgb(&mut state.persistent_settings.background_color);
+                        }
                         ui.label("Background color");
                     });
 
@@ -538,8 +973,54 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx:
                     state.player.cache.cache_size = state.persistent_settings.max_cache;
                     state.player.cache.clear();
                 }
+                    if ui
+                        .checkbox(&mut state.persistent_settings.prefetch, "Prefetch neighbours")
+                        .on_hover_text("Decode the next and previous image of the folder in the background, so they show at once")
+                        .changed()
+                        && !state.persistent_settings.prefetch
+                    {
+                        state.player.prefetch(&[]);
+                    }
                 });
 
+                ui.end_row();
+                let listing = state.persistent_settings.listing.clone();
+                ui.horizontal(|ui| {
+                    let listing = &mut state.persistent_settings.listing;
+                    egui::ComboBox::from_label("Image order")
+                        .selected_text(listing.sort.to_string())
+                        .show_ui(ui, |ui| {
+                            for sort in SortOrder::iter() {
+                                ui.selectable_value(&mut listing.sort, sort, sort.to_string());
+                            }
+                        });
+                    ui.checkbox(&mut listing.descending, "Reverse");
+                    ui.checkbox(&mut listing.recursive, "Include subfolders")
+                        .on_hover_text("Go through the images of subfolders too, one folder after the other");
+                    egui::ComboBox::from_label("Rated")
+                        .selected_text(match listing.min_rating {
+                            0 => "Any".to_string(),
+                            r => format!("{r}+ {STAR}"),
+                        })
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut listing.min_rating, 0, "Any");
+                            for r in 1..=5 {
+                                ui.selectable_value(&mut listing.min_rating, r, format!("{r}+ {STAR}"));
+                            }
+                        })
+                        .response
+                        .on_hover_text("Only go through images with at least this many stars in their XMP sidecar");
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Only show");
+                    ui.add(egui::TextEdit::singleline(&mut state.persistent_settings.listing.filter).desired_width(120.))
+                        .on_hover_text("File name patterns like *.jpg or IMG_*, or extensions like cr2, separated by spaces. Every image is shown if this is empty.");
+                });
+                if listing != state.persistent_settings.listing {
+                    if let Some(p) = &state.current_path {
+                        state.scrubber.open(p, &state.persistent_settings.listing);
+                    }
+                }
                 ui.end_row();
                 ui
                     .checkbox(&mut state.persistent_settings.keep_view, "Do not reset image view")
@@ -553,11 +1034,44 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx:
                         "When a new image is loaded, keep current edits",
                     );
                 ui.end_row();
+                ui
+                    .checkbox(&mut state.persistent_settings.auto_reload, "Reload changed images")
+                    .on_hover_text(
+                        "Load the image again when it changes on disk, keeping zoom and position. Useful as a live preview of renders and exports.",
+                    );
+                ui.add_enabled(
+                    state.persistent_settings.auto_reload,
+                    egui::Checkbox::new(&mut state.persistent_settings.reload_keeps_edits, "Keep edits on reload"),
+                )
+                .on_hover_text("Apply the current edits to the changed image");
+                ui.end_row();
+                ui
+                    .checkbox(&mut state.persistent_settings.auto_sidecar, "Auto-save edits")
+                    .on_hover_text(
+                        "Save edits to a .oculante file next to the image whenever they change, and restore them when the image is opened again",
+                    );
+                ui.end_row();
+                ui
+                    .checkbox(&mut state.persistent_settings.gpu_edits, "Preview edits on GPU")
+                    .on_hover_text(
+                        "Render supported color operators on the GPU while adjusting them. The final image is always computed on the CPU.",
+                    );
+                ui.end_row();
                 ui
                     .checkbox(&mut state.persistent_settings.show_checker_background, "Show checker background where transparent")
                     .on_hover_text(
-                        "Show checker pattern as backdrop.",
+                        "Show checker pattern as backdrop. Without it, transparent pixels show the background color.",
                     );
+                ui.horizontal(|ui| {
+                    let checker = &mut state.persistent_settings.checker;
+                    let size = ui.add(egui::DragValue::new(&mut checker.size).clamp_range(1..=128).prefix("Checker size: "));
+                    let first = ui.color_edit_button_srgb(&mut checker.colors[0]);
+                    let second = ui.color_edit_button_srgb(&mut checker.colors[1]);
+                    if size.changed() || first.changed() || second.changed() {
+                        state.checker_texture = None;
+                    }
+                });
+                ui.end_row();
 
                 ui
                     .checkbox(&mut state.persistent_settings.show_frame, "Draw frame around image")
@@ -565,6 +1079,19 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx:
                         "Draw a small frame around the image. It is centered on the outmost pixel. This can be helpful on images with lots of transparency.",
                     );
                     ui.end_row();
+                let grid = &mut state.persistent_settings.pixel_grid;
+                ui.checkbox(&mut grid.enabled, "Pixel grid")
+                    .on_hover_text("Draw lines between pixels when zoomed in, to count them");
+                ui.horizontal(|ui| {
+                    ui.add_enabled_ui(grid.enabled, |ui| {
+                        ui.add(egui::DragValue::new(&mut grid.min_zoom).clamp_range(2.0..=64.0).prefix("From zoom: ").suffix("x"))
+                            .on_hover_text("Only draw the grid when pixels are at least this large");
+                        ui.add(egui::DragValue::new(&mut grid.major_every).clamp_range(0..=1024).prefix("Strong line every: "))
+                            .on_hover_text("Draw a stronger line every this many pixels. 0 to turn them off.");
+                        ui.color_edit_button_srgba_unmultiplied(&mut grid.color);
+                    });
+                });
+                ui.end_row();
                 if ui.checkbox(&mut state.persistent_settings.zen_mode, "Turn on Zen mode").on_hover_text("Zen mode hides all UI and fits the image to the frame.").changed(){
                     set_title(app, state);
                 }
@@ -615,6 +1142,10 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx:
                     }
                 });
 
+                egui::CollapsingHeader::new("Export sizes").show(ui, |ui| {
+                    state.persistent_settings.export.presets_ui(ui);
+                });
+
                 if ui.link("Visit github repo").on_hover_text("Check out the source code, request a feature, submit a bug or leave a star if you like it!").clicked() {
                     _ = webbrowser::open("https://github.com/woelper/oculante");
                 }
@@ -642,7 +1173,86 @@ pub fn settings_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx:
     state.settings_enabled = settings_enabled;
 }
 
-pub fn advanced_ui(ui: &mut Ui, state: &mut OculanteState) {
+/// Convert all images of the current folder to another format
+pub fn convert_ui(ctx: &Context, state: &mut OculanteState) {
+    let mut convert_enabled = state.convert_enabled;
+    egui::Window::new("Convert folder")
+        .collapsible(false)
+        .open(&mut convert_enabled)
+        .resizable(true)
+        .default_width(400.)
+        .show(ctx, |ui| {
+            state.persistent_settings.export.ui(ui);
+            ui.separator();
+
+            let Some(job) = &state.convert_job else {
+                let folder = state
+                    .current_path
+                    .as_ref()
+                    .and_then(|p| p.parent())
+                    .map(|p| p.to_path_buf());
+                ui.add_enabled_ui(folder.is_some() && !state.scrubber.entries.is_empty(), |ui| {
+                    if ui
+                        .button(format!("{FOLDERS} Convert {} images", state.scrubber.entries.len()))
+                        .on_hover_text("Convert all images in this folder to the default format and save them to a 'converted' subfolder")
+                        .clicked()
+                    {
+                        if let Some(folder) = folder {
+                            state.convert_job = Some(ConvertJob::start(
+                                state.scrubber.entries.clone(),
+                                folder.join("converted"),
+                                state.persistent_settings.export.clone(),
+                                state.message_channel.0.clone(),
+                            ));
+                        }
+                    }
+                });
+                return;
+            };
+
+            let mut close = false;
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::ProgressBar::new(job.progress())
+                        .desired_width(ui.available_width() - 70.)
+                        .show_percentage(),
+                );
+                if job.is_finished() {
+                    close = ui.button("Close").clicked();
+                } else if ui.button("Cancel").clicked() {
+                    job.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+            });
+            let status = job.status.lock().map(|s| s.clone()).unwrap_or_default();
+            egui::ScrollArea::vertical().max_height(300.).show(ui, |ui| {
+                egui::Grid::new("convert status").show(ui, |ui| {
+                    for (file, status) in job.files.iter().zip(status) {
+                        ui.label(file.file_name().unwrap_or_default().to_string_lossy());
+                        match status {
+                            FileStatus::Waiting => ui.label("..."),
+                            FileStatus::Done(dest) => ui
+                                .label(format!("{CHECK}"))
+                                .on_hover_text(dest.display().to_string()),
+                            FileStatus::Failed(e) => ui
+                                .colored_label(Color32::RED, format!("{WARNING}"))
+                                .on_hover_text(e),
+                        };
+                        ui.end_row();
+                    }
+                });
+            });
+            if !job.is_finished() {
+                // keep the progress moving
+                ui.ctx().request_repaint();
+            }
+            if close {
+                state.convert_job = None;
+            }
+        });
+    state.convert_enabled = convert_enabled;
+}
+
+pub fn advanced_ui(ui: &mut Ui, state: &mut OculanteState, gfx: &mut Graphics) {
     if let Some(info) = &state.image_info {
         egui::Grid::new("extended").show(ui, |ui| {
             ui.label("Number of colors");
@@ -660,18 +1270,130 @@ pub fn advanced_ui(ui: &mut Ui, state: &mut OculanteState) {
             ui.end_row();
         });
 
-        if !info.exif.is_empty() {
-            ui.collapsing("EXIF", |ui| {
-                egui::ScrollArea::new([true, false]).show(ui, |ui| {
-                    egui::Grid::new("extended_exif")
-                        .striped(true)
-                        .show(ui, |ui| {
-                            for (key, val) in &info.exif {
-                                ui.label(key);
-                                ui.label(val);
-                                ui.end_row();
+        if let Some(gps) = info.metadata.gps {
+            let preview = &mut state.map_preview;
+            let map_texture = &mut state.map_texture;
+            // the map of the image before
+            if preview.as_ref().map_or(false, |p| p.gps != gps) {
+                *preview = None;
+                *map_texture = None;
+            }
+            ui.collapsing(format!("{MAP_PIN} Location"), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(gps.to_string());
+                    if ui
+                        .link(format!("{GLOBE} OpenStreetMap"))
+                        .on_hover_text("Open the location in the browser")
+                        .clicked()
+                    {
+                        _ = webbrowser::open(&gps.map_url());
+                    }
+                });
+                if let Some(altitude) = gps.altitude {
+                    ui.label(format!("{altitude:.0} m above sea level"));
+                }
+                match preview {
+                    None => {
+                        if ui
+                            .button("Show map")
+                            .on_hover_text(
+                                "Download the map around the location from OpenStreetMap",
+                            )
+                            .clicked()
+                        {
+                            *preview = Some(MapPreview::fetch(gps));
+                        }
+                    }
+                    Some(preview) => {
+                        if preview.poll() {
+                            *map_texture =
+                                preview.tile.as_ref().and_then(|t| t.to_texture(gfx, true));
+                        }
+                        if let Some(e) = &preview.error {
+                            ui.colored_label(Color32::RED, format!("Could not load the map: {e}"));
+                        } else if let Some(texture) = map_texture {
+                            let texture = gfx.egui_register_texture(texture);
+                            let size = Vec2::splat(ui.available_width().min(256.));
+                            let (rect, response) = ui.allocate_exact_size(size, Sense::click());
+                            egui::Image::new(texture).paint_at(ui, rect);
+                            let (_, _, [x, y]) = gps.tile(MAP_ZOOM);
+                            let pin = rect.min + Vec2::new(x, y) * rect.size();
+                            ui.painter().circle(
+                                pin,
+                                5.,
+                                Color32::RED,
+                                Stroke::new(2., Color32::WHITE),
+                            );
+                            if response
+                                .on_hover_text("Open the location in the browser")
+                                .clicked()
+                            {
+                                _ = webbrowser::open(&gps.map_url());
                             }
-                        });
+                            ui.label(RichText::new("© OpenStreetMap contributors").small());
+                        } else {
+                            ui.spinner();
+                            ui.ctx().request_repaint();
+                        }
+                    }
+                }
+            });
+        }
+
+        if !info.metadata.tags.is_empty() {
+            let filter = &mut state.metadata_filter;
+            ui.collapsing(format!("Metadata ({})", info.metadata.tags.len()), |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(MAGNIFYING_GLASS);
+                    ui.add(egui::TextEdit::singleline(filter).hint_text("Search tags and values"));
+                });
+                let query = filter.trim();
+                // the matching tags by where they come from, in the order they are stored
+                let mut groups: Vec<(&str, Vec<&Tag>)> = vec![];
+                for tag in info
+                    .metadata
+                    .tags
+                    .iter()
+                    .filter(|t| query.is_empty() || t.matches(query))
+                {
+                    match groups.iter_mut().find(|(group, _)| *group == tag.group) {
+                        Some((_, tags)) => tags.push(tag),
+                        None => groups.push((tag.group.as_str(), vec![tag])),
+                    }
+                }
+                if groups.is_empty() {
+                    ui.label("No tags match");
+                }
+                egui::ScrollArea::new([true, false]).show(ui, |ui| {
+                    for (group, tags) in groups {
+                        egui::CollapsingHeader::new(format!("{group} ({})", tags.len()))
+                            .id_source(group)
+                            .open((!query.is_empty()).then_some(true))
+                            .show(ui, |ui| {
+                                egui::Grid::new(format!("metadata {group}"))
+                                    .striped(true)
+                                    .num_columns(2)
+                                    .show(ui, |ui| {
+                                        for tag in tags {
+                                            ui.label(&tag.name);
+                                            if ui
+                                                .add(
+                                                    egui::Label::new(&tag.value)
+                                                        .wrap(true)
+                                                        .sense(Sense::click()),
+                                                )
+                                                .on_hover_text("Click to copy")
+                                                .clicked()
+                                            {
+                                                ui.output_mut(|o| {
+                                                    o.copied_text = tag.value.clone()
+                                                });
+                                            }
+                                            ui.end_row();
+                                        }
+                                    });
+                            });
+                    }
                 });
             });
         }
@@ -725,6 +1447,18 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
             // A flag to indicate that the image needs to be rebuilt
             let mut image_changed = false;
             let mut pixels_changed = false;
+            // An operator value changed while being dragged
+            let mut dragging = false;
+
+            // shaders linked to a file are compiled again when it changes
+            if state.edit_state.has_shader_files() {
+                if state.shader_check.elapsed() >= SHADER_CHECK_INTERVAL {
+                    state.shader_check = Instant::now();
+                    pixels_changed = state.edit_state.reload_shader_files();
+                }
+                // the file is edited in another window, so keep checking
+                app.window().request_frame();
+            }
 
             if let Some(img) = &state.current_image {
                 // Ensure that edit result image is always filled
@@ -755,7 +1489,13 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         ImageOperation::Posterize(8),
                         ImageOperation::ChannelSwap((Channel::Red, Channel::Red)),
                         ImageOperation::Rotate(90),
+                        ImageOperation::AutoOrient(1),
                         ImageOperation::HSV((0, 100, 100)),
+                        ImageOperation::HslBands([[0; 3]; 8]),
+                        ImageOperation::RedEye { points: vec![], radius: 20 },
+                        ImageOperation::AutoLevels(None),
+                        ImageOperation::AutoContrast(None),
+                        ImageOperation::AutoWhiteBalance(None),
                         ImageOperation::Crop([0, 0, 0, 0]),
                         ImageOperation::CropPerspective{points: [
                             (0,0),
@@ -776,86 +1516,447 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         ImageOperation::Noise {
                             amt: 50,
                             mono: false,
+                            seed: 0,
+                            distribution: NoiseDistribution::Uniform,
                         },
                         ImageOperation::Add([0, 0, 0]),
                         ImageOperation::Resize {
                             dimensions: state.image_geometry.dimensions,
                             aspect: true,
                             filter: ScaleFilter::Hamming,
+                            mode: ResizeMode::Absolute,
                         },
                         ImageOperation::Invert,
+                        ImageOperation::Erase,
                         ImageOperation::Flip(false),
                         ImageOperation::ChromaticAberration(15),
+                        ImageOperation::PixelShader {
+                            fragment: gpu::DEFAULT_SHADER.into(),
+                            params: vec![],
+                            path: None,
+                        },
                     ];
 
+                    ui.label_i(&format!("{SELECTION} Select"));
+                    ui.horizontal(|ui| {
+                        for (tool, icon) in SelectionTool::ALL.into_iter().zip([SELECTION, CIRCLE_DASHED, MAGIC_WAND, SCRIBBLE_LOOP]) {
+                            let active = state.edit_state.selection_tool == Some(tool);
+                            if ui.selectable_label(active, icon).on_hover_text(tool.name()).clicked() {
+                                state.edit_state.selection_tool = if active { None } else { Some(tool) };
+                            }
+                        }
+                        if let Some(selection) = &mut state.edit_state.selection {
+                            ui.separator();
+                            if ui.toggle_value(&mut selection.invert, SELECTION_INVERSE)
+                                .on_hover_text("Invert the selection")
+                                .changed()
+                            {
+                                state.edit_state.history.mark_changed();
+                            }
+                            if ui.button(SELECTION_SLASH).on_hover_text("Select nothing").clicked() {
+                                state.edit_state.selection = None;
+                                state.edit_state.history.mark_changed();
+                            }
+                            if ui.button(ERASER).on_hover_text("Make the selection transparent").clicked() {
+                                let item = state.edit_state.new_item(ImageOperation::Erase);
+                                state.edit_state.pixel_op_stack.push(item);
+                                pixels_changed = true;
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("New filters only affect the selection");
+                    ui.end_row();
+
+                    if state.edit_state.selection_tool == Some(SelectionTool::MagicWand) {
+                        ui.label("Tolerance");
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Slider::new(&mut state.edit_state.wand.tolerance, 0..=255));
+                            ui.checkbox(&mut state.edit_state.wand.contiguous, "Contiguous")
+                                .on_hover_text("Only select pixels connected to the clicked one");
+                        });
+                        ui.end_row();
+                    }
+
                     ui.label_i("➕ Filter");
                     let available_w_single_spacing =
                         ui.available_width();
                         //  - ui.style().spacing.item_spacing.x;
 
-                    egui::ComboBox::from_id_source("Imageops")
+                    let filters = egui::ComboBox::from_id_source("Imageops")
                         .selected_text("Select a filter to add...")
                         .width(available_w_single_spacing)
                         .show_ui(ui, |ui| {
                             for op in &mut ops {
                                 if ui.label_i_selected(false, &format!("{op}")).clicked() {
+                                    let item = state.edit_state.new_item(op.clone());
                                     if op.is_per_pixel() {
-                                        state.edit_state.pixel_op_stack.push(op.clone());
+                                        state.edit_state.pixel_op_stack.push(item);
                                         pixels_changed = true;
                                     } else {
-                                        state.edit_state.image_op_stack.push(op.clone());
+                                        state.edit_state.image_op_stack.push(item);
                                         image_changed = true;
                                     }
                                 }
                             }
+                            // filters from the plugin directory
+                            for plugin in state.plugins.clone() {
+                                if ui
+                                    .label_i_selected(false, &format!("{PLUG} {}", plugin.name))
+                                    .on_hover_text(plugin.description.as_str())
+                                    .clicked()
+                                {
+                                    let item = state.edit_state.new_item(ImageOperation::Plugin {
+                                        id: plugin.id,
+                                        name: plugin.name,
+                                        params: plugin.params,
+                                    });
+                                    state.edit_state.image_op_stack.push(item);
+                                    image_changed = true;
+                                }
+                            }
                         });
+                    if filters.response.clicked() {
+                        state.plugins = plugins::list();
+                    }
                     ui.end_row();
 
-                    modifier_stack_ui(&mut state.edit_state.image_op_stack, &mut image_changed, ui, &state.image_geometry, &mut state.edit_state.block_panning);
+                    if let Some(img) = &state.current_image {
+                        ctx.data_mut(|w| w.insert_temp(Id::new("edit_source_size"), img.dimensions()));
+                    }
+                    modifier_stack_ui(&mut state.edit_state.image_op_stack, &mut image_changed, &mut dragging, ui, &state.image_geometry, &mut state.edit_state.block_panning, &state.image_op_thumbnails, gfx);
                     modifier_stack_ui(
                         &mut state.edit_state.pixel_op_stack,
                         &mut pixels_changed,
-                        ui, &state.image_geometry, &mut state.edit_state.block_panning
+                        &mut dragging,
+                        ui, &state.image_geometry, &mut state.edit_state.block_panning,
+                        &state.pixel_op_thumbnails, gfx
                     );
 
                     ui.label_i(&format!("{RECYCLE} Reset"));
                     ui.centered_and_justified(|ui| {
                         if ui.button("Reset all edits").clicked() {
+                            // keep the history so the reset can be undone
+                            let history = std::mem::take(&mut state.edit_state.history);
                             state.edit_state = Default::default();
+                            state.edit_state.history = history;
                             pixels_changed = true
                         }
                     });
                     ui.end_row();
 
-                    ui.label_i(&format!("{GIT_DIFF} Compare"));
+                    ui.label_i(&format!("{CLOCK_COUNTER_CLOCKWISE} History"));
                     let available_w_single_spacing =
                         ui.available_width() - ui.style().spacing.item_spacing.x;
                     ui.horizontal(|ui| {
                         if ui
-                            .add_sized(
-                                egui::vec2(available_w_single_spacing / 2., ui.available_height()),
-                                egui::Button::new("Original"),
+                            .add_enabled(
+                                state.edit_state.history.can_undo(),
+                                egui::Button::new(format!("{ARROW_U_UP_LEFT} Undo"))
+                                    .min_size(vec2(available_w_single_spacing / 2., 0.)),
                             )
+                            .on_hover_text(lookup(&state.persistent_settings.shortcuts, &InputEvent::Undo))
                             .clicked()
                         {
-                            if let Some(img) = &state.current_image {
-                                state.image_geometry.dimensions = img.dimensions();
-                                state.current_texture = img.to_texture(gfx, state.persistent_settings.linear_mag_filter);
+                            state.edit_state.undo();
+                        }
+                        if ui
+                            .add_enabled(
+                                state.edit_state.history.can_redo(),
+                                egui::Button::new(format!("{ARROW_U_UP_RIGHT} Redo"))
+                                    .min_size(vec2(available_w_single_spacing / 2., 0.)),
+                            )
+                            .on_hover_text(lookup(&state.persistent_settings.shortcuts, &InputEvent::Redo))
+                            .clicked()
+                        {
+                            state.edit_state.redo();
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label_i(&format!("{CLIPBOARD} Edits"));
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add(
+                                egui::Button::new(format!("{COPY} Copy"))
+                                    .min_size(vec2(available_w_single_spacing / 2., 0.)),
+                            )
+                            .on_hover_text(format!(
+                                "Copy the edit stacks to paste them onto other images ({})",
+                                lookup(&state.persistent_settings.shortcuts, &InputEvent::CopyEdits)
+                            ))
+                            .clicked()
+                        {
+                            state.copied_edits = Some(state.edit_state.copy_stacks());
+                        }
+                        if ui
+                            .add_enabled(
+                                state.copied_edits.is_some(),
+                                egui::Button::new(format!("{CLIPBOARD} Paste"))
+                                    .min_size(vec2(available_w_single_spacing / 2., 0.)),
+                            )
+                            .on_hover_text(format!(
+                                "Replace the edit stacks with copied ones ({})",
+                                lookup(&state.persistent_settings.shortcuts, &InputEvent::PasteEdits)
+                            ))
+                            .clicked()
+                        {
+                            if let Some(stacks) = &state.copied_edits {
+                                state.edit_state.paste_stacks(stacks);
+                                image_changed = true;
+                            }
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label_i(&format!("{BOOKMARK_SIMPLE} Presets"));
+                    ui.horizontal(|ui| {
+                        let list = egui::ComboBox::from_id_source("presets")
+                            .selected_text("Apply...")
+                            .width(available_w_single_spacing / 2.)
+                            .show_ui(ui, |ui| {
+                                let names = state.preset_names.get_or_insert_with(EditPreset::list).clone();
+                                for name in names {
+                                    if ui.selectable_label(false, &name).clicked() {
+                                        match EditPreset::load(&name) {
+                                            Ok(preset) => {
+                                                state.edit_state.pixel_op_stack = preset.pixel_op_stack;
+                                                state.edit_state.image_op_stack = preset.image_op_stack;
+                                                image_changed = true;
+                                            }
+                                            Err(e) => state.send_message_err(&format!("Could not load preset: {e}")),
+                                        }
+                                    }
+                                }
+                            });
+                        // read again the next time the list opens
+                        if list.inner.is_none() {
+                            state.preset_names = None;
+                        }
+
+                        let name_id = Id::new("preset_name");
+                        let mut name = ctx.data(|r| r.get_temp::<String>(name_id)).unwrap_or_default();
+                        ui.add(egui::TextEdit::singleline(&mut name).hint_text("Name").desired_width(60.));
+                        if ui
+                            .add_enabled(!name.trim().is_empty(), egui::Button::new(FLOPPY_DISK))
+                            .on_hover_text("Save the current edits as preset")
+                            .clicked()
+                        {
+                            let preset = EditPreset {
+                                pixel_op_stack: state.edit_state.pixel_op_stack.clone(),
+                                image_op_stack: state.edit_state.image_op_stack.clone(),
+                            };
+                            match preset.save(name.trim()) {
+                                Ok(_) => state.send_message_info(&format!("Saved preset {}", name.trim())),
+                                Err(e) => state.send_message_err(&format!("Could not save preset: {e}")),
+                            }
+                        }
+                        ctx.data_mut(|w| w.insert_temp(name_id, name));
+                    });
+                    ui.end_row();
+
+                    ui.label_i(&format!("{CODE} Script"));
+                    ui.vertical(|ui| {
+                        let script_id = Id::new("script");
+                        let mut script = ctx.data_mut(|w| w.get_persisted::<String>(script_id)).unwrap_or_default();
+                        ui.add(
+                            egui::TextEdit::multiline(&mut script)
+                                .code_editor()
+                                .desired_rows(3)
+                                .desired_width(available_w_single_spacing)
+                                .hint_text("add_op(\"brightness\", 20);"),
+                        );
+                        if let Some(result) = state.script_job.as_ref().and_then(|job| job.poll()) {
+                            state.script_job = None;
+                            match result {
+                                Ok(result) => {
+                                    if result.image_replaced {
+                                        state.current_image = result.image;
+                                        // the result is quantized, so the full range source no longer matches
+                                        state.current_hdr_image = None;
+                                        // cached edit results are based on the previous image
+                                        state.edit_state.caches.clear();
+                                        pixels_changed = true;
+                                    }
+                                    state.edit_state.pixel_op_stack = result.stacks.pixel_op_stack;
+                                    state.edit_state.image_op_stack = result.stacks.image_op_stack;
+                                    image_changed = true;
+                                }
+                                Err(e) => state.send_message_err(&format!("Script failed: {e}")),
+                            }
+                        }
+                        if state.script_job.is_some() {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label("Running script...");
+                            });
+                            // keep checking for the result
+                            ui.ctx().request_repaint();
+                        } else if ui
+                            .add_enabled(!script.trim().is_empty(), egui::Button::new(format!("{PLAY} Run")))
+                            .on_hover_text("Run the script on the current image and edits")
+                            .clicked()
+                        {
+                            let context = scripting::ScriptContext {
+                                image: state.current_image.clone(),
+                                path: state.current_path.clone(),
+                                stacks: EditPreset {
+                                    pixel_op_stack: state.edit_state.pixel_op_stack.clone(),
+                                    image_op_stack: state.edit_state.image_op_stack.clone(),
+                                },
+                                image_replaced: false,
+                            };
+                            state.script_job = Some(scripting::ScriptJob::start(script.clone(), context));
+                        }
+                        ctx.data_mut(|w| w.insert_persisted(script_id, script));
+                    });
+                    ui.end_row();
+
+                    ui.label_i(&format!("{GIT_DIFF} Compare"));
+                    let available_w_single_spacing =
+                        ui.available_width() - ui.style().spacing.item_spacing.x;
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_sized(
+                                egui::vec2(available_w_single_spacing / 3., ui.available_height()),
+                                egui::Button::new("Original"),
+                            )
+                            .clicked()
+                        {
+                            if let Some(img) = &state.current_image {
+                                state.image_geometry.dimensions = img.dimensions();
+                                state.current_texture = img.to_texture(gfx, state.persistent_settings.linear_mag_filter);
                             }
                         }
                         if ui
                             .add_sized(
-                                egui::vec2(available_w_single_spacing / 2., ui.available_height()),
+                                egui::vec2(available_w_single_spacing / 3., ui.available_height()),
                                 egui::Button::new("Modified"),
                             )
                             .clicked()
                         {
                             pixels_changed = true;
                         }
+                        let split = state.edit_state.split_compare.is_some();
+                        if ui
+                            .add_sized(
+                                egui::vec2(available_w_single_spacing / 3., ui.available_height()),
+                                egui::SelectableLabel::new(split, "Split"),
+                            )
+                            .on_hover_text("Show the original left of a draggable line")
+                            .clicked()
+                        {
+                            state.edit_state.split_compare = if split { None } else { Some(0.5) };
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label_i(&format!("{STACK_SIMPLE} Layers"));
+                    ui.vertical(|ui| {
+                        let mut delete: Option<usize> = None;
+                        let mut swap: Option<(usize, usize)> = None;
+                        for (i, layer) in state.edit_state.layers.iter_mut().enumerate() {
+                            ui.push_id(("layer", i), |ui| {
+                                ui.horizontal(|ui| {
+                                    if layer.ui(ui).changed() {
+                                        pixels_changed = true;
+                                    }
+                                    if ui.small_button("⏶").on_hover_text("Move behind the previous layer").clicked() && i > 0 {
+                                        swap = Some((i - 1, i));
+                                    }
+                                    if ui.small_button("❌").on_hover_text("Remove layer").clicked() {
+                                        delete = Some(i);
+                                    }
+                                });
+                            });
+                        }
+                        if let Some((a, b)) = swap {
+                            state.edit_state.layers.swap(a, b);
+                            pixels_changed = true;
+                        }
+                        if let Some(i) = delete {
+                            state.edit_state.layers.remove(i);
+                            pixels_changed = true;
+                        }
+
+                        ui.horizontal(|ui| {
+                            #[cfg(feature = "file_open")]
+                            {
+                                if ui.button(format!("{IMAGE} Add image")).clicked() {
+                                    if let Some(path) = rfd::FileDialog::new()
+                                        .set_directory(state.persistent_settings.last_open_directory.clone())
+                                        .pick_file()
+                                    {
+                                        let name = path
+                                            .file_stem()
+                                            .map(|s| s.to_string_lossy().to_string())
+                                            .unwrap_or_default();
+                                        state.edit_state.layers.push(Layer::new(&name, LayerSource::File(path)));
+                                        pixels_changed = true;
+                                    }
+                                }
+                            }
+                            let strokes = state
+                                .edit_state
+                                .paint_strokes
+                                .iter()
+                                .filter(|s| !s.is_empty())
+                                .map(|s| PaintStroke {
+                                    highlight: false,
+                                    committed: false,
+                                    ..s.clone()
+                                })
+                                .collect::<Vec<_>>();
+                            if ui
+                                .add_enabled(!strokes.is_empty(), egui::Button::new(format!("{PAINT_BRUSH_HOUSEHOLD} Strokes to layer")))
+                                .on_hover_text("Move all paint strokes to their own layer")
+                                .clicked()
+                            {
+                                state.edit_state.paint_strokes.clear();
+                                state.edit_state.layers.push(Layer::new("Paint", LayerSource::Strokes(strokes)));
+                                // strokes may have been committed to the image already
+                                image_changed = true;
+                            }
+                        });
                     });
                     ui.end_row();
                 });
 
+            if let Some(tool) = state.edit_state.selection_tool {
+                if !state.edit_state.painting {
+                    let selected = if tool == SelectionTool::MagicWand {
+                        selection::wand_ui(
+                            ctx,
+                            &state.edit_state.result_pixel_op,
+                            state.edit_state.wand,
+                            &mut state.edit_state.selection,
+                            &state.image_geometry,
+                        )
+                    } else if tool == SelectionTool::Lasso {
+                        selection::lasso_ui(
+                            ctx,
+                            &mut state.edit_state.selection,
+                            &mut state.edit_state.selection_anchor,
+                            &state.image_geometry,
+                        )
+                    } else {
+                        selection::drag_ui(
+                            ctx,
+                            tool,
+                            &mut state.edit_state.selection,
+                            &mut state.edit_state.selection_anchor,
+                            &state.image_geometry,
+                        )
+                    };
+                    if selected {
+                        state.edit_state.history.mark_changed();
+                    }
+                }
+            }
+            if let Some(selection) = &state.edit_state.selection {
+                selection::draw_outline(ctx, selection, &state.image_geometry);
+            }
+
             ui.vertical_centered_justified(|ui| {
                 if state.edit_state.painting {
 
@@ -898,16 +1999,79 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         .on_hover_text("Keep all paint history and edit it. Slower.");
                     ui.end_row();
 
+                    ui.label(format!("{SHAPES} Tool"));
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut state.edit_state.paint_tool, PaintTool::Brush, PAINT_BRUSH)
+                            .on_hover_text("Brush");
+                        ui.selectable_value(&mut state.edit_state.paint_tool, PaintTool::Fill, PAINT_BUCKET)
+                            .on_hover_text("Fill");
+                        for (kind, icon) in AnnotationKind::ALL.into_iter().zip([LINE_SEGMENT, ARROW_UP_RIGHT, RECTANGLE, CIRCLE]) {
+                            ui.selectable_value(&mut state.edit_state.paint_tool, PaintTool::Shape(kind), icon)
+                                .on_hover_text(kind.name());
+                        }
+                        ui.separator();
+                        for (style, icon) in RedactionStyle::ALL.into_iter().zip([GRID_FOUR, EYE_SLASH]) {
+                            ui.selectable_value(&mut state.edit_state.paint_tool, PaintTool::Redact(style), icon)
+                                .on_hover_text(format!("Redact: {}", style.name()));
+                        }
+                    });
+                    ui.end_row();
+
+                    if state.edit_state.paint_tool == PaintTool::Fill {
+                        ui.label("Tolerance");
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Slider::new(&mut state.edit_state.fill.tolerance, 0..=255));
+                            ui.checkbox(&mut state.edit_state.fill.contiguous, "Contiguous")
+                                .on_hover_text("Only fill pixels connected to the clicked one");
+                        });
+                        ui.end_row();
+                    }
+
+                    ui.label(format!("{PAINT_BRUSH} Brushes"));
+                    #[cfg(feature = "file_open")]
+                    {
+                        if ui
+                            .button("Load brush…")
+                            .on_hover_text("Import a grayscale or alpha PNG as brush")
+                            .clicked()
+                        {
+                            if let Some(path) = rfd::FileDialog::new().add_filter("Brush", &["png"]).pick_file() {
+                                load_brush(state, path, false);
+                            }
+                        }
+                    }
+                    #[cfg(not(feature = "file_open"))]
+                    {
+                        if ui.button("Load brush…").clicked() {
+                            ui.ctx().memory_mut(|w| w.open_popup(Id::new("brush_file")));
+                        }
+                        if ui.ctx().memory(|w| w.is_popup_open(Id::new("brush_file"))) {
+                            filebrowser::browse_modal(
+                                false,
+                                &["png"],
+                                |p| load_brush(state, p.clone(), false),
+                                ui.ctx(),
+                            );
+                        }
+                    }
+                    ui.end_row();
+
+                    let index = state.edit_state.paint_strokes.len().saturating_sub(1);
                     if let Some(stroke) = state.edit_state.paint_strokes.last_mut() {
                         if stroke.is_empty() {
                             ui.label("Color");
                             ui.label("Fade");
                             ui.label("Flip");
                             ui.label("Width");
+                            ui.label("Opacity");
+                            ui.label("Flow");
+                            ui.label("Spacing");
                             ui.label("Brush");
+                            ui.label("Blend");
+                            ui.label("Mode");
                             ui.end_row();
 
-                            stroke_ui(stroke, &state.edit_state.brushes, ui, gfx);
+                            stroke_ui(stroke, index, &state.edit_state.brushes, ui, gfx);
                         }
                     }
                 });
@@ -923,9 +2087,9 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     ui.separator();
                     ui.horizontal(|ui| {
                         ui.label("Strokes");
-                        if ui.button("↩").clicked() {
-                            let _ = state.edit_state.paint_strokes.pop();
-                            let _ = state.edit_state.paint_strokes.pop();
+                        if ui.button("↩").on_hover_text("Remove the last stroke").clicked()
+                            && state.edit_state.pop_stroke()
+                        {
                             pixels_changed = true;
                         }
                         if ui.button("Clear all").clicked() {
@@ -947,7 +2111,12 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                                         ui.label("Fade");
                                         ui.label("Flip");
                                         ui.label("Width");
+                                        ui.label("Opacity");
+                                        ui.label("Flow");
+                                        ui.label("Spacing");
                                         ui.label("Brush");
+                                        ui.label("Blend");
+                                        ui.label("Mode");
                                         ui.label("Del");
                                         ui.end_row();
 
@@ -960,6 +2129,7 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
 
                                             let r = stroke_ui(
                                                 stroke,
+                                                i,
                                                 &state.edit_state.brushes,
                                                 ui,
                                                 gfx,
@@ -1005,12 +2175,149 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
 
                 ui.end_row();
 
+                if !state.edit_state.annotations.is_empty() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Shapes");
+                        if ui.button("Clear all").clicked() {
+                            state.edit_state.annotations.clear();
+                            pixels_changed = true;
+                        }
+                    });
+                    let mut delete_annotation: Option<usize> = None;
+                    egui::Grid::new("annotations").show(ui, |ui| {
+                        ui.label("Shape");
+                        ui.label("Color");
+                        ui.label("Width");
+                        ui.label("Fill");
+                        ui.label("Del");
+                        ui.end_row();
+                        for (i, annotation) in state.edit_state.annotations.iter_mut().enumerate() {
+                            if annotation.ui(ui).changed() {
+                                pixels_changed = true;
+                            }
+                            if ui.button(TRASH).clicked() {
+                                delete_annotation = Some(i);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(i) = delete_annotation {
+                        state.edit_state.annotations.remove(i);
+                        pixels_changed = true;
+                    }
+                    if !state.edit_state.drawing_annotation
+                        && annotation::handles_ui(ctx, &mut state.edit_state.annotations, &state.image_geometry)
+                    {
+                        pixels_changed = true;
+                    }
+                }
+
+                if !state.edit_state.redactions.is_empty() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label("Redactions");
+                        if ui.button("Clear all").clicked() {
+                            state.edit_state.redactions.clear();
+                            pixels_changed = true;
+                        }
+                    });
+                    let mut delete_redaction: Option<usize> = None;
+                    egui::Grid::new("redactions").show(ui, |ui| {
+                        for (i, redaction) in state.edit_state.redactions.iter_mut().enumerate() {
+                            egui::ComboBox::from_id_source(("redaction", i))
+                                .selected_text(redaction.style.name())
+                                .show_ui(ui, |ui| {
+                                    for style in RedactionStyle::ALL {
+                                        if ui.selectable_value(&mut redaction.style, style, style.name()).clicked() {
+                                            pixels_changed = true;
+                                        }
+                                    }
+                                });
+                            if ui.button(TRASH).clicked() {
+                                delete_redaction = Some(i);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(i) = delete_redaction {
+                        state.edit_state.redactions.remove(i);
+                        pixels_changed = true;
+                    }
+                }
+
                 // If we have no lines, create an empty one
                 if state.edit_state.paint_strokes.is_empty() {
                     state.edit_state.paint_strokes.push(PaintStroke::new());
                 }
 
-                if let Some(current_stroke) = state.edit_state.paint_strokes.last_mut() {
+                if state.edit_state.paint_tool == PaintTool::Fill {
+                    let pressed = ctx.input(|i| i.pointer.primary_pressed());
+                    if pressed && !state.pointer_over_ui && !ctx.is_pointer_over_area() {
+                        let uv = (
+                            state.cursor_relative.x / state.image_geometry.dimensions.0 as f32,
+                            state.cursor_relative.y / state.image_geometry.dimensions.1 as f32,
+                        );
+                        if (0. ..1.).contains(&uv.0) && (0. ..1.).contains(&uv.1) {
+                            let strokes = &mut state.edit_state.paint_strokes;
+                            if let Some(current_stroke) = strokes.last_mut() {
+                                // the fill is a stroke of its own, so it can be undone like one
+                                let next = current_stroke.without_points();
+                                current_stroke.points = vec![uv];
+                                current_stroke.flood_fill = Some(state.edit_state.fill);
+                                strokes.push(next);
+                                pixels_changed = true;
+                            }
+                        }
+                    }
+                } else if let tool @ (PaintTool::Shape(_) | PaintTool::Redact(_)) = state.edit_state.paint_tool {
+                    let uv = (
+                        (state.cursor_relative.x / state.image_geometry.dimensions.0 as f32).clamp(0., 1.),
+                        (state.cursor_relative.y / state.image_geometry.dimensions.1 as f32).clamp(0., 1.),
+                    );
+                    let (pressed, down) = ctx.input(|i| (i.pointer.primary_pressed(), i.pointer.primary_down()));
+                    let edit_state = &mut state.edit_state;
+                    if pressed && !state.pointer_over_ui && !ctx.is_pointer_over_area() {
+                        match tool {
+                            PaintTool::Redact(style) => edit_state.redactions.push(Redaction::new(style, uv)),
+                            PaintTool::Shape(kind) => {
+                                // shapes take the color of the brush
+                                let color = edit_state.paint_strokes.last().map(|s| s.color).unwrap_or([1., 0., 0., 1.]);
+                                edit_state.annotations.push(Annotation::new(kind, uv, color));
+                            }
+                            _ => {}
+                        }
+                        edit_state.drawing_annotation = true;
+                    } else if edit_state.drawing_annotation {
+                        let (start, end) = match tool {
+                            PaintTool::Redact(_) => match edit_state.redactions.last_mut() {
+                                Some(r) => (r.start, Some(&mut r.end)),
+                                None => (uv, None),
+                            },
+                            _ => match edit_state.annotations.last_mut() {
+                                Some(a) => (a.start, Some(&mut a.end)),
+                                None => (uv, None),
+                            },
+                        };
+                        if let Some(end) = end {
+                            if *end != uv {
+                                *end = uv;
+                                pixels_changed = true;
+                            }
+                        }
+                        if !down {
+                            edit_state.drawing_annotation = false;
+                            // a click without dragging doesn't draw anything
+                            if start == uv {
+                                if let PaintTool::Redact(_) = tool {
+                                    edit_state.redactions.pop();
+                                } else {
+                                    edit_state.annotations.pop();
+                                }
+                            }
+                        }
+                    }
+                } else if let Some(current_stroke) = state.edit_state.paint_strokes.last_mut() {
                     // if state.mouse_delta.x > 0.0 {
                     if ctx.input(|i|i.pointer.primary_down()) && !state.pointer_over_ui {
                         debug!("PAINT");
@@ -1043,6 +2350,8 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                 {
                     if let Some(img) = &mut state.current_image {
                         *img = state.edit_state.result_pixel_op.clone();
+                        // the result is quantized, so the full range source no longer matches
+                        state.current_hdr_image = None;
                         state.edit_state = Default::default();
                         // state.dimensions = img.dimensions();
                         pixels_changed = true;
@@ -1053,17 +2362,115 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
 
             // Do the processing
 
+            // the history compares the edits only after a change, not on every frame
+            if image_changed || pixels_changed {
+                state.edit_state.history.mark_changed();
+            }
+
+            // While an operator is dragged, large images are processed at a lower resolution.
+            // Pixel operators the GPU can preview don't need this.
+            let exceeds_proxy = state
+                .current_image
+                .as_ref()
+                .map(|img| img.width().max(img.height()) > PROXY_SIZE)
+                .unwrap_or_default();
+            let gpu_capable = state.persistent_settings.gpu_edits
+                && !image_changed
+                && gpu::encode_edit(&state.edit_state).is_some();
+            if dragging
+                && exceeds_proxy
+                && !gpu_capable
+                && (image_changed || pixels_changed)
+                && state.edit_state.supports_proxy()
+            {
+                if let Some(img) = &state.current_image {
+                    let stamp = Instant::now();
+                    state.edit_state.process_proxy(img);
+                    state.current_texture = state
+                        .edit_state
+                        .result_pixel_op
+                        .to_texture(gfx, state.persistent_settings.linear_mag_filter);
+                    debug!("Proxy processed in {}s", stamp.elapsed().as_secs_f32());
+                    image_changed = false;
+                    pixels_changed = false;
+                }
+            } else if state.edit_state.proxy_scale.is_some()
+                && ctx.memory(|m| m.dragged_id().is_none())
+            {
+                // the drag ended, compute the full resolution result
+                state.edit_state.proxy_scale = None;
+                image_changed = true;
+            }
+            if state.edit_state.proxy_scale.is_some() && state.edit_state.proxy_is_approximate() {
+                ui.label(format!(
+                    "{INFO} Kernels, noise and plugins are approximate at preview resolution"
+                ));
+            }
+
             // If expensive operations happened (modifying image geometry), process them here
             if image_changed {
                 if let Some(img) = &mut state.current_image {
                     let stamp = Instant::now();
                     // start with a fresh copy of the unmodified image
                     state.edit_state.result_image_op = img.clone();
-                    for operation in &mut state.edit_state.image_op_stack {
-                        if let Err(e) = operation.process_image(&mut state.edit_state.result_image_op) {
-                            error!("{e}")
+                    // the orientation depends on the current file, its EXIF data is read once
+                    if let Some(p) = &state.current_path {
+                        for item in &mut state.edit_state.image_op_stack {
+                            if let ImageOperation::AutoOrient(orientation) = &mut item.operation {
+                                *orientation = *state
+                                    .edit_state
+                                    .caches
+                                    .orientation
+                                    .get_or_insert_with(|| exif_orientation(p).unwrap_or(1));
+                            }
                         }
                     }
+                    // high dynamic range sources are edited in float and only quantized for display
+                    state.edit_state.result_image_op_f32 = state
+                        .current_hdr_image
+                        .clone()
+                        .filter(|hdr| hdr.dimensions() == img.dimensions());
+                    // only operators from the first changed one on are processed again
+                    if let Some(hdr) = &mut state.edit_state.result_image_op_f32 {
+                        state.edit_state.caches.image_ops_f32.process(
+                            hdr,
+                            &state.edit_state.image_op_stack,
+                            |items, buffer| {
+                                for item in items.iter().filter(|i| i.active) {
+                                    if let Err(e) = item.process_image_f32(buffer) {
+                                        error!("{e}")
+                                    }
+                                }
+                            },
+                        );
+                        state.edit_state.result_image_op = quantize(hdr);
+                    } else {
+                        state.edit_state.caches.image_ops.process(
+                            &mut state.edit_state.result_image_op,
+                            &state.edit_state.image_op_stack,
+                            |items, buffer| {
+                                for item in items.iter().filter(|i| i.active) {
+                                    if let Err(e) = item.process_image(buffer) {
+                                        error!("{e}")
+                                    }
+                                }
+                            },
+                        );
+                    }
+                    // the input of the pixel stack changed
+                    state.edit_state.caches.clear_pixel_ops();
+                    if let Some(editor) = &mut state.gpu_editor {
+                        editor.invalidate_source();
+                    }
+                    // only the thumbnails of changed operators are uploaded again
+                    let thumbnails = &mut state.edit_state.caches.image_op_thumbnails;
+                    let changed = thumbnails.update(img, &state.edit_state.image_op_stack);
+                    state.image_op_thumbnails.truncate(changed);
+                    state.image_op_thumbnails.extend(
+                        thumbnails.thumbnails()[changed..]
+                            .iter()
+                            .filter_map(|t| t.to_texture(gfx, true)),
+                    );
                     debug!(
                         "Image changed. Finished evaluating in {}s",
                         stamp.elapsed().as_secs_f32()
@@ -1077,6 +2484,46 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                 pixels_changed = true;
             }
 
+            // The GPU preview is only shown while interacting, the exact result is computed afterwards
+            let interacting = ctx.input(|i| i.pointer.any_down());
+            if state.edit_state.gpu_preview && !interacting {
+                state.edit_state.gpu_preview = false;
+                pixels_changed = true;
+            }
+            if pixels_changed
+                && interacting
+                && state.persistent_settings.gpu_edits
+                && state.edit_state.result_image_op.dimensions()
+                    == state.edit_state.result_pixel_op.dimensions()
+            {
+                if let Some(program) = gpu::encode_edit(&state.edit_state) {
+                    if state.gpu_editor.is_none() {
+                        match GpuEditor::new(gfx) {
+                            Ok(editor) => state.gpu_editor = Some(editor),
+                            Err(e) => {
+                                state.persistent_settings.gpu_edits = false;
+                                state.send_message_err(&format!("GPU preview is not available: {e}"));
+                            }
+                        }
+                    }
+                    if let Some(editor) = &mut state.gpu_editor {
+                        match editor.render(
+                            gfx,
+                            &state.edit_state.result_image_op,
+                            &program,
+                            state.persistent_settings.linear_mag_filter,
+                        ) {
+                            Ok(texture) => {
+                                state.current_texture = Some(texture);
+                                state.edit_state.gpu_preview = true;
+                                pixels_changed = false;
+                            }
+                            Err(e) => error!("GPU preview failed, using CPU: {e}"),
+                        }
+                    }
+                }
+            }
+
             if pixels_changed {
                 // init result as a clean copy of image operation result
                 let stamp = Instant::now();
@@ -1086,11 +2533,42 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
 
                 // only process pixel stack if it is empty so we don't run through pixels without need
                 if !state.edit_state.pixel_op_stack.is_empty() {
-                    let ops = &state.edit_state.pixel_op_stack;
-                    process_pixels(&mut state.edit_state.result_pixel_op, ops);
-
+                    // automatic adjustments compute their parameters once, from the input of the pixel stack
+                    for item in &mut state.edit_state.pixel_op_stack {
+                        if item.operation.needs_analysis() {
+                            item.operation.analyze(&state.edit_state.result_image_op);
+                        }
+                    }
+                    // high dynamic range sources are kept in float, so they are not quantized
+                    if let Some(hdr) = &state.edit_state.result_image_op_f32 {
+                        let mut result = hdr.clone();
+                        state.edit_state.caches.pixel_ops_f32.process(
+                            &mut result,
+                            &state.edit_state.pixel_op_stack,
+                            |items, buffer| process_pixel_stack_f32(buffer, items),
+                        );
+                        state.edit_state.result_pixel_op = quantize(&result);
+                    } else {
+                        state.edit_state.caches.pixel_ops.process(
+                            &mut state.edit_state.result_pixel_op,
+                            &state.edit_state.pixel_op_stack,
+                            |items, buffer| process_pixel_stack(buffer, items),
+                        );
+                    }
                 }
 
+                let thumbnails = &mut state.edit_state.caches.pixel_op_thumbnails;
+                let changed = thumbnails.update(
+                    &state.edit_state.result_image_op,
+                    &state.edit_state.pixel_op_stack,
+                );
+                state.pixel_op_thumbnails.truncate(changed);
+                state.pixel_op_thumbnails.extend(
+                    thumbnails.thumbnails()[changed..]
+                        .iter()
+                        .filter_map(|t| t.to_texture(gfx, true)),
+                );
+
                                 debug!(
                     "Finished Pixel op stack in {} s",
                     stamp.elapsed().as_secs_f32()
@@ -1106,7 +2584,35 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     }
                 }
 
+                // composite layers on top of the edited photo
+                let dimensions = state.edit_state.result_pixel_op.dimensions();
+                for layer in &mut state.edit_state.layers {
+                    if layer.needs_refresh() {
+                        if let Err(e) = layer.refresh(dimensions, &state.edit_state.brushes) {
+                            error!("Could not load layer {}: {e}", layer.name);
+                        }
+                    }
+                    layer.composite(&mut state.edit_state.result_pixel_op);
+                }
+
+                // shapes stay editable, so they are drawn on top every time
+                for annotation in &state.edit_state.annotations {
+                    annotation.render(&mut state.edit_state.result_pixel_op);
+                }
+
+                // redactions hide everything below them
+                for redaction in &state.edit_state.redactions {
+                    redaction.render(&mut state.edit_state.result_pixel_op);
+                }
+
+                // custom shaders run on the GPU, on the result of everything else
+                state.edit_state.shader_input = (!state.edit_state.shaders().is_empty())
+                    .then(|| state.edit_state.result_pixel_op.clone());
+                render_shaders(app, ctx, state, gfx);
+
                 // Update the texture
+                state.histogram = None;
+                state.scope_texture = None;
                 if let Some(tex) = &mut state.current_texture {
                     if let Some(img) = &state.current_image {
                         if tex.width() as u32 == state.edit_state.result_pixel_op.width()
@@ -1131,6 +2637,17 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     //  _ = sender.send(f);
 
 
+            } else if state.edit_state.shader_input.is_some()
+                && state.shaders.iter().any(|s| s.is_animated())
+            {
+                // shaders using the time or cursor are rendered again every frame
+                render_shaders(app, ctx, state, gfx);
+                state.histogram = None;
+                state.scope_texture = None;
+                if let Some(tex) = &mut state.current_texture {
+                    state.edit_state.result_pixel_op.update_texture(gfx, tex);
+                }
+                app.window().request_frame();
             }
 
             // render uncommitted strokes if destructive to speed up painting
@@ -1144,6 +2661,8 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     .count()
                     > 1
                     && !state.edit_state.non_destructive_painting
+                    // the quantized buffer is rebuilt from the float result, so strokes can't be baked in
+                    && state.edit_state.result_image_op_f32.is_none()
                 {
                     let stroke_count = state.edit_state.paint_strokes.len();
 
@@ -1160,7 +2679,30 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                 }
             }
 
-            state.image_geometry.dimensions = state.edit_state.result_pixel_op.dimensions();
+            let (width, height) = state.edit_state.result_pixel_op.dimensions();
+            state.image_geometry.dimensions = match state.edit_state.proxy_scale {
+                // the proxy is shown at the size of the full result
+                Some(scale) => (
+                    (width as f32 / scale).round() as u32,
+                    (height as f32 / scale).round() as u32,
+                ),
+                None => (width, height),
+            };
+
+            if let Some(split) = &mut state.edit_state.split_compare {
+                split_compare_ui(ctx, split, &state.image_geometry);
+            }
+
+            // record edits once the user lets go, so dragging a value is a single undo step
+            if !ctx.input(|i| i.pointer.any_down()) && state.edit_state.record_history() {
+                if state.persistent_settings.auto_sidecar {
+                    if let Some(p) = &state.current_path {
+                        if let Err(e) = save_sidecar(p, &state.edit_state) {
+                            error!("Could not save edits: {e}");
+                        }
+                    }
+                }
+            }
 
             ui.vertical_centered_justified(|ui| {
                 if let Some(path) = &state.current_path {
@@ -1179,12 +2721,16 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                 #[cfg(feature = "turbo")]
                 jpg_lossless_ui(state, ui);
 
+                egui::CollapsingHeader::new(format!("{EXPORT} Export settings")).show(ui, |ui| {
+                    state.persistent_settings.export.ui(ui);
+                });
+
 
                 if state.current_path.is_none() && state.current_image.is_some() {
                     #[cfg(not(feature = "file_open"))]
                     {
                         if ui.button("Create output file").on_hover_text("This image does not have any file associated with it. Click to create a default one.").clicked() {
-                            let dest = state.persistent_settings.last_open_directory.clone().join("untitled").with_extension(&state.edit_state.export_extension);
+                            let dest = state.persistent_settings.last_open_directory.clone().join("untitled").with_extension(state.persistent_settings.export.format.extension());
                             state.current_path = Some(dest);
                             set_title(app, state);
                         }
@@ -1201,21 +2747,33 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         let msg_sender = state.message_channel.0.clone();
                         let err_sender = state.message_channel.0.clone();
                         let image_info = state.image_info.clone();
+                        let export = state.persistent_settings.export.clone();
+                        let source = state.current_path.clone();
+                        // pasted images have no file, so they get a default name
+                        let file_name = format!(
+                            "{}.{}",
+                            source
+                                .as_ref()
+                                .and_then(|p| p.file_stem())
+                                .map(|s| s.to_string_lossy().to_string())
+                                .unwrap_or_else(|| "untitled".into()),
+                            export.format.extension()
+                        );
 
                         std::thread::spawn(move || {
                             let file_dialog_result = rfd::FileDialog::new()
                                 .set_directory(start_directory)
+                                .set_file_name(file_name)
                                 .save_file();
 
                                 if let Some(file_path) = file_dialog_result {
                                     debug!("Selected File Path = {:?}", file_path);
-                                    match image_to_save
-                                        .save(&file_path) {
+                                    match export.save(&image_to_save, &file_path, source.as_deref()) {
                                             Ok(_) => {
                                                 _ = msg_sender.send(Message::Saved(file_path.clone()));
                                                 debug!("Saved to {}", file_path.display());
                                                 // Re-apply exif
-                                                if let Some(info) = &image_info {
+                                                if let Some(info) = image_info.as_ref().filter(|_| !export.strip_metadata) {
                                                     debug!("Extended image info present");
 
                                                     // before doing anything, make sure we have raw exif data
@@ -1256,18 +2814,18 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
 
 
                         let msg_sender = state.message_channel.0.clone();
+                        let source = state.current_path.clone();
 
                         filebrowser::browse_modal(
                             true,
-                            &["png", "jpg", "bmp", "webp", "tif", "tga"],
+                            &crate::export::ExportFormat::extensions(),
                             |p| {
-                                    match state.edit_state.result_pixel_op
-                                    .save(&p) {
+                                    match state.persistent_settings.export.save(&state.edit_state.result_pixel_op, &p, source.as_deref()) {
                                         Ok(_) => {
                                             _ = msg_sender.send(Message::Saved(p.clone()));
                                             debug!("Saved to {}", p.display());
                                             // Re-apply exif
-                                            if let Some(info) = &state.image_info {
+                                            if let Some(info) = state.image_info.as_ref().filter(|_| !state.persistent_settings.export.strip_metadata) {
                                                 debug!("Extended image info present");
 
                                                 // before doing anything, make sure we have raw exif data
@@ -1293,9 +2851,60 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     }
                 }
 
+                if state.current_image.is_some() {
+                    if ui.button(format!("{COPY} Copy to clipboard")).on_hover_text(format!("Copy the edited image to the clipboard ({})", lookup(&state.persistent_settings.shortcuts, &InputEvent::CopyEdited))).clicked() {
+                        clipboard_copy(&state.edit_state.result_pixel_op);
+                        state.send_message_info("Edited image copied");
+                    }
+                    if ui.button(format!("{CODE} Copy as data URI")).on_hover_text("Copy the edited image as a base64 encoded PNG, to embed it in HTML, CSS or Markdown").clicked() {
+                        match state.persistent_settings.export.data_uri(&state.edit_state.result_pixel_op) {
+                            Ok(uri) => {
+                                let len = uri.len();
+                                match arboard::Clipboard::new().and_then(|mut c| c.set_text(uri)) {
+                                    // browsers and many editors struggle with very long URIs
+                                    Ok(_) if len > 1_000_000 => state.send_message_warn(&format!("Data URI copied. It is {} MB, which is too large for most uses.", len / 1_000_000)),
+                                    Ok(_) => state.send_message_info(&format!("Data URI copied ({} KB)", len / 1000)),
+                                    Err(e) => state.send_message_err(&format!("Could not copy: {e}")),
+                                }
+                            }
+                            Err(e) => state.send_message_err(&format!("Could not encode image: {e}")),
+                        }
+                    }
+                    if let Some(selection) = state.edit_state.selection.clone() {
+                        if ui.button(format!("{SELECTION} Export selection")).on_hover_text("Save only the selected part of the edited image, next to the original").clicked() {
+                            match selection::crop_to_selection(&state.edit_state.result_pixel_op, &selection) {
+                                Some(img) => save_region(state, &img, "selection"),
+                                None => state.send_message_warn("Nothing is selected"),
+                            }
+                        }
+                    }
+                    if ui.button(format!("{FRAME_CORNERS} Export visible area")).on_hover_text("Save the part of the edited image that is visible in the window at full resolution, next to the original").clicked() {
+                        let (x, y, w, h) = visible_region(&state.image_geometry, state.window_size, state.edit_state.result_pixel_op.dimensions());
+                        if w == 0 || h == 0 {
+                            state.send_message_warn("The image is not visible");
+                        } else {
+                            let img = image::imageops::crop_imm(&state.edit_state.result_pixel_op, x, y, w, h).to_image();
+                            save_region(state, &img, "visible");
+                        }
+                    }
+                }
+
+                if state.current_image.is_some() && ui.button(format!("{FLOPPY_DISK} Save as template")).on_hover_text(format!("Save as {}, next to the original. The name can be changed in the export settings.", state.persistent_settings.export.name_template)).clicked() {
+                    let dest = state.persistent_settings.export.templated_path(
+                        state.current_path.as_deref(),
+                        &state.persistent_settings.last_open_directory,
+                        state.edit_state.result_pixel_op.dimensions(),
+                    );
+                    // only reached with numbering turned off
+                    if dest.exists() {
+                        state.pending_save = Some(dest);
+                    } else {
+                        save_edited(state, &dest);
+                    }
+                }
+
                 if let Some(p) = &state.current_path {
                     let text = if p
-                        // .with_extension(&state.edit_state.export_extension)
                         .exists()
                     {
                         format!("{FLOPPY_DISK} Overwrite")
@@ -1304,36 +2913,33 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                     };
 
                     if ui.button(text).on_hover_text("Save the image. This will create a new file or overwrite.").clicked() {
-                        match state
-                        .edit_state
-                        .result_pixel_op
-                        .save(p) {
-                            Ok(_) => {
-                                debug!("Saved to {}", p.display());
-                                state.send_message_info(&format!("Saved to {}", p.display()));
-                                // Re-apply exif
-                                if let Some(info) = &state.image_info {
-                                    debug!("Extended image info present");
-                                    // before doing anything, make sure we have raw exif data
-                                    if info.raw_exif.is_some() {
-                                        if let Err(e) = fix_exif(&p, info.raw_exif.clone()) {
-                                            error!("{e}");
-                                        } else {
-                                            info!("Saved EXIF.")
-                                        }
-                                    } else {
-                                        debug!("No raw exif");
-                                    }
+                        let p = p.clone();
+                        save_edited(state, &p);
+                    }
+
+                    if state.is_animation && ui.button(format!("{FILM_STRIP} Save animation")).on_hover_text("Apply the edits to every frame and save the animation next to the original, in the animation format of the export settings").clicked() {
+                        let export = state.persistent_settings.export.clone();
+                        let stem = p.file_stem().unwrap_or_default().to_string_lossy();
+                        let dest = p.with_file_name(format!("{stem}-edited.{}", export.animation_format.extension()));
+                        let source = p.clone();
+                        let edit_state = state.edit_state.clone();
+                        let msg_sender = state.message_channel.0.clone();
+                        state.send_message_info("Saving animation...");
+                        std::thread::spawn(move || {
+                            match save_animation(&source, &dest, &edit_state, &export) {
+                                Ok(_) => {
+                                    debug!("Saved to {}", dest.display());
+                                    _ = msg_sender.send(Message::Saved(dest));
+                                }
+                                Err(e) => {
+                                    _ = msg_sender.send(Message::err(&format!("Could not save animation: {e}")));
                                 }
                             }
-                            Err(e) => {
-                                state.send_message_err(&format!("Could not save: {e}"));
-                            }
-                        }
+                        });
                     }
 
                     if ui.button(format!("{ARCHIVE_TRAY} Save edits")).on_hover_text("Saves an .oculante metafile in the same directory as the image. This file will contain all edits and will be restored automatically if you open the image again. This leaves the original image unmodified and allows you to continue editing later.").clicked() {
-                        if let Ok(f) = std::fs::File::create(p.with_extension("oculante")) {
+                        if let Ok(f) = std::fs::File::create(sidecar_path(p)) {
                             _ = serde_json::to_writer_pretty(&f, &state.edit_state);
                         }
                     }
@@ -1346,6 +2952,44 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
                         }
 
                     }
+
+                    if let Some(job) = &state.batch_job {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::ProgressBar::new(job.progress())
+                                    .desired_width(ui.available_width() - 70.)
+                                    .show_percentage(),
+                            );
+                            if ui.button("Cancel").clicked() {
+                                job.cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+                            }
+                        });
+                        // keep the progress bar moving
+                        ui.ctx().request_repaint();
+                        if job.is_finished() {
+                            state.batch_job = None;
+                        }
+                    } else if ui
+                        .button(format!("{FOLDERS} Apply edits to folder"))
+                        .on_hover_text("Apply the current edits to all images in this folder and save them to an 'edited' subfolder. The originals are not modified.")
+                        .clicked()
+                    {
+                        if !state.edit_state.shaders().is_empty() {
+                            state.send_message_warn("Shader operators can only be applied in the editor. Disable them to apply the other edits to the folder.");
+                        } else if let Some(parent) = p.parent() {
+                            let preset = EditPreset {
+                                pixel_op_stack: state.edit_state.pixel_op_stack.clone(),
+                                image_op_stack: state.edit_state.image_op_stack.clone(),
+                            };
+                            state.batch_job = Some(BatchJob::start(
+                                state.scrubber.entries.clone(),
+                                parent.join("edited"),
+                                preset,
+                                state.persistent_settings.export.clone(),
+                                state.message_channel.0.clone(),
+                            ));
+                        }
+                    }
                 }
             });
 
@@ -1360,6 +3004,180 @@ pub fn edit_ui(app: &mut App, ctx: &Context, state: &mut OculanteState, gfx: &mu
         });
 }
 
+/// The part of an image of `dimensions` that is visible in the window, as (x, y, width, height)
+fn visible_region(
+    geo: &ImageGeometry,
+    window_size: nalgebra::Vector2<f32>,
+    dimensions: (u32, u32),
+) -> (u32, u32, u32, u32) {
+    let (w, h) = (dimensions.0 as f32, dimensions.1 as f32);
+    // the displayed size can differ from `dimensions` while edits are processed
+    let size = (
+        geo.dimensions.0.max(1) as f32 * geo.scale,
+        geo.dimensions.1.max(1) as f32 * geo.scale,
+    );
+    let x0 = (-geo.offset.x / size.0 * w).floor().clamp(0., w);
+    let y0 = (-geo.offset.y / size.1 * h).floor().clamp(0., h);
+    let x1 = ((window_size.x - geo.offset.x) / size.0 * w)
+        .ceil()
+        .clamp(0., w);
+    let y1 = ((window_size.y - geo.offset.y) / size.1 * h)
+        .ceil()
+        .clamp(0., h);
+    (
+        x0 as u32,
+        y0 as u32,
+        (x1 - x0).max(0.) as u32,
+        (y1 - y0).max(0.) as u32,
+    )
+}
+
+/// Save the edited image to `dest` and copy the EXIF data of the original, unless the export
+/// settings remove metadata
+fn save_edited(state: &OculanteState, dest: &Path) {
+    if let Some(parent) = dest.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent) {
+            state.send_message_err(&format!("Could not create {}: {e}", parent.display()));
+            return;
+        }
+    }
+    let export = &state.persistent_settings.export;
+    // rotating a JPEG only needs a new orientation tag
+    if let (Some(source), Some(orientation)) =
+        (&state.current_path, state.edit_state.as_orientation())
+    {
+        match export.save_orientation(source, dest, orientation) {
+            Ok(true) => {
+                state.send_message_info(&format!("Saved to {} with EXIF rotation", dest.display()));
+                return;
+            }
+            Ok(false) => (),
+            Err(e) => {
+                state.send_message_err(&format!("Could not save: {e}"));
+                return;
+            }
+        }
+    }
+    match export.save(
+        &state.edit_state.result_pixel_op,
+        dest,
+        state.current_path.as_deref(),
+    ) {
+        Ok(_) => {
+            debug!("Saved to {}", dest.display());
+            state.send_message_info(&format!("Saved to {}", dest.display()));
+            // Re-apply exif
+            if let Some(info) = state.image_info.as_ref().filter(|_| !export.strip_metadata) {
+                debug!("Extended image info present");
+                // before doing anything, make sure we have raw exif data
+                if info.raw_exif.is_some() {
+                    if let Err(e) = fix_exif(dest, info.raw_exif.clone()) {
+                        error!("{e}");
+                    } else {
+                        info!("Saved EXIF.")
+                    }
+                } else {
+                    debug!("No raw exif");
+                }
+            }
+        }
+        Err(e) => {
+            state.send_message_err(&format!("Could not save: {e}"));
+        }
+    }
+}
+
+/// Import the brush at `path`. Unless `replace` is set, ask first if there is one with its name.
+fn load_brush(state: &mut OculanteState, path: PathBuf, replace: bool) {
+    let existing = brush_name(&path)
+        .ok()
+        .and_then(|name| state.edit_state.brushes.iter().position(|b| b.name == name));
+    // bundled brushes can't be replaced, `import_brush` says so
+    if existing.is_some_and(|i| i >= bundled_brushes().len()) && !replace {
+        state.pending_brush = Some(path);
+        return;
+    }
+    match import_brush(&path) {
+        Ok(brush) => match existing {
+            Some(i) => state.edit_state.brushes[i] = brush,
+            None => state.edit_state.brushes.push(brush),
+        },
+        Err(e) => state.send_message_err(&format!("Could not load brush: {e}")),
+    }
+}
+
+/// Ask before an imported brush replaces one with the same name
+pub fn brush_overwrite_ui(ctx: &Context, state: &mut OculanteState) {
+    let Some(path) = state.pending_brush.clone() else {
+        return;
+    };
+    egui::Window::new("Brush exists")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "There is a brush named {} already. Strokes painted with it will use the new one.",
+                brush_name(&path).unwrap_or_default()
+            ));
+            ui.horizontal(|ui| {
+                if ui.button("Replace").clicked() {
+                    state.pending_brush = None;
+                    load_brush(state, path.clone(), true);
+                }
+                if ui.button("Cancel").clicked() {
+                    state.pending_brush = None;
+                }
+            });
+        });
+}
+
+/// Ask before a templated save overwrites an existing file
+pub fn overwrite_ui(ctx: &Context, state: &mut OculanteState) {
+    let Some(dest) = state.pending_save.clone() else {
+        return;
+    };
+    egui::Window::new("File exists")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+        .show(ctx, |ui| {
+            ui.label(format!("{} already exists.", dest.display()));
+            ui.horizontal(|ui| {
+                if ui.button(format!("{FLOPPY_DISK} Overwrite")).clicked() {
+                    state.pending_save = None;
+                    save_edited(state, &dest);
+                }
+                if ui.button("Cancel").clicked() {
+                    state.pending_save = None;
+                }
+            });
+        });
+}
+
+/// Save a part of the edited image next to the current file, with `suffix` added to its name
+fn save_region(state: &OculanteState, img: &RgbaImage, suffix: &str) {
+    let export = &state.persistent_settings.export;
+    let (dir, stem) = match &state.current_path {
+        Some(p) => (
+            p.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+            p.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        ),
+        None => (
+            state.persistent_settings.last_open_directory.clone(),
+            "untitled".into(),
+        ),
+    };
+    let dest = dir.join(format!("{stem}-{suffix}.{}", export.format.extension()));
+    match export.save(img, &dest, state.current_path.as_deref()) {
+        Ok(_) => state.send_message_info(&format!("Saved to {}", dest.display())),
+        Err(e) => state.send_message_err(&format!("Could not save: {e}")),
+    }
+}
+
 // TODO redo as impl UI
 pub fn tooltip(r: Response, tooltip: &str, hotkey: &str, _ui: &mut Ui) -> Response {
     r.on_hover_ui(|ui| {
@@ -1406,9 +3224,12 @@ pub fn unframed_button_colored(text: impl Into<String>, is_colored: bool, ui: &m
     }
 }
 
+/// The settings of a stroke, `index` is its position in the list of strokes and tells the
+/// controls of different strokes apart
 pub fn stroke_ui(
     stroke: &mut PaintStroke,
-    brushes: &[RgbaImage],
+    index: usize,
+    brushes: &[Brush],
     ui: &mut Ui,
     gfx: &mut Graphics,
 ) -> Response {
@@ -1446,8 +3267,41 @@ pub fn stroke_ui(
         combined_response.hovered = true;
     }
 
+    for (value, range, hover) in [
+        (
+            &mut stroke.opacity,
+            0.0..=1.0,
+            "Opacity of the whole stroke",
+        ),
+        (
+            &mut stroke.flow,
+            0.0..=1.0,
+            "Opacity of each stamp, which builds up where they overlap",
+        ),
+        (
+            &mut stroke.spacing,
+            0.05..=2.0,
+            "Distance between stamps, relative to the brush width",
+        ),
+    ] {
+        let r = ui
+            .add(egui::DragValue::new(value).clamp_range(range).speed(0.01))
+            .on_hover_text(hover);
+        if r.changed() {
+            combined_response.changed = true;
+        }
+        if r.hovered() {
+            combined_response.hovered = true;
+        }
+    }
+
     ui.horizontal(|ui| {
-        if let Some(notan_texture) = brushes[stroke.brush_index].to_texture_premult(gfx) {
+        if let Some(notan_texture) = brushes
+            .iter()
+            .find(|b| b.name == stroke.brush)
+            .or(brushes.first())
+            .and_then(|b| b.image.to_texture_premult(gfx))
+        {
             let texture_id = gfx.egui_register_texture(&notan_texture);
             ui.add(
                 egui::Image::new(texture_id)
@@ -1455,12 +3309,12 @@ pub fn stroke_ui(
             );
         }
 
-        let r = egui::ComboBox::from_id_source(format!("s {:?}", stroke.points))
-            .selected_text(format!("Brush {}", stroke.brush_index))
+        let r = egui::ComboBox::from_id_source(("stroke brush", index))
+            .selected_text(&stroke.brush)
             .show_ui(ui, |ui| {
-                for (b_i, b) in brushes.iter().enumerate() {
+                for b in brushes {
                     ui.horizontal(|ui| {
-                        if let Some(notan_texture) = b.to_texture_premult(gfx) {
+                        if let Some(notan_texture) = b.image.to_texture_premult(gfx) {
                             let texture_id = gfx.egui_register_texture(&notan_texture);
                             ui.add(
                                 egui::Image::new(texture_id)
@@ -1469,7 +3323,7 @@ pub fn stroke_ui(
                         }
 
                         if ui
-                            .selectable_value(&mut stroke.brush_index, b_i, format!("Brush {b_i}"))
+                            .selectable_value(&mut stroke.brush, b.name.clone(), &b.name)
                             .clicked()
                         {
                             combined_response.changed = true
@@ -1484,6 +3338,33 @@ pub fn stroke_ui(
         }
     });
 
+    let r = stroke.blend_mode.ui(ui, ("stroke blend", index));
+    if r.changed() {
+        combined_response.changed = true;
+    }
+    if r.hovered() {
+        combined_response.hovered = true;
+    }
+
+    let r = egui::ComboBox::from_id_source(("stroke mode", index))
+        .selected_text(stroke.mode.to_string())
+        .width(60.)
+        .show_ui(ui, |ui| {
+            for mode in BrushMode::ALL {
+                if ui
+                    .selectable_value(&mut stroke.mode, mode, mode.to_string())
+                    .clicked()
+                {
+                    combined_response.changed = true;
+                }
+            }
+        })
+        .response
+        .on_hover_text("Paint with the color, or blur or smudge the pixels under the brush");
+    if r.hovered() {
+        combined_response.hovered = true;
+    }
+
     if combined_response.hovered() {
         stroke.highlight = true;
     } else {
@@ -1495,27 +3376,178 @@ pub fn stroke_ui(
     combined_response
 }
 
+/// Run the custom shaders of the edit on `shader_input`, into the result of the edit
+fn render_shaders(app: &App, ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
+    let Some(mut result) = state.edit_state.shader_input.clone() else {
+        return;
+    };
+    let inputs = ShaderInputs {
+        time: app.timer.elapsed_f32(),
+        mouse: [state.cursor_relative.x, state.cursor_relative.y],
+        zoom: state.image_geometry.scale,
+    };
+    let shaders = state
+        .edit_state
+        .shaders()
+        .into_iter()
+        .map(|(fragment, params)| (fragment.to_string(), params.to_vec()))
+        .collect::<Vec<_>>();
+    state.shaders.truncate(shaders.len());
+    for (i, (fragment, params)) in shaders.iter().enumerate() {
+        if state.shaders.len() == i {
+            match ShaderState::new(gfx) {
+                Ok(shader) => state.shaders.push(shader),
+                Err(e) => {
+                    error!("Could not set up shader: {e}");
+                    break;
+                }
+            }
+        }
+        let shader = &mut state.shaders[i];
+        shader.compile(gfx, fragment, params);
+        ctx.data_mut(|w| w.insert_temp(shader_error_id(fragment), shader.error.clone()));
+        if shader.error.is_none() {
+            match shader.render(gfx, &result, inputs, params) {
+                Ok(img) => result = img,
+                Err(e) => error!("Could not render shader: {e}"),
+            }
+        }
+    }
+    state.edit_state.result_pixel_op = result;
+}
+
+/// The draggable line between the original and the edited image
+fn split_compare_ui(ctx: &Context, split: &mut f32, geo: &ImageGeometry) {
+    let width = geo.dimensions.0 as f32 * geo.scale;
+    let height = geo.dimensions.1 as f32 * geo.scale;
+    if width <= 0. {
+        return;
+    }
+    egui::Area::new(Id::new("split compare"))
+        .fixed_pos(Pos2::new(
+            geo.offset.x + *split * width - ICON_SIZE / 2.,
+            geo.offset.y,
+        ))
+        .show(ctx, |ui| {
+            let (rect, response) =
+                ui.allocate_exact_size(Vec2::new(ICON_SIZE, height), Sense::drag());
+            ui.painter().vline(
+                rect.center().x,
+                rect.y_range(),
+                Stroke::new(2., ui.style().visuals.selection.bg_fill),
+            );
+            if response.dragged() {
+                *split = drag_split(*split, response.drag_delta().x, geo);
+            }
+            response.on_hover_cursor(CursorIcon::ResizeHorizontal);
+        });
+}
+
+/// The position of the split line after dragging it by `delta` screen pixels
+pub fn drag_split(split: f32, delta: f32, geo: &ImageGeometry) -> f32 {
+    let width = geo.dimensions.0 as f32 * geo.scale;
+    if width <= 0. {
+        return split;
+    }
+    (split + delta / width).clamp(0., 1.)
+}
+
 fn modifier_stack_ui(
-    stack: &mut Vec<ImageOperation>,
+    stack: &mut Vec<ImgOpItem>,
     image_changed: &mut bool,
+    dragging: &mut bool,
     ui: &mut Ui,
     geo: &ImageGeometry,
     mouse_grab: &mut bool,
+    thumbnails: &[Texture],
+    gfx: &mut Graphics,
 ) {
     let mut delete: Option<usize> = None;
     let mut swap: Option<(usize, usize)> = None;
+    let mut move_op: Option<(usize, usize)> = None;
 
     // egui::Grid::new("dfdfd").num_columns(2).show(ui, |ui| {
-    for (i, operation) in stack.iter_mut().enumerate() {
-        ui.label_i(&format!("{operation}"));
+    for (i, item) in stack.iter_mut().enumerate() {
+        let ImgOpItem {
+            active,
+            operation,
+            mask,
+            blend_mode,
+        } = item;
+        // the label doubles as drag handle to reorder operators
+        let per_pixel = operation.is_per_pixel();
+        let label = ui
+            .dnd_drag_source(
+                Id::new(("operator", per_pixel, i)),
+                DraggedOperator {
+                    index: i,
+                    per_pixel,
+                },
+                |ui| {
+                    ui.horizontal(|ui| {
+                        // the result up to and including this operator
+                        if let Some(texture) = thumbnails.get(i) {
+                            let texture_id = gfx.egui_register_texture(texture);
+                            ui.add(
+                                egui::Image::new(texture_id)
+                                    .fit_to_exact_size(Vec2::splat(ui.available_height())),
+                            )
+                            .on_hover_ui(|ui| {
+                                ui.add(
+                                    egui::Image::new(texture_id)
+                                        .fit_to_exact_size(Vec2::splat(128.)),
+                                );
+                            });
+                        }
+                        ui.label_i(&format!("{operation}"))
+                    })
+                    .inner
+                },
+            )
+            .response;
+
+        if let Some(dragged) = label.dnd_hover_payload::<DraggedOperator>() {
+            if dragged.per_pixel == per_pixel && dragged.index != i {
+                // show where the operator will end up
+                let y = if dragged.index < i {
+                    label.rect.bottom()
+                } else {
+                    label.rect.top()
+                };
+                ui.painter().hline(
+                    label.rect.x_range(),
+                    y,
+                    Stroke::new(2., ui.style().visuals.selection.bg_fill),
+                );
+            }
+        }
+
+        if let Some(dragged) = label.dnd_release_payload::<DraggedOperator>() {
+            // pixel and image operators live in separate stacks
+            if dragged.per_pixel == per_pixel && dragged.index != i {
+                move_op = Some((dragged.index, i));
+                *image_changed = true;
+            }
+        }
 
         // let op draw itself and check for response
 
-        ui.push_id(i, |ui| {
+        // both stacks are drawn into the same ui, so the ids of the widgets of an operator
+        // need to include the stack as well. Combo boxes with a fixed id, like the noise
+        // distribution, are told apart by this too, as their id is made from the ui's.
+        ui.push_id((per_pixel, i), |ui| {
             // ui.end_row();
 
             // draw the image operator
-            if operation.ui(ui, geo, mouse_grab).changed() {
+            let r = operation.ui(ui, geo, mouse_grab);
+            if r.changed() {
+                *image_changed = true;
+                if r.dragged() || ui.memory(|m| m.dragged_id().is_some()) {
+                    *dragging = true;
+                }
+            }
+
+            if operation.supports_blend_mode() && blend_mode.ui(ui, ("op blend", i)).changed() {
                 *image_changed = true;
             }
 
@@ -1543,6 +3575,39 @@ fn modifier_stack_ui(
                     *image_changed = true;
                 }
 
+                if egui::Button::new(if mask.is_some() {
+                    SELECTION_SLASH
+                } else {
+                    SELECTION
+                })
+                .small()
+                .frame(false)
+                .ui(ui)
+                .on_hover_text(if mask.is_some() {
+                    "Remove mask"
+                } else {
+                    "Add mask"
+                })
+                .clicked()
+                {
+                    *mask = match mask {
+                        Some(_) => None,
+                        None => Some(Default::default()),
+                    };
+                    *image_changed = true;
+                }
+
+                if egui::Button::new(if *active { EYE } else { EYE_SLASH })
+                    .small()
+                    .frame(false)
+                    .ui(ui)
+                    .on_hover_text("Toggle operator")
+                    .clicked()
+                {
+                    *active = !*active;
+                    *image_changed = true;
+                }
+
                 if egui::Button::new("⏶")
                     .small()
                     .frame(false)
@@ -1570,6 +3635,16 @@ fn modifier_stack_ui(
         });
 
         ui.end_row();
+
+        if let Some(mask) = mask {
+            ui.label_i(&format!("{} Mask", SELECTION));
+            ui.push_id(("mask", i), |ui| {
+                if mask.ui(ui, geo, mouse_grab).changed() {
+                    *image_changed = true;
+                }
+            });
+            ui.end_row();
+        }
     }
     // });
 
@@ -1582,6 +3657,16 @@ fn modifier_stack_ui(
             stack.swap(swap.0, swap.1);
         }
     }
+
+    if let Some((from, to)) = move_op {
+        move_operator(stack, from, to);
+    }
+}
+
+/// Drag and drop payload to reorder operators
+struct DraggedOperator {
+    index: usize,
+    per_pixel: bool,
 }
 
 /// A ui for lossless JPEG editing
@@ -1673,94 +3758,600 @@ fn jpg_lossless_ui(state: &mut OculanteState, ui: &mut Ui) {
                 }
             });
 
-            ui.vertical_centered_justified(|ui| {
+            ui.separator();
+            // everything else needs the decoded image
+            let blocker = state.edit_state.lossless_blocker();
+            let has_ops = state
+                .edit_state
+                .image_op_stack
+                .iter()
+                .any(|i| i.active);
+            ui.add_enabled_ui(has_ops && blocker.is_none(), |ui| {
+                let r = ui
+                    .button(format!("{CROP} Apply edits losslessly"))
+                    .on_hover_text("Write the Crop, Rotate and Flip operators above to the file without recompressing it. Crops are extended to the 8 or 16 pixel block grid of the JPEG.");
+                let r = match &blocker {
+                    Some(op) => r.on_disabled_hover_text(format!("{op} can't be applied without recompressing. Disable or remove it first.")),
+                    None => r.on_disabled_hover_text("Add a Crop, Rotate or Flip operator first"),
+                };
+                if r.clicked() {
+                    match lossless_apply(p, &state.edit_state.image_op_stack) {
+                        Ok(_) => {
+                            // the file now contains the edits
+                            state.edit_state.image_op_stack.retain(|i| !i.active);
+                            state.send_message_info("Edits written losslessly");
+                            reload = true;
+                        }
+                        Err(e) => state.send_message_err(&format!("Could not transform: {e}")),
+                    }
+                }
+            });
+
+            if reload {
+                state.is_loaded = false;
+                state.player.cache.clear();
+                state.player.load(&p, state.message_channel.0.clone());
+            }
+        });
+    }
+}
+
+pub fn scrubber_ui(state: &mut OculanteState, ui: &mut Ui) {
+    let len = state.scrubber.len().saturating_sub(1);
+
+    if ui
+        .slider_timeline(&mut state.scrubber.index, 0..=len)
+        .changed()
+    {
+        let p = state.scrubber.set(state.scrubber.index);
+        state.current_path = Some(p.clone());
+        state.player.load(&p, state.message_channel.0.clone());
+    }
+}
+
+/// The folder tree, and the images of the folder selected in it
+pub fn folder_panel_ui(ctx: &Context, state: &mut OculanteState) {
+    let current_folder = state
+        .current_path
+        .as_ref()
+        .and_then(|p| p.parent())
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| state.persistent_settings.last_open_directory.clone());
+    let current_path = state.current_path.clone();
+    let mut open = None;
+
+    egui::SidePanel::left("folders")
+        .resizable(true)
+        .default_width(PANEL_WIDTH)
+        .show(ctx, |ui| {
+            let tree = &mut state.folder_tree;
+            ui.horizontal(|ui| {
+                ui.label_i(&format!("{FOLDERS} Folders"));
+                if ui
+                    .small_button(ARROW_CLOCKWISE)
+                    .on_hover_text("Read the folders again")
+                    .clicked()
+                {
+                    tree.refresh();
+                }
+            });
+            let roots = folders::roots(&current_folder);
+            // the path to the current folder is expanded under the first root it is in
+            let open_root = roots
+                .iter()
+                .find(|r| current_folder.starts_with(r))
+                .cloned()
+                .unwrap_or_default();
+            egui::ScrollArea::vertical()
+                .id_source("folder tree")
+                .auto_shrink([false; 2])
+                .max_height(ui.available_height() / 2.)
+                .show(ui, |ui| {
+                    for root in &roots {
+                        folder_node(ui, tree, root, &current_folder, &open_root);
+                    }
+                });
+
+            ui.separator();
+            let folder = tree
+                .selected
+                .clone()
+                .unwrap_or_else(|| current_folder.clone());
+            ui.label_i(&format!("{FOLDER} {}", folders::name(&folder)));
+            let images = tree.images(&folder);
+            if images.is_empty() {
+                ui.label("No images");
+            }
+            egui::ScrollArea::vertical()
+                .id_source("folder images")
+                .auto_shrink([false; 2])
+                .show_rows(
+                    ui,
+                    ui.spacing().interact_size.y,
+                    images.len(),
+                    |ui, range| {
+                        for image in &images[range] {
+                            let is_current = current_path.as_ref() == Some(image);
+                            if ui
+                                .selectable_label(is_current, folders::name(image))
+                                .clicked()
+                            {
+                                open = Some(image.clone());
+                            }
+                        }
+                    },
+                );
+        });
+
+    if let Some(image) = open {
+        state.folder_tree.selected = None;
+        load_image_from_path(&image, state);
+    }
+}
 
+/// A folder of the tree and, once expanded, the folders in it
+fn folder_node(
+    ui: &mut Ui,
+    tree: &mut FolderTree,
+    folder: &Path,
+    current: &Path,
+    open_root: &Path,
+) {
+    let id = ui.make_persistent_id(folder);
+    let default_open = current.starts_with(folder) && folder.starts_with(open_root);
+    let selected = tree.selected.as_deref().unwrap_or(current) == folder;
+    egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, default_open)
+        .show_header(ui, |ui| {
+            if ui
+                .selectable_label(selected, folders::name(folder))
+                .clicked()
+            {
+                tree.selected = Some(folder.to_path_buf());
+            }
+        })
+        .body(|ui| {
+            for subfolder in tree.subfolders(folder).to_vec() {
+                folder_node(ui, tree, &subfolder, current, open_root);
+            }
+        });
+}
 
+/// The thumbnails of the folder in a grid. Arrow keys move the selection, Enter opens it.
+pub fn gallery_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
+    let Some(gallery) = state.gallery.as_mut() else {
+        return;
+    };
+    gallery.receive(gfx);
+    let entries = &state.scrubber.entries;
+    let cell = Vec2::new(THUMBNAIL_SIZE as f32 + 16., THUMBNAIL_SIZE as f32 + 36.);
+    let mut open = None;
+    let mut close = false;
+
+    egui::CentralPanel::default().show(ctx, |ui| {
+        if entries.is_empty() {
+            ui.centered_and_justified(|ui| ui.label("There are no images in this folder"));
+            return;
+        }
+        let spacing = ui.spacing().item_spacing.x;
+        let columns = (((ui.available_width() + spacing) / (cell.x + spacing)) as usize).max(1);
+        let len = entries.len();
+        ctx.input(|i| {
+            if i.key_pressed(Key::ArrowRight) {
+                gallery.select(1, len);
+            }
+            if i.key_pressed(Key::ArrowLeft) {
+                gallery.select(-1, len);
+            }
+            if i.key_pressed(Key::ArrowDown) {
+                gallery.select(columns as isize, len);
+            }
+            if i.key_pressed(Key::ArrowUp) {
+                gallery.select(-(columns as isize), len);
+            }
+            if i.key_pressed(Key::Home) {
+                gallery.select(-(len as isize), len);
+            }
+            if i.key_pressed(Key::End) {
+                gallery.select(len as isize, len);
+            }
+            if i.key_pressed(Key::Enter) {
+                open = Some(gallery.selected);
+            }
+            if i.key_pressed(Key::Escape) {
+                close = true;
+            }
+        });
 
-                let crop_ops = state
-                    .edit_state
-                    .image_op_stack
-                    .iter()
-                    .filter(|op| matches!(op, ImageOperation::Crop(_)))
-                    .collect::<Vec<_>>();
+        let rows = (len + columns - 1) / columns;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false; 2])
+            .show_rows(ui, cell.y, rows, |ui, row_range| {
+                for row in row_range {
+                    ui.horizontal(|ui| {
+                        for index in row * columns..((row + 1) * columns).min(len) {
+                            let path = &entries[index];
+                            let (rect, response) = ui.allocate_exact_size(cell, Sense::click());
+                            let selected = index == gallery.selected;
+                            if selected || response.hovered() {
+                                let visuals = ui.style().interact_selectable(&response, selected);
+                                ui.painter().rect_filled(rect, 4., visuals.weak_bg_fill);
+                            }
 
-                let crop = crop_ops
-                    .first()
-                    .cloned()
-                    .cloned()
-                    .unwrap_or(ImageOperation::Crop([0, 0, 0, 0]));
+                            let image_rect = Rect::from_min_size(
+                                rect.min + Vec2::splat(8.),
+                                Vec2::splat(THUMBNAIL_SIZE as f32),
+                            );
+                            if let Some(texture) = gallery.thumbnail(path) {
+                                let texture = gfx.egui_register_texture(texture);
+                                let fit = (image_rect.size() / texture.size).min_elem();
+                                egui::Image::new(texture).paint_at(
+                                    ui,
+                                    Rect::from_center_size(image_rect.center(), texture.size * fit),
+                                );
+                            } else if gallery.failed(path) {
+                                ui.painter().text(
+                                    image_rect.center(),
+                                    Align2::CENTER_CENTER,
+                                    WARNING,
+                                    FontId::proportional(32.),
+                                    ui.style().visuals.weak_text_color(),
+                                );
+                            } else {
+                                ui.put(image_rect, egui::Spinner::new());
+                            }
 
-                if crop_ops.is_empty() {
-                    info!("A missing crop operator was added.");
-                    state
-                        .edit_state
-                        .image_op_stack
-                        .push(ImageOperation::Crop([0, 0, 0, 0]))
+                            let name = path
+                                .file_name()
+                                .map(|f| f.to_string_lossy().to_string())
+                                .unwrap_or_default();
+                            let name_rect = Rect::from_min_max(
+                                egui::pos2(rect.left() + 4., image_rect.bottom() + 4.),
+                                rect.right_bottom() - Vec2::splat(4.),
+                            );
+                            ui.put(name_rect, egui::Label::new(name.as_str()).truncate(true));
+
+                            let response = response.on_hover_text(name);
+                            if response.clicked() {
+                                gallery.selected = index;
+                            }
+                            if response.double_clicked() {
+                                open = Some(index);
+                            }
+                            if selected && gallery.scroll_to_selected {
+                                ui.scroll_to_rect(rect, None);
+                                gallery.scroll_to_selected = false;
+                            }
+                        }
+                    });
                 }
+            });
+    });
 
-                ui.add_enabled_ui(crop != ImageOperation::Crop([0, 0, 0, 0]), |ui| {
+    if close {
+        state.gallery = None;
+    }
+    if let Some(index) = open {
+        let path = state.scrubber.set(index);
+        load_image_from_path(&path, state);
+        state.gallery = None;
+    }
+}
 
-                    if ui
-                        .button("Crop")
-                        .on_hover_text("Crop according to values defined in the operator stack above")
-                        .on_disabled_hover_text("Please modify crop values above before cropping. You would be cropping nothing right now.")
-                        .clicked()
-                    {
-                        match crop {
-                            ImageOperation::Crop(amt) => {
-                                debug!("CROP {:?}", amt);
-
-                                let dim = state
-                                    .current_image
-                                    .as_ref()
-                                    .map(|i| i.dimensions())
-                                    .unwrap_or_default();
-
-                                let crop_range = cropped_range(&amt, &dim);
-
-                                match lossless_tx(
-                                    p,
-                                    turbojpeg::Transform {
-                                        op: turbojpeg::TransformOp::None,
-                                        crop: Some(turbojpeg::TransformCrop {
-                                            x: crop_range[0] as usize,
-                                            y: crop_range[1] as usize,
-                                            width: Some(crop_range[2] as usize),
-                                            height: Some(crop_range[3] as usize),
-                                        }),
-                                        ..turbojpeg::Transform::default()
-                                    },
-                                ) {
-                                    Ok(_) => reload = true,
-                                    Err(e) => log::warn!("{e}"),
-                                };
-                            }
-                            _ => (),
+/// The pixels around the cursor magnified, and the value of the one under it before and after
+/// the edits. Floating, or docked to the right.
+pub fn inspector_ui(ctx: &Context, state: &mut OculanteState) {
+    if !state.persistent_settings.show_inspector {
+        return;
+    }
+    let x = state.cursor_relative.x.floor() as i64;
+    let y = state.cursor_relative.y.floor() as i64;
+    let original = state.current_image.as_ref();
+    let edited = Some(&state.edit_state.result_pixel_op).filter(|r| r.width() > 0);
+    let hdr = state.current_hdr_image.as_ref();
+    let mut dock = state.persistent_settings.dock_inspector;
+
+    let contents = |ui: &mut Ui| {
+        let Some(shown) = edited.or(original) else {
+            ui.label("No image");
+            return;
+        };
+        // the magnified pixels, the one under the cursor in the middle
+        let side = (inspector::RADIUS * 2 + 1) as f32;
+        let size = ui.available_width().min(240.);
+        let (rect, _) = ui.allocate_exact_size(Vec2::splat(size), Sense::hover());
+        let cell = size / side;
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0., ui.style().visuals.extreme_bg_color);
+        for (i, p) in inspector::neighbourhood(shown, x, y)
+            .into_iter()
+            .enumerate()
+        {
+            if let Some(p) = p {
+                let min = rect.min
+                    + Vec2::new((i as f32 % side).floor(), (i as f32 / side).floor()) * cell;
+                painter.rect_filled(
+                    Rect::from_min_size(min, Vec2::splat(cell)),
+                    0.,
+                    Color32::from_rgba_unmultiplied(p[0], p[1], p[2], p[3]),
+                );
+            }
+        }
+        let center = Rect::from_center_size(rect.center(), Vec2::splat(cell));
+        painter.rect_stroke(center, 0., Stroke::new(3., Color32::BLACK));
+        painter.rect_stroke(center, 0., Stroke::new(1., Color32::WHITE));
+
+        ui.label(RichText::new(format!("{x}, {y}")).monospace());
+        // the value of the pixel in every notation, before and after the edits
+        let mut columns = vec![original
+            .and_then(|o| inspector::pixel(o, x, y))
+            .map(|p| inspector::notations(p, inspector::float(p, hdr, x, y)))];
+        if let Some(edited) = edited {
+            columns.push(
+                inspector::pixel(edited, x, y)
+                    .map(|p| inspector::notations(p, inspector::float(p, None, x, y))),
+            );
+        }
+        egui::Grid::new("inspector_values")
+            .striped(true)
+            .show(ui, |ui| {
+                if edited.is_some() {
+                    ui.label("");
+                    ui.label("Original");
+                    ui.label("Edited");
+                    ui.end_row();
+                }
+                for (i, label) in inspector::NOTATIONS.iter().enumerate() {
+                    ui.label(*label);
+                    for column in &columns {
+                        match column {
+                            Some(values) => ui.label(RichText::new(&values[i]).monospace()),
+                            None => ui.label("-"),
                         };
                     }
+                    ui.end_row();
+                }
+            });
+        ui.checkbox(&mut dock, "Dock to the side");
+    };
+
+    if dock {
+        egui::SidePanel::right("inspector")
+            .default_width(PANEL_WIDTH)
+            .show(ctx, contents);
+    } else {
+        let mut open = true;
+        egui::Window::new("Pixel inspector")
+            .open(&mut open)
+            .resizable(false)
+            .default_pos(egui::pos2(ctx.screen_rect().right() - 280., 60.))
+            .show(ctx, contents);
+        state.persistent_settings.show_inspector = open;
+    }
+    state.persistent_settings.dock_inspector = dock;
+}
+
+/// The histogram of the image on screen, computed again when the image or its edits change
+pub fn histogram_ui(ctx: &Context, state: &mut OculanteState) {
+    if !state.persistent_settings.show_histogram {
+        return;
+    }
+    if state.histogram.is_none() {
+        let edited = Some(&state.edit_state.result_pixel_op).filter(|r| r.width() > 0);
+        state.histogram = edited
+            .or(state.current_image.as_ref())
+            .map(histogram::Histogram::from_image);
+    }
+    let hist = state.histogram.as_ref();
+    let log = &mut state.persistent_settings.histogram_log;
+    let mut open = true;
+    egui::Window::new("Histogram")
+        .open(&mut open)
+        .default_width(PANEL_WIDTH)
+        .default_pos(egui::pos2(ctx.screen_rect().right() - 280., 400.))
+        .show(ctx, |ui| {
+            let Some(hist) = hist else {
+                ui.label("No image");
+                return;
+            };
+            ui.checkbox(log, "Log scale");
+            let colors = [
+                Color32::from_gray(200),
+                Color32::RED,
+                Color32::GREEN,
+                Color32::BLUE,
+            ];
+            Plot::new("live_histogram")
+                .allow_zoom(false)
+                .allow_drag(false)
+                .allow_scroll(false)
+                .show_y(false)
+                .include_x(0.)
+                .include_x(255.)
+                .include_y(0.)
+                .height(140.)
+                .show(ui, |plot_ui| {
+                    for ((name, counts), color) in hist.channels().into_iter().zip(colors) {
+                        let points = counts
+                            .iter()
+                            .enumerate()
+                            .map(|(i, c)| [i as f64, histogram::height(*c, *log)])
+                            .collect::<PlotPoints>();
+                        plot_ui.line(Line::new(points).name(name).color(color).fill(0.).width(1.));
+                    }
                 });
-                });
+        });
+    state.persistent_settings.show_histogram = open;
+}
 
+/// The waveform, RGB parade or vectorscope of the image on screen. Floating, or docked to the
+/// right.
+pub fn scopes_ui(ctx: &Context, state: &mut OculanteState, gfx: &mut Graphics) {
+    if !state.persistent_settings.show_scopes {
+        return;
+    }
+    let mut scope = state.persistent_settings.scope;
+    let mut dock = state.persistent_settings.dock_scopes;
+    if state.scope_texture.is_none() {
+        let edited = Some(&state.edit_state.result_pixel_op).filter(|r| r.width() > 0);
+        state.scope_texture = edited
+            .or(state.current_image.as_ref())
+            .and_then(|img| scope.render(&scopes::downsample(img)).to_texture(gfx, true));
+    }
+    let texture = state
+        .scope_texture
+        .as_ref()
+        .map(|t| gfx.egui_register_texture(t));
 
-            if reload {
-                state.is_loaded = false;
-                state.player.cache.clear();
-                state.player.load(&p, state.message_channel.0.clone());
+    let contents = |ui: &mut Ui| {
+        ui.horizontal(|ui| {
+            for s in Scope::iter() {
+                ui.selectable_value(&mut scope, s, s.to_string());
             }
         });
+        let Some(texture) = texture else {
+            ui.label("No image");
+            return;
+        };
+        let width = ui.available_width().max(100.);
+        let height = match scope {
+            Scope::Vectorscope => width,
+            _ => width * 0.6,
+        };
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(width, height), Sense::hover());
+        egui::Image::new(texture).paint_at(ui, rect);
+
+        // the graticule
+        let painter = ui.painter_at(rect);
+        let stroke = Stroke::new(1., Color32::from_white_alpha(50));
+        match scope {
+            Scope::Vectorscope => {
+                painter.circle_stroke(rect.center(), width / 2., stroke);
+                painter.hline(rect.x_range(), rect.center().y, stroke);
+                painter.vline(rect.center().x, rect.y_range(), stroke);
+                // skin tones of any complexion are close to this line
+                let angle = 123_f32.to_radians();
+                painter.line_segment(
+                    [
+                        rect.center(),
+                        rect.center() + Vec2::new(angle.cos(), -angle.sin()) * width / 2.,
+                    ],
+                    Stroke::new(1., Color32::from_rgba_unmultiplied(255, 180, 120, 90)),
+                );
+            }
+            _ => {
+                for step in 0..=4 {
+                    let y = rect.bottom() - rect.height() * step as f32 / 4.;
+                    painter.hline(rect.x_range(), y, stroke);
+                    painter.text(
+                        Pos2::new(rect.left() + 2., y),
+                        Align2::LEFT_BOTTOM,
+                        format!("{}", step * 25),
+                        FontId::monospace(9.),
+                        Color32::from_white_alpha(120),
+                    );
+                }
+                if scope == Scope::Parade {
+                    for i in 1..3 {
+                        painter.vline(
+                            rect.left() + rect.width() * i as f32 / 3.,
+                            rect.y_range(),
+                            stroke,
+                        );
+                    }
+                }
+            }
+        }
+        ui.checkbox(&mut dock, "Dock to the side");
+    };
+
+    if dock {
+        egui::SidePanel::right("scopes")
+            .default_width(PANEL_WIDTH)
+            .show(ctx, contents);
+    } else {
+        let mut open = true;
+        egui::Window::new("Scopes")
+            .open(&mut open)
+            .default_width(320.)
+            .default_pos(egui::pos2(ctx.screen_rect().right() - 380., 120.))
+            .show(ctx, contents);
+        state.persistent_settings.show_scopes = open;
     }
+    if scope != state.persistent_settings.scope {
+        state.persistent_settings.scope = scope;
+        state.scope_texture = None;
+    }
+    state.persistent_settings.dock_scopes = dock;
 }
 
-pub fn scrubber_ui(state: &mut OculanteState, ui: &mut Ui) {
-    let len = state.scrubber.len().saturating_sub(1);
+/// A list of the recently opened images to find one by typing parts of its path
+pub fn recent_ui(ctx: &Context, state: &mut OculanteState) {
+    let Some(filter) = state.recent_filter.as_mut() else {
+        return;
+    };
+    let mut matches = state
+        .persistent_settings
+        .recent_images
+        .iter()
+        .filter(|r| r.exists())
+        .filter_map(|r| Some((fuzzy_match(filter, &r.to_string_lossy())?, r)))
+        .collect::<Vec<_>>();
+    // stable, so equal matches stay in the order they were opened
+    matches.sort_by_key(|(skipped, _)| *skipped);
+    let mut open = None;
+    let mut close = false;
+
+    egui::Window::new("Recent files")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(Align2::CENTER_TOP, Vec2::new(0., 60.))
+        .show(ctx, |ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(filter)
+                    .hint_text("Type to filter")
+                    .desired_width(400.),
+            );
+            response.request_focus();
+            ctx.input(|i| {
+                if i.key_pressed(Key::Enter) {
+                    open = matches.first().map(|(_, r)| r.to_path_buf());
+                }
+                if i.key_pressed(Key::Escape) {
+                    close = true;
+                }
+            });
+            if matches.is_empty() {
+                ui.label("No recent files match");
+            }
+            egui::ScrollArea::vertical()
+                .max_height(400.)
+                .show(ui, |ui| {
+                    for (i, (_, r)) in matches.iter().enumerate() {
+                        let name = r
+                            .file_name()
+                            .map(|f| f.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        let folder = r
+                            .parent()
+                            .map(|p| p.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        ui.horizontal(|ui| {
+                            // the first one is opened with Enter
+                            if ui.selectable_label(i == 0, name).clicked() {
+                                open = Some(r.to_path_buf());
+                            }
+                            ui.label(RichText::new(folder).weak());
+                        });
+                    }
+                });
+        });
 
-    if ui
-        .slider_timeline(&mut state.scrubber.index, 0..=len)
-        .changed()
-    {
-        let p = state.scrubber.set(state.scrubber.index);
-        state.current_path = Some(p.clone());
-        state.player.load(&p, state.message_channel.0.clone());
+    if let Some(path) = open {
+        load_image_from_path(&path, state);
+        close = true;
+    }
+    if close {
+        state.recent_filter = None;
     }
 }
 
@@ -1847,12 +4438,171 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, gfx: &mu
             ui.ctx().memory_mut(|w| w.open_popup(Id::new("OPEN")));
         }
 
+        if unframed_button(GRID_FOUR, ui)
+            .on_hover_text(format!(
+                "Show the images of the folder ({})",
+                lookup(&state.persistent_settings.shortcuts, &Gallery)
+            ))
+            .clicked()
+        {
+            toggle_gallery(state);
+        }
+
+        if unframed_button_colored(EYEDROPPER, state.persistent_settings.show_inspector, ui)
+            .on_hover_text(format!(
+                "Show the pixel inspector ({})",
+                lookup(&state.persistent_settings.shortcuts, &PixelInspector)
+            ))
+            .clicked()
+        {
+            state.persistent_settings.show_inspector = !state.persistent_settings.show_inspector;
+        }
+
+        if unframed_button_colored(CHART_BAR, state.persistent_settings.show_histogram, ui)
+            .on_hover_text(format!(
+                "Show the histogram ({})",
+                lookup(&state.persistent_settings.shortcuts, &Histogram)
+            ))
+            .clicked()
+        {
+            state.persistent_settings.show_histogram = !state.persistent_settings.show_histogram;
+        }
+
+        if unframed_button_colored(WAVE_SINE, state.persistent_settings.show_scopes, ui)
+            .on_hover_text(format!(
+                "Show the waveform and vectorscope ({})",
+                lookup(&state.persistent_settings.shortcuts, &Scopes)
+            ))
+            .clicked()
+        {
+            state.persistent_settings.show_scopes = !state.persistent_settings.show_scopes;
+        }
+
+        if unframed_button(TREE_STRUCTURE, ui)
+            .on_hover_text(format!(
+                "Show the folder panel ({})",
+                lookup(&state.persistent_settings.shortcuts, &FolderPanel)
+            ))
+            .clicked()
+        {
+            state.persistent_settings.show_folder_panel =
+                !state.persistent_settings.show_folder_panel;
+        }
+
+        if let Some(path) = state.current_path.clone() {
+            let is_favourite = state.persistent_settings.favourite_images.contains(&path);
+            if unframed_button_colored(HEART, is_favourite, ui)
+                .on_hover_text(format!(
+                    "{} favourites ({})",
+                    if is_favourite {
+                        "Remove from"
+                    } else {
+                        "Add to"
+                    },
+                    lookup(&state.persistent_settings.shortcuts, &Favourite)
+                ))
+                .clicked()
+            {
+                toggle_favourite(state);
+            }
+
+            let stars = state.rating.stars;
+            let mut icon = RichText::new(match stars {
+                0 => STAR.to_string(),
+                _ => format!("{STAR} {stars}"),
+            })
+            .size(ICON_SIZE);
+            if let Some(label) = state.rating.label {
+                icon = icon.color(label.color());
+            }
+            ui.menu_button(icon, |ui| {
+                ui.horizontal(|ui| {
+                    for s in 1..=5 {
+                        if ui.selectable_label(s <= stars, STAR).clicked() {
+                            // clicking the rating again removes it
+                            set_rating(state, if s == stars { 0 } else { s });
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    for label in ColorLabel::iter() {
+                        if ui
+                            .selectable_label(
+                                state.rating.label == Some(label),
+                                RichText::new(CIRCLE).color(label.color()),
+                            )
+                            .on_hover_text(label.to_string())
+                            .clicked()
+                        {
+                            toggle_label(state, label);
+                        }
+                    }
+                });
+            })
+            .response
+            .on_hover_text(format!(
+                "Rating and color label, saved to an XMP sidecar ({} to {})",
+                lookup(&state.persistent_settings.shortcuts, &RateNone),
+                lookup(&state.persistent_settings.shortcuts, &RateFive)
+            ));
+        }
+
+        let capture_button =
+            unframed_button(VIDEO_CAMERA, ui).on_hover_text(match &state.capture {
+                Some(capture) => format!("Stop capturing {}", capture.device),
+                None => "Open capture device".into(),
+            });
+        if capture_button.clicked() && state.capture.take().is_none() {
+            // ffmpeg can take a while to list the devices
+            let (sender, receiver) = std::sync::mpsc::channel();
+            std::thread::spawn(move || _ = sender.send(crate::capture::devices()));
+            state.capture_devices.clear();
+            state.capture_device_listing = Some(receiver);
+            ui.ctx().memory_mut(|w| w.open_popup(Id::new("CAPTURE")));
+        }
+        if let Some(devices) = state
+            .capture_device_listing
+            .as_ref()
+            .and_then(|r| r.try_recv().ok())
+        {
+            state.capture_devices = devices;
+            state.capture_device_listing = None;
+        }
+        let listing = state.capture_device_listing.is_some();
+        let devices = &state.capture_devices;
+        let device = egui::popup_below_widget(ui, Id::new("CAPTURE"), &capture_button, |ui| {
+            if listing {
+                ui.spinner();
+                ui.ctx().request_repaint();
+            } else if devices.is_empty() {
+                ui.label("No capture devices found");
+            }
+            let mut chosen = None;
+            for device in devices {
+                if ui.button(device.as_str()).clicked() {
+                    chosen = Some(device.clone());
+                }
+            }
+            chosen
+        })
+        .flatten();
+        if let Some(device) = device {
+            state.player.stop();
+            state.current_path = None;
+            state.redraw = true;
+            state.capture = Some(crate::capture::Capture::start(
+                &device,
+                state.texture_channel.0.clone(),
+                state.message_channel.0.clone(),
+            ));
+        }
+
         #[cfg(not(feature = "file_open"))]
         {
             if ui.ctx().memory(|w| w.is_popup_open(Id::new("OPEN"))) {
                 filebrowser::browse_modal(
                     false,
-                    SUPPORTED_EXTENSIONS,
+                    supported_extensions(),
                     |p| {
                         let _ = state.load_channel.0.clone().send(p.to_path_buf());
                         ui.ctx().memory_mut(|w| w.close_popup());
@@ -1966,6 +4716,61 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, gfx: &mu
             }
         }
 
+        if state.is_animation {
+            let playback = state
+                .player
+                .playback
+                .lock()
+                .map(|p| p.clone())
+                .unwrap_or_default();
+            if tooltip(
+                unframed_button(SKIP_BACK, ui),
+                "Previous frame",
+                &lookup(&state.persistent_settings.shortcuts, &PreviousFrame),
+                ui,
+            )
+            .clicked()
+            {
+                state.player.step(-1);
+            }
+            if tooltip(
+                unframed_button(if playback.paused { PLAY } else { PAUSE }, ui),
+                if playback.paused { "Play" } else { "Pause" },
+                &lookup(&state.persistent_settings.shortcuts, &PauseAnimation),
+                ui,
+            )
+            .clicked()
+            {
+                state.player.toggle_pause();
+            }
+            if tooltip(
+                unframed_button(SKIP_FORWARD, ui),
+                "Next frame",
+                &lookup(&state.persistent_settings.shortcuts, &NextFrame),
+                ui,
+            )
+            .clicked()
+            {
+                state.player.step(1);
+            }
+            let mut speed = playback.speed;
+            egui::ComboBox::from_id_source("playback speed")
+                .width(60.)
+                .selected_text(format!("{speed}x"))
+                .show_ui(ui, |ui| {
+                    for s in Playback::SPEEDS {
+                        ui.selectable_value(&mut speed, s, format!("{s}x"));
+                    }
+                })
+                .response
+                .on_hover_text("Playback speed");
+            if speed != playback.speed {
+                if let Ok(mut p) = state.player.playback.lock() {
+                    p.speed = speed;
+                }
+            }
+        }
+
         if state.current_image.is_some() {
             if tooltip(
                 // ui.checkbox(&mut state.info_enabled, "ℹ Info"),
@@ -2064,6 +4869,34 @@ pub fn main_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App, gfx: &mu
             }
         }
 
+        if state
+            .download
+            .as_ref()
+            .map(|d| d.is_finished())
+            .unwrap_or(true)
+        {
+            state.download = None;
+        }
+        if let Some(download) = &state.download {
+            ui.horizontal(|ui| {
+                match download.progress() {
+                    Some(progress) => {
+                        ui.add(
+                            egui::ProgressBar::new(progress)
+                                .desired_width(100.)
+                                .show_percentage(),
+                        );
+                    }
+                    None => {
+                        ui.add(egui::Spinner::default());
+                    }
+                }
+                ui.label(format!("Downloading {}", download.url))
+                    .on_hover_text(format!("{:.1} MB", download.received() as f64 / 1_000_000.));
+            });
+            app.window().request_frame();
+        }
+
         drag_area(ui, state, app);
 
         ui.add_space(ui.available_width() - 32.);
@@ -2118,30 +4951,54 @@ pub fn draw_hamburger_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App
                 .clicked()
                 || key_pressed(app, state, Paste)
             {
-                match clipboard_to_image() {
-                    Ok(img) => {
-                        state.current_path = None;
-                        // Stop in the even that an animation is running
-                        state.player.stop();
-                        _ = state
-                            .player
-                            .image_sender
-                            .send(crate::utils::Frame::new_still(img));
-                        // Since pasted data has no path, make sure it's not set
-                        state.send_message_info("Image pasted");
-                    }
-                    Err(e) => state.send_message_err(&e.to_string()),
-                }
+                paste(state);
+                ui.close_menu();
+            }
+
+            if ui.button(format!("{FOLDERS} Convert folder")).clicked() {
+                state.convert_enabled = !state.convert_enabled;
                 ui.close_menu();
             }
 
+            let folder = state
+                .hot_folder
+                .as_ref()
+                .map(|h| h.folder.clone())
+                .or_else(|| {
+                    state
+                        .current_path
+                        .as_ref()?
+                        .parent()
+                        .map(|p| p.to_path_buf())
+                });
+            if let Some(folder) = folder {
+                if ui
+                    .selectable_label(state.hot_folder.is_some(), format!("{EYE} Watch folder"))
+                    .on_hover_text(format!(
+                        "Show new images as they appear in {}",
+                        folder.display()
+                    ))
+                    .clicked()
+                {
+                    watch_folder(state, &folder);
+                    ui.close_menu();
+                }
+            }
+
             if ui.button("⛭ Preferences").clicked() {
                 state.settings_enabled = !state.settings_enabled;
                 ui.close_menu();
             }
 
             ui.menu_button("Recent", |ui| {
-                for r in &state.persistent_settings.recent_images.clone() {
+                let recent = state
+                    .persistent_settings
+                    .recent_images
+                    .iter()
+                    .take(10)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for r in &recent {
                     if let Some(filename) = r.file_name() {
                         if ui.button(filename.to_string_lossy()).clicked() {
                             load_image_from_path(r, state);
@@ -2149,24 +5006,60 @@ pub fn draw_hamburger_menu(ui: &mut Ui, state: &mut OculanteState, app: &mut App
                         }
                     }
                 }
+                ui.separator();
+                if ui
+                    .button("Find a recent file")
+                    .on_hover_text(lookup(&state.persistent_settings.shortcuts, &RecentFiles))
+                    .clicked()
+                {
+                    state.recent_filter = Some(Default::default());
+                    ui.close_menu();
+                }
+                if ui.button("Clear").clicked() {
+                    state.persistent_settings.recent_images.clear();
+                    ui.close_menu();
+                }
             });
 
             if ui.button("Quit").clicked() {
                 app.backend.exit();
             }
 
-            // TODO: expose favourites with a tool button
-            // ui.menu_button("Favourites", |ui| {
-            //     for r in &state.persistent_settings.favourite_images.clone() {
-            //         if let Some(filename) = r.file_name() {
-            //             if ui.button(filename.to_string_lossy()).clicked() {
-            //ui.close_menu();
-
-            //             }
-            //         }
-            //     }
-
-            // });
+            ui.menu_button("Favourites", |ui| {
+                if ui
+                    .selectable_label(state.favourites_only, "Browse favourites only")
+                    .on_hover_text(format!(
+                        "Go through the favourites instead of the folder ({})",
+                        lookup(&state.persistent_settings.shortcuts, &FavouritesOnly)
+                    ))
+                    .clicked()
+                {
+                    toggle_favourites_only(state);
+                    ui.close_menu();
+                }
+                ui.separator();
+                let mut favourites = state
+                    .persistent_settings
+                    .favourite_images
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                favourites.sort_unstable_by(|a, b| {
+                    lexical_sort::natural_lexical_cmp(&a.to_string_lossy(), &b.to_string_lossy())
+                });
+                for r in &favourites {
+                    if let Some(filename) = r.file_name() {
+                        if ui
+                            .button(filename.to_string_lossy())
+                            .on_hover_text(r.to_string_lossy())
+                            .clicked()
+                        {
+                            load_image_from_path(r, state);
+                            ui.close_menu();
+                        }
+                    }
+                }
+            });
         });
 
         // });
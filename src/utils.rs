@@ -18,19 +18,28 @@ use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
 use image::{self, ImageBuffer};
-use image::{EncodableLayout, Rgba, RgbaImage};
+use image::{EncodableLayout, Rgba, Rgba32FImage, RgbaImage};
 use std::sync::mpsc::{self};
 use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
 use strum::Display;
 use strum_macros::EnumIter;
 
 use crate::appstate::{ImageGeometry, Message, OculanteState};
-use crate::cache::Cache;
+use crate::cache::{Cache, Prefetch};
+use crate::gallery::Gallery;
+use crate::hotfolder::HotFolder;
 use crate::image_editing::{self, ImageOperation};
 use crate::image_loader::open_image;
+use crate::metadata::Metadata;
+use crate::scrubber::Scrubber;
 use crate::shortcuts::{lookup, InputEvent, Shortcuts};
+use crate::tiled::TiledImage;
+use crate::video::{self, Video, VIDEO_EXTENSIONS};
+use crate::xmp::{ColorLabel, Rating};
 
 pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "basis",
     "bmp",
     "dds",
     "exr",
@@ -65,18 +74,57 @@ pub const SUPPORTED_EXTENSIONS: &[&str] = &[
     "nrw",
     "raw",
     "avif",
+    "avifs",
     "jxl",
     "ppm",
+    "pgm",
+    "pbm",
+    "pam",
+    "pfm",
     "qoi",
+    "ora",
+    "kra",
+    "fits",
+    "fit",
+    "fts",
+    "dcm",
+    "dicom",
     "ktx2",
+    "zip",
+    "cbz",
+    "cbr",
     #[cfg(feature = "j2k")]
     "jp2",
+    #[cfg(feature = "j2k")]
+    "j2k",
+    #[cfg(feature = "j2k")]
+    "j2c",
+    #[cfg(feature = "j2k")]
+    "jpc",
+    #[cfg(feature = "j2k")]
+    "jpf",
+    #[cfg(feature = "j2k")]
+    "jpx",
     #[cfg(feature = "heif")]
     "heif",
     #[cfg(feature = "heif")]
     "heic",
+    #[cfg(feature = "heif")]
+    "heics",
 ];
 
+/// All extensions that can be opened. Videos are included if ffmpeg is installed.
+pub fn supported_extensions() -> &'static [&'static str] {
+    static EXTENSIONS: OnceLock<Vec<&str>> = OnceLock::new();
+    EXTENSIONS.get_or_init(|| {
+        let mut extensions = SUPPORTED_EXTENSIONS.to_vec();
+        if video::is_available() {
+            extensions.extend(VIDEO_EXTENSIONS);
+        }
+        extensions
+    })
+}
+
 fn is_pixel_fully_transparent(p: &Rgba<u8>) -> bool {
     p.0 == [0, 0, 0, 0]
 }
@@ -89,7 +137,8 @@ pub struct ExtendedImageInfo {
     pub red_histogram: Vec<(i32, i32)>,
     pub green_histogram: Vec<(i32, i32)>,
     pub blue_histogram: Vec<(i32, i32)>,
-    pub exif: HashMap<String, String>,
+    /// EXIF, XMP and IPTC tags, and the position the image was taken at
+    pub metadata: Metadata,
     pub raw_exif: Option<Bytes>,
     pub name: String,
 }
@@ -97,18 +146,17 @@ pub struct ExtendedImageInfo {
 impl ExtendedImageInfo {
     pub fn with_exif(&mut self, image_path: &Path) -> Result<()> {
         self.name = image_path.to_string_lossy().to_string();
+        let input = std::fs::read(image_path)?;
+        self.metadata = Metadata::read(image_path, &input);
         if image_path.extension() == Some(OsStr::new("gif")) {
             return Ok(());
         }
 
-        let input = std::fs::read(image_path)?;
-
         // Store original EXIF to write in in case of save event
         if let Some(d) = DynImage::from_bytes(input.clone().into())? {
             self.raw_exif = d.exif()
         }
 
-        // User-friendly Exif in key/value form
         let mut c = Cursor::new(input);
         let exifreader = exif::Reader::new();
         let exif = exifreader.read_from_container(&mut c)?;
@@ -118,12 +166,6 @@ impl ExtendedImageInfo {
         if self.raw_exif.is_none() {
             self.raw_exif = Some(exif.buf().to_vec().into());
         }
-        for f in exif.fields() {
-            self.exif.insert(
-                f.tag.to_string(),
-                f.display_value().with_unit(&exif).to_string(),
-            );
-        }
         Ok(())
     }
 
@@ -176,20 +218,90 @@ impl ExtendedImageInfo {
             red_histogram,
             raw_exif: Default::default(),
             name: Default::default(),
-            exif: Default::default(),
+            metadata: Default::default(),
         }
     }
 }
 
+/// How an animation plays, shared with the thread that sends its frames
+#[derive(Debug, Clone)]
+pub struct Playback {
+    pub paused: bool,
+    /// How much faster than normal the animation plays
+    pub speed: f32,
+    /// Frames to move while paused, negative to go back
+    pub step: i32,
+    /// The index of the frame that was sent last
+    pub frame: usize,
+    /// The delay of the frame that was sent last, in milliseconds
+    pub delay: u16,
+    /// How many frames have been decoded so far
+    pub frame_count: usize,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            paused: false,
+            speed: 1.,
+            step: 0,
+            frame: 0,
+            delay: 0,
+            frame_count: 0,
+        }
+    }
+}
+
+impl Playback {
+    pub const SPEEDS: [f32; 7] = [0.1, 0.25, 0.5, 1., 2., 4., 8.];
+
+    /// Move to the next of `len` frames, or by `step` frames while paused. Returns the new frame
+    /// and whether playback went past the last frame, or `None` while paused without a step.
+    pub fn advance(&mut self, len: usize) -> Option<(usize, bool)> {
+        if len == 0 {
+            return None;
+        }
+        let (frame, wrapped) = if self.paused {
+            if self.step == 0 {
+                return None;
+            }
+            let frame = (self.frame as i64 + self.step as i64).rem_euclid(len as i64) as usize;
+            self.step = 0;
+            (frame, false)
+        } else if self.frame + 1 >= len {
+            (0, true)
+        } else {
+            (self.frame + 1, false)
+        };
+        self.frame = frame;
+        Some((frame, wrapped))
+    }
+
+    /// How long a frame with a `delay` in milliseconds is shown at the current speed
+    pub fn wait(&self, delay: u16) -> Duration {
+        let delay = if delay > 0 { delay } else { 40 };
+        // cap at 60fps
+        let ms = (delay as f32 / self.speed.max(0.01)).max(17.);
+        Duration::from_secs_f32(ms / 1000.)
+    }
+}
+
 #[derive(Debug)]
 pub struct Player {
     pub image_sender: Sender<Frame>,
     pub stop_sender: Sender<()>,
+    /// Playback of the current animation. Replaced on every load, so an old thread can't change it.
+    pub playback: Arc<Mutex<Playback>>,
     pub cache: Cache,
+    pub prefetch: Prefetch,
     pub max_texture_size: u32,
-    watcher: HashMap<PathBuf, SystemTime>,
+    /// The file shown and its modification time when it was loaded, see `watch`
+    watched: Arc<Mutex<Option<(PathBuf, SystemTime)>>>,
 }
 
+/// How often the file shown is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(800);
+
 impl Player {
     /// Create a new Player
     pub fn new(image_sender: Sender<Frame>, cache_size: usize, max_texture_size: u32) -> Player {
@@ -197,31 +309,45 @@ impl Player {
         Player {
             image_sender,
             stop_sender,
+            playback: Default::default(),
             cache: Cache {
                 data: Default::default(),
                 cache_size,
             },
+            prefetch: Default::default(),
             max_texture_size,
-            watcher: Default::default(),
+            watched: Default::default(),
         }
     }
 
-    pub fn check_modified(&mut self, path: &Path, message_sender: Sender<Message>) {
-        if let Some(watched_mod) = self.watcher.get(path) {
-            if let Ok(meta) = std::fs::metadata(path) {
-                if let Ok(modified) = meta.modified() {
-                    if watched_mod != &modified {
-                        debug!(
-                            "Modified! read from meta {:?} stored: {:?}",
-                            modified, watched_mod
-                        );
-
-                        self.cache.data.remove(path);
-                        self.load(path, message_sender);
+    /// Check the file shown for changes in a thread, which sends it to `reload_sender` when it
+    /// was modified since it was loaded. The thread ends with the player.
+    pub fn watch(&self, reload_sender: Sender<PathBuf>) {
+        let watched = Arc::downgrade(&self.watched);
+        thread::spawn(move || loop {
+            thread::sleep(WATCH_INTERVAL);
+            let Some(watched) = watched.upgrade() else {
+                return;
+            };
+            let Some((path, loaded)) = watched.lock().ok().and_then(|w| w.clone()) else {
+                continue;
+            };
+            let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) else {
+                continue;
+            };
+            if modified != loaded {
+                debug!("Modified! read from meta {modified:?} stored: {loaded:?}");
+                if let Ok(mut watched) = watched.lock() {
+                    // another file may have been opened in the meantime
+                    if watched.as_ref().map(|(p, _)| p) == Some(&path) {
+                        *watched = Some((path.clone(), modified));
                     }
                 }
+                if reload_sender.send(path).is_err() {
+                    return;
+                }
             }
-        }
+        });
     }
 
     pub fn load_advanced(
@@ -234,6 +360,12 @@ impl Player {
         self.stop();
         let (stop_sender, stop_receiver): (Sender<()>, Receiver<()>) = mpsc::channel();
         self.stop_sender = stop_sender;
+        // the speed is kept for the next animation
+        let speed = self.playback.lock().map(|p| p.speed).unwrap_or(1.);
+        self.playback = Arc::new(Mutex::new(Playback {
+            speed,
+            ..Default::default()
+        }));
 
         if let Some(cached_image) = self.cache.get(img_location) {
             let mut frame = Frame::new_still(cached_image);
@@ -245,22 +377,37 @@ impl Player {
             return;
         }
 
+        if forced_frame_source.is_none() {
+            if let Some(frame) = self.prefetch.take(img_location) {
+                _ = self.image_sender.send(frame);
+                debug!("Prefetch hit for {}", img_location.display());
+                return;
+            }
+        }
+
         send_image_threaded(
             img_location,
             self.image_sender.clone(),
             message_sender,
             stop_receiver,
+            self.playback.clone(),
             self.max_texture_size,
             forced_frame_source,
         );
 
-        if let Ok(meta) = std::fs::metadata(img_location) {
-            if let Ok(modified) = meta.modified() {
-                self.watcher.insert(img_location.into(), modified);
-            }
+        if let Ok(mut watched) = self.watched.lock() {
+            *watched = std::fs::metadata(img_location)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(|modified| (img_location.to_path_buf(), modified));
         }
     }
 
+    /// Decode `paths` in the background, to be opened next
+    pub fn prefetch(&mut self, paths: &[PathBuf]) {
+        self.prefetch.request(paths, self.max_texture_size);
+    }
+
     pub fn load(&mut self, img_location: &Path, message_sender: Sender<Message>) {
         self.load_advanced(img_location, None, message_sender);
     }
@@ -268,6 +415,20 @@ impl Player {
     pub fn stop(&self) {
         _ = self.stop_sender.send(());
     }
+
+    pub fn toggle_pause(&self) {
+        if let Ok(mut p) = self.playback.lock() {
+            p.paused = !p.paused;
+        }
+    }
+
+    /// Pause and move `frames` frames, negative to go back
+    pub fn step(&self, frames: i32) {
+        if let Ok(mut p) = self.playback.lock() {
+            p.paused = true;
+            p.step += frames;
+        }
+    }
 }
 
 pub fn send_image_threaded(
@@ -275,6 +436,7 @@ pub fn send_image_threaded(
     texture_sender: Sender<Frame>,
     message_sender: Sender<Message>,
     stop_receiver: Receiver<()>,
+    playback: Arc<Mutex<Playback>>,
     max_texture_size: u32,
     forced_frame_source: Option<FrameSource>,
 ) {
@@ -292,6 +454,13 @@ pub fn send_image_threaded(
 
                 let mut first = true;
                 for mut f in frame_receiver.iter() {
+                    if let FrameSource::Preview(_) = f.source {
+                        // a forced source is meant for the finished image
+                        if forced_frame_source.is_none() {
+                            _ = texture_sender.send(f);
+                        }
+                        continue;
+                    }
                     if let Some(ref fs) = forced_frame_source {
                         f.source = fs.clone();
                     }
@@ -324,6 +493,7 @@ pub fn send_image_threaded(
                                 dimensions: new_dimensions,
                                 aspect: true,
                                 filter: image_editing::ScaleFilter::Box,
+                                mode: image_editing::ResizeMode::Absolute,
                             };
                             _ = op.process_image(&mut frame.buffer);
                             let _ = texture_sender.send(frame);
@@ -335,6 +505,17 @@ pub fn send_image_threaded(
                     }
                     if f.source == FrameSource::Animation {
                         framecache.push(f.clone());
+                        let (paused, speed) = match playback.lock() {
+                            Ok(mut p) => {
+                                p.frame_count = framecache.len();
+                                (p.paused, p.speed)
+                            }
+                            Err(_) => (false, 1.),
+                        };
+                        // keep decoding while paused, but stay on the frame that is shown
+                        if paused && !first {
+                            continue;
+                        }
                         if first {
                             _ = texture_sender
                                 .clone()
@@ -342,8 +523,13 @@ pub fn send_image_threaded(
                         } else {
                             let _ = texture_sender.send(f.clone());
                         }
+                        if let Ok(mut p) = playback.lock() {
+                            p.frame = framecache.len() - 1;
+                            p.delay = f.delay;
+                        }
                         let elapsed = timer.elapsed().as_millis();
-                        let wait_time_after_loading = f.delay.saturating_sub(elapsed as u16);
+                        let delay = (f.delay as f32 / speed.max(0.01)) as u16;
+                        let wait_time_after_loading = delay.saturating_sub(elapsed as u16);
                         debug!("elapsed {elapsed}, wait {wait_time_after_loading}");
                         std::thread::sleep(Duration::from_millis(wait_time_after_loading as u64));
                         timer = std::time::Instant::now();
@@ -352,21 +538,33 @@ pub fn send_image_threaded(
                     first = false;
                 }
 
+                // continue after the frame that is shown
+                let mut loops = 0;
                 // loop over the image. For sanity, stop at a limit of iterations.
-                for _ in 0..500 {
-                    // let frames = col.frames.clone();
-                    for frame in &framecache {
-                        if stop_receiver.try_recv().is_ok() {
-                            debug!("Stopped from receiver.");
-                            return;
-                        }
-                        let _ = texture_sender.send(frame.clone());
-                        if frame.delay > 0 {
-                            //                                                  cap at 60fps
-                            thread::sleep(Duration::from_millis(frame.delay.max(17) as u64));
-                        } else {
-                            thread::sleep(Duration::from_millis(40_u64));
-                        }
+                while loops < 500 && !framecache.is_empty() {
+                    if stop_receiver.try_recv().is_ok() {
+                        debug!("Stopped from receiver.");
+                        return;
+                    }
+                    let Ok(mut p) = playback.lock() else {
+                        return;
+                    };
+                    let Some((index, wrapped)) = p.advance(framecache.len()) else {
+                        drop(p);
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    };
+                    if wrapped {
+                        loops += 1;
+                    }
+                    let frame = &framecache[index];
+                    p.delay = frame.delay;
+                    let wait = (!p.paused).then(|| p.wait(frame.delay));
+                    drop(p);
+
+                    let _ = texture_sender.send(frame.clone());
+                    if let Some(wait) = wait {
+                        thread::sleep(wait);
                     }
                 }
             }
@@ -389,21 +587,37 @@ pub enum FrameSource {
     Still,
     EditResult,
     CompareResult,
+    /// The file at this path shown again in another way, like a vector image rendered at another
+    /// size or a mip level of a texture
+    Rerender(PathBuf),
+    /// A small version of an image that is still being decoded, with the size of the full image
+    Preview((u32, u32)),
+    /// A live frame of a capture device
+    Capture,
 }
 
 /// A single frame
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub buffer: RgbaImage,
+    /// Unclamped, gamma encoded pixels of high dynamic range sources, matching `buffer`
+    pub hdr_buffer: Option<Rgba32FImage>,
     /// How long to pause until the next frame, in milliseconds
     pub delay: u16,
     pub source: FrameSource,
+    /// A very large image that `buffer` is the overview of
+    pub tiled: Option<Arc<TiledImage>>,
+    /// The video `buffer` is the first frame of
+    pub video: Option<Video>,
 }
 
 impl Frame {
     pub fn new(buffer: RgbaImage, delay_ms: u16, source: FrameSource) -> Frame {
         Frame {
             buffer,
+            hdr_buffer: None,
+            tiled: None,
+            video: None,
             delay: delay_ms,
             source,
         }
@@ -412,6 +626,9 @@ impl Frame {
     pub fn new_reset(buffer: RgbaImage) -> Frame {
         Frame {
             buffer,
+            hdr_buffer: None,
+            tiled: None,
+            video: None,
             delay: 0,
             source: FrameSource::AnimationStart,
         }
@@ -421,6 +638,9 @@ impl Frame {
     pub fn new_edit(buffer: RgbaImage) -> Frame {
         Frame {
             buffer,
+            hdr_buffer: None,
+            tiled: None,
+            video: None,
             delay: 0,
             source: FrameSource::EditResult,
         }
@@ -429,10 +649,21 @@ impl Frame {
     pub fn new_still(buffer: RgbaImage) -> Frame {
         Frame {
             buffer,
+            hdr_buffer: None,
+            tiled: None,
+            video: None,
             delay: 0,
             source: FrameSource::Still,
         }
     }
+
+    /// A still frame which keeps the full range of a high dynamic range source for editing
+    pub fn new_still_hdr(buffer: RgbaImage, hdr_buffer: Rgba32FImage) -> Frame {
+        Frame {
+            hdr_buffer: Some(hdr_buffer),
+            ..Frame::new_still(buffer)
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, EnumIter, Display, Clone, Copy)]
@@ -512,7 +743,7 @@ pub fn toggle_fullscreen(app: &mut App, state: &mut OculanteState) {
 
 /// Determine if an enxtension is compatible with oculante
 pub fn is_ext_compatible(fname: &Path) -> bool {
-    SUPPORTED_EXTENSIONS.contains(
+    supported_extensions().contains(
         &fname
             .extension()
             .unwrap_or_default()
@@ -796,6 +1027,145 @@ pub fn set_title(app: &mut App, state: &mut OculanteState) {
     app.window().set_title(&title_string);
 }
 
+/// How well `text` matches what was typed, if it contains the characters of `query` in order.
+/// Lower is better: the count of characters skipped between the matched ones.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<usize> {
+    let query = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    let text = text
+        .chars()
+        .flat_map(char::to_lowercase)
+        .collect::<Vec<_>>();
+    let Some(first) = query.first() else {
+        return Some(0);
+    };
+    // the best of the matches starting at every occurrence of the first character
+    (0..text.len())
+        .filter(|&start| text[start] == *first)
+        .filter_map(|start| {
+            let mut skipped = 0;
+            let mut rest = text[start + 1..].iter();
+            for q in &query[1..] {
+                loop {
+                    if rest.next()? == q {
+                        break;
+                    }
+                    skipped += 1;
+                }
+            }
+            Some(skipped)
+        })
+        .min()
+}
+
+/// Show the images that appear in `folder` from now on, or stop watching if it is watched already
+pub fn watch_folder(state: &mut OculanteState, folder: &Path) {
+    if state.hot_folder.as_ref().map(|h| h.folder.as_path()) == Some(folder) {
+        state.hot_folder = None;
+        state.send_message_info("Stopped watching the folder");
+    } else if folder.is_dir() {
+        state.hot_folder = Some(HotFolder::start(folder, state.load_channel.0.clone()));
+        state.send_message_info(&format!("Watching {} for new images", folder.display()));
+    } else {
+        state.send_message_err(&format!("{} is not a folder", folder.display()));
+    }
+}
+
+/// Mark the current image as a favourite, or unmark it
+pub fn toggle_favourite(state: &mut OculanteState) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    if state.persistent_settings.favourite_images.remove(&path) {
+        state.send_message_info("Removed from favourites");
+    } else {
+        state.persistent_settings.favourite_images.insert(path);
+        state.send_message_info("Added to favourites");
+    }
+    state.persistent_settings.save_threaded();
+}
+
+/// Give the current image a star rating, 0 to remove it, and store it in its XMP sidecar
+pub fn set_rating(state: &mut OculanteState, stars: u8) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    let rating = Rating {
+        stars,
+        ..state.rating
+    };
+    match rating.write(&path) {
+        Ok(_) => {
+            state.rating = rating;
+            match stars {
+                0 => state.send_message_info("Rating removed"),
+                1 => state.send_message_info("Rated 1 star"),
+                _ => state.send_message_info(&format!("Rated {stars} stars")),
+            }
+        }
+        Err(e) => state.send_message_err(&format!("Could not save the rating: {e}")),
+    }
+}
+
+/// Give the current image a color label, or remove it if it has this one already
+pub fn toggle_label(state: &mut OculanteState, label: ColorLabel) {
+    let Some(path) = state.current_path.clone() else {
+        return;
+    };
+    let rating = Rating {
+        label: (state.rating.label != Some(label)).then_some(label),
+        ..state.rating
+    };
+    match rating.write(&path) {
+        Ok(_) => state.rating = rating,
+        Err(e) => state.send_message_err(&format!("Could not save the label: {e}")),
+    }
+}
+
+/// Go through the favourite images only, or through the folder of the current image again
+pub fn toggle_favourites_only(state: &mut OculanteState) {
+    state.favourites_only = !state.favourites_only;
+    let listing = &state.persistent_settings.listing;
+    if state.favourites_only {
+        state.scrubber = Scrubber::favourites(
+            &state.persistent_settings.favourite_images,
+            state.current_path.as_deref(),
+            listing,
+        );
+        if state.scrubber.entries.is_empty() {
+            state.favourites_only = false;
+            state.send_message_warn("There are no favourites yet");
+        } else if !state
+            .scrubber
+            .entries
+            .contains(&state.current_path.clone().unwrap_or_default())
+        {
+            let first = state.scrubber.set(0);
+            load_image_from_path(&first, state);
+        }
+    } else if let Some(path) = &state.current_path {
+        state.scrubber = Scrubber::new(path, listing);
+    }
+    state.scrubber.wrap = state.persistent_settings.wrap_folder;
+}
+
+/// Show or hide the thumbnails of the folder of the current image, or of the last folder opened
+pub fn toggle_gallery(state: &mut OculanteState) {
+    if state.gallery.take().is_some() {
+        return;
+    }
+    if state.scrubber.entries.is_empty() {
+        state.scrubber = Scrubber::new(
+            &state.persistent_settings.last_open_directory,
+            &state.persistent_settings.listing,
+        );
+    }
+    state.gallery = Some(Gallery::new(state.scrubber.index));
+}
+
 pub fn compare_next(state: &mut OculanteState) {
     if let Some(p) = &(state.current_path).clone() {
         let mut compare_list: Vec<(PathBuf, ImageGeometry)> =
@@ -851,6 +1221,170 @@ pub fn fix_exif(p: &Path, exif: Option<Bytes>) -> Result<()> {
     Ok(())
 }
 
+/// Read the EXIF orientation (1-8) of an image file
+pub fn exif_orientation(p: &Path) -> Result<u8> {
+    // the rotation of HEIF based images is stored in the container and applied when decoding
+    let ext = p
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    if matches!(ext.as_str(), "avif" | "avifs" | "heic" | "heics" | "heif") {
+        return Ok(1);
+    }
+    let file = std::fs::File::open(p)?;
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file))?;
+    let orientation = exif
+        .get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        .context("No orientation tag")?;
+    Ok(orientation as u8)
+}
+
+/// Change the EXIF orientation (1-8) of a file without touching the pixels. The tag is updated in
+/// place if it exists, otherwise the EXIF data is written again with it.
+pub fn set_exif_orientation(p: &Path, orientation: u8) -> Result<()> {
+    let input = std::fs::read(p)?;
+    let mut dynimage = DynImage::from_bytes(input.into())?.context("Unsupported EXIF format")?;
+    let mut tiff = dynimage.exif().map(|e| e.to_vec()).unwrap_or_default();
+    if !patch_orientation(&mut tiff, orientation) {
+        let exif = exif::Reader::new().read_raw(tiff).ok();
+        let orientation_field = exif::Field {
+            tag: exif::Tag::Orientation,
+            ifd_num: exif::In::PRIMARY,
+            value: exif::Value::Short(vec![orientation as u16]),
+        };
+        let mut writer = exif::experimental::Writer::new();
+        writer.push_field(&orientation_field);
+        if let Some(exif) = &exif {
+            for field in exif.fields().filter(|f| f.tag != exif::Tag::Orientation) {
+                writer.push_field(field);
+            }
+        }
+        let mut buf = Cursor::new(vec![]);
+        writer.write(
+            &mut buf,
+            exif.as_ref().map(|e| e.little_endian()).unwrap_or_default(),
+        )?;
+        tiff = buf.into_inner();
+    }
+    dynimage.set_exif(Some(tiff.into()));
+    dynimage.encoder().write_to(std::fs::File::create(p)?)?;
+    Ok(())
+}
+
+/// Overwrite the orientation tag of the first IFD of TIFF/EXIF data. Returns false if there is none.
+fn patch_orientation(tiff: &mut [u8], orientation: u8) -> bool {
+    let Some((offset, little_endian)) = orientation_offset(tiff) else {
+        return false;
+    };
+    let value = if little_endian {
+        [orientation, 0]
+    } else {
+        [0, orientation]
+    };
+    tiff[offset..offset + 2].copy_from_slice(&value);
+    true
+}
+
+/// Where the value of a SHORT orientation tag is stored in the first IFD, and the byte order
+fn orientation_offset(tiff: &[u8]) -> Option<(usize, bool)> {
+    let little_endian = match tiff.get(0..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read = |pos: usize, len: usize| -> Option<usize> {
+        let bytes = tiff.get(pos..pos + len)?;
+        let mut value = 0;
+        for i in 0..len {
+            let b = if little_endian {
+                bytes[len - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = value << 8 | b as usize;
+        }
+        Some(value)
+    };
+    let ifd = read(4, 4)?;
+    for i in 0..read(ifd, 2)? {
+        let entry = ifd + 2 + i * 12;
+        // type 3 is SHORT, which fits into the entry itself
+        if read(entry, 2)? == 0x0112 && read(entry + 2, 2)? == 3 && tiff.len() >= entry + 10 {
+            return Some((entry + 8, little_endian));
+        }
+    }
+    None
+}
+
+/// A file copied in a file manager, which puts its path or a `file://` link on the clipboard
+pub fn clipboard_path(text: &str) -> Option<PathBuf> {
+    text.lines()
+        .map(|l| l.trim())
+        .map(|l| match l.strip_prefix("file://") {
+            Some(uri) => percent_decode(uri.strip_prefix("localhost").unwrap_or(uri)),
+            None => l.to_string(),
+        })
+        .map(PathBuf::from)
+        .find(|p| p.is_file() && is_ext_compatible(p))
+}
+
+/// Decode the `%20` style escapes of a link
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Open what is on the clipboard. Bitmaps, like screenshots or images copied in a browser, become
+/// an image without a file. Copied files and links to images are opened.
+pub fn paste(state: &mut OculanteState) {
+    match clipboard_to_image() {
+        Ok(img) => {
+            // Since pasted data has no path, make sure it's not set
+            state.current_path = None;
+            // Stop in the even that an animation is running
+            state.player.stop();
+            state.send_message_info(&format!("Pasted a {}x{} image", img.width(), img.height()));
+            _ = state.player.image_sender.send(Frame::new_still(img));
+        }
+        Err(e) => {
+            let text = Clipboard::new()
+                .and_then(|mut c| c.get_text())
+                .unwrap_or_default();
+            if crate::download::is_url(text.trim()) {
+                state.download = Some(crate::download::Download::start(
+                    text.trim(),
+                    state.load_channel.0.clone(),
+                    state.message_channel.0.clone(),
+                ));
+            } else if let Some(path) = clipboard_path(&text) {
+                _ = state.load_channel.0.send(path);
+            } else {
+                state.send_message_err(&format!("The clipboard has no image: {e}"));
+            }
+        }
+    }
+}
+
 pub fn clipboard_to_image() -> Result<ImageBuffer<Rgba<u8>, Vec<u8>>> {
     let clipboard = &mut Clipboard::new()?;
 
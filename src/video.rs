@@ -0,0 +1,159 @@
+//! Single frames of video files, decoded by the `ffmpeg` and `ffprobe` programs, which need to
+//! be installed. Frames are found by their time, from the frame rate of the first video stream.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, OnceLock};
+
+use anyhow::{bail, Context, Result};
+use image::RgbaImage;
+
+use crate::appstate::Message;
+use crate::utils::{Frame, FrameSource};
+
+pub const VIDEO_EXTENSIONS: &[&str] = &[
+    "avi", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "webm", "wmv",
+];
+
+/// An open video and the frame shown of it
+#[derive(Debug, Clone)]
+pub struct Video {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second
+    pub rate: f64,
+    pub frame_count: usize,
+    pub frame: usize,
+    /// The frame that was asked for last, so earlier ones that finish decoding later are dropped
+    requested: Arc<AtomicUsize>,
+}
+
+/// A frame rate like "30000/1001"
+pub fn parse_rate(rate: &str) -> Option<f64> {
+    match rate.split_once('/') {
+        Some((num, den)) => Some(num.parse::<f64>().ok()? / den.parse::<f64>().ok()?),
+        None => rate.parse().ok(),
+    }
+}
+
+/// The output of a program, or its error message
+fn run(command: &mut Command) -> Result<Vec<u8>> {
+    let output = command
+        .output()
+        .context("Could not run ffmpeg, is it installed?")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(output.stdout)
+}
+
+/// Whether ffmpeg and ffprobe are installed. They are only looked for once.
+pub fn is_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        ["ffmpeg", "ffprobe"]
+            .iter()
+            .all(|program| run(Command::new(program).arg("-version")).is_ok())
+    })
+}
+
+impl Video {
+    pub fn probe(path: &Path) -> Result<Self> {
+        let output = run(Command::new("ffprobe")
+            .args(&["-v", "error", "-select_streams", "v:0", "-show_entries"])
+            .arg("stream=width,height,avg_frame_rate,nb_frames:format=duration")
+            .args(&["-of", "default=noprint_wrappers=1"])
+            .arg(path))?;
+        Self::from_probe(path, &String::from_utf8_lossy(&output))
+    }
+
+    /// The video at `path`, from the `key=value` lines printed by ffprobe
+    pub fn from_probe(path: &Path, output: &str) -> Result<Self> {
+        let values = output
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .collect::<HashMap<_, _>>();
+        let number = |key: &str| values.get(key).and_then(|v| v.trim().parse::<f64>().ok());
+
+        let width = number("width").context("The file has no video")? as u32;
+        let height = number("height").context("The file has no video")? as u32;
+        let rate = values
+            .get("avg_frame_rate")
+            .and_then(|r| parse_rate(r))
+            .filter(|r| r.is_finite() && *r > 0.)
+            .unwrap_or(25.);
+        // not all containers store the number of frames
+        let frame_count = number("nb_frames")
+            .or_else(|| number("duration").map(|d| (d * rate).floor()))
+            .unwrap_or(1.)
+            .max(1.) as usize;
+        Ok(Self {
+            path: path.to_path_buf(),
+            width,
+            height,
+            rate,
+            frame_count,
+            frame: 0,
+            requested: Default::default(),
+        })
+    }
+
+    /// The time of the frame shown, in seconds
+    pub fn time(&self) -> f64 {
+        self.frame as f64 / self.rate
+    }
+
+    pub fn decode(&self) -> Result<RgbaImage> {
+        // rotation is ignored, so the frame has the size of the stream
+        let output = run(Command::new("ffmpeg")
+            .args(&["-v", "error", "-noautorotate", "-ss"])
+            .arg(format!("{:.6}", self.time()))
+            .arg("-i")
+            .arg(&self.path)
+            .args(&["-frames:v", "1", "-f", "rawvideo", "-pix_fmt", "rgba", "-"]))?;
+        RgbaImage::from_raw(self.width, self.height, output).context("ffmpeg returned no frame")
+    }
+
+    /// Show the frame `frames` away from the current one
+    pub fn step(
+        &mut self,
+        frames: isize,
+        texture_sender: &Sender<Frame>,
+        message_sender: &Sender<Message>,
+    ) {
+        let frame = (self.frame as isize + frames).clamp(0, self.frame_count as isize - 1);
+        self.seek(frame as usize, texture_sender, message_sender);
+    }
+
+    /// Show `frame`, which is decoded in the background
+    pub fn seek(
+        &mut self,
+        frame: usize,
+        texture_sender: &Sender<Frame>,
+        message_sender: &Sender<Message>,
+    ) {
+        self.frame = frame;
+        self.requested.store(frame, Ordering::SeqCst);
+        let video = self.clone();
+        let texture_sender = texture_sender.clone();
+        let message_sender = message_sender.clone();
+        std::thread::spawn(move || {
+            let decoded = video.decode();
+            if video.requested.load(Ordering::SeqCst) != video.frame {
+                return;
+            }
+            match decoded {
+                Ok(buf) => {
+                    _ = texture_sender.send(Frame::new(buf, 0, FrameSource::Rerender(video.path)));
+                }
+                Err(e) => {
+                    _ = message_sender.send(Message::err(&format!("Can't decode frame: {e}")));
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,168 @@
+//! Star ratings and color labels in XMP sidecar files, the way Lightroom, darktable and
+//! digiKam keep them next to the images. Only the rating and the label are touched, everything
+//! else in an existing sidecar is kept as it is.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use notan::egui::Color32;
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter};
+
+const XMP_NAMESPACE: &str = r#"xmlns:xmp="http://ns.adobe.com/xap/1.0/""#;
+
+/// A sidecar for images that don't have one yet
+const EMPTY_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"/>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display, EnumIter)]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub fn color(&self) -> Color32 {
+        match self {
+            ColorLabel::Red => Color32::from_rgb(230, 70, 60),
+            ColorLabel::Yellow => Color32::from_rgb(240, 200, 50),
+            ColorLabel::Green => Color32::from_rgb(90, 190, 80),
+            ColorLabel::Blue => Color32::from_rgb(60, 130, 230),
+            ColorLabel::Purple => Color32::from_rgb(160, 90, 200),
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Self::iter().find(|l| l.to_string().eq_ignore_ascii_case(s.trim()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rating {
+    /// 0-5, where 0 is unrated. Rejected images count as unrated.
+    pub stars: u8,
+    pub label: Option<ColorLabel>,
+}
+
+impl Rating {
+    /// The rating in the sidecar of `image`, unrated if there is none
+    pub fn read(image: &Path) -> Self {
+        sidecars(image)
+            .iter()
+            .find_map(|s| std::fs::read_to_string(s).ok())
+            .map(|xmp| Self::parse(&xmp))
+            .unwrap_or_default()
+    }
+
+    fn parse(xmp: &str) -> Self {
+        Self {
+            stars: value(xmp, "xmp:Rating")
+                .and_then(|r| r.trim().parse::<i8>().ok())
+                .unwrap_or_default()
+                .clamp(0, 5) as u8,
+            label: value(xmp, "xmp:Label").and_then(|l| ColorLabel::parse(&l)),
+        }
+    }
+
+    /// Store the rating in the sidecar of `image`, which is made if there is none
+    pub fn write(&self, image: &Path) -> Result<()> {
+        let candidates = sidecars(image);
+        let existing = candidates.iter().find(|s| s.is_file());
+        let mut xmp = match existing {
+            Some(sidecar) => std::fs::read_to_string(sidecar)?,
+            None if *self == Self::default() => return Ok(()),
+            None => EMPTY_SIDECAR.to_string(),
+        };
+        let stars = (self.stars > 0).then(|| self.stars.to_string());
+        set_value(&mut xmp, "xmp:Rating", stars.as_deref())?;
+        set_value(
+            &mut xmp,
+            "xmp:Label",
+            self.label.map(|l| l.to_string()).as_deref(),
+        )?;
+        let sidecar = existing.unwrap_or(&candidates[0]);
+        std::fs::write(sidecar, xmp)
+            .with_context(|| format!("Could not write {}", sidecar.display()))
+    }
+}
+
+/// The sidecars an image may have: `IMG_0001.xmp` as Lightroom names them, or `IMG_0001.CR2.xmp`
+/// like darktable
+pub fn sidecars(image: &Path) -> [PathBuf; 2] {
+    let mut appended = image.as_os_str().to_owned();
+    appended.push(".xmp");
+    [image.with_extension("xmp"), appended.into()]
+}
+
+/// The value of a property, written as an attribute or as an element
+fn value(xmp: &str, name: &str) -> Option<String> {
+    if let Some((start, end)) = attribute(xmp, name) {
+        return Some(xmp[start..end].to_string());
+    }
+    let (start, end) = element(xmp, name)?;
+    Some(xmp[start..end].to_string())
+}
+
+/// Where the value of the attribute `name` starts and ends
+fn attribute(xmp: &str, name: &str) -> Option<(usize, usize)> {
+    for quote in ['"', '\''] {
+        let needle = format!("{name}={quote}");
+        if let Some(start) = xmp.find(&needle).map(|s| s + needle.len()) {
+            let end = start + xmp[start..].find(quote)?;
+            return Some((start, end));
+        }
+    }
+    None
+}
+
+/// Where the text of the element `name` starts and ends
+fn element(xmp: &str, name: &str) -> Option<(usize, usize)> {
+    let open = format!("<{name}>");
+    let start = xmp.find(&open)? + open.len();
+    let end = start + xmp[start..].find(&format!("</{name}>"))?;
+    Some((start, end))
+}
+
+/// Replace, add or, if `value` is `None`, remove a property
+fn set_value(xmp: &mut String, name: &str, value: Option<&str>) -> Result<()> {
+    if let Some((start, end)) = attribute(xmp, name) {
+        match value {
+            Some(value) => xmp.replace_range(start..end, value),
+            None => {
+                // the attribute with the whitespace before it, and its closing quote
+                let name_start = xmp[..start].rfind(name).unwrap_or(start);
+                let name_start = xmp[..name_start].trim_end().len();
+                xmp.replace_range(name_start..end + 1, "");
+            }
+        }
+    } else if let Some((start, end)) = element(xmp, name) {
+        match value {
+            Some(value) => xmp.replace_range(start..end, value),
+            None => {
+                let open = start - name.len() - 2;
+                let close = end + name.len() + 3;
+                xmp.replace_range(open..close, "");
+            }
+        }
+    } else if let Some(value) = value {
+        let description = "<rdf:Description";
+        let at = xmp
+            .find(description)
+            .context("The sidecar has no rdf:Description")?
+            + description.len();
+        let mut attributes = format!(r#" {name}="{value}""#);
+        if !xmp.contains("xmlns:xmp=") {
+            attributes = format!(" {XMP_NAMESPACE}{attributes}");
+        }
+        xmp.insert_str(at, &attributes);
+    }
+    Ok(())
+}